@@ -20,7 +20,7 @@ fn main() {
   let devices = {
     let devices = client.devices();
 
-    if devices.len() == 0 {
+    if devices.is_empty() {
       println!("Waiting 15 more seconds to discover devices...");
       println!("  This might be caused by a busy network...");
 
@@ -1,23 +1,26 @@
 extern crate env_logger;
 extern crate lifx;
 
+use std::time::Duration;
+
 use lifx::Light::*;
-use lifx::{Client, Payload, Power};
+use lifx::{Client, Config, Payload, Power, TransitionMs};
 
 const TARGET: u64 = 3732340569040;
 
-static ADDR: &'static str = "10.0.1.4:56700";
-
 fn main() {
   env_logger::init();
 
   let client = Client::new("0.0.0.0:1234").unwrap();
-  let _ = client.send_msg(
-    ADDR,
-    Payload::Light(SetPower(Power::Max, 500)),
+  client.start(Config::default());
+
+  let bulb = client
+    .wait_for_device(TARGET, Duration::from_secs(10))
+    .unwrap();
+  let _ = bulb.send_msg(
+    Payload::Light(SetPower(Power::Max, TransitionMs::from_millis(500))),
     true,
-    TARGET,
   );
 
-  client.close();
+  client.stop();
 }
@@ -1,18 +1,16 @@
 extern crate env_logger;
 extern crate lifx;
-#[macro_use]
-extern crate log;
 
 use std::thread;
 use std::time::Duration;
 
 use lifx::Light::*;
-use lifx::{Client, Color, Payload, HSBK, MAX_BRIGHTNESS};
+use lifx::{Brightness, Client, Hue, Kelvin, Payload, Saturation, HSBK};
 
-const DELAY: u32 = 500;
+const DELAY: Duration = Duration::from_millis(500);
 const TARGET: u64 = 3732340569040;
 
-static ADDR: &'static str = "10.0.1.4:56700";
+static ADDR: &str = "10.0.1.4:56700";
 
 fn main() {
   env_logger::init();
@@ -23,16 +21,21 @@ fn main() {
   println!("Setting to green...");
 
   //let _ = client.send_msg(ADDR,
-  //                        Payload::Light(SetColor(Color::Green.to_hsbk(MAX_BRIGHTNESS), DELAY)),
+  //                        Payload::Light(SetColor(Color::Green.to_hsbk(Brightness::MAX), DELAY.into())),
   //                        true,
   //                        TARGET);
 
   println!("Setting to white...");
 
-  let white = HSBK::new(0, 3000, !0, 2500);
+  let white = HSBK::new(
+    Hue::MIN,
+    Saturation::from(3000),
+    Brightness::MAX,
+    Kelvin::MIN,
+  );
   let _ = client.send_msg(
     ADDR,
-    Payload::Light(SetColor(white, DELAY)),
+    Payload::Light(SetColor(white, DELAY.into())),
     false,
     TARGET,
   );
@@ -1,408 +1,6764 @@
-use std::collections::HashMap;
-use std::fmt::{Debug, Display, Error, Formatter};
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::mem;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::net::UdpSocket;
 use std::ops::{Deref, Drop};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
-use std::sync::{Arc, RwLock};
+use std::option;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::task::{Context, Poll, Wake, Waker};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use message::Message;
-use net2::{UdpBuilder, UdpSocketExt};
-use payload::{Device, Light, Payload, Service};
-use serialize;
+use consts;
+use error::Error;
+use events::{DeviceEvent, DeviceEvents, DeviceState, Health, Subscribers};
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+use header::Header;
+use if_addrs::IfAddr;
+use inventory::{self, InventoryFormat};
+use mac::MacAddress;
+use message::{Message, SendOptions};
+use payload::{Array64, Button, Device, FirmwareVersion, Light, MoveDirection,
+              MultiZone, MultiZoneEffectType, Payload, PayloadRef, Power, Relay,
+              Service, Waveform, HSBK};
+use saved::{self, Saved, SavedDevice};
+use serialize::{self, DecodeError};
+use socket2::{Domain, Protocol, Socket, Type};
+use transport::{random_source, route_keys, RouteKey, Sequences, Transport};
+use units::{Brightness, Hue, Kelvin, TransitionMs};
+#[cfg(test)]
+use units::Saturation;
 
 pub const MESSAGE_INTERVAL: u8 = 50;
 
+/// how long the listen thread waits for a datagram before checking for devices
+/// that went offline, unless changed with `ClientBuilder::read_timeout`.
+/// `run_until` and `start` poll for as long.
+///
+const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// how long a device can go unheard before the client considers it offline,
+/// unless changed with `Client::set_offline_after`.
+///
+pub const OFFLINE_AFTER: Duration = Duration::from_secs(30);
+
+/// how long sending a datagram can block, unless changed with
+/// `ClientBuilder::write_timeout`.
+///
+const SEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// how long the listen thread waits before asking an unknown device to
+/// announce itself again, when it keeps hearing from it.
+///
+const PROBE_AGAIN_AFTER: Duration = Duration::from_secs(1);
+
+/// how long a message sent with ack_required stays in flight when nothing is
+/// waiting for its ack, before its sequence number can be used again.
+///
+const FORGET_ACK_AFTER: Duration = Duration::from_secs(30);
+
+/// how long a `Host` uses the address its name resolved to.
+///
+pub const HOST_TTL: Duration = Duration::from_secs(60);
+
+/// how many of a device's latest echo probes decide whether it is degraded.
+///
+const HEALTH_WINDOW: usize = 4;
+
 /// udp broadcast ip address and lifx default port.
 ///
-static BROADCAST_IP: &'static str = "255.255.255.255:56700";
+const BROADCAST_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::BROADCAST, consts::PORT);
 
-/// sequence number counter used to confirm acks.
+/// a callback that is handed every datagram the client receives, along with
+/// the address it came from, before it is decoded.
 ///
-static SEQUENCE_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+pub type RawHook = Box<dyn Fn(&[u8], SocketAddr) + Send + Sync>;
 
-/// returns the next sequence number (global, shared counter).
+/// callbacks registered on a client, which `listen` invokes as frames arrive.
 ///
-fn next_sequence() -> u8 {
-  SEQUENCE_COUNTER.fetch_add(1, Ordering::SeqCst) as u8
+#[derive(Default)]
+struct Callbacks {
+  state_change: Vec<StateChangeHook>,
+  device_discovered: Vec<DiscoveredHook>,
+  ack: Vec<Box<dyn Fn(MacAddress, u8) + Send + Sync>>,
+  unknown_message: Vec<UnknownMessageHook>,
+  rejected_frame: Vec<RejectedFrameHook>,
+  enrichment: Option<EnrichmentHook>,
 }
 
-/// sends a message to the specified address.
+type StateChangeHook = Box<dyn Fn(MacAddress, &Payload) + Send + Sync>;
+type DiscoveredHook = Box<dyn Fn(&Bulb<SocketAddr>) + Send + Sync>;
+type UnknownMessageHook = Box<dyn Fn(MacAddress, u16, &[u8]) + Send + Sync>;
+type EnrichmentHook =
+  Box<dyn Fn(&Bulb<SocketAddr>) -> DiscoverOptions + Send + Sync>;
+type RejectedFrameHook = Box<dyn Fn(SocketAddr, &[u8], &DecodeError) + Send + Sync>;
+
+/// a channel returned by `Client::incoming`.
 ///
-fn send_msg<S: Deref<Target = UdpSocket>, A: ToSocketAddrs>(
-  socket: &S,
-  addr: A,
-  payload: Payload,
-  ack_required: bool,
-  target: u64,
-) -> Result<u8, String> {
-  let seq = next_sequence();
-  let msg = Message::new(payload, ack_required, target, seq);
-  let encoded = try!(serialize::encode(&msg).or(err!("failed to encode")));
-  let bytes = try!(
-    socket
-      .send_to(&encoded[..], addr)
-      .or(err!("failed to send message"))
-  );
+type Feed = Sender<(SocketAddr, Message)>;
 
-  debug!(target: "device.out", "    Sending: {:?}", msg);
+/// the channels returned by `Client::errors`.
+///
+#[derive(Default)]
+struct Errors(Mutex<Vec<Sender<Error>>>);
 
-  if bytes == encoded.len() {
-    Ok(seq)
-  } else {
-    err!("wrong number of bytes written")
+impl Errors {
+  fn report(&self, e: Error) {
+    self.0.lock().unwrap().retain(|feed| feed.send(duplicate(&e)).is_ok());
   }
 }
 
-bitflags! {
-  pub struct DiscoverOptions: u8 {
-    const GET_LABEL         = 0b0000_0001;
-    const GET_WIFI          = 0b0000_0010;
-    const GET_LOCATION      = 0b0000_0100;
-    const GET_HOST_FIRMWARE = 0b0000_1000;
-    const GET_GROUP         = 0b0001_0000;
-    const GET_POWER         = 0b0010_0000;
-    const GET_HOST_INFO     = 0b0100_0000;
-    const GET_ALL           = DiscoverOptions::GET_LABEL.bits |
-                              DiscoverOptions::GET_WIFI.bits |
-                              DiscoverOptions::GET_LOCATION.bits | DiscoverOptions::GET_HOST_FIRMWARE.bits |
-                              DiscoverOptions::GET_GROUP.bits | DiscoverOptions::GET_POWER.bits |
-                              DiscoverOptions::GET_HOST_INFO.bits;
+/// checks whether a receive gave up because nothing arrived in time.
+///
+fn timed_out(e: &io::Error) -> bool {
+  e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// the time left until `deadline`. there is no deadline when a timeout is too
+/// long to represent, so all the time there is is left.
+///
+fn time_left(deadline: Option<Instant>) -> Duration {
+  match deadline {
+    Some(at) => at.saturating_duration_since(Instant::now()),
+    None => Duration::MAX,
   }
 }
 
-/// a bulb is a LiFX device where the service is Udp.
+/// copies an error for each receiver of `Client::errors`. an io error can't be
+/// cloned, so its copy keeps only the kind and message.
 ///
-#[derive(Clone)]
-pub struct Bulb<A: ToSocketAddrs> {
-  label: Option<String>,
-  location: Option<String>,
-  ip: A,
-  port: u32,
-  target: u64,
-  socket: Arc<UdpSocket>,
+fn duplicate(e: &Error) -> Error {
+  match *e {
+    Error::Io(ref e) => Error::Io(io::Error::new(e.kind(), e.to_string())),
+    Error::Encode(ref e) => Error::Encode(io::Error::new(e.kind(), e.to_string())),
+    Error::Decode(ref e) => Error::Decode(e.clone()),
+    Error::ShortWrite { expected, actual } => Error::ShortWrite { expected, actual },
+    Error::Timeout { waiting_for } => Error::Timeout { waiting_for },
+    Error::Unacknowledged { seq, attempts } => {
+      Error::Unacknowledged { seq, attempts }
+    }
+    Error::UnknownSequence(seq) => Error::UnknownSequence(seq),
+    Error::NotDiscovered(mac) => Error::NotDiscovered(mac),
+    Error::UnknownAlias(ref alias) => Error::UnknownAlias(alias.clone()),
+    Error::NotReceived { property } => Error::NotReceived { property },
+    Error::UnexpectedResponse(ref payload) => {
+      Error::UnexpectedResponse(payload.clone())
+    }
+    Error::InvalidMacAddress(ref s) => Error::InvalidMacAddress(s.clone()),
+    Error::OutOfRange {
+      quantity,
+      value,
+      min,
+      max,
+    } => Error::OutOfRange {
+      quantity,
+      value,
+      min,
+      max,
+    },
+    Error::VectorMismatch { name, offset } => {
+      Error::VectorMismatch { name, offset }
+    }
+  }
 }
 
-impl<A> Bulb<A>
-where
-  A: ToSocketAddrs,
-{
-  /// returns the label of the bulb, if one was received.
-  ///
-  pub fn label(&self) -> Option<&str> {
-    match self.label {
-      Some(ref label) => Some(&label[..]),
-      None => None,
+/// returns a random duration no longer than `max`.
+///
+fn random_up_to(max: Duration) -> Duration {
+  let nanos = max.as_nanos().min(u128::from(u64::MAX)) as u64;
+  match nanos {
+    0 => Duration::ZERO,
+    _ => {
+      let random = RandomState::new().build_hasher().finish();
+      Duration::from_nanos(random % nanos.saturating_add(1))
     }
   }
+}
 
-  /// sends a message to this bulb.
-  ///
-  pub fn send_msg(
-    &self,
-    payload: Payload,
-    ack_required: bool,
-  ) -> Result<u8, String> {
-    send_msg(
-      &self.socket,
-      &self.ip,
-      payload,
-      ack_required,
-      self.target,
-    )
+/// a device, by its mac address or by an alias given to it with
+/// `Client::set_alias`. the client's methods that look up a device take
+/// anything that converts into one, so a device can be named either way:
+/// `client.get_power("kitchen-left", timeout)`.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum DeviceId {
+  Mac(MacAddress),
+  Alias(String),
+}
+
+impl From<MacAddress> for DeviceId {
+  #[inline]
+  fn from(mac: MacAddress) -> DeviceId {
+    DeviceId::Mac(mac)
   }
+}
 
-  /// sends a message to this bulb, and waits the recommended amount of time.
-  ///
-  pub fn send_msg_and_wait(
-    &self,
-    payload: Payload,
-    ack_required: bool,
-  ) -> Result<u8, String> {
-    let res = self.send_msg(payload, ack_required);
-    thread::sleep(Duration::from_millis(MESSAGE_INTERVAL as u64));
-    res
+impl From<u64> for DeviceId {
+  #[inline]
+  fn from(target: u64) -> DeviceId {
+    DeviceId::Mac(target.into())
   }
 }
 
-impl<A> Display for Bulb<A>
-where
-  A: ToSocketAddrs + Display,
-{
-  fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-    write!(
-      f,
-      "'{:?}' ({}:{} {})",
-      self.label(),
-      self.ip,
-      self.port,
-      self.target
-    )
+impl From<[u8; 6]> for DeviceId {
+  #[inline]
+  fn from(octets: [u8; 6]) -> DeviceId {
+    DeviceId::Mac(octets.into())
   }
 }
 
-impl<A> Debug for Bulb<A>
-where
-  A: ToSocketAddrs + Debug,
-{
-  fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-    write!(
-      f,
-      "Bulb ({}):\nLabel: '{:?}'\nAddr.: {:?}:{}",
-      self.target,
-      self.label(),
-      self.ip,
-      self.port
-    )
+impl<'a> From<&'a str> for DeviceId {
+  #[inline]
+  fn from(alias: &'a str) -> DeviceId {
+    DeviceId::Alias(alias.to_string())
   }
 }
 
-/// the client handles device messages from from any lifx bulb.
+impl From<String> for DeviceId {
+  #[inline]
+  fn from(alias: String) -> DeviceId {
+    DeviceId::Alias(alias)
+  }
+}
+
+impl Display for DeviceId {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match *self {
+      DeviceId::Mac(ref mac) => Display::fmt(mac, f),
+      DeviceId::Alias(ref alias) => f.write_str(alias),
+    }
+  }
+}
+
+/// a device's host name, such as one given out by local dns or an mdns
+/// forwarder. pass it wherever an address is taken, such as to `send_msg`,
+/// or to `ClientBuilder::with_device_host`. the name is resolved when first
+/// used, and again once `HOST_TTL` has passed or `forget` is called. a name
+/// without a port uses 56700.
 ///
-pub struct Client {
-  closed: Arc<AtomicBool>,
-  socket: Arc<UdpSocket>,
-  devices: Arc<RwLock<HashMap<u64, Bulb<SocketAddr>>>>,
+#[derive(Debug, Clone)]
+pub struct Host {
+  name: String,
+  resolved: Arc<Mutex<Option<(SocketAddr, Instant)>>>,
 }
 
-impl Client {
-  /// creates a new client that will read responses from any lifx bulb.
-  ///
-  pub fn new<A: ToSocketAddrs>(addr: A) -> Result<Client, String> {
-    let closed = Arc::new(AtomicBool::new(false));
-    let devices = Arc::new(RwLock::new(HashMap::new()));
-    let udp_builder =
-      try!(UdpBuilder::new_v4().or(err!("failed to create builder")));
-    let udp_socket = Arc::new(try!(
-      udp_builder
-        .bind(addr)
-        .or(err!("failed to bind to addr"))
-    ));
-
-    try!(
-      udp_socket
-        .set_read_timeout_ms(Some(500))
-        .or(err!("failed to set read timeout"))
-    );
-    try!(
-      udp_socket
-        .set_write_timeout_ms(Some(500))
-        .or(err!("failed to set write timeout"))
-    );
+impl Host {
+  pub fn new(name: &str) -> Host {
+    Host {
+      name: name.to_string(),
+      resolved: Arc::new(Mutex::new(None)),
+    }
+  }
 
-    let client = Client {
-      closed: closed,
-      socket: udp_socket,
-      devices: devices,
-    };
+  /// returns the name, as it was given.
+  ///
+  pub fn name(&self) -> &str {
+    &self.name[..]
+  }
 
-    Ok(client)
+  /// forgets the address the name resolved to, so it is resolved again the
+  /// next time it is used. bulbs added with `ClientBuilder::with_device_host`
+  /// do this when a send to them fails.
+  ///
+  pub fn forget(&self) {
+    *self.resolved.lock().unwrap() = None;
   }
 
-  /// listens for certain messages, and updates the client object accordingly
+  /// resolves the name, adding the default port unless it has one.
   ///
-  pub fn listen(&self) -> JoinHandle<()> {
-    let socket = self.socket.clone();
-    let closed = self.closed.clone();
-    let devices = self.devices.clone();
+  fn lookup(&self) -> io::Result<SocketAddr> {
+    let name = &self.name[..];
+    let addrs = match name.parse::<IpAddr>() {
+      Ok(ip) => return Ok(SocketAddr::new(ip, consts::PORT)),
+      Err(_) => match name.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => name.to_socket_addrs(),
+        _ => (name, consts::PORT).to_socket_addrs(),
+      },
+    };
 
-    thread::spawn(move || {
-      let mut buf = [0; 256];
+    addrs?.next().ok_or_else(|| {
+      io::Error::new(io::ErrorKind::NotFound, "the host resolved to nothing")
+    })
+  }
+}
 
-      while !closed.load(Ordering::SeqCst) {
-        let (amt, src) = match socket.recv_from(&mut buf[..]) {
-          Ok(received) => received,
-          Err(_) => continue,
-        };
-        let resp = serialize::decode::<Message>(&buf[..amt]).unwrap();
-        let (payload, target) = resp.unpack();
+impl ToSocketAddrs for Host {
+  type Iter = option::IntoIter<SocketAddr>;
 
-        macro_rules! update_device_property(
-          ($prop:ident, $val:expr) => (
-            if let Some(bulb) = devices.write().unwrap().get_mut(&target) {
-              bulb.$prop = $val;
-            }
-          )
-        );
+  fn to_socket_addrs(&self) -> io::Result<option::IntoIter<SocketAddr>> {
+    let mut resolved = self.resolved.lock().unwrap();
+    if let Some((addr, at)) = *resolved {
+      if at.elapsed() < HOST_TTL {
+        return Ok(Some(addr).into_iter());
+      }
+    }
 
-        match payload {
-          Payload::Device(Device::StateService(Service::Udp, port)) => {
-            info!(target: "device.in", "Received device with port: {}", port);
-
-            devices
-              .write()
-              .unwrap()
-              .entry(target)
-              .or_insert(Bulb {
-                label: None,
-                location: None,
-                ip: src,
-                port: port,
-                target: target,
-                socket: socket.clone(),
-              });
-
-            info!(target: "device.in", "Devices:");
-
-            for d in devices.read().unwrap().values() {
-              info!(target: "device.in", "  Devices: {:?}", d);
-            }
-          }
-          Payload::Device(Device::StateLabel(label)) => {
-            info!(
-                target: "device.in",
-                "Received device label: '{:?}' for {:#X}",
-                label,
-                target);
+    let addr = self.lookup()?;
+    *resolved = Some((addr, Instant::now()));
+    Ok(Some(addr).into_iter())
+  }
+}
 
-            update_device_property!(label, Some(label.clone()));
-          }
-          Payload::Device(Device::StateLocation(_, location, _)) => {
-            info!(
-                target: "device.in",
-                "Received location label: '{:?}' for {:#X}",
-                location,
-                target);
+/// checks whether `text` matches a glob `pattern`, where `*` matches any run
+/// of characters and `?` any one character. after a mismatch, the most recent
+/// `*` is retried one character further on.
+///
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+  let (mut p, mut t) = (0, 0);
+  let mut star = None;
 
-            update_device_property!(location, Some(location.clone()));
-          }
-          Payload::Light(Light::State(color, power, label)) => {
-            info!(
-              target: "device.in",
-              "Received light state: '{:?} (Power {})' for '{}'",
-              color,
-              power,
-              label);
-          }
-          _ => (),
-        }
+  while t < text.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        star = Some((p, t));
+        p += 1;
       }
-    })
+      Some(&c) if c == '?' || c == text[t] => {
+        p += 1;
+        t += 1;
+      }
+      _ => match star {
+        Some((star_p, star_t)) => {
+          star = Some((star_p, star_t + 1));
+          p = star_p + 1;
+          t = star_t + 1;
+        }
+        None => return false,
+      },
+    }
   }
 
-  /// broadcasts messages to the client at a set interval. use `listen` to
-  /// have the client process certain messages.
-  ///
-  pub fn discover(&self, wait: u64, options: DiscoverOptions) -> JoinHandle<()> {
-    use Device::*;
+  pattern[p..].iter().all(|&c| c == '*')
+}
 
-    let socket = self.socket.clone();
-    let closed = self.closed.clone();
-    let devices = self.devices.clone();
+/// returns the first address `addr` resolves to.
+///
+fn resolve<A: ToSocketAddrs>(addr: A) -> Result<SocketAddr, Error> {
+  addr.to_socket_addrs()?.next().ok_or_else(|| {
+    Error::Io(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "the address resolved to nothing",
+    ))
+  })
+}
 
-    thread::spawn(move || {
-      while !closed.load(Ordering::SeqCst) {
-        // TODO: Technically, there should be a LOCK on the socket here. Messages
-        // should not be able to be sent between the time the socket is set to
-        // broadcast.
-        let _ = socket.set_broadcast(true);
-        let _ = send_msg(
-          &socket,
-          BROADCAST_IP,
-          Payload::Device(GetService),
-          false,
-          0,
-        );
-        let _ = socket.set_broadcast(false);
-
-        for d in devices.read().unwrap().values() {
-          if !(options & DiscoverOptions::GET_LABEL).is_empty() {
-            let _ = d.send_msg_and_wait(Payload::Device(Device::GetLabel), false);
-          }
+/// opens a new transport, when a client rebinds.
+///
+type Rebinder = Box<dyn Fn() -> Result<Arc<dyn Transport>, Error> + Send + Sync>;
 
-          if !(options & DiscoverOptions::GET_POWER).is_empty() {
-            let _ = d.send_msg_and_wait(Payload::Device(Device::GetPower), false);
-          }
+/// how many receives in a row have to fail before the background threads
+/// rebind the socket.
+///
+pub const REBIND_AFTER: u32 = 5;
 
-          if !(options & DiscoverOptions::GET_LOCATION).is_empty() {
-            let _ = d.send_msg_and_wait(Payload::Device(Device::GetLocation), false);
-          }
+/// a transport that does nothing, while a client rebinds.
+///
+struct Unbound;
 
-          if !(options & DiscoverOptions::GET_GROUP).is_empty() {
-            let _ = d.send_msg_and_wait(Payload::Device(Device::GetGroup), false);
-          }
+impl Transport for Unbound {
+  fn send_to(&self, _: &[u8], _: SocketAddr) -> io::Result<usize> {
+    Err(io::ErrorKind::NotConnected.into())
+  }
 
-          if !(options & DiscoverOptions::GET_HOST_INFO).is_empty() {
-            let _ = d.send_msg_and_wait(Payload::Device(Device::GetHostInfo), false);
-          }
+  fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    Err(io::ErrorKind::NotConnected.into())
+  }
+}
 
-          if !(options & DiscoverOptions::GET_HOST_FIRMWARE).is_empty() {
-            let _ =
-              d.send_msg_and_wait(Payload::Device(Device::GetHostFirmware), false);
-          }
+/// the transport a client uses, which can be replaced by rebinding if the
+/// client knows how to open another one.
+///
+struct Rebindable {
+  current: RwLock<Arc<dyn Transport>>,
+  rebinder: Option<Rebinder>,
+}
 
-          if !(options & DiscoverOptions::GET_WIFI).is_empty() {
-            let _ =
-              d.send_msg_and_wait(Payload::Device(Device::GetWifiFirmware), false);
-          }
-        }
+impl Rebindable {
+  #[inline]
+  fn get(&self) -> Arc<dyn Transport> {
+    self.current.read().unwrap().clone()
+  }
 
-        thread::sleep(Duration::from_millis(wait));
-      }
-    })
+  #[inline]
+  fn can_rebind(&self) -> bool {
+    self.rebinder.is_some()
+  }
+
+  /// drops the current transport, so a socket's port is free again, and opens
+  /// another.
+  ///
+  fn rebind(&self) -> Result<(), Error> {
+    let rebinder = self.rebinder.as_ref().ok_or_else(|| {
+      Error::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the client can't open another transport",
+      ))
+    })?;
+    let mut current = self.current.write().unwrap();
+
+    let _ = current.wake();
+    *current = Arc::new(Unbound);
+    let transport = rebinder()?;
+    let _ = transport.set_broadcast(true);
+    *current = transport;
+    Ok(())
+  }
+}
+
+impl Transport for Rebindable {
+  #[inline]
+  fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+    self.get().send_to(buf, addr)
+  }
+
+  /// doesn't hold up `rebind` while it waits.
+  ///
+  #[inline]
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    self.get().recv_from(buf)
+  }
+
+  #[inline]
+  fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+    self.get().set_broadcast(broadcast)
+  }
+
+  #[inline]
+  fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+    self.get().set_read_timeout(timeout)
+  }
+
+  #[inline]
+  fn wake(&self) -> io::Result<()> {
+    self.get().wake()
   }
+}
+
+/// what a client, and the bulbs it finds, send with: the transport, the
+/// source identifier devices copy into their replies, and the sequence
+/// counters.
+///
+#[derive(Clone)]
+struct Endpoint {
+  socket: Arc<Rebindable>,
+  source: u32,
+  sequences: Arc<Sequences>,
+  broadcasts: Arc<[SocketAddr]>,
+  every_interface: bool,
+  recv_timeout: Duration,
+}
 
+impl Endpoint {
   /// sends a message to the specified address.
   ///
-  pub fn send_msg<A: ToSocketAddrs>(
+  fn send_msg<A: ToSocketAddrs>(
     &self,
     addr: A,
     payload: Payload,
     ack_required: bool,
     target: u64,
-  ) -> Result<u8, String> {
-    send_msg(&self.socket, addr, payload, ack_required, target)
+  ) -> Result<u8, Error> {
+    let options = SendOptions::for_payload(&payload, ack_required);
+    self.send_msg_with(addr, payload, options, target)
   }
 
-  /// returns a snapshot of the devices that the client has found.
+  /// sends a message to the specified address, with the ack and response
+  /// flags set from `options`.
   ///
-  pub fn devices(&self) -> HashMap<u64, Bulb<SocketAddr>> {
-    self.devices.read().unwrap().deref().clone()
+  fn send_msg_with<A: ToSocketAddrs>(
+    &self,
+    addr: A,
+    payload: Payload,
+    options: SendOptions,
+    target: u64,
+  ) -> Result<u8, Error> {
+    let seq = self.sequences.next(target);
+    self.send_msg_seq(addr, payload, options, target, seq)
   }
 
-  /// returns a snapshot of a particular device, given its target id.
+  /// sends a message to the specified address, with a sequence number that
+  /// was already taken from the counters.
   ///
-  pub fn device(&self, target: u64) -> Option<Bulb<SocketAddr>> {
-    match self.devices.read() {
-      Ok(devices) => devices.get(&target).map(|d| d.clone()),
-      Err(_) => None,
+  fn send_msg_seq<A: ToSocketAddrs>(
+    &self,
+    addr: A,
+    payload: Payload,
+    options: SendOptions,
+    target: u64,
+    seq: u8,
+  ) -> Result<u8, Error> {
+    let msg =
+      Message::new_with(payload, options, target, seq).with_source(self.source);
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let len = serialize::encode_into(&msg, &mut buf[..])?;
+    let bytes = self.socket.send_to(&buf[..len], resolve(addr)?)?;
+
+    debug!(target: "device.out", "    Sending: {:?}", msg);
+
+    if bytes == len {
+      Ok(seq)
+    } else {
+      Err(Error::ShortWrite {
+        expected: len,
+        actual: bytes,
+      })
     }
   }
+}
 
-  /// closes a client. it will no longer receive responses from the socket.
-  ///
-  #[inline(always)]
-  pub fn close(&self) {
-    self.closed.store(true, Ordering::SeqCst)
+bitflags! {
+  pub struct DiscoverOptions: u8 {
+    const GET_LABEL         = 0b0000_0001;
+    const GET_WIFI          = 0b0000_0010;
+    const GET_LOCATION      = 0b0000_0100;
+    const GET_HOST_FIRMWARE = 0b0000_1000;
+    const GET_GROUP         = 0b0001_0000;
+    const GET_POWER         = 0b0010_0000;
+    const GET_HOST_INFO     = 0b0100_0000;
+    const GET_VERSION       = 0b1000_0000;
+    const GET_ALL           = DiscoverOptions::GET_LABEL.bits |
+                              DiscoverOptions::GET_WIFI.bits |
+                              DiscoverOptions::GET_LOCATION.bits | DiscoverOptions::GET_HOST_FIRMWARE.bits |
+                              DiscoverOptions::GET_GROUP.bits | DiscoverOptions::GET_POWER.bits |
+                              DiscoverOptions::GET_HOST_INFO.bits |
+                              DiscoverOptions::GET_VERSION.bits;
   }
+}
 
-  /// checks if a client is closed.
+impl DiscoverOptions {
+  /// returns the queries these options ask for, in the order they are sent.
   ///
-  #[inline(always)]
-  pub fn is_closed(&self) -> bool {
-    self.closed.load(Ordering::SeqCst)
+  fn queries(self) -> Vec<Payload> {
+    [
+      (DiscoverOptions::GET_LABEL, Device::GetLabel),
+      (DiscoverOptions::GET_POWER, Device::GetPower),
+      (DiscoverOptions::GET_LOCATION, Device::GetLocation),
+      (DiscoverOptions::GET_GROUP, Device::GetGroup),
+      (DiscoverOptions::GET_HOST_INFO, Device::GetHostInfo),
+      (DiscoverOptions::GET_HOST_FIRMWARE, Device::GetHostFirmware),
+      (DiscoverOptions::GET_WIFI, Device::GetWifiFirmware),
+      (DiscoverOptions::GET_VERSION, Device::GetVersion),
+    ]
+    .iter()
+    .filter(|&&(option, _)| self.contains(option))
+    .map(|(_, query)| Payload::Device(query.clone()))
+    .collect()
   }
 }
 
-impl Drop for Client {
-  fn drop(&mut self) {
-    self.close();
-  }
-}
+/// what the task started by `Client::start` does, besides listening. the
+/// backoff, jitter and verify settings also apply to `Client::discover`, from
+/// the client's own config.
+///
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Config {
+  /// how often to run a round of discovery, or `None` to only listen.
+  ///
+  pub discover_every: Option<Duration>,
 
-#[test]
-fn test_sequence_counter_overflow() {
-  use std::u8;
+  /// the queries sent to every known device in each round of discovery.
+  ///
+  pub discover_options: DiscoverOptions,
 
-  assert_eq!(0, next_sequence());
-  for _ in 1..(u8::MAX as usize) + 1 {
-    next_sequence();
-  }
-  assert_eq!(0, next_sequence());
-  for _ in 1..(u8::MAX as usize) + 1 {
-    next_sequence();
+  /// the longest wait between rounds. while no new device is found, the wait
+  /// doubles after every round up to this, and it goes back to
+  /// `discover_every` as soon as one is. no longer than `discover_every`
+  /// turns the backoff off.
+  ///
+  pub discover_max: Duration,
+
+  /// the most, in percent of the wait, added at random to each wait between
+  /// rounds, so clients started together don't broadcast together.
+  ///
+  pub jitter: u8,
+
+  /// whether rounds only broadcast for devices, without sending
+  /// `discover_options` to every known one, while no new device is found.
+  ///
+  pub verify_when_stable: bool,
+
+  /// how often to probe the health of every device, as
+  /// `Client::probe_health` does, or `None` to never probe.
+  ///
+  pub probe_every: Option<Duration>,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      discover_every: Some(Duration::from_secs(5)),
+      discover_options: DiscoverOptions::GET_LABEL,
+      discover_max: Duration::from_secs(60),
+      jitter: 20,
+      verify_when_stable: true,
+      probe_every: None,
+    }
+  }
+}
+
+/// when to run rounds of discovery, and what each one queries, as a `Config`
+/// asks.
+///
+struct Schedule {
+  every: Duration,
+  wait: Duration,
+  options: DiscoverOptions,
+  config: Config,
+  known: Option<usize>,
+}
+
+impl Schedule {
+  fn new(every: Duration, options: DiscoverOptions, config: Config) -> Schedule {
+    Schedule {
+      every,
+      wait: every,
+      options,
+      config,
+      known: None,
+    }
+  }
+
+  /// starts a round, given how many devices the client knows. returns the
+  /// queries to send each known device, and how long to wait before the next
+  /// round.
+  ///
+  fn round(&mut self, known: usize) -> (DiscoverOptions, Duration) {
+    let stable = self.known.is_some_and(|last| known <= last);
+    self.known = Some(known);
+
+    self.wait = match stable {
+      true => (self.wait * 2).min(self.config.discover_max).max(self.every),
+      false => self.every,
+    };
+    let options = match stable && self.config.verify_when_stable {
+      true => DiscoverOptions::empty(),
+      false => self.options,
+    };
+
+    let jitter = self.wait * u32::from(self.config.jitter) / 100;
+    (options, self.wait + random_up_to(jitter))
+  }
+}
+
+/// how `Client::send_reliable` retransmits a message until it is acked.
+///
+/// # Notes
+///
+///   * The wait for an ack starts at `initial`, and doubles after every
+///     attempt up to `max`.
+///   * The client gives up once `attempts` sends have gone unacked, or once
+///     `deadline` has passed since the first send, whichever is first.
+///
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Backoff {
+  pub attempts: u32,
+  pub initial: Duration,
+  pub max: Duration,
+  pub deadline: Duration,
+}
+
+impl Default for Backoff {
+  fn default() -> Backoff {
+    Backoff {
+      attempts: 5,
+      initial: Duration::from_millis(100),
+      max: Duration::from_secs(1),
+      deadline: Duration::from_secs(5),
+    }
+  }
+}
+
+/// configures a `Client` before it is created.
+///
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+  source: Option<u32>,
+  read_timeout: Duration,
+  write_timeout: Duration,
+  recv_buffer_size: Option<usize>,
+  send_buffer_size: Option<usize>,
+  reuse_address: bool,
+  reuse_port: bool,
+  interface: Option<String>,
+  broadcasts: Vec<SocketAddr>,
+  every_interface: bool,
+  accept_foreign: bool,
+  passive_discovery: bool,
+  monitor: Option<SocketAddr>,
+  devices: Vec<(u64, SocketAddr)>,
+  hosts: Vec<(u64, Host)>,
+  saved: Vec<SavedDevice>,
+  aliases: Vec<(String, u64)>,
+  config: Config,
+}
+
+impl Default for ClientBuilder {
+  fn default() -> ClientBuilder {
+    ClientBuilder {
+      source: None,
+      read_timeout: RECV_TIMEOUT,
+      write_timeout: SEND_TIMEOUT,
+      recv_buffer_size: None,
+      send_buffer_size: None,
+      reuse_address: false,
+      reuse_port: false,
+      interface: None,
+      broadcasts: vec![BROADCAST_ADDR.into()],
+      every_interface: false,
+      accept_foreign: false,
+      passive_discovery: true,
+      monitor: None,
+      devices: Vec::new(),
+      hosts: Vec::new(),
+      saved: Vec::new(),
+      aliases: Vec::new(),
+      config: Config::default(),
+    }
+  }
+}
+
+impl ClientBuilder {
+  /// sets the source identifier the client sends in every message, instead of
+  /// a random one. 0 asks devices to broadcast their replies, so it is
+  /// replaced with a random source too.
+  ///
+  pub fn source(mut self, source: u32) -> ClientBuilder {
+    self.source = if source == 0 { None } else { Some(source) };
+    self
+  }
+
+  /// sets how long the client waits for a datagram before checking for
+  /// devices that went offline. must not be zero.
+  ///
+  pub fn read_timeout(mut self, timeout: Duration) -> ClientBuilder {
+    self.read_timeout = timeout;
+    self
+  }
+
+  /// sets how long sending a datagram can block. must not be zero, and is
+  /// only used by `bind`.
+  ///
+  pub fn write_timeout(mut self, timeout: Duration) -> ClientBuilder {
+    self.write_timeout = timeout;
+    self
+  }
+
+  /// sets the size of the socket's receive buffer, instead of the system's
+  /// default. only used by `bind`.
+  ///
+  pub fn recv_buffer_size(mut self, size: usize) -> ClientBuilder {
+    self.recv_buffer_size = Some(size);
+    self
+  }
+
+  /// sets the size of the socket's send buffer, instead of the system's
+  /// default. only used by `bind`.
+  ///
+  pub fn send_buffer_size(mut self, size: usize) -> ClientBuilder {
+    self.send_buffer_size = Some(size);
+    self
+  }
+
+  /// sets `SO_REUSEADDR` on the socket, so several clients can bind the same
+  /// port. off by default, and only used by `bind`.
+  ///
+  pub fn reuse_address(mut self, reuse: bool) -> ClientBuilder {
+    self.reuse_address = reuse;
+    self
+  }
+
+  /// sets `SO_REUSEPORT` on the socket, so clients in several processes can
+  /// bind the same port. off by default, only used by `bind`, and only
+  /// available on unix; elsewhere `bind` fails if this is set.
+  ///
+  /// # Notes
+  ///
+  ///   * Every client sharing the port receives broadcasts, but the system
+  ///     hands each unicast datagram to just one of them. Devices reply to
+  ///     the port a request came from, so a client should bind its own port
+  ///     to send requests, and share the port only to overhear traffic with
+  ///     `accept_foreign`.
+  ///
+  pub fn reuse_port(mut self, reuse: bool) -> ClientBuilder {
+    self.reuse_port = reuse;
+    self
+  }
+
+  /// binds the socket to the network interface named `name` (`eth0`, `en0`,
+  /// ..), so traffic, discovery included, only goes out that interface. only
+  /// used by `bind`, and only available on linux, android and apple
+  /// platforms; elsewhere `bind` fails if this is set.
+  ///
+  /// # Notes
+  ///
+  ///   * Linux kernels before 5.7 only let privileged processes do this.
+  ///
+  pub fn interface(mut self, name: &str) -> ClientBuilder {
+    self.interface = Some(name.to_string());
+    self
+  }
+
+  /// sets the address discovery broadcasts to, instead of 255.255.255.255 on
+  /// the lifx port. a subnet's broadcast address keeps discovery to one
+  /// interface.
+  ///
+  pub fn broadcast(self, addr: SocketAddr) -> ClientBuilder {
+    self.broadcasts(Some(addr))
+  }
+
+  /// sets every address discovery broadcasts to, such as the directed
+  /// broadcast addresses of several subnets (`192.168.1.255:56700`). these
+  /// get through routers and docker bridges that drop 255.255.255.255.
+  ///
+  pub fn broadcasts<I>(mut self, addrs: I) -> ClientBuilder
+  where
+    I: IntoIterator<Item = SocketAddr>,
+  {
+    self.broadcasts = addrs.into_iter().collect();
+    self
+  }
+
+  /// also broadcasts discovery to the directed broadcast address of every
+  /// ipv4 interface other than loopback, so devices are found on each network
+  /// of a host with several. the interfaces are looked up again on every
+  /// round, to find ones that came up later. off by default.
+  ///
+  pub fn every_interface(mut self, every: bool) -> ClientBuilder {
+    self.every_interface = every;
+    self
+  }
+
+  /// sets whether the client handles frames that carry another controller's
+  /// source, such as replies overheard on a port shared with `reuse_port`.
+  /// off by default, so only replies to this client update its devices.
+  ///
+  pub fn accept_foreign(mut self, accept: bool) -> ClientBuilder {
+    self.accept_foreign = accept;
+    self
+  }
+
+  /// sets whether the listen thread finds devices from any frame they send,
+  /// by asking a device it does not know for its service, then its label.
+  /// on by default, so devices are found even where broadcasts are blocked,
+  /// as long as something talks to them.
+  ///
+  pub fn passive_discovery(mut self, passive: bool) -> ClientBuilder {
+    self.passive_discovery = passive;
+    self
+  }
+
+  /// also receives on a second socket bound to `addr`, usually
+  /// `0.0.0.0:56700`, where devices broadcast their state when it changes.
+  /// frames received there are handled whichever controller they were meant
+  /// for, so the client stays up to date without asking. the socket always
+  /// reuses its address, since other controllers may listen on the port too.
+  ///
+  /// # Notes
+  ///
+  ///   * Only `bind` and `transport_with` open the socket, and only `listen`
+  ///     and `start` receive on it, with a thread of its own.
+  ///
+  pub fn monitor(mut self, addr: SocketAddr) -> ClientBuilder {
+    self.monitor = Some(addr);
+    self
+  }
+
+  /// adds a device the client knows from the start, by its mac address and
+  /// the address it listens on, usually port 56700. use this where broadcasts
+  /// are blocked, so the device can be controlled and polled without ever
+  /// being discovered. a device that is discovered anyway is updated as usual.
+  ///
+  pub fn with_device<T: Into<MacAddress>>(
+    mut self,
+    target: T,
+    addr: SocketAddr,
+  ) -> ClientBuilder {
+    self.devices.push((target.into().into(), addr));
+    self
+  }
+
+  /// like `with_device`, but for a device known by its host name. the name is
+  /// resolved whenever a message is sent to the device, as `Host` describes,
+  /// and again as soon as a send fails.
+  ///
+  pub fn with_device_host<T: Into<MacAddress>>(
+    mut self,
+    target: T,
+    host: &str,
+  ) -> ClientBuilder {
+    self.hosts.push((target.into().into(), Host::new(host)));
+    self
+  }
+
+  /// gives a device an alias, as `Client::set_alias` does.
+  ///
+  pub fn with_alias<T: Into<MacAddress>>(
+    mut self,
+    alias: &str,
+    target: T,
+  ) -> ClientBuilder {
+    self.aliases.push((alias.to_string(), target.into().into()));
+    self
+  }
+
+  /// restores the devices and aliases written to `path` by
+  /// `Client::save_devices`, so the client can control them straight away,
+  /// while discovery catches up in the background. devices added with
+  /// `with_device` take their place. a file that does not exist yet restores
+  /// nothing.
+  ///
+  pub fn with_saved_devices<P: AsRef<Path>>(
+    mut self,
+    path: P,
+  ) -> Result<ClientBuilder, Error> {
+    let file = match File::open(path) {
+      Ok(file) => file,
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(self),
+      Err(e) => return Err(e.into()),
+    };
+
+    let saved = saved::read_saved(BufReader::new(file))?;
+    self.saved.extend(saved.devices);
+    self.aliases.extend(saved.aliases);
+    Ok(self)
+  }
+
+  /// sets the discovery returned by `Client::config`.
+  ///
+  pub fn config(mut self, config: Config) -> ClientBuilder {
+    self.config = config;
+    self
+  }
+
+  /// creates a client with a udp socket bound to `addr`, which may be an ipv4
+  /// or ipv6 address. the socket can always send to broadcast addresses.
+  ///
+  pub fn bind<A: ToSocketAddrs>(self, addr: A) -> Result<Client, Error> {
+    let addr = resolve(addr)?;
+    let builder = self.clone();
+
+    self.transport_with(move || builder.open(addr))
+  }
+
+  /// creates a client that sends and receives through the transport `open`
+  /// returns. `open` is called again whenever the client rebinds.
+  ///
+  pub fn transport_with<T, F>(self, open: F) -> Result<Client, Error>
+  where
+    T: Transport + 'static,
+    F: Fn() -> Result<T, Error> + Send + Sync + 'static,
+  {
+    let transport = Arc::new(open()?);
+    let rebinder: Rebinder =
+      Box::new(move || open().map(|t| Arc::new(t) as Arc<dyn Transport>));
+    let monitor = match self.monitor {
+      Some(addr) => Some(Arc::new(self.open_monitor(addr)?) as Arc<dyn Transport>),
+      None => None,
+    };
+
+    Ok(Client::from_builder(self, transport, Some(rebinder), monitor))
+  }
+
+  /// creates a client that sends and receives through `transport`. the client
+  /// can't rebind.
+  ///
+  pub fn transport<T: Transport + 'static>(self, transport: T) -> Client {
+    Client::from_builder(self, Arc::new(transport), None, None)
+  }
+
+  /// opens the socket asked for by `monitor`.
+  ///
+  fn open_monitor(&self, addr: SocketAddr) -> Result<UdpSocket, Error> {
+    let mut builder = self.clone();
+    builder.reuse_address = true;
+    builder.open(addr)
+  }
+
+  /// opens a udp socket bound to `addr`.
+  ///
+  fn open(&self, addr: SocketAddr) -> Result<UdpSocket, Error> {
+    let socket =
+      Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    socket.set_reuse_address(self.reuse_address)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(self.reuse_port)?;
+    #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+    {
+      if self.reuse_port {
+        return Err(Error::Io(io::Error::new(
+          io::ErrorKind::Unsupported,
+          "SO_REUSEPORT is not available on this platform",
+        )));
+      }
+    }
+    // `close` wakes the listen thread, so this only paces the offline sweep.
+    socket.set_read_timeout(Some(self.read_timeout))?;
+    socket.set_write_timeout(Some(self.write_timeout))?;
+    if let Some(size) = self.recv_buffer_size {
+      socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = self.send_buffer_size {
+      socket.set_send_buffer_size(size)?;
+    }
+    if let Some(ref name) = self.interface {
+      bind_to_interface(&socket, name, &addr)?;
+    }
+    socket.bind(&addr.into())?;
+
+    Ok(UdpSocket::from(socket))
+  }
+}
+
+/// a bulb is a LiFX device where the service is Udp.
+///
+#[derive(Clone)]
+pub struct Bulb<A: ToSocketAddrs> {
+  label: Option<String>,
+  location: Option<String>,
+  group: Option<String>,
+  ip: A,
+  port: u32,
+  host: Option<Host>,
+  target: MacAddress,
+  version: Option<(u32, u32, u32)>,
+  host_firmware: Option<FirmwareVersion>,
+  wifi_firmware: Option<FirmwareVersion>,
+  host_info: Option<(f32, u32, u32)>,
+  wifi_info: Option<(f32, u32, u32)>,
+  info: Option<(u64, u64, u64)>,
+  infrared: Option<u16>,
+  updated: HashMap<Property, Instant>,
+  services: Vec<(Service, u32)>,
+  unhandled: Vec<u16>,
+  power: Option<Power>,
+  color: Option<HSBK>,
+  last_seen: Instant,
+  online: bool,
+  configured: bool,
+  health: Health,
+  echoes: VecDeque<bool>,
+  echoes_sent: u64,
+  echo_pending: Option<u64>,
+  endpoint: Endpoint,
+}
+
+impl Bulb<SocketAddr> {
+  fn new(
+    ip: SocketAddr,
+    port: u32,
+    target: u64,
+    endpoint: Endpoint,
+  ) -> Bulb<SocketAddr> {
+    Bulb {
+      label: None,
+      location: None,
+      group: None,
+      ip,
+      port,
+      host: None,
+      target: target.into(),
+      version: None,
+      host_firmware: None,
+      wifi_firmware: None,
+      host_info: None,
+      wifi_info: None,
+      info: None,
+      infrared: None,
+      updated: HashMap::new(),
+      services: Vec::new(),
+      unhandled: Vec::new(),
+      power: None,
+      color: None,
+      last_seen: Instant::now(),
+      online: true,
+      configured: false,
+      health: Health::Online,
+      echoes: VecDeque::new(),
+      echoes_sent: 0,
+      echo_pending: None,
+      endpoint,
+    }
+  }
+
+  /// creates a bulb the client was told about, rather than found, which is
+  /// never evicted.
+  ///
+  fn configured(
+    addr: SocketAddr,
+    target: u64,
+    endpoint: Endpoint,
+  ) -> Bulb<SocketAddr> {
+    let port = addr.port().into();
+    let mut bulb = Bulb::new(addr, port, target, endpoint);
+    bulb.services.push((Service::Udp, port));
+    bulb.configured = true;
+    bulb
+  }
+
+  /// restores a bulb written by `Client::save_devices`.
+  ///
+  fn restored(saved: &SavedDevice, endpoint: Endpoint) -> Bulb<SocketAddr> {
+    let mut bulb = Bulb::new(saved.addr, saved.port, saved.target, endpoint);
+    bulb.host = saved.host.as_ref().map(|host| Host::new(host));
+    bulb.label = saved.label.clone();
+    bulb.location = saved.location.clone();
+    bulb.group = saved.group.clone();
+    bulb.services = saved.services.clone();
+    bulb.unhandled = saved.unhandled.clone();
+    bulb
+  }
+
+  /// returns what `Client::save_devices` writes for this bulb.
+  ///
+  fn saved(&self) -> SavedDevice {
+    SavedDevice {
+      target: self.target.into(),
+      addr: self.ip,
+      port: self.port,
+      host: self.host.as_ref().map(|host| host.name().to_string()),
+      label: self.label.clone(),
+      location: self.location.clone(),
+      group: self.group.clone(),
+      services: self.services.clone(),
+      unhandled: self.unhandled.clone(),
+    }
+  }
+
+  /// returns the address messages to this bulb are sent to: the address it
+  /// replied from, with the port it advertised for udp, if that differs.
+  ///
+  pub fn addr(&self) -> SocketAddr {
+    self.dest().unwrap_or(self.ip)
+  }
+
+  /// returns the state of the bulb that `DeviceEvent`s report changes to.
+  ///
+  pub fn state(&self) -> DeviceState {
+    DeviceState {
+      addr: self.ip,
+      label: self.label.clone(),
+      location: self.location.clone(),
+      group: self.group.clone(),
+      power: self.power,
+      color: self.color,
+      infrared: self.infrared,
+      online: self.online,
+      health: self.health,
+    }
+  }
+}
+
+impl<A> Bulb<A>
+where
+  A: ToSocketAddrs,
+{
+  /// returns the label of the bulb, if one was received.
+  ///
+  pub fn label(&self) -> Option<&str> {
+    match self.label {
+      Some(ref label) => Some(&label[..]),
+      None => None,
+    }
+  }
+
+  /// returns the label of the bulb's location, if one was received.
+  ///
+  pub fn location(&self) -> Option<&str> {
+    self.location.as_deref()
+  }
+
+  /// returns the label of the bulb's group, if one was received.
+  ///
+  pub fn group(&self) -> Option<&str> {
+    self.group.as_deref()
+  }
+
+  /// returns the mac address of the bulb, which messages sent to it target.
+  ///
+  pub fn target(&self) -> MacAddress {
+    self.target
+  }
+
+  /// returns the (vendor, product, version) of the bulb, if they were
+  /// received.
+  ///
+  pub fn version(&self) -> Option<(u32, u32, u32)> {
+    self.version
+  }
+
+  /// returns the host firmware version of the bulb, if one was received.
+  ///
+  pub fn host_firmware(&self) -> Option<FirmwareVersion> {
+    self.host_firmware
+  }
+
+  /// returns the wifi firmware version of the bulb, if one was received.
+  ///
+  pub fn wifi_firmware(&self) -> Option<FirmwareVersion> {
+    self.wifi_firmware
+  }
+
+  /// returns the (signal, tx, rx) of the bulb's host mcu, if they were
+  /// received.
+  ///
+  pub fn host_info(&self) -> Option<(f32, u32, u32)> {
+    self.host_info
+  }
+
+  /// returns the (signal, tx, rx) of the bulb's wifi, if they were received.
+  /// the signal is in milliwatts.
+  ///
+  pub fn wifi_info(&self) -> Option<(f32, u32, u32)> {
+    self.wifi_info
+  }
+
+  /// returns the (time, uptime, downtime) of the bulb in nanoseconds, as of
+  /// the last `Device::StateInfo` received.
+  ///
+  pub fn info(&self) -> Option<(u64, u64, u64)> {
+    self.info
+  }
+
+  /// returns the infrared brightness of the bulb, if one was received.
+  ///
+  pub fn infrared(&self) -> Option<u16> {
+    self.infrared
+  }
+
+  /// returns when the client last received a property from the bulb, whether
+  /// or not it changed.
+  ///
+  pub fn updated(&self, property: Property) -> Option<Instant> {
+    self.updated.get(&property).cloned()
+  }
+
+  /// returns the port the bulb advertised for udp, in `Device::StateService`.
+  ///
+  pub fn port(&self) -> u32 {
+    self.port
+  }
+
+  /// returns every (service, port) pair this bulb has advertised.
+  ///
+  pub fn services(&self) -> &[(Service, u32)] {
+    &self.services[..]
+  }
+
+  /// returns the message types this bulb has reported it does not handle
+  /// (via `Device::StateUnhandled`).
+  ///
+  pub fn unhandled(&self) -> &[u16] {
+    &self.unhandled[..]
+  }
+
+  /// returns the power level of the bulb, if one was received.
+  ///
+  pub fn power(&self) -> Option<Power> {
+    self.power
+  }
+
+  /// returns the color of the bulb, if one was received.
+  ///
+  pub fn color(&self) -> Option<HSBK> {
+    self.color
+  }
+
+  /// returns when the client last received a message from the bulb.
+  ///
+  pub fn last_seen(&self) -> Instant {
+    self.last_seen
+  }
+
+  /// checks if the client has heard from the bulb recently. see
+  /// `Client::set_offline_after`.
+  ///
+  pub fn is_online(&self) -> bool {
+    self.online
+  }
+
+  /// returns how the bulb is doing: offline when `is_online` is false,
+  /// degraded when it missed any of the latest echo probes, and otherwise
+  /// online. see `Client::probe_health`.
+  ///
+  pub fn health(&self) -> Health {
+    self.health
+  }
+
+  /// records whether an echo probe was answered, forgetting old ones.
+  ///
+  fn record_echo(&mut self, answered: bool) {
+    self.echoes.push_back(answered);
+    while self.echoes.len() > HEALTH_WINDOW {
+      self.echoes.pop_front();
+    }
+  }
+
+  /// works out the bulb's health again, and returns it if it changed.
+  ///
+  fn update_health(&mut self) -> Option<Health> {
+    let health = if !self.online {
+      Health::Offline
+    } else if self.echoes.contains(&false) {
+      Health::Degraded
+    } else {
+      Health::Online
+    };
+
+    match mem::replace(&mut self.health, health) != health {
+      true => Some(health),
+      false => None,
+    }
+  }
+
+  /// checks if this bulb has reported that it does not handle a message type.
+  ///
+  pub fn handles(&self, typ: u16) -> bool {
+    !self.unhandled.contains(&typ)
+  }
+
+  /// returns the advertised udp port, unless it can't be one.
+  ///
+  fn udp_port(&self) -> Option<u16> {
+    match self.port {
+      0 => None,
+      port => u16::try_from(port).ok(),
+    }
+  }
+
+  /// returns the host name the bulb was added with, if it was added with
+  /// `ClientBuilder::with_device_host`.
+  ///
+  pub fn host(&self) -> Option<&Host> {
+    self.host.as_ref()
+  }
+
+  /// resolves the address to send to, like `Bulb::addr`.
+  ///
+  fn dest(&self) -> Result<SocketAddr, Error> {
+    let mut addr = match self.host {
+      Some(ref host) => resolve(host)?,
+      None => resolve(&self.ip)?,
+    };
+    if let Some(port) = self.udp_port() {
+      addr.set_port(port);
+    }
+    Ok(addr)
+  }
+
+  /// sends a message to this bulb, at the port it advertised.
+  ///
+  pub fn send_msg(
+    &self,
+    payload: Payload,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    let options = SendOptions::for_payload(&payload, ack_required);
+    self.send_msg_with(payload, options)
+  }
+
+  /// sends a message to this bulb, choosing whether it should be acked and
+  /// responded to.
+  ///
+  pub fn send_msg_with(
+    &self,
+    payload: Payload,
+    options: SendOptions,
+  ) -> Result<u8, Error> {
+    let target = self.target;
+    let dest = self.dest();
+    let sent = dest.and_then(|dest| {
+      self.endpoint.send_msg_with(dest, payload, options, target.into())
+    });
+    if let (Err(_), Some(host)) = (&sent, &self.host) {
+      host.forget();
+    }
+    sent
+  }
+
+  /// sends a message to this bulb, and waits the recommended amount of time.
+  ///
+  pub fn send_msg_and_wait(
+    &self,
+    payload: Payload,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    let res = self.send_msg(payload, ack_required);
+    thread::sleep(Duration::from_millis(MESSAGE_INTERVAL as u64));
+    res
+  }
+
+  /// asks the bulb again for its label, group, location, version, host
+  /// firmware and light state, pausing `MESSAGE_INTERVAL` milliseconds after
+  /// each request. the client's copy of the bulb is updated as the responses
+  /// arrive, while `listen` runs; `Client::refresh` also waits for them.
+  ///
+  pub fn refresh(&self) -> Result<(), Error> {
+    for query in refresh_queries() {
+      self.send_msg_and_wait(query, false)?;
+    }
+    Ok(())
+  }
+
+  /// turns this bulb off if it was on when the client last heard from it, and
+  /// on otherwise, over `duration` if one is given. returns the power level
+  /// it was set to. `Client::toggle` requests the power first if its copy is
+  /// stale.
+  ///
+  pub fn toggle(
+    &self,
+    duration: Option<Duration>,
+    ack_required: bool,
+  ) -> Result<Power, Error> {
+    let (level, payload) = toggled(self.power, duration);
+    self.send_msg(payload, ack_required)?;
+    Ok(level)
+  }
+
+  /// changes only the hue of this bulb, over `duration`, leaving the rest of
+  /// its color as it is. a bulb that has reported it does not handle
+  /// `Light::SetWaveformOptional` is sent `Light::SetColor` with the rest of
+  /// this copy's color, and fails with `Error::NotReceived` if there is none.
+  /// the copy's color is updated once the message is sent, but a change made
+  /// through another copy is not seen; `Client::set_hue_only` asks the bulb
+  /// for its color instead.
+  ///
+  pub fn set_hue_only(
+    &mut self,
+    hue: Hue,
+    duration: Duration,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    self.set_component(Component::Hue(hue), duration, ack_required)
+  }
+
+  /// changes only the brightness of this bulb, as `set_hue_only` changes the
+  /// hue.
+  ///
+  pub fn set_brightness_only(
+    &mut self,
+    brightness: Brightness,
+    duration: Duration,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    let brightness = Component::Brightness(brightness);
+    self.set_component(brightness, duration, ack_required)
+  }
+
+  /// changes only the temperature of this bulb, as `set_hue_only` changes the
+  /// hue.
+  ///
+  pub fn set_kelvin_only(
+    &mut self,
+    kelvin: Kelvin,
+    duration: Duration,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    self.set_component(Component::Kelvin(kelvin), duration, ack_required)
+  }
+
+  fn set_component(
+    &mut self,
+    component: Component,
+    duration: Duration,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    let waveform = self.handles(consts::LIGHT_SET_WAVEFORM_OPTIONAL);
+    let payload = match component.payload(waveform, self.color, duration) {
+      Some(payload) => payload,
+      None => return Err(Error::NotReceived { property: "color" }),
+    };
+
+    let seq = self.send_msg(payload, ack_required)?;
+    self.color = self.color.map(|color| component.apply(color));
+    Ok(seq)
+  }
+
+  /// requests the infrared brightness of this bulb. the response is a
+  /// `Light::StateInfrared`.
+  ///
+  pub fn get_infrared(&self) -> Result<u8, Error> {
+    self.send_msg(Payload::Light(Light::GetInfrared), false)
+  }
+
+  /// sets the infrared brightness of this bulb.
+  ///
+  pub fn set_infrared(
+    &self,
+    brightness: u16,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    self.send_msg(Payload::Light(Light::SetInfrared(brightness)), ack_required)
+  }
+
+  /// requests the running firmware effect of this strip. the response is a
+  /// `MultiZone::StateMultiZoneEffect`.
+  ///
+  pub fn get_multizone_effect(&self) -> Result<u8, Error> {
+    self.send_msg(Payload::MultiZone(MultiZone::GetMultiZoneEffect), false)
+  }
+
+  /// starts the move effect on this strip. `speed` is the time in milliseconds
+  /// for one cycle of the effect.
+  ///
+  pub fn start_move_effect(
+    &self,
+    speed: u32,
+    direction: MoveDirection,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    let mut parameters = [0; 8];
+    parameters[1] = direction.into();
+
+    self.send_msg(
+      Payload::MultiZone(MultiZone::SetMultiZoneEffect(
+        0,
+        MultiZoneEffectType::Move,
+        speed,
+        0,
+        parameters,
+      )),
+      ack_required,
+    )
+  }
+
+  /// stops any firmware effect running on this strip.
+  ///
+  pub fn stop_multizone_effect(&self, ack_required: bool) -> Result<u8, Error> {
+    self.send_msg(
+      Payload::MultiZone(MultiZone::SetMultiZoneEffect(
+        0,
+        MultiZoneEffectType::Off,
+        0,
+        0,
+        [0; 8],
+      )),
+      ack_required,
+    )
+  }
+
+  /// requests the power level of a relay on this switch. the response is a
+  /// `Relay::StateRPower`.
+  ///
+  pub fn get_relay(&self, index: u8) -> Result<u8, Error> {
+    self.send_msg(Payload::Relay(Relay::GetRPower(index)), false)
+  }
+
+  /// sets the power level of a relay on this switch.
+  ///
+  pub fn set_relay(
+    &self,
+    index: u8,
+    level: Power,
+    ack_required: bool,
+  ) -> Result<u8, Error> {
+    self.send_msg(Payload::Relay(Relay::SetRPower(index, level)), ack_required)
+  }
+}
+
+impl<A> Display for Bulb<A>
+where
+  A: ToSocketAddrs + Display,
+{
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(
+      f,
+      "'{:?}' ({}:{} {})",
+      self.label(),
+      self.ip,
+      self.port,
+      self.target
+    )
+  }
+}
+
+impl<A> Debug for Bulb<A>
+where
+  A: ToSocketAddrs + Debug,
+{
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(
+      f,
+      "Bulb ({}):\nLabel: '{:?}'\nAddr.: {:?}:{}",
+      self.target,
+      self.label(),
+      self.ip,
+      self.port
+    )
+  }
+}
+
+/// a snapshot of the devices a client knows.
+///
+type Snapshot = Arc<HashMap<u64, Arc<Bulb<SocketAddr>>>>;
+
+/// the properties of a device that the client keeps, for `Bulb::updated`.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Property {
+  Label,
+  Location,
+  Group,
+  Power,
+  Color,
+  Version,
+  HostFirmware,
+  WifiFirmware,
+  HostInfo,
+  WifiInfo,
+  Info,
+  Infrared,
+}
+
+/// the queries `Bulb::refresh` and `Client::refresh` send, for every property
+/// the client keeps other than power, which `Light::State` carries.
+///
+fn refresh_queries() -> Vec<Payload> {
+  vec![
+    Payload::Device(Device::GetLabel),
+    Payload::Device(Device::GetGroup),
+    Payload::Device(Device::GetLocation),
+    Payload::Device(Device::GetVersion),
+    Payload::Device(Device::GetHostFirmware),
+    Payload::Light(Light::Get),
+  ]
+}
+
+/// the power level that inverts `power`, and the message that sets it. a
+/// transition needs `Light::SetPower`, as `Device::SetPower` has no duration.
+///
+fn toggled(power: Option<Power>, duration: Option<Duration>) -> (Power, Payload) {
+  let level = match power {
+    Some(power) if power.is_on() => Power::Standby,
+    _ => Power::Max,
+  };
+  let payload = match duration {
+    Some(duration) => Payload::Light(Light::SetPower(level, duration.into())),
+    None => Payload::Device(Device::SetPower(level)),
+  };
+  (level, payload)
+}
+
+/// the one component of a color that `Bulb::set_hue_only` and its siblings
+/// change.
+///
+/// # Notes
+///
+///   * `Light::SetWaveformOptional` changes the component alone, so it is sent
+///     unless the bulb has reported that it does not handle it. otherwise
+///     `Light::SetColor` is sent with the rest of the bulb's current color,
+///     which must be known.
+///
+#[derive(Debug, Copy, Clone)]
+enum Component {
+  Hue(Hue),
+  Brightness(Brightness),
+  Kelvin(Kelvin),
+}
+
+impl Component {
+  /// replaces this component of `color`.
+  ///
+  fn apply(self, color: HSBK) -> HSBK {
+    match self {
+      Component::Hue(hue) => color.with_hue(hue),
+      Component::Brightness(brightness) => color.with_brightness(brightness),
+      Component::Kelvin(kelvin) => color.with_kelvin(kelvin),
+    }
+  }
+
+  /// the message that changes this component of a bulb whose color is
+  /// `color`, or none if it needs the color and that is unknown.
+  ///
+  fn payload(
+    self,
+    waveform: bool,
+    color: Option<HSBK>,
+    duration: Duration,
+  ) -> Option<Payload> {
+    if !waveform {
+      let color = self.apply(color?);
+      return Some(Payload::Light(Light::SetColor(color, duration.into())));
+    }
+
+    // a single, non transient saw cycle moves to the color over the period,
+    // and stays there.
+    let period = u32::from(TransitionMs::from(duration));
+    Some(Payload::Light(Light::SetWaveformOptional(
+      false,
+      self.apply(color.unwrap_or_default()),
+      period,
+      1.0,
+      0,
+      Waveform::Saw,
+      matches!(self, Component::Hue(_)),
+      false,
+      matches!(self, Component::Brightness(_)),
+      matches!(self, Component::Kelvin(_)),
+    )))
+  }
+}
+
+/// the devices a client knows. readers take a snapshot, and never wait for
+/// the listen thread: a writer changes a copy of the map, which shares the
+/// bulbs it does not change, and swaps it in whole. writers are serialized.
+///
+struct Registry {
+  current: Mutex<Snapshot>,
+  writer: Mutex<()>,
+}
+
+impl Registry {
+  fn new(devices: HashMap<u64, Bulb<SocketAddr>>) -> Registry {
+    let devices = devices.into_iter().map(|(t, bulb)| (t, Arc::new(bulb)));
+    Registry {
+      current: Mutex::new(Arc::new(devices.collect())),
+      writer: Mutex::new(()),
+    }
+  }
+
+  /// returns the devices as they are now. the lock is only held to clone the
+  /// pointer to them.
+  ///
+  fn load(&self) -> Snapshot {
+    self.current.lock().unwrap().clone()
+  }
+
+  /// returns a copy of one device.
+  ///
+  fn get(&self, target: u64) -> Option<Bulb<SocketAddr>> {
+    self.load().get(&target).map(|bulb| Bulb::clone(bulb))
+  }
+
+  /// returns a copy of every device.
+  ///
+  fn cloned(&self) -> Vec<Bulb<SocketAddr>> {
+    self.load().values().map(|bulb| Bulb::clone(bulb)).collect()
+  }
+
+  /// starts a change, which readers see once it is dropped. the devices are
+  /// only copied once one of them is changed.
+  ///
+  fn write(&self) -> RegistryWrite<'_> {
+    let writer = self.writer.lock().unwrap();
+    RegistryWrite {
+      devices: self.load(),
+      registry: self,
+      changed: false,
+      _writer: writer,
+    }
+  }
+}
+
+/// a change to a `Registry`, returned by `Registry::write`. the methods that
+/// hand out a bulb to change copy it first, unless nothing else shares it, so
+/// they should only be called for a bulb that will change.
+///
+struct RegistryWrite<'a> {
+  devices: Snapshot,
+  registry: &'a Registry,
+  changed: bool,
+  _writer: MutexGuard<'a, ()>,
+}
+
+impl<'a> RegistryWrite<'a> {
+  /// returns the devices to change, copying the map the first time.
+  ///
+  fn devices_mut(&mut self) -> &mut HashMap<u64, Arc<Bulb<SocketAddr>>> {
+    self.changed = true;
+    Arc::make_mut(&mut self.devices)
+  }
+
+  fn get_mut(&mut self, target: &u64) -> Option<&mut Bulb<SocketAddr>> {
+    if !self.devices.contains_key(target) {
+      return None;
+    }
+    self.devices_mut().get_mut(target).map(Arc::make_mut)
+  }
+
+  fn values_mut(&mut self) -> impl Iterator<Item = &mut Bulb<SocketAddr>> {
+    self.devices_mut().values_mut().map(Arc::make_mut)
+  }
+
+  /// returns the device for `target`, adding the one `f` creates if there is
+  /// none.
+  ///
+  fn get_or_insert_with<F>(&mut self, target: u64, f: F) -> &mut Bulb<SocketAddr>
+  where
+    F: FnOnce() -> Bulb<SocketAddr>,
+  {
+    let devices = self.devices_mut();
+    let bulb = devices.entry(target).or_insert_with(|| Arc::new(f()));
+    Arc::make_mut(bulb)
+  }
+
+  fn insert(
+    &mut self,
+    target: u64,
+    bulb: Bulb<SocketAddr>,
+  ) -> Option<Bulb<SocketAddr>> {
+    let replaced = self.devices_mut().insert(target, Arc::new(bulb));
+    replaced.map(unshare)
+  }
+
+  fn remove(&mut self, target: &u64) -> Option<Bulb<SocketAddr>> {
+    if !self.devices.contains_key(target) {
+      return None;
+    }
+    self.devices_mut().remove(target).map(unshare)
+  }
+
+  /// removes every device, returning them.
+  ///
+  fn clear(&mut self) -> Vec<Bulb<SocketAddr>> {
+    if self.devices.is_empty() {
+      return Vec::new();
+    }
+    self.devices_mut().drain().map(|(_, bulb)| unshare(bulb)).collect()
+  }
+}
+
+impl<'a> Deref for RegistryWrite<'a> {
+  type Target = HashMap<u64, Arc<Bulb<SocketAddr>>>;
+
+  fn deref(&self) -> &HashMap<u64, Arc<Bulb<SocketAddr>>> {
+    &self.devices
+  }
+}
+
+impl<'a> Drop for RegistryWrite<'a> {
+  fn drop(&mut self) {
+    if self.changed {
+      let devices = mem::take(&mut self.devices);
+      *self.registry.current.lock().unwrap() = devices;
+    }
+  }
+}
+
+/// takes a bulb out of its `Arc`, copying it if a snapshot still shares it.
+///
+fn unshare(bulb: Arc<Bulb<SocketAddr>>) -> Bulb<SocketAddr> {
+  Arc::try_unwrap(bulb).unwrap_or_else(|bulb| Bulb::clone(&bulb))
+}
+
+/// routes received frames to the requests that caused them.
+///
+struct Router {
+  routes: Mutex<HashMap<RouteKey, Sender<(MacAddress, Payload)>>>,
+}
+
+impl Router {
+  fn new() -> Router {
+    Router {
+      routes: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn insert(&self, key: RouteKey) -> Receiver<(MacAddress, Payload)> {
+    let (tx, rx) = mpsc::channel();
+    self.routes.lock().unwrap().insert(key, tx);
+    rx
+  }
+
+  fn remove(&self, key: RouteKey) {
+    self.routes.lock().unwrap().remove(&key);
+  }
+
+  /// hands a frame to the request it answers, if there is one. a request
+  /// sent to every device is answered by any target.
+  ///
+  fn route(&self, header: &Header, payload: &PayloadRef) -> bool {
+    let [key, any] = route_keys(header);
+    let routes = self.routes.lock().unwrap();
+
+    match routes.get(&key).or_else(|| routes.get(&any)) {
+      Some(reply) => {
+        let _ = reply.send((header.target(), payload.clone().into_owned()));
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+/// the responses to a message sent with `Client::send_routed`, each with the
+/// target of the device that sent it. responses stop being routed here once
+/// this is dropped.
+///
+pub struct Responses {
+  key: RouteKey,
+  router: Arc<Router>,
+  sequences: Arc<Sequences>,
+  rx: Receiver<(MacAddress, Payload)>,
+}
+
+impl Responses {
+  /// the sequence number the message was sent with.
+  ///
+  #[inline]
+  pub fn sequence(&self) -> u8 {
+    self.key.1
+  }
+
+  /// waits up to `timeout` for the next response.
+  ///
+  pub fn recv_timeout(
+    &self,
+    timeout: Duration,
+  ) -> Result<(MacAddress, Payload), Error> {
+    self.rx.recv_timeout(timeout).or(Err(Error::Timeout {
+      waiting_for: "a response",
+    }))
+  }
+
+  /// returns the next response, if one has already arrived.
+  ///
+  pub fn try_recv(&self) -> Option<(MacAddress, Payload)> {
+    self.rx.try_recv().ok()
+  }
+}
+
+impl Drop for Responses {
+  fn drop(&mut self) {
+    self.router.remove(self.key);
+    self.sequences.release(self.key.2, self.key.1);
+  }
+}
+
+/// wakes a thread that parked while waiting for the listen thread.
+///
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+  fn wake(self: Arc<Self>) {
+    self.0.unpark();
+  }
+}
+
+/// the devices from `Client::discover_stream`: the ones the client already
+/// knew, then each one as it is found. every device is yielded once. as an
+/// `Iterator` this blocks for the next device, and with the `stream` feature
+/// it is also a `futures_core::Stream`; both end once the listen thread stops.
+///
+pub struct DiscoveredBulbs {
+  known: VecDeque<Bulb<SocketAddr>>,
+  seen: HashSet<MacAddress>,
+  events: DeviceEvents,
+  devices: Arc<Registry>,
+}
+
+impl DiscoveredBulbs {
+  /// polls for the next device. resolves to `None` once the listen thread has
+  /// stopped.
+  ///
+  pub fn poll_bulb(&mut self, cx: &mut Context) -> Poll<Option<Bulb<SocketAddr>>> {
+    if let Some(bulb) = self.known.pop_front() {
+      return Poll::Ready(Some(bulb));
+    }
+
+    loop {
+      let target = match self.events.poll_event(cx) {
+        Poll::Ready(Some(DeviceEvent::Discovered(target))) => target,
+        Poll::Ready(Some(_)) => continue,
+        Poll::Ready(None) => return Poll::Ready(None),
+        Poll::Pending => return Poll::Pending,
+      };
+
+      if self.seen.insert(target) {
+        if let Some(bulb) = self.devices.get(target.into()) {
+          return Poll::Ready(Some(bulb));
+        }
+      }
+    }
+  }
+}
+
+impl Iterator for DiscoveredBulbs {
+  type Item = Bulb<SocketAddr>;
+
+  fn next(&mut self) -> Option<Bulb<SocketAddr>> {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+      match self.poll_bulb(&mut cx) {
+        Poll::Ready(bulb) => return bulb,
+        Poll::Pending => thread::park(),
+      }
+    }
+  }
+}
+
+#[cfg(feature = "stream")]
+impl Stream for DiscoveredBulbs {
+  type Item = Bulb<SocketAddr>;
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Option<Bulb<SocketAddr>>> {
+    self.get_mut().poll_bulb(cx)
+  }
+}
+
+/// the state of one device, from `Client::subscribe_state`. it yields the
+/// device's state when first polled, if the client knows the device, and
+/// again whenever it changes. as an `Iterator` this blocks for the next
+/// change, and with the `stream` feature it is also a `futures_core::Stream`;
+/// both end once the listen thread stops. a device that is forgotten keeps
+/// its last state.
+///
+pub struct StateWatch {
+  target: u64,
+  seen: Option<DeviceState>,
+  events: DeviceEvents,
+  devices: Arc<Registry>,
+}
+
+impl StateWatch {
+  /// returns the device's state now, whether or not it has changed.
+  ///
+  pub fn latest(&self) -> Option<DeviceState> {
+    self.devices.load().get(&self.target).map(|bulb| bulb.state())
+  }
+
+  /// polls for a state other than the one last yielded. resolves to `None`
+  /// once the listen thread has stopped.
+  ///
+  pub fn poll_changed(&mut self, cx: &mut Context) -> Poll<Option<DeviceState>> {
+    loop {
+      if let Some(state) = self.latest() {
+        if self.seen.as_ref() != Some(&state) {
+          self.seen = Some(state.clone());
+          return Poll::Ready(Some(state));
+        }
+      }
+
+      // every change to the state is announced, so nothing else wakes this.
+      match self.events.poll_event(cx) {
+        Poll::Ready(Some(_)) => continue,
+        Poll::Ready(None) => return Poll::Ready(None),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+}
+
+impl Iterator for StateWatch {
+  type Item = DeviceState;
+
+  fn next(&mut self) -> Option<DeviceState> {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+      match self.poll_changed(&mut cx) {
+        Poll::Ready(state) => return state,
+        Poll::Pending => thread::park(),
+      }
+    }
+  }
+}
+
+#[cfg(feature = "stream")]
+impl Stream for StateWatch {
+  type Item = DeviceState;
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Option<DeviceState>> {
+    self.get_mut().poll_changed(cx)
+  }
+}
+
+/// future returned by `Client::wait_for_device_async`. resolves to the device
+/// once the client has found it, or fails with `Error::Timeout` if it is not
+/// found in time, and with `Error::NotDiscovered` if the listen thread stops
+/// first. waiting for an alias that no device has fails straight away with
+/// `Error::UnknownAlias`.
+///
+pub struct WaitForDevice {
+  target: Result<u64, Error>,
+  deadline: Option<Instant>,
+  events: DeviceEvents,
+  devices: Arc<Registry>,
+  alarm: Alarm,
+}
+
+impl WaitForDevice {
+  /// checks for the device, without arranging to be woken at the deadline.
+  ///
+  fn poll_device(
+    &mut self,
+    cx: &mut Context,
+  ) -> Poll<Result<Bulb<SocketAddr>, Error>> {
+    let stopped = loop {
+      match self.events.poll_event(cx) {
+        Poll::Ready(Some(_)) => continue,
+        Poll::Ready(None) => break true,
+        Poll::Pending => break false,
+      }
+    };
+
+    let target = match self.target {
+      Ok(target) => target,
+      Err(ref e) => return Poll::Ready(Err(duplicate(e))),
+    };
+    if let Some(bulb) = self.devices.get(target) {
+      return Poll::Ready(Ok(bulb));
+    }
+
+    if stopped {
+      Poll::Ready(Err(Error::NotDiscovered(target.into())))
+    } else if self.deadline.is_some_and(|at| Instant::now() >= at) {
+      Poll::Ready(Err(Error::Timeout {
+        waiting_for: "the device to be discovered",
+      }))
+    } else {
+      Poll::Pending
+    }
+  }
+
+  /// blocks the calling thread until the future resolves.
+  ///
+  fn wait(mut self) -> Result<Bulb<SocketAddr>, Error> {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+      match self.poll_device(&mut cx) {
+        Poll::Ready(result) => return result,
+        Poll::Pending => match self.deadline {
+          Some(at) => thread::park_timeout(at.duration_since(Instant::now())),
+          None => thread::park(),
+        },
+      }
+    }
+  }
+}
+
+impl Future for WaitForDevice {
+  type Output = Result<Bulb<SocketAddr>, Error>;
+
+  fn poll(
+    self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Result<Bulb<SocketAddr>, Error>> {
+    let this = self.get_mut();
+    let polled = this.poll_device(cx);
+    if let (true, Some(at)) = (polled.is_pending(), this.deadline) {
+      this.alarm.set(at, cx.waker());
+    }
+    polled
+  }
+}
+
+/// future returned by `Client::wait_for_devices_async`. resolves to every
+/// device the client has found, once it has found at least `min_count`, and,
+/// if `settled` was called, once no new device has been found for a while.
+/// fails with `Error::Timeout` if that does not happen in time.
+///
+pub struct WaitForDevices {
+  min_count: usize,
+  quiet: Option<Duration>,
+  deadline: Option<Instant>,
+  last_found: Instant,
+  events: DeviceEvents,
+  devices: Arc<Registry>,
+  alarm: Alarm,
+}
+
+impl WaitForDevices {
+  /// also waits until no new device has been found for `quiet`, counting
+  /// from when the wait started. use this when the number of devices is not
+  /// known, to tell when discovery has settled.
+  ///
+  pub fn settled(mut self, quiet: Duration) -> WaitForDevices {
+    self.quiet = Some(quiet);
+    self
+  }
+
+  /// the next time the future can resolve without hearing from a device, if
+  /// there is one.
+  ///
+  fn wake_at(&self) -> Option<Instant> {
+    let quiet = self.quiet.and_then(|quiet| self.last_found.checked_add(quiet));
+    match (self.deadline, quiet) {
+      (Some(deadline), Some(quiet)) => Some(deadline.min(quiet)),
+      (deadline, quiet) => deadline.or(quiet),
+    }
+  }
+
+  /// checks the devices, without arranging to be woken at `wake_at`.
+  ///
+  fn poll_devices(
+    &mut self,
+    cx: &mut Context,
+  ) -> Poll<Result<Vec<Bulb<SocketAddr>>, Error>> {
+    while let Poll::Ready(Some(event)) = self.events.poll_event(cx) {
+      if let DeviceEvent::Discovered(_) = event {
+        self.last_found = Instant::now();
+      }
+    }
+
+    let now = Instant::now();
+    let settled = match self.quiet {
+      Some(quiet) => self.last_found.checked_add(quiet).is_some_and(|at| now >= at),
+      None => true,
+    };
+    let devices = self.devices.load();
+    if settled && devices.len() >= self.min_count {
+      Poll::Ready(Ok(devices.values().map(|bulb| Bulb::clone(bulb)).collect()))
+    } else if self.deadline.is_some_and(|at| now >= at) {
+      Poll::Ready(Err(Error::Timeout {
+        waiting_for: "devices to be discovered",
+      }))
+    } else {
+      Poll::Pending
+    }
+  }
+
+  /// blocks the calling thread until the future resolves.
+  ///
+  fn wait(mut self) -> Result<Vec<Bulb<SocketAddr>>, Error> {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+      match self.poll_devices(&mut cx) {
+        Poll::Ready(result) => return result,
+        Poll::Pending => match self.wake_at() {
+          Some(at) => thread::park_timeout(at.duration_since(Instant::now())),
+          None => thread::park(),
+        },
+      }
+    }
+  }
+}
+
+impl Future for WaitForDevices {
+  type Output = Result<Vec<Bulb<SocketAddr>>, Error>;
+
+  fn poll(
+    self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Result<Vec<Bulb<SocketAddr>>, Error>> {
+    let this = self.get_mut();
+    let polled = this.poll_devices(cx);
+    if let (true, Some(at)) = (polled.is_pending(), this.wake_at()) {
+      this.alarm.set(at, cx.waker());
+    }
+    polled
+  }
+}
+
+/// wakes a task at a given time, from a thread, for futures that would
+/// otherwise not be woken when they time out. the thread is started on the
+/// first `set`, sleeps until the latest time it was given, and exits when the
+/// alarm is dropped.
+///
+#[derive(Default)]
+struct Alarm(Option<Arc<AlarmState>>);
+
+#[derive(Default)]
+struct AlarmState {
+  pending: Mutex<PendingAlarm>,
+  changed: Condvar,
+}
+
+#[derive(Default)]
+struct PendingAlarm {
+  at: Option<Instant>,
+  waker: Option<Waker>,
+  dropped: bool,
+}
+
+impl Alarm {
+  /// arranges for `waker` to be woken at `at`, replacing the time and waker
+  /// of an earlier call.
+  ///
+  fn set(&mut self, at: Instant, waker: &Waker) {
+    let state = self.0.get_or_insert_with(|| {
+      let state = Arc::new(AlarmState::default());
+      let timer = state.clone();
+      thread::spawn(move || timer.run());
+      state
+    });
+
+    let mut pending = state.pending.lock().unwrap();
+    pending.waker = Some(waker.clone());
+    if pending.at != Some(at) {
+      pending.at = Some(at);
+      state.changed.notify_one();
+    }
+  }
+}
+
+impl Drop for Alarm {
+  fn drop(&mut self) {
+    if let Some(ref state) = self.0 {
+      state.pending.lock().unwrap().dropped = true;
+      state.changed.notify_one();
+    }
+  }
+}
+
+impl AlarmState {
+  /// the timer thread. the waker is woken without the lock held, in case
+  /// waking polls the future, and so sets the alarm again, in place.
+  ///
+  fn run(&self) {
+    let mut pending = self.pending.lock().unwrap();
+    loop {
+      if pending.dropped {
+        return;
+      }
+      let now = Instant::now();
+      pending = match pending.at {
+        Some(at) if now >= at => {
+          pending.at = None;
+          let waker = pending.waker.take();
+          drop(pending);
+          if let Some(waker) = waker {
+            waker.wake();
+          }
+          self.pending.lock().unwrap()
+        }
+        Some(at) => self.changed.wait_timeout(pending, at - now).unwrap().0,
+        None => self.changed.wait(pending).unwrap(),
+      };
+    }
+  }
+}
+
+/// messages sent with ack_required, by target and sequence number.
+///
+struct InFlight {
+  acks: Mutex<HashMap<(u64, u8), Pending>>,
+  acked: Condvar,
+}
+
+/// a message in flight. one that nothing has waited on is forgotten after
+/// `FORGET_ACK_AFTER`, so a message sent without waiting for its ack does not
+/// hold its sequence number forever.
+///
+struct Pending {
+  acked: bool,
+  awaited: bool,
+  sent: Instant,
+}
+
+impl InFlight {
+  fn new() -> InFlight {
+    InFlight {
+      acks: Mutex::new(HashMap::new()),
+      acked: Condvar::new(),
+    }
+  }
+
+  /// adds a message, which is `awaited` if the caller waits for its ack
+  /// from the start.
+  ///
+  fn insert(&self, target: u64, seq: u8, awaited: bool) {
+    let pending = Pending {
+      acked: false,
+      awaited,
+      sent: Instant::now(),
+    };
+    self.acks.lock().unwrap().insert((target, seq), pending);
+  }
+
+  fn remove(&self, target: u64, seq: u8) {
+    self.acks.lock().unwrap().remove(&(target, seq));
+  }
+
+  /// marks a message as acked. an ack from a device is matched to the
+  /// message sent to it, or failing that to a message sent to every device.
+  ///
+  fn ack(&self, target: u64, seq: u8) {
+    let mut acks = self.acks.lock().unwrap();
+    let key = if acks.contains_key(&(target, seq)) {
+      (target, seq)
+    } else {
+      (0, seq)
+    };
+
+    if let Some(pending) = acks.get_mut(&key) {
+      pending.acked = true;
+      self.acked.notify_all();
+    }
+  }
+
+  /// waits up to `timeout` for a message to be acked, and returns whether it
+  /// was. the message stays in flight either way.
+  ///
+  fn wait_for(
+    &self,
+    target: u64,
+    seq: u8,
+    timeout: Duration,
+  ) -> Result<bool, Error> {
+    let key = (target, seq);
+    let mut acks = self.acks.lock().unwrap();
+    match acks.get_mut(&key) {
+      Some(pending) => pending.awaited = true,
+      None => return Err(Error::UnknownSequence(seq)),
+    }
+
+    let acked = |acks: &HashMap<_, Pending>| acks.get(&key).map(|p| p.acked);
+    let (acks, _) = self
+      .acked
+      .wait_timeout_while(acks, timeout, |acks| acked(acks) == Some(false))
+      .unwrap();
+
+    Ok(acked(&acks) == Some(true))
+  }
+
+  /// forgets the messages that nothing has waited on for `FORGET_ACK_AFTER`,
+  /// returning them so their sequence numbers can be released.
+  ///
+  fn expire(&self) -> Vec<(u64, u8)> {
+    let mut expired = Vec::new();
+    self.acks.lock().unwrap().retain(|&key, pending| {
+      let forget = !pending.awaited && pending.sent.elapsed() > FORGET_ACK_AFTER;
+      if forget {
+        expired.push(key);
+      }
+      !forget
+    });
+    expired
+  }
+
+  fn wait(&self, target: u64, seq: u8, timeout: Duration) -> Result<(), Error> {
+    let acked = self.wait_for(target, seq, timeout);
+    self.remove(target, seq);
+
+    match acked? {
+      true => Ok(()),
+      false => Err(Error::Timeout {
+        waiting_for: "an acknowledgement",
+      }),
+    }
+  }
+}
+
+/// the state the listen loop works on, shared with the client it came from.
+///
+#[derive(Clone)]
+struct Listener {
+  endpoint: Endpoint,
+  closed: Arc<AtomicBool>,
+  strict: Arc<AtomicBool>,
+  accept_foreign: bool,
+  rejected: Arc<AtomicUsize>,
+  raw_hook: Arc<RwLock<Option<RawHook>>>,
+  callbacks: Arc<RwLock<Callbacks>>,
+  feeds: Arc<Mutex<Vec<Feed>>>,
+  errors: Arc<Errors>,
+  events: Arc<Subscribers>,
+  offline_after: Arc<Mutex<Duration>>,
+  evict_after: Arc<Mutex<Option<Duration>>>,
+  router: Arc<Router>,
+  in_flight: Arc<InFlight>,
+  probes: Option<Arc<Mutex<HashMap<u64, Instant>>>>,
+  monitor: Option<Arc<dyn Transport>>,
+  devices: Arc<Registry>,
+}
+
+impl Listener {
+  /// marks the devices that have not been heard from recently as offline,
+  /// and forgets the acks nothing is waiting for.
+  ///
+  fn sweep(&self) {
+    for (target, seq) in self.in_flight.expire() {
+      self.endpoint.sequences.release(target, seq);
+    }
+
+    let offline_after = *self.offline_after.lock().unwrap();
+    let evict_after = *self.evict_after.lock().unwrap();
+    let offline = |bulb: &Bulb<SocketAddr>| {
+      bulb.online && bulb.last_seen.elapsed() > offline_after
+    };
+    let stale = |bulb: &Bulb<SocketAddr>| match evict_after {
+      Some(after) => !bulb.configured && bulb.last_seen.elapsed() > after,
+      None => false,
+    };
+
+    // most sweeps change nothing, and should not copy the devices.
+    let changing: Vec<u64> = self
+      .devices
+      .load()
+      .values()
+      .filter(|bulb| offline(bulb) || stale(bulb))
+      .map(|bulb| u64::from(bulb.target))
+      .collect();
+    if changing.is_empty() {
+      return;
+    }
+
+    let mut events = Vec::new();
+    {
+      let mut devices = self.devices.write();
+      for target in changing {
+        // the listen thread may have heard from it since.
+        let (offline, stale) = match devices.get(&target) {
+          Some(bulb) => (offline(bulb), stale(bulb)),
+          None => continue,
+        };
+        if offline {
+          let bulb = devices.get_mut(&target).unwrap();
+          bulb.online = false;
+          events.push(DeviceEvent::WentOffline(target.into()));
+          if let Some(health) = bulb.update_health() {
+            events.push(DeviceEvent::HealthChanged(target.into(), health));
+          }
+        }
+        if stale {
+          devices.remove(&target);
+          events.push(DeviceEvent::Evicted(target.into()));
+        }
+      }
+    }
+    for event in events {
+      self.events.emit(event);
+    }
+  }
+
+  /// rebinds the socket, and broadcasts for devices so the ones that are
+  /// still there are seen again.
+  ///
+  fn rebind(&self) -> Result<(), Error> {
+    self.endpoint.socket.rebind()?;
+    let options = DiscoverOptions::empty();
+    discovery_round(&self.endpoint, &self.devices, options, false, &self.errors)
+  }
+
+  /// reports a receive that failed, and rebinds the socket once `REBIND_AFTER`
+  /// have failed in a row.
+  ///
+  fn recv_failed(&self, failures: &mut u32, e: Error) {
+    self.errors.report(e);
+    *failures += 1;
+
+    if *failures >= REBIND_AFTER && self.endpoint.socket.can_rebind() {
+      *failures = 0;
+      if let Err(e) = self.rebind() {
+        self.errors.report(e);
+      }
+    }
+
+    // a broken socket fails straight away, so this keeps from spinning.
+    thread::sleep(self.endpoint.recv_timeout);
+  }
+
+  /// starts a thread that handles the datagrams received on the monitor
+  /// socket, if there is one, until the client is closed.
+  ///
+  fn spawn_monitor(&self) -> Option<JoinHandle<()>> {
+    let socket = self.monitor.clone()?;
+    let listener = self.clone();
+
+    Some(thread::spawn(move || {
+      let mut buf = [0; consts::MAX_FRAME_SIZE];
+
+      while !listener.closed.load(Ordering::SeqCst) {
+        let (amt, src) = match socket.recv_from(&mut buf[..]) {
+          Ok(received) => received,
+          Err(ref e) if timed_out(e) => continue,
+          Err(e) => {
+            listener.errors.report(e.into());
+            thread::sleep(listener.endpoint.recv_timeout);
+            continue;
+          }
+        };
+
+        if listener.closed.load(Ordering::SeqCst) {
+          break;
+        }
+
+        listener.dispatch(&buf[..amt], src, true);
+      }
+    }))
+  }
+
+  /// waits up to `timeout` for a datagram, and handles it. returns whether a
+  /// datagram was handled.
+  ///
+  fn poll(&self, buf: &mut [u8], timeout: Duration) -> Result<bool, Error> {
+    let socket = &self.endpoint.socket;
+
+    self.sweep();
+    // a zero timeout would block forever.
+    socket.set_read_timeout(timeout.max(Duration::from_millis(1)))?;
+
+    match socket.recv_from(buf) {
+      Ok(_) if self.closed.load(Ordering::SeqCst) => Ok(false),
+      Ok((amt, src)) => {
+        self.dispatch(&buf[..amt], src, self.accept_foreign);
+        Ok(true)
+      }
+      Err(ref e) if timed_out(e) => Ok(false),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// asks a device the client does not know, but heard from, for its
+  /// service, so the reply adds it to the devices. does nothing unless
+  /// passive discovery is on, or if the device was asked recently.
+  ///
+  fn probe(&self, target: u64, src: SocketAddr) {
+    let probes = match self.probes {
+      Some(ref probes) => probes,
+      None => return,
+    };
+
+    let now = Instant::now();
+    match probes.lock().unwrap().entry(target) {
+      Entry::Occupied(ref entry) if now < *entry.get() + PROBE_AGAIN_AFTER => {
+        return
+      }
+      Entry::Occupied(mut entry) => {
+        entry.insert(now);
+      }
+      Entry::Vacant(entry) => {
+        entry.insert(now);
+      }
+    }
+
+    debug!(
+      target: "device.in",
+      "Probing unknown device {:#X} at {}",
+      target,
+      src);
+    let get_service = Payload::Device(Device::GetService);
+    if let Err(e) = self.endpoint.send_msg(src, get_service, false, target) {
+      self.errors.report(e);
+    }
+  }
+
+  /// handles every frame in a datagram received from `src`. frames that
+  /// carry another controller's source are dropped unless `accept_foreign`.
+  ///
+  fn dispatch(&self, datagram: &[u8], src: SocketAddr, accept_foreign: bool) {
+    let Listener {
+      ref endpoint,
+      ref strict,
+      ref rejected,
+      ref raw_hook,
+      ref callbacks,
+      ref feeds,
+      ref errors,
+      ref router,
+      ref in_flight,
+      ref devices,
+      ..
+    } = *self;
+
+    if let Some(ref hook) = *raw_hook.read().unwrap() {
+      hook(datagram, src);
+    }
+    let mut rest = datagram;
+
+    while !rest.is_empty() {
+      let (header, payload, next) = match Message::decode_ref(rest) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+          rejected.fetch_add(1, Ordering::SeqCst);
+          warn!(
+            target: "device.in",
+            "Ignoring {} trailing bytes from {}: {}",
+            rest.len(),
+            src,
+            e);
+          for f in &callbacks.read().unwrap().rejected_frame {
+            f(src, rest, &e);
+          }
+          errors.report(Error::Decode(e));
+          break;
+        }
+      };
+      let target = u64::from(header.target());
+      let frame = &rest[..rest.len() - next.len()];
+      rest = next;
+
+      if strict.load(Ordering::SeqCst) {
+        if let Err(e) = header.validate() {
+          rejected.fetch_add(1, Ordering::SeqCst);
+          warn!(
+            target: "device.in",
+            "Rejecting frame from {}: {}",
+            src,
+            e);
+          for f in &callbacks.read().unwrap().rejected_frame {
+            f(src, frame, &e);
+          }
+          errors.report(Error::Decode(e));
+          continue;
+        }
+      }
+
+      if header.source() != endpoint.source && !accept_foreign {
+        debug!(
+          target: "device.in",
+          "Ignoring frame from {} for source {}",
+          src,
+          header.source());
+        continue;
+      }
+
+      // devices never tag their replies, so this is a broadcast of ours.
+      if header.source() == endpoint.source && header.tagged() {
+        continue;
+      }
+
+      // the contact and the state the frame carries are cached in a single
+      // change, and reported after the callbacks have seen the frame.
+      let mut changes = Changes::default();
+      let known = {
+        let mut devices = devices.write();
+        let known = match devices.get_mut(&target) {
+          Some(bulb) => {
+            bulb.last_seen = Instant::now();
+            // a device that was given a new address by dhcp. a frame from
+            // another controller, to the device, says nothing about where it
+            // is.
+            if from_device(header.typ()) && bulb.ip != src {
+              bulb.ip = src;
+              info!(
+                target: "device.in",
+                "Device {:#X} moved to {}",
+                target,
+                src);
+              changes.events.push(DeviceEvent::AddressChanged(target.into(), src));
+            }
+            let back_online = !mem::replace(&mut bulb.online, true);
+            if let Some(health) = bulb.update_health() {
+              changes.events.push(DeviceEvent::HealthChanged(target.into(), health));
+            }
+            if back_online {
+              changes.events.push(DeviceEvent::Discovered(target.into()));
+            }
+            true
+          }
+          None => false,
+        };
+        self.record(&mut devices, target, src, &payload, &mut changes);
+        known
+      };
+      // only a device's reply says a device is at `src`.
+      let typ = header.typ();
+      let unknown = !known && target != 0 && typ != consts::DEVICE_STATE_SERVICE;
+      if unknown && from_device(typ) {
+        self.probe(target, src);
+      }
+
+      {
+        let mut feeds = feeds.lock().unwrap();
+        if !feeds.is_empty() {
+          if let Ok(msg) = Message::from_bytes(frame) {
+            feeds.retain(|feed| feed.send((src, msg.clone())).is_ok());
+          }
+        }
+      }
+
+      if header.source() == endpoint.source
+        && header.typ() == consts::DEVICE_ACKNOWLEDGEMENT
+      {
+        in_flight.ack(target, header.sequence());
+      }
+
+      {
+        let callbacks = callbacks.read().unwrap();
+
+        match payload {
+          _ if header.typ() == consts::DEVICE_ACKNOWLEDGEMENT => {
+            for f in &callbacks.ack {
+              f(header.target(), header.sequence());
+            }
+          }
+          PayloadRef::Unknown { typ, bytes } => {
+            for f in &callbacks.unknown_message {
+              f(header.target(), typ, bytes);
+            }
+          }
+          _ if !callbacks.state_change.is_empty() => {
+            let state = payload.clone().into_owned();
+            if state.is_state() {
+              for f in &callbacks.state_change {
+                f(header.target(), &state);
+              }
+            }
+          }
+          _ => (),
+        }
+      }
+
+      // responses are routed once the cache reflects them.
+      self.report(target, src, changes);
+      let ack = header.typ() == consts::DEVICE_ACKNOWLEDGEMENT;
+      if header.source() == endpoint.source && !ack {
+        router.route(&header, &payload);
+      }
+    }
+  }
+
+  /// updates the cached state of the device that sent `payload`.
+  ///
+  fn update_cache(&self, target: u64, src: SocketAddr, payload: &PayloadRef) {
+    let mut changes = Changes::default();
+    self.record(&mut self.devices.write(), target, src, payload, &mut changes);
+    self.report(target, src, changes);
+  }
+
+  /// reports what `record` changed, once the change has been published.
+  ///
+  fn report(&self, target: u64, src: SocketAddr, changes: Changes) {
+    for event in changes.events {
+      self.events.emit(event);
+    }
+
+    let bulb = match changes.discovered {
+      Some(bulb) => bulb,
+      None => return,
+    };
+
+    let probed = match self.probes {
+      Some(ref probes) => probes.lock().unwrap().remove(&target).is_some(),
+      None => false,
+    };
+    if probed {
+      let get_label = Payload::Device(Device::GetLabel);
+      if let Err(e) = self.endpoint.send_msg(src, get_label, false, target) {
+        self.errors.report(e);
+      }
+    }
+
+    let callbacks = self.callbacks.read().unwrap();
+    for f in &callbacks.device_discovered {
+      f(&bulb);
+    }
+
+    if let Some(ref enrichment) = callbacks.enrichment {
+      for query in enrichment(&bulb).queries() {
+        if let Err(e) = bulb.send_msg(query, false) {
+          self.errors.report(e);
+        }
+      }
+    }
+  }
+
+  /// changes the cached state of the device that sent `payload`, collecting
+  /// the events to report in `changes`.
+  ///
+  fn record(
+    &self,
+    devices: &mut RegistryWrite,
+    target: u64,
+    src: SocketAddr,
+    payload: &PayloadRef,
+    changes: &mut Changes,
+  ) {
+    let endpoint = &self.endpoint;
+
+    macro_rules! update_device_property(
+      ($prop:ident, $property:ident, $val:expr) => (
+        if let Some(bulb) = devices.get_mut(&target) {
+          bulb.$prop = $val;
+          bulb.updated.insert(Property::$property, Instant::now());
+        }
+      )
+    );
+
+    // only copies the label when it changes, and returns whether it did.
+    macro_rules! update_device_label(
+      ($prop:ident, $property:ident, $val:expr) => (
+        match devices.get_mut(&target) {
+          Some(bulb) => {
+            bulb.updated.insert(Property::$property, Instant::now());
+            let val: &str = &$val;
+            let changed = bulb.$prop.as_deref() != Some(val);
+            if changed {
+              bulb.$prop = Some(val.to_string());
+            }
+            changed
+          }
+          None => false,
+        }
+      )
+    );
+
+    // only emits an event when the value changes.
+    macro_rules! update_device_state(
+      ($prop:ident, $property:ident, $val:expr, $event:ident) => (
+        let changed = match devices.get_mut(&target) {
+          Some(bulb) => {
+            bulb.updated.insert(Property::$property, Instant::now());
+            bulb.$prop.replace($val) != Some($val)
+          }
+          None => false,
+        };
+        if changed {
+          changes.events.push(DeviceEvent::$event(target.into(), $val));
+        }
+      )
+    );
+
+    let payload = match *payload {
+      PayloadRef::StateLabel(ref label) => {
+        info!(
+            target: "device.in",
+            "Received device label: '{:?}' for {:#X}",
+            label,
+            target);
+
+        if update_device_label!(label, Label, label) {
+          let label = label.to_string();
+          changes.events.push(DeviceEvent::LabelChanged(target.into(), label));
+        }
+
+        return;
+      }
+      PayloadRef::StateLocation(_, ref location, _) => {
+        info!(
+            target: "device.in",
+            "Received location label: '{:?}' for {:#X}",
+            location,
+            target);
+
+        if update_device_label!(location, Location, location) {
+          let location = location.to_string();
+          changes.events.push(DeviceEvent::LocationChanged(target.into(), location));
+        }
+
+        return;
+      }
+      PayloadRef::StateGroup(_, ref group, _) => {
+        info!(
+            target: "device.in",
+            "Received group label: '{:?}' for {:#X}",
+            group,
+            target);
+
+        if update_device_label!(group, Group, group) {
+          let group = group.to_string();
+          changes.events.push(DeviceEvent::GroupChanged(target.into(), group));
+        }
+
+        return;
+      }
+      PayloadRef::LightState(color, power, ref label) => {
+        info!(
+          target: "device.in",
+          "Received light state: '{:?} (Power {:?})' for '{}'",
+          color,
+          power,
+          label);
+
+        if update_device_label!(label, Label, label) {
+          let label = label.to_string();
+          changes.events.push(DeviceEvent::LabelChanged(target.into(), label));
+        }
+        update_device_state!(power, Power, power, PowerChanged);
+        update_device_state!(color, Color, color, ColorChanged);
+
+        return;
+      }
+      PayloadRef::Unknown { typ, bytes } => {
+        debug!(
+          target: "device.in",
+          "Received unknown message type {} ({} bytes) for {:#X}",
+          typ,
+          bytes.len(),
+          target);
+
+        return;
+      }
+      PayloadRef::Owned(ref payload) => payload,
+      _ => return,
+    };
+
+    match *payload {
+      Payload::Device(Device::StateService(service, port)) => {
+        info!(
+          target: "device.in",
+          "Received device with service: {:?} port: {}",
+          service,
+          port);
+
+        let discovered = !devices.contains_key(&target);
+        let known = devices.get(&target).is_some_and(|bulb| {
+          (service != Service::Udp || bulb.port == port)
+            && bulb.services.contains(&(service, port))
+        });
+        if !known {
+          let bulb = devices.get_or_insert_with(target, || {
+            Bulb::new(src, src.port().into(), target, endpoint.clone())
+          });
+
+          if service == Service::Udp {
+            bulb.port = port;
+          }
+
+          if !bulb.services.contains(&(service, port)) {
+            bulb.services.push((service, port));
+          }
+
+          if discovered {
+            changes.events.push(DeviceEvent::Discovered(target.into()));
+            changes.discovered = Some(bulb.clone());
+          }
+        }
+
+        info!(target: "device.in", "Devices:");
+
+        for d in devices.values() {
+          info!(target: "device.in", "  Devices: {:?}", d);
+        }
+      }
+      Payload::Device(Device::StatePower(level))
+      | Payload::Light(Light::StatePower(level)) => {
+        info!(
+            target: "device.in",
+            "Received power level: {:?} for {:#X}",
+            level,
+            target);
+
+        update_device_state!(power, Power, level, PowerChanged);
+      }
+      Payload::Device(Device::StateVersion(vendor, product, version)) => {
+        info!(
+            target: "device.in",
+            "Received version: {}, {}, {} for {:#X}",
+            vendor,
+            product,
+            version,
+            target);
+
+        update_device_property!(version, Version, Some((vendor, product, version)));
+      }
+      Payload::Device(Device::StateHostFirmware(firmware)) => {
+        info!(
+            target: "device.in",
+            "Received host firmware: {} for {:#X}",
+            firmware,
+            target);
+
+        update_device_property!(host_firmware, HostFirmware, Some(firmware));
+      }
+      Payload::Device(Device::StateWifiFirmware(firmware)) => {
+        info!(
+            target: "device.in",
+            "Received wifi firmware: {} for {:#X}",
+            firmware,
+            target);
+
+        update_device_property!(wifi_firmware, WifiFirmware, Some(firmware));
+      }
+      Payload::Device(Device::EchoResponse(Array64(echo))) => {
+        let mut id = [0; 8];
+        id.copy_from_slice(&echo[..8]);
+        let id = u64::from_le_bytes(id);
+
+        let health = match devices.get_mut(&target) {
+          Some(bulb) if bulb.echo_pending == Some(id) => {
+            bulb.echo_pending = None;
+            bulb.record_echo(true);
+            bulb.update_health()
+          }
+          _ => None,
+        };
+        if let Some(health) = health {
+          changes.events.push(DeviceEvent::HealthChanged(target.into(), health));
+        }
+      }
+      Payload::Device(Device::StateUnhandled(typ)) => {
+        info!(
+            target: "device.in",
+            "Received unhandled message type: {} for {:#X}",
+            typ,
+            target);
+
+        let handled = devices.get(&target).is_some_and(|bulb| bulb.handles(typ));
+        if handled {
+          devices.get_mut(&target).unwrap().unhandled.push(typ);
+        }
+      }
+      Payload::Device(Device::StateHostInfo(signal, tx, rx)) => {
+        info!(
+            target: "device.in",
+            "Received host info: {}, {}, {} for {:#X}",
+            signal,
+            tx,
+            rx,
+            target);
+
+        update_device_property!(host_info, HostInfo, Some((signal, tx, rx)));
+      }
+      Payload::Device(Device::StateWifiInfo(signal, tx, rx)) => {
+        info!(
+            target: "device.in",
+            "Received wifi info: {}, {}, {} for {:#X}",
+            signal,
+            tx,
+            rx,
+            target);
+
+        update_device_property!(wifi_info, WifiInfo, Some((signal, tx, rx)));
+      }
+      Payload::Device(Device::StateInfo(time, uptime, downtime)) => {
+        info!(
+            target: "device.in",
+            "Received info: {}, {}, {} for {:#X}",
+            time,
+            uptime,
+            downtime,
+            target);
+
+        update_device_property!(info, Info, Some((time, uptime, downtime)));
+      }
+      Payload::Light(Light::StateInfrared(brightness)) => {
+        info!(
+          target: "device.in",
+          "Received infrared brightness: {} for {:#X}",
+          brightness,
+          target);
+
+        update_device_state!(infrared, Infrared, brightness, InfraredChanged);
+      }
+      Payload::Relay(Relay::StateRPower(index, level)) => {
+        info!(
+          target: "device.in",
+          "Received relay {} power: {:?} for {:#X}",
+          index,
+          level,
+          target);
+      }
+      Payload::Button(Button::StateButton(count, index, ref buttons)) => {
+        info!(
+          target: "device.in",
+          "Received buttons {}/{}: '{:?}' for {:#X}",
+          index,
+          count,
+          buttons,
+          target);
+      }
+      Payload::MultiZone(MultiZone::StateZone(count, index, color)) => {
+        info!(
+          target: "device.in",
+          "Received zone {}/{}: '{:?}' for {:#X}",
+          index,
+          count,
+          color,
+          target);
+      }
+      Payload::MultiZone(MultiZone::StateMultiZone(count, index, ref colors)) => {
+        info!(
+          target: "device.in",
+          "Received zones {}-{}/{}: '{:?}' for {:#X}",
+          index,
+          index as usize + colors.len() - 1,
+          count,
+          colors,
+          target);
+      }
+      _ => (),
+    }
+  }
+}
+
+/// checks if a message type is only ever sent by a device: a state message,
+/// an ack or an echo response.
+///
+fn from_device(typ: u16) -> bool {
+  typ == consts::DEVICE_ACKNOWLEDGEMENT
+    || typ == consts::DEVICE_ECHO_RESPONSE
+    || Payload::type_name(typ).is_some_and(|name| name.contains("::State"))
+}
+
+/// what a received frame changed, to report once the registry has published
+/// it.
+///
+#[derive(Default)]
+struct Changes {
+  events: Vec<DeviceEvent>,
+  discovered: Option<Bulb<SocketAddr>>,
+}
+
+/// binds `socket`, which will be bound to `addr`, to the interface `name`.
+///
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_to_interface(
+  socket: &Socket,
+  name: &str,
+  _addr: &SocketAddr,
+) -> io::Result<()> {
+  socket.bind_device(Some(name.as_bytes()))
+}
+
+/// binds `socket`, which will be bound to `addr`, to the interface `name`.
+///
+#[cfg(any(
+  target_os = "ios",
+  target_os = "macos",
+  target_os = "tvos",
+  target_os = "visionos",
+  target_os = "watchos"
+))]
+fn bind_to_interface(
+  socket: &Socket,
+  name: &str,
+  addr: &SocketAddr,
+) -> io::Result<()> {
+  let index = if_addrs::get_if_addrs()?
+    .into_iter()
+    .find(|interface| interface.name == name)
+    .and_then(|interface| interface.index)
+    .and_then(::std::num::NonZeroU32::new)
+    .ok_or_else(|| {
+      io::Error::new(io::ErrorKind::NotFound, "no interface with that name")
+    })?;
+
+  match *addr {
+    SocketAddr::V4(_) => socket.bind_device_by_index_v4(Some(index)),
+    SocketAddr::V6(_) => socket.bind_device_by_index_v6(Some(index)),
+  }
+}
+
+/// binds `socket`, which will be bound to `addr`, to the interface `name`.
+///
+#[cfg(not(any(
+  target_os = "android",
+  target_os = "fuchsia",
+  target_os = "linux",
+  target_os = "ios",
+  target_os = "macos",
+  target_os = "tvos",
+  target_os = "visionos",
+  target_os = "watchos"
+)))]
+fn bind_to_interface(
+  _socket: &Socket,
+  _name: &str,
+  _addr: &SocketAddr,
+) -> io::Result<()> {
+  Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "binding to an interface is not available on this platform",
+  ))
+}
+
+/// returns the directed broadcast address, on the lifx port, of every ipv4
+/// interface other than loopback and point to point links.
+///
+fn interface_broadcasts() -> io::Result<Vec<SocketAddr>> {
+  let mut addrs = Vec::new();
+
+  for interface in if_addrs::get_if_addrs()? {
+    let v4 = match interface.addr {
+      IfAddr::V4(ref v4) if !v4.ip.is_loopback() && v4.prefixlen < 32 => v4,
+      _ => continue,
+    };
+    let ip = v4.broadcast.unwrap_or_else(|| {
+      Ipv4Addr::from(u32::from(v4.ip) | !u32::from(v4.netmask))
+    });
+    let addr = SocketAddr::from((ip, consts::PORT));
+
+    if !addrs.contains(&addr) {
+      addrs.push(addr);
+    }
+  }
+
+  Ok(addrs)
+}
+
+/// sends an echo request to every known device, counting the previous one as
+/// missed if it was not answered. the requests are sent once the listen
+/// thread can update the devices again.
+///
+fn probe_health(
+  devices: &Registry,
+  events: &Subscribers,
+  errors: &Errors,
+) {
+  let mut health = Vec::new();
+  let requests: Vec<(u64, Payload)> = devices
+    .write()
+    .values_mut()
+    .map(|bulb| {
+      if bulb.echo_pending.take().is_some() {
+        bulb.record_echo(false);
+      }
+
+      bulb.echoes_sent += 1;
+      bulb.echo_pending = Some(bulb.echoes_sent);
+      if let Some(changed) = bulb.update_health() {
+        health.push((bulb.target, changed));
+      }
+
+      let mut echo = [0; 64];
+      echo[..8].copy_from_slice(&bulb.echoes_sent.to_le_bytes());
+      let request = Payload::Device(Device::EchoRequest(Array64(echo)));
+      (u64::from(bulb.target), request)
+    })
+    .collect();
+
+  let sent = devices.load();
+  let mut failed = Vec::new();
+  for (target, request) in requests {
+    if let Some(bulb) = sent.get(&target) {
+      if let Err(e) = bulb.send_msg(request, false) {
+        failed.push(target);
+        errors.report(e);
+      }
+    }
+  }
+
+  // an echo that could not be sent is missed at once.
+  if !failed.is_empty() {
+    let mut devices = devices.write();
+    for target in failed {
+      if let Some(bulb) = devices.get_mut(&target) {
+        bulb.echo_pending = None;
+        bulb.record_echo(false);
+        if let Some(changed) = bulb.update_health() {
+          health.push((target.into(), changed));
+        }
+      }
+    }
+  }
+
+  for (target, changed) in health {
+    events.emit(DeviceEvent::HealthChanged(target, changed));
+  }
+}
+
+/// broadcasts a `GetService` to every broadcast address, and sends the queries
+/// chosen by `options` to every known device, waiting the recommended interval
+/// after each one if `pace` is set. fails if any broadcast could not be sent;
+/// queries that could not be sent are reported to `errors`.
+///
+fn discovery_round(
+  endpoint: &Endpoint,
+  devices: &Registry,
+  options: DiscoverOptions,
+  pace: bool,
+  errors: &Errors,
+) -> Result<(), Error> {
+  let query = |d: &Bulb<SocketAddr>, payload| {
+    let sent = match pace {
+      true => d.send_msg_and_wait(payload, false),
+      false => d.send_msg(payload, false),
+    };
+    if let Err(e) = sent {
+      errors.report(e);
+    }
+  };
+
+  let mut sent = Ok(());
+  let mut broadcasts = endpoint.broadcasts.to_vec();
+  if endpoint.every_interface {
+    match interface_broadcasts() {
+      Ok(addrs) => {
+        for addr in addrs {
+          if !broadcasts.contains(&addr) {
+            broadcasts.push(addr);
+          }
+        }
+      }
+      Err(e) => sent = Err(e.into()),
+    }
+  }
+
+  for addr in &broadcasts {
+    let get_service = Payload::Device(Device::GetService);
+    if let Err(e) = endpoint.send_msg(addr, get_service, false, 0) {
+      sent = Err(e);
+    }
+  }
+
+  let queries = options.queries();
+  for d in devices.load().values() {
+    for payload in &queries {
+      query(d, payload.clone());
+    }
+  }
+
+  sent
+}
+
+/// the client handles device messages from from any lifx bulb.
+///
+pub struct Client {
+  closed: Arc<AtomicBool>,
+  strict: Arc<AtomicBool>,
+  accept_foreign: bool,
+  rejected: Arc<AtomicUsize>,
+  raw_hook: Arc<RwLock<Option<RawHook>>>,
+  callbacks: Arc<RwLock<Callbacks>>,
+  feeds: Arc<Mutex<Vec<Feed>>>,
+  errors: Arc<Errors>,
+  events: Arc<Subscribers>,
+  offline_after: Arc<Mutex<Duration>>,
+  evict_after: Arc<Mutex<Option<Duration>>>,
+  router: Arc<Router>,
+  in_flight: Arc<InFlight>,
+  probes: Option<Arc<Mutex<HashMap<u64, Instant>>>>,
+  monitor: Option<Arc<dyn Transport>>,
+  endpoint: Endpoint,
+  devices: Arc<Registry>,
+  aliases: RwLock<HashMap<String, u64>>,
+  task: Mutex<Option<JoinHandle<()>>>,
+  config: Config,
+}
+
+impl Client {
+  /// creates a new client that will read responses from any lifx bulb.
+  ///
+  pub fn new<A: ToSocketAddrs>(addr: A) -> Result<Client, Error> {
+    Client::builder().bind(addr)
+  }
+
+  /// creates a new client that sends and receives through `transport`, rather
+  /// than a udp socket.
+  ///
+  pub fn with_transport<T: Transport + 'static>(transport: T) -> Client {
+    Client::builder().transport(transport)
+  }
+
+  /// returns a builder, to configure a client before creating it.
+  ///
+  #[inline]
+  pub fn builder() -> ClientBuilder {
+    ClientBuilder::default()
+  }
+
+  fn from_builder(
+    builder: ClientBuilder,
+    transport: Arc<dyn Transport>,
+    rebinder: Option<Rebinder>,
+    monitor: Option<Arc<dyn Transport>>,
+  ) -> Client {
+    // broadcast stays on, so discovery never toggles it under other sends.
+    let _ = transport.set_broadcast(true);
+
+    let endpoint = Endpoint {
+      socket: Arc::new(Rebindable {
+        current: RwLock::new(transport),
+        rebinder,
+      }),
+      source: builder.source.unwrap_or_else(random_source),
+      sequences: Arc::new(Sequences::default()),
+      broadcasts: builder.broadcasts.into(),
+      every_interface: builder.every_interface,
+      recv_timeout: builder.read_timeout,
+    };
+    let mut devices = builder
+      .saved
+      .iter()
+      .map(|saved| (saved.target, Bulb::restored(saved, endpoint.clone())))
+      .collect::<HashMap<_, _>>();
+    for &(target, addr) in &builder.devices {
+      devices.insert(target, Bulb::configured(addr, target, endpoint.clone()));
+    }
+    for &(target, ref host) in &builder.hosts {
+      // until the name resolves, only `addr` and the display use this.
+      let ip = resolve(host).unwrap_or_else(|_| {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), consts::PORT)
+      });
+      let mut bulb = Bulb::new(ip, 0, target, endpoint.clone());
+      bulb.host = Some(host.clone());
+      bulb.configured = true;
+      devices.insert(target, bulb);
+    }
+
+    Client {
+      closed: Arc::new(AtomicBool::new(false)),
+      strict: Arc::new(AtomicBool::new(true)),
+      accept_foreign: builder.accept_foreign,
+      rejected: Arc::new(AtomicUsize::new(0)),
+      raw_hook: Arc::new(RwLock::new(None)),
+      callbacks: Arc::new(RwLock::new(Callbacks::default())),
+      feeds: Arc::new(Mutex::new(Vec::new())),
+      errors: Arc::new(Errors::default()),
+      events: Arc::new(Subscribers::default()),
+      offline_after: Arc::new(Mutex::new(OFFLINE_AFTER)),
+      evict_after: Arc::new(Mutex::new(None)),
+      router: Arc::new(Router::new()),
+      in_flight: Arc::new(InFlight::new()),
+      probes: match builder.passive_discovery {
+        true => Some(Arc::new(Mutex::new(HashMap::new()))),
+        false => None,
+      },
+      monitor,
+      endpoint,
+      devices: Arc::new(Registry::new(devices)),
+      aliases: RwLock::new(builder.aliases.into_iter().collect()),
+      task: Mutex::new(None),
+      config: builder.config,
+    }
+  }
+
+  fn listener(&self) -> Listener {
+    Listener {
+      endpoint: self.endpoint.clone(),
+      closed: self.closed.clone(),
+      strict: self.strict.clone(),
+      accept_foreign: self.accept_foreign,
+      rejected: self.rejected.clone(),
+      raw_hook: self.raw_hook.clone(),
+      callbacks: self.callbacks.clone(),
+      feeds: self.feeds.clone(),
+      errors: self.errors.clone(),
+      events: self.events.clone(),
+      offline_after: self.offline_after.clone(),
+      evict_after: self.evict_after.clone(),
+      router: self.router.clone(),
+      in_flight: self.in_flight.clone(),
+      probes: self.probes.clone(),
+      monitor: self.monitor.clone(),
+      devices: self.devices.clone(),
+    }
+  }
+
+  /// listens for certain messages, and updates the client object accordingly
+  ///
+  /// # Notes
+  ///
+  ///   * While the client is strict (the default), frames whose header fails
+  ///     `Header::validate` are dropped. Dropped frames, and datagrams that do
+  ///     not decode, are counted by `rejected_frames`.
+  ///   * Frames that carry another controller's source are ignored, unless
+  ///     the client was built with `ClientBuilder::accept_foreign`. So are the
+  ///     client's own broadcasts, when they loop back.
+  ///
+  pub fn listen(&self) -> JoinHandle<()> {
+    let listener = self.listener();
+
+    thread::spawn(move || {
+      let mut buf = [0; consts::MAX_FRAME_SIZE];
+      let mut failures = 0;
+      let monitor = listener.spawn_monitor();
+
+      while !listener.closed.load(Ordering::SeqCst) {
+        listener.sweep();
+
+        let socket = &listener.endpoint.socket;
+        let (amt, src) = match socket.recv_from(&mut buf[..]) {
+          Ok(received) => received,
+          Err(ref e) if timed_out(e) => continue,
+          Err(e) => {
+            listener.recv_failed(&mut failures, e.into());
+            continue;
+          }
+        };
+        failures = 0;
+
+        // `close` wakes the thread with an empty datagram.
+        if listener.closed.load(Ordering::SeqCst) {
+          break;
+        }
+
+        listener.dispatch(&buf[..amt], src, listener.accept_foreign);
+      }
+
+      if let Some(monitor) = monitor {
+        let _ = monitor.join();
+      }
+      listener.events.close();
+    })
+  }
+
+  /// broadcasts messages to the client every `wait` milliseconds, backing
+  /// off while no new device is found, as `Client::config` asks. use `listen`
+  /// to have the client process certain messages.
+  ///
+  pub fn discover(&self, wait: u64, options: DiscoverOptions) -> JoinHandle<()> {
+    let endpoint = self.endpoint.clone();
+    let closed = self.closed.clone();
+    let devices = self.devices.clone();
+    let errors = self.errors.clone();
+    let every = Duration::from_millis(wait);
+    let mut schedule = Schedule::new(every, options, self.config);
+
+    thread::spawn(move || {
+      while !closed.load(Ordering::SeqCst) {
+        let known = devices.load().len();
+        let (options, wait) = schedule.round(known);
+        let sent = discovery_round(&endpoint, &devices, options, true, &errors);
+        if let Err(e) = sent {
+          errors.report(e);
+        }
+
+        // sleeps a little at a time, so a long backoff doesn't hold up close.
+        let next_round = Instant::now() + wait;
+        while !closed.load(Ordering::SeqCst) {
+          let now = Instant::now();
+          if now >= next_round {
+            break;
+          }
+          thread::sleep((next_round - now).min(endpoint.recv_timeout));
+        }
+      }
+    })
+  }
+
+  /// starts a background task that listens, and discovers devices as `config`
+  /// asks, on a single thread. the task is stopped by `stop`, or when the
+  /// client is dropped. returns false, and does nothing, if the task was
+  /// already started.
+  ///
+  /// # Notes
+  ///
+  ///   * This takes the place of `listen` and `discover`. Don't use them on a
+  ///     client that was started.
+  ///   * Discovery doesn't wait between messages, so that receiving is never
+  ///     held up.
+  ///
+  pub fn start(&self, config: Config) -> bool {
+    let mut task = self.task.lock().unwrap();
+    if task.is_some() {
+      return false;
+    }
+
+    let listener = self.listener();
+    let mut schedule = config.discover_every.map(|every| {
+      Schedule::new(every, config.discover_options, config)
+    });
+
+    *task = Some(thread::spawn(move || {
+      let mut buf = [0; consts::MAX_FRAME_SIZE];
+      let mut next_round = Instant::now();
+      let mut next_probe = Instant::now();
+      let mut failures = 0;
+      let monitor = listener.spawn_monitor();
+
+      while !listener.closed.load(Ordering::SeqCst) {
+        let mut timeout = listener.endpoint.recv_timeout;
+
+        if let Some(ref mut schedule) = schedule {
+          let now = Instant::now();
+          if now >= next_round {
+            let known = listener.devices.load().len();
+            let (options, wait) = schedule.round(known);
+            let sent = discovery_round(
+              &listener.endpoint,
+              &listener.devices,
+              options,
+              false,
+              &listener.errors,
+            );
+            if let Err(e) = sent {
+              listener.errors.report(e);
+            }
+            next_round = now + wait;
+          }
+          timeout = timeout.min(next_round - now);
+        }
+
+        if let Some(every) = config.probe_every {
+          let now = Instant::now();
+          if now >= next_probe {
+            let Listener {
+              ref devices,
+              ref events,
+              ref errors,
+              ..
+            } = listener;
+            probe_health(devices, events, errors);
+            next_probe = now + every;
+          }
+          timeout = timeout.min(next_probe - now);
+        }
+
+        match listener.poll(&mut buf, timeout) {
+          Ok(_) => failures = 0,
+          Err(e) => listener.recv_failed(&mut failures, e),
+        }
+      }
+
+      if let Some(monitor) = monitor {
+        let _ = monitor.join();
+      }
+      listener.events.close();
+    }));
+    true
+  }
+
+  /// closes the client, and waits for the task started by `start` to finish.
+  ///
+  pub fn stop(&self) {
+    self.close();
+
+    if let Some(task) = self.task.lock().unwrap().take() {
+      // the task can't wait for itself, if it drops the last client.
+      if task.thread().id() != thread::current().id() {
+        let _ = task.join();
+      }
+    }
+  }
+
+  /// replaces the client's socket with a new one bound to the same address,
+  /// and broadcasts for devices, so the ones that are still there are seen
+  /// again. the background threads do this on their own once `REBIND_AFTER`
+  /// receives in a row have failed, such as after the network went down or the
+  /// host was suspended. fails for a client created with `transport`, which
+  /// has no way to open another transport.
+  ///
+  pub fn rebind(&self) -> Result<(), Error> {
+    self.listener().rebind()
+  }
+
+  /// receives and handles at most one datagram on the calling thread, waiting
+  /// up to `timeout` for it. returns whether a datagram was handled.
+  ///
+  /// # Notes
+  ///
+  ///   * This is for applications with their own event loop, and drives the
+  ///     client in place of `listen`. Don't use both on the same client.
+  ///   * A `Transport` that doesn't override `set_read_timeout` waits for as
+  ///     long as its `recv_from` does.
+  ///
+  pub fn poll_once(&self, timeout: Duration) -> Result<bool, Error> {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    self.listener().poll(&mut buf, timeout)
+  }
+
+  /// calls `poll_once` until `done` returns true, or the client is closed.
+  /// `done` is called after every poll, including ones that time out.
+  ///
+  pub fn run_until<F>(&self, mut done: F) -> Result<(), Error>
+  where
+    F: FnMut(&Client) -> bool,
+  {
+    while !self.is_closed() {
+      self.poll_once(self.endpoint.recv_timeout)?;
+      if done(self) {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// runs one round of discovery on the calling thread: broadcasts a
+  /// `GetService`, and sends the queries chosen by `options` to every known
+  /// device. unlike `discover`, this does not wait between messages. fails if
+  /// any of the broadcasts could not be sent, once they have all been tried.
+  ///
+  pub fn tick_discovery(&self, options: DiscoverOptions) -> Result<(), Error> {
+    discovery_round(&self.endpoint, &self.devices, options, false, &self.errors)
+  }
+
+  /// broadcasts for devices, and returns the ones that reply within `window`,
+  /// in the order they replied.
+  ///
+  /// # Notes
+  ///
+  ///   * Like `poll_once`, this receives on the calling thread, so it needs no
+  ///     `listen` or `discover`. Don't use it while they, or `start`, are
+  ///     running.
+  ///
+  pub fn discover_once(
+    &self,
+    window: Duration,
+  ) -> Result<Vec<Bulb<SocketAddr>>, Error> {
+    let incoming = self.incoming();
+    let deadline = Instant::now().checked_add(window);
+    let mut targets = Vec::new();
+
+    self.tick_discovery(DiscoverOptions::empty())?;
+
+    loop {
+      let left = time_left(deadline);
+      if left.is_zero() {
+        break;
+      }
+      self.poll_once(left)?;
+
+      for (_, msg) in incoming.try_iter() {
+        let replied = match *msg.payload() {
+          Payload::Device(Device::StateService(..)) => true,
+          _ => false,
+        };
+        if replied && !targets.contains(&msg.target()) {
+          targets.push(msg.target());
+        }
+      }
+    }
+
+    Ok(
+      targets
+        .iter()
+        .filter_map(|&target| self.devices.get(target.into()))
+        .collect(),
+    )
+  }
+
+  /// sends a message to the specified address. `target` is the mac address of
+  /// the device, either as a `MacAddress` or as the u64 used on the wire.
+  ///
+  pub fn send_msg<A: ToSocketAddrs, T: Into<MacAddress>>(
+    &self,
+    addr: A,
+    payload: Payload,
+    ack_required: bool,
+    target: T,
+  ) -> Result<u8, Error> {
+    let options = SendOptions::for_payload(&payload, ack_required);
+    self.send_msg_with(addr, payload, options, target)
+  }
+
+  /// sends a message to the specified address, choosing whether it should be
+  /// acked and responded to.
+  ///
+  pub fn send_msg_with<A: ToSocketAddrs, T: Into<MacAddress>>(
+    &self,
+    addr: A,
+    payload: Payload,
+    options: SendOptions,
+    target: T,
+  ) -> Result<u8, Error> {
+    let target: u64 = target.into().into();
+
+    if !options.contains(SendOptions::ACK_REQUIRED) {
+      return self.endpoint.send_msg_with(addr, payload, options, target);
+    }
+
+    let seq = self.endpoint.sequences.reserve(target);
+    self.in_flight.insert(target, seq, false);
+
+    let res = self.endpoint.send_msg_seq(addr, payload, options, target, seq);
+    if res.is_err() {
+      self.in_flight.remove(target, seq);
+      self.endpoint.sequences.release(target, seq);
+    }
+
+    res
+  }
+
+  /// waits up to `timeout` for the device to acknowledge the message sent to
+  /// `target` with sequence number `seq`. only messages sent with
+  /// ack_required through this client (not through a `Bulb`) can be waited
+  /// on, and only once. until then, their sequence number is not reused for
+  /// the target, unless 30 seconds pass without anything waiting on it.
+  /// `listen` must be running.
+  ///
+  pub fn await_ack<T: Into<MacAddress>>(
+    &self,
+    target: T,
+    seq: u8,
+    timeout: Duration,
+  ) -> Result<(), Error> {
+    let target: u64 = target.into().into();
+    let res = self.in_flight.wait(target, seq, timeout);
+    match res {
+      Err(Error::UnknownSequence(_)) => (),
+      _ => self.endpoint.sequences.release(target, seq),
+    }
+
+    res
+  }
+
+  /// sends a message with ack_required, and retransmits it with the same
+  /// sequence number until the device acks it, following `backoff`. returns
+  /// the sequence number once the message is acked. `listen` must be running.
+  ///
+  pub fn send_reliable<A: ToSocketAddrs, T: Into<MacAddress>>(
+    &self,
+    addr: A,
+    payload: Payload,
+    target: T,
+    backoff: Backoff,
+  ) -> Result<u8, Error> {
+    let target: u64 = target.into().into();
+    let addr = resolve(addr)?;
+    let options = SendOptions::for_payload(&payload, true);
+    let seq = self.endpoint.sequences.reserve(target);
+    let msg = Message::new_with(payload, options, target, seq)
+      .with_source(self.endpoint.source);
+    let start = Instant::now();
+    let mut wait = backoff.initial;
+    let mut attempts = 0;
+    let bytes = match serialize::encode(&msg) {
+      Ok(bytes) => bytes,
+      Err(e) => {
+        self.endpoint.sequences.release(target, seq);
+        return Err(e);
+      }
+    };
+
+    self.in_flight.insert(target, seq, true);
+
+    let res = loop {
+      if attempts == backoff.attempts {
+        break Err(Error::Unacknowledged { seq, attempts });
+      }
+
+      match self.endpoint.socket.send_to(&bytes[..], addr) {
+        Ok(n) if n == bytes.len() => (),
+        Ok(n) => {
+          break Err(Error::ShortWrite {
+            expected: bytes.len(),
+            actual: n,
+          })
+        }
+        Err(e) => break Err(e.into()),
+      }
+      attempts += 1;
+
+      debug!(
+        target: "device.out",
+        "    Sending (attempt {}): {:?}",
+        attempts,
+        msg);
+
+      let left = backoff.deadline.saturating_sub(start.elapsed());
+      match self.in_flight.wait_for(target, seq, wait.min(left)) {
+        Ok(true) => break Ok(seq),
+        Ok(false) if left <= wait => {
+          break Err(Error::Unacknowledged { seq, attempts })
+        }
+        Ok(false) => wait = (wait * 2).min(backoff.max),
+        Err(e) => break Err(e),
+      }
+    };
+    self.in_flight.remove(target, seq);
+    self.endpoint.sequences.release(target, seq);
+
+    res
+  }
+
+  /// sends bytes to the specified address as they are, without building a
+  /// header. returns the number of bytes sent.
+  ///
+  pub fn send_raw<A: ToSocketAddrs>(
+    &self,
+    addr: A,
+    bytes: &[u8],
+  ) -> Result<usize, Error> {
+    let sent = self.endpoint.socket.send_to(bytes, resolve(addr)?)?;
+
+    debug!(target: "device.out", "    Sending {} raw bytes", sent);
+
+    Ok(sent)
+  }
+
+  /// sets a callback that is handed every datagram received by `listen`,
+  /// before it is decoded. replaces any callback that was set before.
+  ///
+  pub fn set_raw_hook<F>(&self, hook: F)
+  where
+    F: Fn(&[u8], SocketAddr) + Send + Sync + 'static,
+  {
+    *self.raw_hook.write().unwrap() = Some(Box::new(hook));
+  }
+
+  /// removes the callback set by `set_raw_hook`.
+  ///
+  pub fn clear_raw_hook(&self) {
+    *self.raw_hook.write().unwrap() = None;
+  }
+
+  /// returns a channel of every frame `listen` decodes, along with the address
+  /// it came from. every call returns a new channel, which stops being fed
+  /// once its receiver is dropped.
+  ///
+  pub fn incoming(&self) -> Receiver<(SocketAddr, Message)> {
+    let (tx, rx) = mpsc::channel();
+    self.feeds.lock().unwrap().push(tx);
+    rx
+  }
+
+  /// returns a channel of the errors the background threads run into: sends
+  /// that failed during discovery, frames that were rejected, and socket
+  /// errors other than timeouts. every call returns a new channel, which stops
+  /// being fed once its receiver is dropped.
+  ///
+  pub fn errors(&self) -> Receiver<Error> {
+    let (tx, rx) = mpsc::channel();
+    self.errors.0.lock().unwrap().push(tx);
+    rx
+  }
+
+  /// returns the changes `listen` makes to the devices the client has found.
+  /// every call returns a new `DeviceEvents`, which sees the events from then
+  /// on.
+  ///
+  pub fn events(&self) -> DeviceEvents {
+    self.events.subscribe()
+  }
+
+  /// watches the state of one device, such as to drive a widget for it. the
+  /// device doesn't need to have been found yet. an alias that no device has
+  /// fails with `Error::UnknownAlias`.
+  ///
+  pub fn subscribe_state<T: Into<DeviceId>>(
+    &self,
+    target: T,
+  ) -> Result<StateWatch, Error> {
+    Ok(StateWatch {
+      target: self.resolve(target)?,
+      seen: None,
+      events: self.events.subscribe(),
+      devices: self.devices.clone(),
+    })
+  }
+
+  /// returns the devices the client has found, followed by each device
+  /// `listen` finds from then on, as soon as it is found.
+  ///
+  pub fn discover_stream(&self) -> DiscoveredBulbs {
+    let events = self.events.subscribe();
+    let known: VecDeque<_> = self.devices.cloned().into();
+
+    DiscoveredBulbs {
+      seen: known.iter().map(|bulb| bulb.target).collect(),
+      known,
+      events,
+      devices: self.devices.clone(),
+    }
+  }
+
+  /// blocks until the client has found the device with this mac address or
+  /// alias, and returns it. fails with `Error::Timeout` if it is not found within
+  /// `timeout`.
+  ///
+  /// # Notes
+  ///
+  ///   * Devices are found by `listen`, so it should be running, along with
+  ///     something that broadcasts for devices, like `discover` or `start`.
+  ///     If the listen thread stops, this fails with `Error::NotDiscovered`.
+  ///   * A `timeout` too long to represent, like `Duration::MAX`, never
+  ///     expires.
+  ///
+  pub fn wait_for_device<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<Bulb<SocketAddr>, Error> {
+    self.wait_for_device_async(target, timeout).wait()
+  }
+
+  /// like `wait_for_device`, but returns a future rather than blocking.
+  ///
+  pub fn wait_for_device_async<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> WaitForDevice {
+    WaitForDevice {
+      target: self.resolve(target),
+      deadline: Instant::now().checked_add(timeout),
+      events: self.events.subscribe(),
+      devices: self.devices.clone(),
+      alarm: Alarm::default(),
+    }
+  }
+
+  /// blocks until the client has found at least `min_count` devices, and
+  /// returns every device it has found. fails with `Error::Timeout` if it
+  /// has not found that many within `timeout`. like `wait_for_device`, this
+  /// needs `listen` to be running, and a `timeout` too long to represent
+  /// never expires.
+  ///
+  pub fn wait_for_devices(
+    &self,
+    min_count: usize,
+    timeout: Duration,
+  ) -> Result<Vec<Bulb<SocketAddr>>, Error> {
+    self.wait_for_devices_async(min_count, timeout).wait()
+  }
+
+  /// blocks until discovery has settled, meaning no new device has been found
+  /// for `quiet`, and returns every device the client has found. fails with
+  /// `Error::Timeout` if devices are still being found after `timeout`.
+  ///
+  pub fn wait_until_settled(
+    &self,
+    quiet: Duration,
+    timeout: Duration,
+  ) -> Result<Vec<Bulb<SocketAddr>>, Error> {
+    self.wait_for_devices_async(0, timeout).settled(quiet).wait()
+  }
+
+  /// like `wait_for_devices`, but returns a future rather than blocking. call
+  /// `settled` on the future to also wait for discovery to settle.
+  ///
+  pub fn wait_for_devices_async(
+    &self,
+    min_count: usize,
+    timeout: Duration,
+  ) -> WaitForDevices {
+    let now = Instant::now();
+    WaitForDevices {
+      min_count,
+      quiet: None,
+      deadline: now.checked_add(timeout),
+      last_found: now,
+      events: self.events.subscribe(),
+      devices: self.devices.clone(),
+      alarm: Alarm::default(),
+    }
+  }
+
+  /// sets how long a device can go unheard before `listen` marks it offline,
+  /// and emits `DeviceEvent::WentOffline`. devices only answer when asked, so
+  /// this should be longer than the interval passed to `discover`.
+  ///
+  pub fn set_offline_after(&self, after: Duration) {
+    *self.offline_after.lock().unwrap() = after;
+  }
+
+  /// sends an echo request to every device. a device that misses any of the
+  /// latest few is degraded, as `Bulb::health` returns, until it answers
+  /// enough in a row. changes of health are emitted as
+  /// `DeviceEvent::HealthChanged`. `start` does this every
+  /// `Config::probe_every`; otherwise call it periodically, with `listen`
+  /// running.
+  ///
+  pub fn probe_health(&self) {
+    probe_health(&self.devices, &self.events, &self.errors);
+  }
+
+  /// sets how long a device can go unheard before `listen` forgets it, and
+  /// emits `DeviceEvent::Evicted`, or `None` to keep every device, which is
+  /// the default. devices added with `ClientBuilder::with_device` or
+  /// `with_device_host` are never forgotten.
+  ///
+  pub fn set_evict_after(&self, after: Option<Duration>) {
+    *self.evict_after.lock().unwrap() = after;
+  }
+
+  /// registers a callback that `listen` invokes with every `State*` message it
+  /// receives, along with the target of the device that sent it.
+  ///
+  /// # Notes
+  ///
+  ///   * Callbacks run on the listen thread, and must not register or clear
+  ///     callbacks themselves.
+  ///
+  pub fn on_state_change<F>(&self, f: F)
+  where
+    F: Fn(MacAddress, &Payload) + Send + Sync + 'static,
+  {
+    self.callbacks.write().unwrap().state_change.push(Box::new(f));
+  }
+
+  /// registers a callback that `listen` invokes the first time it hears from
+  /// a device.
+  ///
+  pub fn on_device_discovered<F>(&self, f: F)
+  where
+    F: Fn(&Bulb<SocketAddr>) + Send + Sync + 'static,
+  {
+    self.callbacks.write().unwrap().device_discovered.push(Box::new(f));
+  }
+
+  /// sets a closure that `listen` calls the first time it hears from a
+  /// device, to choose the queries sent to just that device straight away.
+  /// with this, `discover` can broadcast with `DiscoverOptions::empty()`
+  /// rather than query every device on every round. replaces any closure set
+  /// before.
+  ///
+  pub fn set_enrichment<F>(&self, f: F)
+  where
+    F: Fn(&Bulb<SocketAddr>) -> DiscoverOptions + Send + Sync + 'static,
+  {
+    self.callbacks.write().unwrap().enrichment = Some(Box::new(f));
+  }
+
+  /// registers a callback that `listen` invokes with the target and sequence
+  /// number of every acknowledgement it receives.
+  ///
+  pub fn on_ack<F>(&self, f: F)
+  where
+    F: Fn(MacAddress, u8) + Send + Sync + 'static,
+  {
+    self.callbacks.write().unwrap().ack.push(Box::new(f));
+  }
+
+  /// registers a callback that `listen` invokes with the target, message type
+  /// and payload bytes of every message type this crate does not understand.
+  ///
+  pub fn on_unknown_message<F>(&self, f: F)
+  where
+    F: Fn(MacAddress, u16, &[u8]) + Send + Sync + 'static,
+  {
+    self.callbacks.write().unwrap().unknown_message.push(Box::new(f));
+  }
+
+  /// registers a callback that `listen` invokes with the sender, bytes and
+  /// error of every frame it rejects, as counted by `rejected_frames`. a
+  /// datagram that stops decoding part way is handed over from that point on.
+  ///
+  pub fn on_rejected_frame<F>(&self, f: F)
+  where
+    F: Fn(SocketAddr, &[u8], &DecodeError) + Send + Sync + 'static,
+  {
+    self.callbacks.write().unwrap().rejected_frame.push(Box::new(f));
+  }
+
+  /// removes every callback registered with the `on_*` methods, and the
+  /// closure set with `set_enrichment`.
+  ///
+  pub fn clear_callbacks(&self) {
+    *self.callbacks.write().unwrap() = Callbacks::default();
+  }
+
+  /// sends a request to a device the client has found, and waits up to
+  /// `timeout` for the response, which is matched to the request by its
+  /// source, sequence number and target. `listen` must be running.
+  ///
+  pub fn request<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    payload: Payload,
+    timeout: Duration,
+  ) -> Result<Payload, Error> {
+    let target = self.resolve(target)?;
+    let addr = match self.device(target) {
+      Some(bulb) => bulb.addr(),
+      None => return Err(Error::NotDiscovered(target.into())),
+    };
+    let responses =
+      self.send_routed(addr, payload, SendOptions::RES_REQUIRED, target)?;
+
+    responses.recv_timeout(timeout).map(|(_, payload)| payload)
+  }
+
+  /// sends a message to the specified address, and returns a channel of the
+  /// responses to it. a response is matched to the message by its source,
+  /// sequence number and target, so responses to other messages never arrive
+  /// on the channel. `listen` must be running.
+  ///
+  pub fn send_routed<A: ToSocketAddrs, T: Into<MacAddress>>(
+    &self,
+    addr: A,
+    payload: Payload,
+    options: SendOptions,
+    target: T,
+  ) -> Result<Responses, Error> {
+    let target: u64 = target.into().into();
+    let seq = self.endpoint.sequences.reserve(target);
+    let key = (self.endpoint.source, seq, target);
+    let responses = Responses {
+      key,
+      router: self.router.clone(),
+      sequences: self.endpoint.sequences.clone(),
+      rx: self.router.insert(key),
+    };
+
+    self.endpoint.send_msg_seq(addr, payload, options, target, seq)?;
+
+    Ok(responses)
+  }
+
+  /// asks a device again for everything `Bulb::refresh` does, and blocks
+  /// until it has answered every request, or `timeout` has passed. returns
+  /// the device as the client then knows it. `listen` must be running.
+  ///
+  pub fn refresh<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<Bulb<SocketAddr>, Error> {
+    let deadline = Instant::now().checked_add(timeout);
+    let target = self.resolve(target)?;
+    let addr = match self.device(target) {
+      Some(bulb) => bulb.addr(),
+      None => return Err(Error::NotDiscovered(target.into())),
+    };
+
+    let mut pending = Vec::new();
+    for query in refresh_queries() {
+      if !pending.is_empty() {
+        thread::sleep(Duration::from_millis(MESSAGE_INTERVAL as u64));
+      }
+      let options = SendOptions::RES_REQUIRED;
+      pending.push(self.send_routed(addr, query, options, target)?);
+    }
+    for responses in pending {
+      responses.recv_timeout(time_left(deadline))?;
+    }
+
+    self.device(target).ok_or_else(|| Error::NotDiscovered(target.into()))
+  }
+
+  /// requests the label of a device.
+  ///
+  pub fn get_label<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<String, Error> {
+    match self.request(target, Payload::Device(Device::GetLabel), timeout)? {
+      Payload::Device(Device::StateLabel(label)) => Ok(label),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// returns a property of a device as the client last received it, if that
+  /// was within `max_age`.
+  ///
+  fn cached<R, F>(
+    &self,
+    target: u64,
+    property: Property,
+    max_age: Duration,
+    read: F,
+  ) -> Option<R>
+  where
+    F: Fn(&Bulb<SocketAddr>) -> Option<R>,
+  {
+    let devices = self.devices.load();
+    let bulb = devices.get(&target)?;
+    match bulb.updated(property) {
+      Some(at) if at.elapsed() <= max_age => read(bulb),
+      _ => None,
+    }
+  }
+
+  /// returns the label of a device from the client's cache if it was received
+  /// within `max_age`, and otherwise requests it, as `get_label` does.
+  ///
+  pub fn label_cached_or_fetch<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    max_age: Duration,
+    timeout: Duration,
+  ) -> Result<String, Error> {
+    let target = self.resolve(target)?;
+    let label = |bulb: &Bulb<SocketAddr>| bulb.label().map(String::from);
+    match self.cached(target, Property::Label, max_age, label) {
+      Some(label) => Ok(label),
+      None => self.get_label(target, timeout),
+    }
+  }
+
+  /// returns the power level of a device from the client's cache if it was
+  /// received within `max_age`, and otherwise requests it.
+  ///
+  pub fn power_cached_or_fetch<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    max_age: Duration,
+    timeout: Duration,
+  ) -> Result<Power, Error> {
+    let target = self.resolve(target)?;
+    match self.cached(target, Property::Power, max_age, Bulb::power) {
+      Some(power) => Ok(power),
+      None => self.get_power(target, timeout),
+    }
+  }
+
+  /// turns a device off if it is on, and on if it is off, over `duration` if
+  /// one is given, and waits up to `timeout` for it to acknowledge. its power
+  /// is read as `power_cached_or_fetch` reads it. returns the power level it
+  /// was set to, which the client's copy of the device is updated to.
+  ///
+  pub fn toggle<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    duration: Option<Duration>,
+    max_age: Duration,
+    timeout: Duration,
+  ) -> Result<Power, Error> {
+    let deadline = Instant::now().checked_add(timeout);
+    let target = self.resolve(target)?;
+    let power = self.power_cached_or_fetch(target, max_age, timeout)?;
+    let addr = match self.device(target) {
+      Some(bulb) => bulb.addr(),
+      None => return Err(Error::NotDiscovered(target.into())),
+    };
+
+    // a response to a set message carries the power from before it, so only
+    // an ack is asked for, and the new level is cached once it arrives.
+    let (level, payload) = toggled(Some(power), duration);
+    let seq = self.send_msg(addr, payload, true, target)?;
+    self.await_ack(target, seq, time_left(deadline))?;
+
+    let state = Payload::Device(Device::StatePower(level));
+    self.listener().update_cache(target, addr, &PayloadRef::Owned(state));
+    Ok(level)
+  }
+
+  /// changes only the hue of a device, over `duration`, as `Bulb::set_hue_only`
+  /// does, and waits up to `timeout` for it to acknowledge. when `SetColor`
+  /// is needed, the device is asked for its current color first, so no
+  /// change made since the client last heard from it is undone. the hue of
+  /// the client's copy of the color is updated once the device acks.
+  ///
+  pub fn set_hue_only<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    hue: Hue,
+    duration: Duration,
+    timeout: Duration,
+  ) -> Result<(), Error> {
+    let hue = Component::Hue(hue);
+    self.set_component(target, hue, duration, timeout)
+  }
+
+  /// changes only the brightness of a device, as `set_hue_only` changes the
+  /// hue.
+  ///
+  pub fn set_brightness_only<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    brightness: Brightness,
+    duration: Duration,
+    timeout: Duration,
+  ) -> Result<(), Error> {
+    let brightness = Component::Brightness(brightness);
+    self.set_component(target, brightness, duration, timeout)
+  }
+
+  /// changes only the temperature of a device, as `set_hue_only` changes the
+  /// hue.
+  ///
+  pub fn set_kelvin_only<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    kelvin: Kelvin,
+    duration: Duration,
+    timeout: Duration,
+  ) -> Result<(), Error> {
+    let kelvin = Component::Kelvin(kelvin);
+    self.set_component(target, kelvin, duration, timeout)
+  }
+
+  /// sends a device the message `Component::payload` chooses, and once it
+  /// acks, applies the component to the client's copy of its color.
+  ///
+  fn set_component<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    component: Component,
+    duration: Duration,
+    timeout: Duration,
+  ) -> Result<(), Error> {
+    let deadline = Instant::now().checked_add(timeout);
+    let target = self.resolve(target)?;
+    let bulb = self.device(target).ok_or(Error::NotDiscovered(target.into()))?;
+    let waveform = bulb.handles(consts::LIGHT_SET_WAVEFORM_OPTIONAL);
+    let color = match waveform {
+      true => bulb.color(),
+      false => Some(self.get_color(target, timeout)?),
+    };
+    let payload = match component.payload(waveform, color, duration) {
+      Some(payload) => payload,
+      None => return Err(Error::NotReceived { property: "color" }),
+    };
+
+    let seq = self.send_msg(bulb.addr(), payload, true, target)?;
+    self.await_ack(target, seq, time_left(deadline))?;
+
+    // the rest of the color is left as the client last heard it, which
+    // `get_color` has just refreshed when it was needed.
+    let color = {
+      let mut devices = self.devices.write();
+      match devices.get(&target).and_then(|bulb| bulb.color()) {
+        Some(color) if component.apply(color) != color => {
+          let bulb = devices.get_mut(&target).unwrap();
+          bulb.color = Some(component.apply(color));
+          bulb.color
+        }
+        _ => None,
+      }
+    };
+    if let Some(color) = color {
+      self.events.emit(DeviceEvent::ColorChanged(target.into(), color));
+    }
+    Ok(())
+  }
+
+  /// returns the color of a device from the client's cache if it was received
+  /// within `max_age`, and otherwise requests it.
+  ///
+  pub fn color_cached_or_fetch<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    max_age: Duration,
+    timeout: Duration,
+  ) -> Result<HSBK, Error> {
+    let target = self.resolve(target)?;
+    match self.cached(target, Property::Color, max_age, Bulb::color) {
+      Some(color) => Ok(color),
+      None => self.get_color(target, timeout),
+    }
+  }
+
+  /// requests the power level of a device.
+  ///
+  pub fn get_power<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<Power, Error> {
+    match self.request(target, Payload::Device(Device::GetPower), timeout)? {
+      Payload::Device(Device::StatePower(level)) => Ok(level),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// requests the color of a light.
+  ///
+  pub fn get_color<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<HSBK, Error> {
+    match self.request(target, Payload::Light(Light::Get), timeout)? {
+      Payload::Light(Light::State(color, _, _)) => Ok(color),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// requests the (vendor, product, version) of a device.
+  ///
+  pub fn get_version<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<(u32, u32, u32), Error> {
+    match self.request(target, Payload::Device(Device::GetVersion), timeout)? {
+      Payload::Device(Device::StateVersion(vendor, product, version)) => {
+        Ok((vendor, product, version))
+      }
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// requests the host firmware version of a device.
+  ///
+  pub fn get_host_firmware<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<FirmwareVersion, Error> {
+    let payload = Payload::Device(Device::GetHostFirmware);
+    match self.request(target, payload, timeout)? {
+      Payload::Device(Device::StateHostFirmware(firmware)) => Ok(firmware),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// requests the wifi firmware version of a device.
+  ///
+  pub fn get_wifi_firmware<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<FirmwareVersion, Error> {
+    let payload = Payload::Device(Device::GetWifiFirmware);
+    match self.request(target, payload, timeout)? {
+      Payload::Device(Device::StateWifiFirmware(firmware)) => Ok(firmware),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// requests the location label of a device.
+  ///
+  pub fn get_location<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<String, Error> {
+    match self.request(target, Payload::Device(Device::GetLocation), timeout)? {
+      Payload::Device(Device::StateLocation(_, label, _)) => Ok(label),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// requests the group label of a device.
+  ///
+  pub fn get_group<T: Into<DeviceId>>(
+    &self,
+    target: T,
+    timeout: Duration,
+  ) -> Result<String, Error> {
+    match self.request(target, Payload::Device(Device::GetGroup), timeout)? {
+      Payload::Device(Device::StateGroup(_, label, _)) => Ok(label),
+      other => Err(Error::UnexpectedResponse(Box::new(other))),
+    }
+  }
+
+  /// returns a snapshot of the devices that the client has found.
+  ///
+  pub fn devices(&self) -> HashMap<MacAddress, Bulb<SocketAddr>> {
+    let devices = self.devices.load();
+    devices.values().map(|bulb| (bulb.target, Bulb::clone(bulb))).collect()
+  }
+
+  /// returns the devices in the group with this name, ordered by mac
+  /// address. a device's group is known once it has answered `GetGroup`, or
+  /// was restored with it.
+  ///
+  pub fn devices_in_group(&self, group: &str) -> Vec<Bulb<SocketAddr>> {
+    self.devices_where(|bulb| bulb.group() == Some(group))
+  }
+
+  /// returns the devices in the location with this name, ordered by mac
+  /// address, like `devices_in_group`.
+  ///
+  pub fn devices_in_location(&self, location: &str) -> Vec<Bulb<SocketAddr>> {
+    self.devices_where(|bulb| bulb.location() == Some(location))
+  }
+
+  /// returns the devices whose label matches a glob pattern, ordered by mac
+  /// address. `*` matches any run of characters and `?` any one character,
+  /// so `"Kitchen*"` finds "Kitchen" and "Kitchen Left". devices without a
+  /// label never match.
+  ///
+  pub fn devices_matching(&self, pattern: &str) -> Vec<Bulb<SocketAddr>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    self.devices_where(|bulb| match bulb.label() {
+      Some(label) => glob_match(&pattern, &label.chars().collect::<Vec<_>>()),
+      None => false,
+    })
+  }
+
+  fn devices_where<F>(&self, f: F) -> Vec<Bulb<SocketAddr>>
+  where
+    F: Fn(&Bulb<SocketAddr>) -> bool,
+  {
+    let devices = self.devices.load();
+    let mut found: Vec<_> = devices
+      .values()
+      .filter(|bulb| f(bulb))
+      .map(|bulb| Bulb::clone(bulb))
+      .collect();
+    found.sort_by_key(|bulb| bulb.target);
+    found
+  }
+
+  /// writes every device the client knows to `path`: its address, labels,
+  /// services and the message types it does not handle, along with every
+  /// alias.
+  /// `ClientBuilder::with_saved_devices` reads them back. the file is
+  /// replaced as a whole, so a crash while saving leaves the old one intact.
+  ///
+  pub fn save_devices<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let mut devices: Vec<_> =
+      self.devices.load().values().map(|bulb| bulb.saved()).collect();
+    devices.sort_by_key(|device| device.target);
+    let aliases = self.aliases.read().unwrap().clone();
+    let mut aliases: Vec<_> = aliases.into_iter().collect();
+    aliases.sort();
+
+    let partial = path.with_extension("partial");
+    let file = BufWriter::new(File::create(&partial)?);
+    saved::write_saved(file, &Saved { devices, aliases })?;
+    fs::rename(&partial, path)?;
+    Ok(())
+  }
+
+  /// writes every device the client knows to `w`, ordered by mac address, as
+  /// json or csv: its mac address, ip, label, group, location, vendor and
+  /// product ids, firmware versions and whether it is on. fields the client
+  /// has not heard yet are left empty, so `discover` with
+  /// `DiscoverOptions::GET_ALL` first fills in as much as it can.
+  ///
+  pub fn export_inventory<W: Write>(
+    &self,
+    w: W,
+    format: InventoryFormat,
+  ) -> Result<(), Error> {
+    let mut bulbs = self.devices.cloned();
+    bulbs.sort_by_key(|bulb| bulb.target);
+    inventory::write_inventory(w, &bulbs, format)?;
+    Ok(())
+  }
+
+  /// adds a device by its mac address and the address it listens on, as
+  /// `ClientBuilder::with_device` does, replacing what the client knew about
+  /// it. returns the device it replaced, if there was one. a new device is
+  /// announced with `DeviceEvent::Discovered`.
+  ///
+  pub fn add_device<T: Into<MacAddress>>(
+    &self,
+    target: T,
+    addr: SocketAddr,
+  ) -> Option<Bulb<SocketAddr>> {
+    let target: u64 = target.into().into();
+    let bulb = Bulb::configured(addr, target, self.endpoint.clone());
+    let replaced = self.devices.write().insert(target, bulb);
+    if replaced.is_none() {
+      self.events.emit(DeviceEvent::Discovered(target.into()));
+    }
+    replaced
+  }
+
+  /// forgets a device, returning it if the client knew it. it is found again
+  /// if it is heard from again. emits `DeviceEvent::Evicted`.
+  ///
+  pub fn forget<T: Into<DeviceId>>(
+    &self,
+    target: T,
+  ) -> Option<Bulb<SocketAddr>> {
+    let target = self.resolve(target).ok()?;
+    let forgotten = self.devices.write().remove(&target);
+    if forgotten.is_some() {
+      self.events.emit(DeviceEvent::Evicted(target.into()));
+    }
+    forgotten
+  }
+
+  /// forgets every device, as `forget` does.
+  ///
+  pub fn clear_devices(&self) {
+    let devices = self.devices.write().clear();
+    for bulb in devices {
+      self.events.emit(DeviceEvent::Evicted(bulb.target));
+    }
+  }
+
+  /// returns a snapshot of a particular device, given its mac address or
+  /// alias.
+  ///
+  pub fn device<T: Into<DeviceId>>(
+    &self,
+    target: T,
+  ) -> Option<Bulb<SocketAddr>> {
+    let target = self.resolve(target).ok()?;
+    self.devices.get(target)
+  }
+
+  /// gives a device an alias, such as "kitchen-left", which the methods that
+  /// look up a device accept in place of its mac address. this is separate
+  /// from the label the device reports, and the device does not need to have
+  /// been found yet. returns the device the alias was given to before, if it
+  /// was.
+  ///
+  pub fn set_alias<T: Into<MacAddress>>(
+    &self,
+    alias: &str,
+    target: T,
+  ) -> Option<MacAddress> {
+    let mut aliases = self.aliases.write().unwrap();
+    let previous = aliases.insert(alias.to_string(), target.into().into());
+    previous.map(MacAddress::from)
+  }
+
+  /// removes an alias, returning the device it was given to.
+  ///
+  pub fn remove_alias(&self, alias: &str) -> Option<MacAddress> {
+    let removed = self.aliases.write().unwrap().remove(alias);
+    removed.map(MacAddress::from)
+  }
+
+  /// returns every alias, and the target of the device it was given to.
+  ///
+  pub fn aliases(&self) -> HashMap<String, MacAddress> {
+    let aliases = self.aliases.read().unwrap();
+    aliases.iter().map(|(alias, &target)| (alias.clone(), target.into())).collect()
+  }
+
+  /// returns the target a device id names, looking up aliases.
+  ///
+  fn resolve<T: Into<DeviceId>>(&self, target: T) -> Result<u64, Error> {
+    match target.into() {
+      DeviceId::Mac(mac) => Ok(mac.into()),
+      DeviceId::Alias(alias) => match self.aliases.read().unwrap().get(&alias) {
+        Some(&target) => Ok(target),
+        None => Err(Error::UnknownAlias(alias)),
+      },
+    }
+  }
+
+  /// returns the source identifier the client sends in every message. only
+  /// acks and responses that carry it are matched to the client's requests.
+  ///
+  #[inline]
+  pub fn source(&self) -> u32 {
+    self.endpoint.source
+  }
+
+  /// returns the discovery the client was built with, to pass to `start`.
+  ///
+  #[inline]
+  pub fn config(&self) -> Config {
+    self.config
+  }
+
+  /// sets whether received frames must pass `Header::validate`. a client that
+  /// is not strict accepts any frame that decodes.
+  ///
+  #[inline]
+  pub fn set_strict(&self, strict: bool) {
+    self.strict.store(strict, Ordering::SeqCst)
+  }
+
+  /// checks if the client validates received frames.
+  ///
+  #[inline]
+  pub fn is_strict(&self) -> bool {
+    self.strict.load(Ordering::SeqCst)
+  }
+
+  /// returns the number of received frames that have been rejected, either
+  /// because they did not decode or because they failed validation.
+  ///
+  #[inline]
+  pub fn rejected_frames(&self) -> usize {
+    self.rejected.load(Ordering::SeqCst)
+  }
+
+  /// closes a client. it will no longer receive responses from the socket,
+  /// and the listen thread stops right away.
+  ///
+  pub fn close(&self) {
+    self.closed.store(true, Ordering::SeqCst);
+    let _ = self.endpoint.socket.wake();
+    if let Some(ref monitor) = self.monitor {
+      let _ = monitor.wake();
+    }
+  }
+
+  /// checks if a client is closed.
+  ///
+  #[inline(always)]
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+}
+
+impl Drop for Client {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+#[test]
+fn test_sequences_are_per_target_and_skip_reserved() {
+  let sequences = Sequences::default();
+
+  assert_eq!(0, sequences.next(0xAB));
+  assert_eq!(0, sequences.next(0xCD));
+  assert_eq!(1, sequences.reserve(0xAB));
+  for seq in 2..=u8::MAX {
+    assert_eq!(seq, sequences.next(0xAB));
+  }
+
+  // wraps around, skipping the sequence still reserved for 0xAB.
+  assert_eq!(0, sequences.next(0xAB));
+  assert_eq!(2, sequences.next(0xAB));
+  assert_eq!(1, sequences.next(0xCD));
+
+  sequences.release(0xAB, 1);
+  for _ in 3..=u8::MAX {
+    sequences.next(0xAB);
+  }
+  assert_eq!(0, sequences.next(0xAB));
+  assert_eq!(1, sequences.next(0xAB));
+}
+
+#[test]
+fn test_raw_hook_sees_raw_datagrams() {
+  let listener = Client::new("127.0.0.1:56798").unwrap();
+  let sender = Client::new("127.0.0.1:0").unwrap();
+  let (tx, rx) = mpsc::channel();
+  let tx = Mutex::new(tx);
+
+  listener.set_raw_hook(move |bytes, _| {
+    let _ = tx.lock().unwrap().send(bytes.to_vec());
+  });
+  let thread = listener.listen();
+
+  assert_eq!(3, sender.send_raw("127.0.0.1:56798", &[1, 2, 3]).unwrap());
+  assert_eq!(
+    vec![1, 2, 3],
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+  );
+
+  listener.close();
+  let _ = thread.join();
+  assert_eq!(1, listener.rejected_frames());
+}
+
+#[test]
+fn test_request_matches_the_response() {
+  let client = Client::new("127.0.0.1:56797").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+
+  client.devices.write().insert(
+    0xAB,
+    Bulb::new(bulb_addr, bulb_addr.port() as u32, 0xAB, client.endpoint.clone()),
+  );
+
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+    let request = Message::from_bytes(&buf[..amt]).unwrap();
+    assert_eq!(&Payload::Device(Device::GetLabel), request.payload());
+    assert!(request.header().res_required());
+
+    // a reply to some other request comes first, and must be ignored.
+    let seq = request.sequence();
+    let source = request.source();
+    for &(label, seq) in &[("Other", seq.wrapping_add(1)), ("Lamp", seq)] {
+      let state = Payload::Device(Device::StateLabel(label.into()));
+      let reply = Message::new(state, false, 0xAB, seq).with_source(source);
+      bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+    }
+  });
+
+  assert_eq!(
+    "Lamp",
+    client.get_label(0xAB, Duration::from_secs(5)).unwrap()
+  );
+  match client.get_label(0xCD, Duration::from_millis(10)) {
+    Err(Error::NotDiscovered(mac)) => assert_eq!(MacAddress::from(0xCD), mac),
+    other => panic!("expected an unknown device, got {:?}", other),
+  }
+
+  responder.join().unwrap();
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_await_ack_matches_acknowledgements() {
+  let client = Client::new("127.0.0.1:56796").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+    let request = Message::from_bytes(&buf[..amt]).unwrap();
+    assert!(request.ack_required());
+
+    let ack = Payload::Device(Device::Acknowledgement);
+    let reply = Message::new(ack, false, 0xAB, request.sequence())
+      .with_source(request.source());
+    bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+  });
+
+  let payload = Payload::Device(Device::SetPower(Power::Max));
+  let seq = client.send_msg(bulb_addr, payload, true, 0xAB).unwrap();
+  client.await_ack(0xAB, seq, Duration::from_secs(5)).unwrap();
+  assert!(client.await_ack(0xAB, seq, Duration::from_millis(10)).is_err());
+
+  let payload = Payload::Device(Device::SetPower(Power::Max));
+  let seq = client.send_msg(bulb_addr, payload, true, 0xAB).unwrap();
+  match client.await_ack(0xAB, seq, Duration::from_millis(10)) {
+    Err(Error::Timeout { .. }) => (),
+    other => panic!("expected a timeout, got {:?}", other),
+  }
+
+  responder.join().unwrap();
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_send_reliable_retransmits_until_acked() {
+  let client = Client::new("127.0.0.1:56795").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let mut sequences = Vec::new();
+
+    // the first send is dropped, and the retransmission is acked.
+    for _ in 0..2 {
+      let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+      let request = Message::from_bytes(&buf[..amt]).unwrap();
+      assert!(request.ack_required());
+      sequences.push(request.sequence());
+
+      if sequences.len() == 2 {
+        let ack = Payload::Device(Device::Acknowledgement);
+        let reply = Message::new(ack, false, 0xAB, request.sequence())
+          .with_source(request.source());
+        bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+      }
+    }
+
+    sequences
+  });
+
+  let backoff = Backoff {
+    initial: Duration::from_millis(50),
+    ..Backoff::default()
+  };
+  let payload = Payload::Device(Device::SetPower(Power::Max));
+  let seq = client
+    .send_reliable(bulb_addr, payload, 0xAB, backoff)
+    .unwrap();
+  assert_eq!(vec![seq, seq], responder.join().unwrap());
+
+  let backoff = Backoff {
+    attempts: 3,
+    initial: Duration::from_millis(10),
+    ..Backoff::default()
+  };
+  let payload = Payload::Device(Device::SetPower(Power::Max));
+  match client.send_reliable(bulb_addr, payload, 0xAB, backoff) {
+    Err(Error::Unacknowledged { attempts, .. }) => assert_eq!(3, attempts),
+    other => panic!("expected a timeout, got {:?}", other),
+  }
+
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_send_routed_only_sees_its_responses() {
+  let client = Client::new("127.0.0.1:56794").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+    let request = Message::from_bytes(&buf[..amt]).unwrap();
+    let seq = request.sequence();
+    let source = request.source();
+
+    // replies from two devices to the broadcast, then one to another request.
+    for &(target, seq) in &[(0xAB, seq), (0xCD, seq), (0xAB, seq.wrapping_add(1))] {
+      let state = Payload::Device(Device::StatePower(Power::Max));
+      let reply = Message::new(state, false, target, seq).with_source(source);
+      bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+    }
+  });
+
+  let payload = Payload::Device(Device::GetPower);
+  let responses = client
+    .send_routed(bulb_addr, payload, SendOptions::RES_REQUIRED, 0)
+    .unwrap();
+  let timeout = Duration::from_secs(5);
+  let state = Payload::Device(Device::StatePower(Power::Max));
+
+  let (target, payload) = responses.recv_timeout(timeout).unwrap();
+  assert_eq!((MacAddress::from(0xAB), &state), (target, &payload));
+  let (target, payload) = responses.recv_timeout(timeout).unwrap();
+  assert_eq!((MacAddress::from(0xCD), &state), (target, &payload));
+  responder.join().unwrap();
+  assert!(responses.recv_timeout(Duration::from_millis(50)).is_err());
+
+  let key = responses.key;
+  drop(responses);
+  assert!(!client.router.routes.lock().unwrap().contains_key(&key));
+
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_callbacks_see_decoded_frames() {
+  let client = Client::new("127.0.0.1:56793").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let thread = client.listen();
+  let (tx, rx) = mpsc::channel();
+
+  let events = Arc::new(Mutex::new(tx));
+  let e = events.clone();
+  client.on_state_change(move |target, payload| {
+    let _ = e.lock().unwrap().send(format!("state {} {:?}", target, payload));
+  });
+  let e = events.clone();
+  client.on_device_discovered(move |bulb| {
+    let _ = e.lock().unwrap().send(format!("discovered {}", bulb.target));
+  });
+  let e = events.clone();
+  client.on_ack(move |target, seq| {
+    let _ = e.lock().unwrap().send(format!("ack {} {}", target, seq));
+  });
+  let e = events;
+  client.on_unknown_message(move |target, typ, bytes| {
+    let _ = e
+      .lock()
+      .unwrap()
+      .send(format!("unknown {} {} {:?}", target, typ, bytes));
+  });
+
+  let unknown = Payload::Unknown {
+    typ: 9999,
+    bytes: vec![1, 2],
+  };
+  for (seq, payload) in vec![
+    Payload::Device(Device::StateService(Service::Udp, 56700)),
+    Payload::Device(Device::StateService(Service::Udp, 56700)),
+    Payload::Device(Device::StatePower(Power::Max)),
+    Payload::Device(Device::Acknowledgement),
+    unknown,
+  ]
+  .into_iter()
+  .enumerate()
+  {
+    let msg =
+      Message::new(payload, false, 0xAB, seq as u8).with_source(client.source());
+    bulb
+      .send_to(&serialize::encode(&msg).unwrap()[..], "127.0.0.1:56793")
+      .unwrap();
+  }
+
+  let mut seen = Vec::new();
+  while seen.len() < 5 {
+    seen.push(rx.recv_timeout(Duration::from_secs(5)).unwrap());
+  }
+  assert_eq!(
+    vec![
+      "state ab:00:00:00:00:00 Device(StateService(Udp, 56700))",
+      "discovered ab:00:00:00:00:00",
+      "state ab:00:00:00:00:00 Device(StateService(Udp, 56700))",
+      "state ab:00:00:00:00:00 Device(StatePower(Max))",
+      "ack ab:00:00:00:00:00 3",
+    ],
+    seen
+  );
+  assert_eq!(
+    "unknown ab:00:00:00:00:00 9999 [1, 2]",
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+  );
+
+  client.clear_callbacks();
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_incoming_feeds_every_frame() {
+  let client = Client::new("127.0.0.1:56792").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+  let incoming = client.incoming();
+
+  // two frames in one datagram, as some devices send them.
+  let mut bytes = Vec::new();
+  for &(power, seq) in &[(Power::Max, 1), (Power::Standby, 2)] {
+    let state = Payload::Device(Device::StatePower(power));
+    let msg = Message::new(state, false, 0xAB, seq).with_source(client.source());
+    bytes.extend(serialize::encode(&msg).unwrap());
+  }
+  bulb.send_to(&bytes[..], "127.0.0.1:56792").unwrap();
+
+  for &(power, seq) in &[(Power::Max, 1), (Power::Standby, 2)] {
+    let (from, msg) = incoming.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(bulb_addr, from);
+    assert_eq!(seq, msg.sequence());
+    assert_eq!(&Payload::Device(Device::StatePower(power)), msg.payload());
+  }
+
+  drop(incoming);
+  bulb.send_to(&bytes[..], "127.0.0.1:56792").unwrap();
+  let start = std::time::Instant::now();
+  while !client.feeds.lock().unwrap().is_empty() {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(10));
+  }
+
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_events_follow_the_device_cache() {
+  let client = Client::new("127.0.0.1:56791").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let mut events = client.events();
+  let thread = client.listen();
+
+  let color = sample_color();
+  for payload in [
+    Payload::Device(Device::StateService(Service::Udp, 56700)),
+    Payload::Device(Device::StateLabel("Lamp".into())),
+    Payload::Device(Device::StateLabel("Lamp".into())),
+    Payload::Light(Light::State(color, Power::Max, "Lamp".into())),
+    Payload::Device(Device::StatePower(Power::Max)),
+    Payload::Device(Device::StatePower(Power::Standby)),
+  ] {
+    let msg = Message::new(payload, false, 0xAB, 0).with_source(client.source());
+    bulb
+      .send_to(&serialize::encode(&msg).unwrap()[..], "127.0.0.1:56791")
+      .unwrap();
+  }
+
+  let expected = vec![
+    DeviceEvent::Discovered(MacAddress::from(0xAB)),
+    DeviceEvent::LabelChanged(MacAddress::from(0xAB), "Lamp".into()),
+    DeviceEvent::PowerChanged(MacAddress::from(0xAB), Power::Max),
+    DeviceEvent::ColorChanged(MacAddress::from(0xAB), color),
+    DeviceEvent::PowerChanged(MacAddress::from(0xAB), Power::Standby),
+  ];
+  let mut seen = Vec::new();
+  let start = Instant::now();
+  while seen.len() < expected.len() {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    match events.try_next() {
+      Some(event) => seen.push(event),
+      None => thread::sleep(Duration::from_millis(10)),
+    }
+  }
+  assert_eq!(expected, seen);
+  assert_eq!(Some(Power::Standby), client.device(0xAB).unwrap().power());
+
+  client.set_offline_after(Duration::from_millis(0));
+  let start = Instant::now();
+  while client.device(0xAB).unwrap().is_online() {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(
+    Some(DeviceEvent::WentOffline(MacAddress::from(0xAB))),
+    events.try_next()
+  );
+  assert_eq!(
+    Some(DeviceEvent::HealthChanged(MacAddress::from(0xAB), Health::Offline)),
+    events.try_next()
+  );
+
+  client.close();
+  let _ = thread.join();
+  let waker = std::task::Waker::noop();
+  let mut cx = std::task::Context::from_waker(waker);
+  assert_eq!(
+    std::task::Poll::Ready(None),
+    events.poll_event(&mut cx)
+  );
+}
+
+/// a fake `Transport` for tests, with a bulb for each of `targets` that answers
+/// GetService with a StateService, and GetLabel with a StateLabel. recv_from
+/// waits for a reply for as long as `set_read_timeout` last asked.
+///
+#[cfg(test)]
+struct Scripted {
+  targets: Vec<u64>,
+  replies: Mutex<VecDeque<Vec<u8>>>,
+  ready: Condvar,
+  timeout: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl Scripted {
+  fn new(targets: &[u64]) -> Scripted {
+    Scripted {
+      targets: targets.to_vec(),
+      replies: Mutex::new(VecDeque::new()),
+      ready: Condvar::new(),
+      timeout: Mutex::new(Duration::from_millis(10)),
+    }
+  }
+}
+
+#[cfg(test)]
+impl Transport for Scripted {
+  fn send_to(&self, buf: &[u8], _: SocketAddr) -> io::Result<usize> {
+    let request = Message::from_bytes(buf).unwrap();
+    let reply = match *request.payload() {
+      Payload::Device(Device::GetService) => {
+        Payload::Device(Device::StateService(Service::Udp, 56700))
+      }
+      Payload::Device(Device::GetLabel) => {
+        Payload::Device(Device::StateLabel("Fake".into()))
+      }
+      _ => return Ok(buf.len()),
+    };
+
+    let mut replies = self.replies.lock().unwrap();
+    for &target in &self.targets {
+      let msg = Message::new(reply.clone(), false, target, request.sequence())
+        .with_source(request.source());
+      replies.push_back(serialize::encode(&msg).unwrap());
+    }
+    self.ready.notify_all();
+    Ok(buf.len())
+  }
+
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    let timeout = *self.timeout.lock().unwrap();
+    let replies = self.replies.lock().unwrap();
+    let (mut replies, _) = self
+      .ready
+      .wait_timeout_while(replies, timeout, |r| r.is_empty())
+      .unwrap();
+
+    match replies.pop_front() {
+      Some(bytes) => {
+        buf[..bytes.len()].copy_from_slice(&bytes[..]);
+        Ok((bytes.len(), "10.0.0.2:56700".parse().unwrap()))
+      }
+      None => Err(io::ErrorKind::WouldBlock.into()),
+    }
+  }
+
+  fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+    *self.timeout.lock().unwrap() = timeout;
+    Ok(())
+  }
+}
+
+/// checks `done` every few milliseconds until it returns true, and fails the
+/// test if that takes more than five seconds.
+///
+#[cfg(test)]
+fn wait_until<F: FnMut() -> bool>(mut done: F) {
+  let start = Instant::now();
+  while !done() {
+    assert!(start.elapsed() < Duration::from_secs(5), "gave up waiting");
+    thread::sleep(Duration::from_millis(10));
+  }
+}
+
+/// the color the tests send to devices, and expect them to report.
+///
+#[cfg(test)]
+pub(crate) fn sample_color() -> HSBK {
+  HSBK::new(
+    Hue::from(1),
+    Saturation::from(2),
+    Brightness::from(3),
+    Kelvin::new(3500).unwrap(),
+  )
+}
+
+#[test]
+fn test_client_with_a_scripted_transport() {
+  let client = Client::with_transport(Scripted::new(&[0xAB]));
+  let listener = client.listen();
+  let discoverer = client.discover(10, DiscoverOptions::empty());
+
+  wait_until(|| client.device(0xAB).is_some());
+  assert_eq!(
+    "10.0.0.2:56700".parse::<SocketAddr>().unwrap(),
+    client.device(0xAB).unwrap().ip
+  );
+  assert_eq!(
+    "Fake",
+    client.get_label(0xAB, Duration::from_secs(5)).unwrap()
+  );
+
+  client.close();
+  let _ = listener.join();
+  let _ = discoverer.join();
+}
+
+#[test]
+fn test_clients_have_their_own_source() {
+  let first = Client::new("127.0.0.1:0").unwrap();
+  let second = Client::new("127.0.0.1:0").unwrap();
+  assert_ne!(0, first.source());
+  assert_ne!(first.source(), second.source());
+
+  let client = Client::builder().source(42).bind("127.0.0.1:0").unwrap();
+  assert_eq!(42, client.source());
+  assert_ne!(0, Client::builder().source(0).bind("127.0.0.1:0").unwrap().source());
+}
+
+#[test]
+fn test_close_wakes_the_listen_thread() {
+  for addr in &["127.0.0.1:0", "0.0.0.0:0"] {
+    let client = Client::new(addr).unwrap();
+    let thread = client.listen();
+
+    // lets the thread block in recv_from before closing.
+    thread::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    client.close();
+    thread.join().unwrap();
+    assert!(start.elapsed() < Duration::from_millis(250));
+  }
+}
+
+#[test]
+fn test_poll_once_on_the_calling_thread() {
+  let client = Client::with_transport(Scripted::new(&[0xAB]));
+  assert!(!client.poll_once(Duration::from_millis(0)).unwrap());
+
+  client.tick_discovery(DiscoverOptions::GET_LABEL).unwrap();
+  assert!(client.device(0xAB).is_none());
+  assert!(client.poll_once(Duration::from_millis(0)).unwrap());
+  assert!(client.device(0xAB).is_some());
+
+  client
+    .run_until(|client| {
+      client.tick_discovery(DiscoverOptions::GET_LABEL).unwrap();
+      client.device(0xAB).unwrap().label() == Some("Fake")
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_start_runs_listen_and_discover_on_one_thread() {
+  let client = Client::with_transport(Scripted::new(&[0xAB]));
+  let mut events = client.events();
+
+  assert!(client.start(Config {
+    discover_every: Some(Duration::from_millis(10)),
+    ..Config::default()
+  }));
+  assert!(!client.start(Config::default()));
+
+  wait_until(|| client.device(0xAB).is_some_and(|d| d.label() == Some("Fake")));
+
+  client.stop();
+  assert!(client.is_closed());
+  assert_eq!(
+    Some(DeviceEvent::Discovered(MacAddress::from(0xAB))),
+    events.try_next()
+  );
+  while events.try_next().is_some() {}
+
+  let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+  assert_eq!(std::task::Poll::Ready(None), events.poll_event(&mut cx));
+}
+
+#[test]
+fn test_client_builder_configures_the_socket_and_discovery() {
+  let bulb = UdpSocket::bind("127.0.0.1:56799").unwrap();
+  bulb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+  let config = Config {
+    discover_every: None,
+    discover_options: DiscoverOptions::GET_ALL,
+    ..Config::default()
+  };
+  let client = Client::builder()
+    .read_timeout(Duration::from_millis(20))
+    .write_timeout(Duration::from_millis(20))
+    .recv_buffer_size(64 * 1024)
+    .broadcast("127.0.0.1:56799".parse().unwrap())
+    .config(config)
+    .bind("127.0.0.1:0")
+    .unwrap();
+  assert_eq!(config, client.config());
+
+  let start = Instant::now();
+  assert!(!client.poll_once(Duration::from_millis(20)).unwrap());
+  assert!(start.elapsed() < Duration::from_secs(1));
+
+  client.tick_discovery(DiscoverOptions::empty()).unwrap();
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  let (amt, _) = bulb.recv_from(&mut buf).unwrap();
+  assert_eq!(
+    &Payload::Device(Device::GetService),
+    Message::from_bytes(&buf[..amt]).unwrap().payload()
+  );
+}
+
+#[test]
+fn test_reuse_address_lets_clients_share_a_port() {
+  let bind = || {
+    Client::builder()
+      .reuse_address(true)
+      .send_buffer_size(64 * 1024)
+      .bind("127.0.0.1:56800")
+  };
+
+  let _first = bind().unwrap();
+  let _second = bind().unwrap();
+  assert!(Client::new("127.0.0.1:56800").is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_reuse_port_lets_clients_share_a_port() {
+  let bind = || Client::builder().reuse_port(true).bind("127.0.0.1:56801");
+
+  let _first = bind().unwrap();
+  let _second = bind().unwrap();
+  assert!(Client::new("127.0.0.1:56801").is_err());
+}
+
+#[test]
+fn test_discovery_broadcasts_to_every_address() {
+  let subnets = [
+    UdpSocket::bind("127.0.0.1:56802").unwrap(),
+    UdpSocket::bind("127.0.0.1:56803").unwrap(),
+  ];
+  let client = Client::builder()
+    .broadcasts(subnets.iter().map(|s| s.local_addr().unwrap()))
+    .bind("127.0.0.1:0")
+    .unwrap();
+
+  client.tick_discovery(DiscoverOptions::empty()).unwrap();
+
+  for subnet in &subnets {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    subnet.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let (amt, _) = subnet.recv_from(&mut buf).unwrap();
+    assert_eq!(
+      &Payload::Device(Device::GetService),
+      Message::from_bytes(&buf[..amt]).unwrap().payload()
+    );
+  }
+}
+
+#[test]
+fn test_interface_broadcasts_skip_loopback() {
+  for addr in interface_broadcasts().unwrap() {
+    match addr {
+      SocketAddr::V4(v4) => assert!(!v4.ip().is_loopback()),
+      SocketAddr::V6(_) => panic!("{} is not ipv4", addr),
+    }
+    assert_eq!(consts::PORT, addr.port());
+  }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_bind_to_an_interface() {
+  assert!(Client::builder().interface("lo").bind("127.0.0.1:0").is_ok());
+  assert!(Client::builder()
+    .interface("no-such-interface")
+    .bind("127.0.0.1:0")
+    .is_err());
+}
+
+#[test]
+fn test_broadcast_is_enabled_once() {
+  /// records every call to set_broadcast.
+  struct Recording(Arc<Mutex<Vec<bool>>>);
+
+  impl Transport for Recording {
+    fn send_to(&self, buf: &[u8], _: SocketAddr) -> io::Result<usize> {
+      Ok(buf.len())
+    }
+
+    fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+      Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+      self.0.lock().unwrap().push(broadcast);
+      Ok(())
+    }
+  }
+
+  let calls = Arc::new(Mutex::new(Vec::new()));
+  let client = Client::with_transport(Recording(calls.clone()));
+  client.tick_discovery(DiscoverOptions::empty()).unwrap();
+  let payload = Payload::Device(Device::GetLabel);
+  client.send_msg("10.0.0.2:56700", payload, false, 0xAB).unwrap();
+  client.tick_discovery(DiscoverOptions::empty()).unwrap();
+
+  assert_eq!(vec![true], *calls.lock().unwrap());
+}
+
+#[test]
+fn test_rejected_frames_are_handed_to_callbacks() {
+  let client = Client::new("127.0.0.1:56804").unwrap();
+  let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let (tx, rx) = mpsc::channel();
+  let tx = Mutex::new(tx);
+
+  client.on_rejected_frame(move |src, bytes, e| {
+    let _ = tx.lock().unwrap().send((src, bytes.to_vec(), e.clone()));
+  });
+
+  let mut garbage = serialize::encode(&Message::new(
+    Payload::Device(Device::GetLabel),
+    false,
+    0xAB,
+    0,
+  ))
+  .unwrap();
+  garbage.extend_from_slice(&[1, 2, 3]);
+  sender.send_to(&garbage, "127.0.0.1:56804").unwrap();
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+
+  let (src, bytes, _) = rx.try_recv().unwrap();
+  assert_eq!(sender.local_addr().unwrap(), src);
+  assert_eq!(vec![1, 2, 3], bytes);
+  assert!(rx.try_recv().is_err());
+  assert_eq!(1, client.rejected_frames());
+}
+
+#[test]
+fn test_foreign_sources_and_own_broadcasts_are_ignored() {
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+  let send = |client: &Client, source: u32, tagged: bool| {
+    let msg = Message::new(state.clone(), false, 0xAB, 0).with_source(source);
+    let mut bytes = serialize::encode(&msg).unwrap();
+    if tagged {
+      // the tagged bit of the header's protocol field.
+      bytes[3] |= 0x20;
+    }
+    bulb.send_to(&bytes[..], "127.0.0.1:56805").unwrap();
+    assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  };
+
+  let client = Client::builder().source(1).bind("127.0.0.1:56805").unwrap();
+  send(&client, 2, false);
+  send(&client, 1, true);
+  assert!(client.devices().is_empty());
+  send(&client, 1, false);
+  assert!(client.device(0xAB).is_some());
+  drop(client);
+
+  let client = Client::builder()
+    .source(1)
+    .accept_foreign(true)
+    .bind("127.0.0.1:56805")
+    .unwrap();
+  send(&client, 2, false);
+  assert!(client.device(0xAB).is_some());
+}
+
+#[test]
+fn test_errors_from_background_threads() {
+  /// fails every send, and receives a single undecodable datagram.
+  struct Broken(Mutex<bool>);
+
+  impl Transport for Broken {
+    fn send_to(&self, _: &[u8], _: SocketAddr) -> io::Result<usize> {
+      Err(io::ErrorKind::PermissionDenied.into())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+      thread::sleep(Duration::from_millis(10));
+      if mem::replace(&mut *self.0.lock().unwrap(), true) {
+        return Err(io::ErrorKind::WouldBlock.into());
+      }
+      buf[..3].copy_from_slice(&[1, 2, 3]);
+      Ok((3, "10.0.0.2:56700".parse().unwrap()))
+    }
+  }
+
+  let client = Client::with_transport(Broken(Mutex::new(false)));
+  let errors = client.errors();
+  let copies = client.errors();
+  let listener = client.listen();
+  let discoverer = client.discover(10, DiscoverOptions::empty());
+
+  let mut seen = (false, false);
+  let start = Instant::now();
+  while seen != (true, true) {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    match errors.recv_timeout(Duration::from_secs(5)).unwrap() {
+      Error::Decode(_) => seen.0 = true,
+      Error::Io(ref e) => {
+        assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+        seen.1 = true;
+      }
+      e => panic!("unexpected error: {}", e),
+    }
+  }
+  assert!(copies.try_recv().is_ok());
+
+  client.close();
+  let _ = listener.join();
+  let _ = discoverer.join();
+}
+
+#[test]
+fn test_rebind_on_the_same_port() {
+  let client = Client::new("127.0.0.1:56806").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  client.rebind().unwrap();
+
+  let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  bulb
+    .send_to(&serialize::encode(&msg).unwrap()[..], "127.0.0.1:56806")
+    .unwrap();
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  assert!(client.device(0xAB).is_some());
+
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  assert!(client.rebind().is_err());
+}
+
+#[test]
+fn test_persistent_failures_rebind() {
+  /// fails every receive until it has been opened again.
+  struct Flaky(bool);
+
+  impl Transport for Flaky {
+    fn send_to(&self, buf: &[u8], _: SocketAddr) -> io::Result<usize> {
+      Ok(buf.len())
+    }
+
+    fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+      match self.0 {
+        true => Err(io::ErrorKind::NetworkDown.into()),
+        false => Err(io::ErrorKind::WouldBlock.into()),
+      }
+    }
+  }
+
+  let opened = Arc::new(AtomicUsize::new(0));
+  let o = opened.clone();
+  let client = Client::builder()
+    .read_timeout(Duration::from_millis(1))
+    .transport_with(move || Ok(Flaky(o.fetch_add(1, Ordering::SeqCst) == 0)))
+    .unwrap();
+  let errors = client.errors();
+  let thread = client.listen();
+
+  for _ in 0..REBIND_AFTER {
+    match errors.recv_timeout(Duration::from_secs(5)).unwrap() {
+      Error::Io(ref e) => assert_eq!(io::ErrorKind::NetworkDown, e.kind()),
+      e => panic!("unexpected error: {}", e),
+    }
+  }
+  let start = Instant::now();
+  while opened.load(Ordering::SeqCst) < 2 {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(10));
+  }
+
+  client.close();
+  let _ = thread.join();
+  assert_eq!(2, opened.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_discover_once_returns_the_bulbs_that_replied() {
+  let client = Client::with_transport(Scripted::new(&[0xAB, 0xCD]));
+  let start = Instant::now();
+  let bulbs = client.discover_once(Duration::from_millis(50)).unwrap();
+
+  assert!(start.elapsed() >= Duration::from_millis(50));
+  assert_eq!(
+    vec![0xAB, 0xCD],
+    bulbs.iter().map(|b| u64::from(b.target)).collect::<Vec<_>>()
+  );
+}
+
+#[test]
+fn test_discover_stream_yields_bulbs_as_they_are_found() {
+  let client = Client::new("127.0.0.1:56807").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let send = |target| {
+    let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+    let msg = Message::new(state, false, target, 0).with_source(client.source());
+    bulb
+      .send_to(&serialize::encode(&msg).unwrap()[..], "127.0.0.1:56807")
+      .unwrap();
+  };
+
+  send(0xAB);
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  let mut found = client.discover_stream();
+  let thread = client.listen();
+
+  send(0xAB);
+  send(0xCD);
+  assert_eq!(Some(0xAB), found.next().map(|b| u64::from(b.target)));
+  assert_eq!(Some(0xCD), found.next().map(|b| u64::from(b.target)));
+
+  client.close();
+  let _ = thread.join();
+  assert!(found.next().is_none());
+}
+
+#[test]
+fn test_enrichment_queries_new_devices() {
+  let client = Client::new("127.0.0.1:56808").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  bulb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  client.set_enrichment(|bulb| {
+    assert_eq!(MacAddress::from(0xAB), bulb.target);
+    DiscoverOptions::GET_LABEL | DiscoverOptions::GET_VERSION
+  });
+
+  let port = bulb.local_addr().unwrap().port().into();
+  let state = Payload::Device(Device::StateService(Service::Udp, port));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  let bytes = serialize::encode(&msg).unwrap();
+  for _ in 0..2 {
+    bulb.send_to(&bytes[..], "127.0.0.1:56808").unwrap();
+    assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  }
+
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  for query in &[Device::GetLabel, Device::GetVersion] {
+    let (amt, _) = bulb.recv_from(&mut buf).unwrap();
+    let msg = Message::from_bytes(&buf[..amt]).unwrap();
+    assert_eq!(MacAddress::from(0xAB), msg.target());
+    assert_eq!(&Payload::Device(query.clone()), msg.payload());
+  }
+
+  bulb.set_nonblocking(true).unwrap();
+  assert!(bulb.recv_from(&mut buf).is_err());
+}
+
+#[test]
+fn test_wait_for_device() {
+  let client = Client::new("127.0.0.1:56809").unwrap();
+  let listener = client.listen();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+  match client.wait_for_device(0xAB, Duration::from_millis(50)) {
+    Err(Error::Timeout { .. }) => (),
+    other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+  }
+
+  let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  let bytes = serialize::encode(&msg).unwrap();
+  let sender = thread::spawn(move || {
+    thread::sleep(Duration::from_millis(50));
+    bulb.send_to(&bytes[..], "127.0.0.1:56809").unwrap();
+  });
+
+  let found = client.wait_for_device(0xAB, Duration::MAX).unwrap();
+  assert_eq!(MacAddress::from(0xAB), found.target);
+  sender.join().unwrap();
+
+  client.close();
+  listener.join().unwrap();
+  match client.wait_for_device(0xCD, Duration::from_secs(5)) {
+    Err(Error::NotDiscovered(mac)) => assert_eq!(0xCD, u64::from(mac)),
+    other => panic!("expected not discovered, got {:?}", other.map(|_| ())),
+  }
+}
+
+#[test]
+fn test_alarm_keeps_one_thread_until_dropped() {
+  struct Count(AtomicUsize);
+
+  impl Wake for Count {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  let count = Arc::new(Count(AtomicUsize::new(0)));
+  let waker = Waker::from(count.clone());
+  let mut alarm = Alarm::default();
+  let soon = Instant::now() + Duration::from_millis(50);
+  alarm.set(soon + Duration::from_secs(60), &waker);
+  alarm.set(soon, &waker);
+  alarm.set(soon, &waker);
+  let state = alarm.0.clone().unwrap();
+
+  thread::sleep(Duration::from_millis(300));
+  assert_eq!(1, count.0.load(Ordering::SeqCst));
+
+  drop(alarm);
+  let deadline = Instant::now() + Duration::from_secs(5);
+  while Arc::strong_count(&state) > 1 {
+    assert!(Instant::now() < deadline, "the timer thread did not stop");
+    thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn test_wait_for_devices_and_settling() {
+  let client = Client::new("127.0.0.1:56810").unwrap();
+  let listener = client.listen();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+  match client.wait_for_devices(1, Duration::from_millis(50)) {
+    Err(Error::Timeout { .. }) => (),
+    other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+  }
+  assert!(client
+    .wait_until_settled(Duration::from_millis(50), Duration::from_secs(5))
+    .unwrap()
+    .is_empty());
+
+  let source = client.source();
+  let sender = thread::spawn(move || {
+    for target in 1..4 {
+      thread::sleep(Duration::from_millis(50));
+      let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+      let msg = Message::new(state, false, target, 0).with_source(source);
+      let bytes = serialize::encode(&msg).unwrap();
+      bulb.send_to(&bytes[..], "127.0.0.1:56810").unwrap();
+    }
+  });
+
+  let found = client.wait_for_devices(2, Duration::from_secs(5)).unwrap();
+  assert!(found.len() >= 2);
+
+  let found = client
+    .wait_until_settled(Duration::from_millis(300), Duration::MAX)
+    .unwrap();
+  assert_eq!(3, found.len());
+  sender.join().unwrap();
+
+  client.close();
+  listener.join().unwrap();
+}
+
+#[test]
+fn test_passive_discovery_probes_unknown_devices() {
+  let client = Client::new("127.0.0.1:56811").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  bulb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  let reply = |payload| {
+    let msg = Message::new(payload, false, 0xAB, 0).with_source(client.source());
+    let bytes = serialize::encode(&msg).unwrap();
+    bulb.send_to(&bytes[..], "127.0.0.1:56811").unwrap();
+    assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  };
+
+  // a request to the device, from another controller, isn't probed, and
+  // doesn't hold up the probe of the device itself.
+  let phone = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let set_power = Payload::Device(Device::SetPower(Power::Max));
+  let msg = Message::new(set_power, false, 0xAB, 0).with_source(client.source());
+  phone
+    .send_to(&serialize::encode(&msg).unwrap()[..], "127.0.0.1:56811")
+    .unwrap();
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+
+  // heard twice, but only asked once.
+  reply(Payload::Device(Device::StateLabel("Kitchen".into())));
+  reply(Payload::Device(Device::StateLabel("Kitchen".into())));
+  reply(Payload::Device(Device::StateService(Service::Udp, 56700)));
+  assert!(client.device(0xAB).is_some());
+
+  for query in &[Device::GetService, Device::GetLabel] {
+    let (amt, _) = bulb.recv_from(&mut buf).unwrap();
+    let msg = Message::from_bytes(&buf[..amt]).unwrap();
+    assert_eq!(MacAddress::from(0xAB), msg.target());
+    assert_eq!(&Payload::Device(query.clone()), msg.payload());
+  }
+
+  bulb.set_nonblocking(true).unwrap();
+  assert!(bulb.recv_from(&mut buf).is_err());
+  phone.set_nonblocking(true).unwrap();
+  assert!(phone.recv_from(&mut buf).is_err());
+}
+
+#[test]
+fn test_discovery_backs_off_while_no_device_is_found() {
+  let every = Duration::from_secs(1);
+  let mut schedule = Schedule::new(
+    every,
+    DiscoverOptions::GET_ALL,
+    Config {
+      discover_max: Duration::from_secs(4),
+      jitter: 0,
+      ..Config::default()
+    },
+  );
+
+  assert_eq!((DiscoverOptions::GET_ALL, every), schedule.round(0));
+  assert_eq!((DiscoverOptions::GET_ALL, every), schedule.round(2));
+  assert_eq!((DiscoverOptions::empty(), every * 2), schedule.round(2));
+  assert_eq!((DiscoverOptions::empty(), every * 4), schedule.round(2));
+  assert_eq!((DiscoverOptions::empty(), every * 4), schedule.round(2));
+  assert_eq!((DiscoverOptions::GET_ALL, every), schedule.round(3));
+
+  schedule.config.jitter = 50;
+  schedule.config.verify_when_stable = false;
+  for _ in 0..10 {
+    let (options, wait) = schedule.round(3);
+    assert_eq!(DiscoverOptions::GET_ALL, options);
+    assert!(wait >= schedule.wait && wait <= schedule.wait * 3 / 2);
+  }
+}
+
+#[test]
+fn test_monitor_handles_frames_for_other_controllers() {
+  let client = Client::builder()
+    .monitor("127.0.0.1:56812".parse().unwrap())
+    .bind("127.0.0.1:0")
+    .unwrap();
+  let listener = client.listen();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+  let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source() ^ 1);
+  let bytes = serialize::encode(&msg).unwrap();
+  bulb.send_to(&bytes[..], "127.0.0.1:56812").unwrap();
+  client.wait_for_device(0xAB, Duration::from_secs(5)).unwrap();
+
+  client.close();
+  listener.join().unwrap();
+}
+
+#[test]
+fn test_bulbs_are_sent_to_the_advertised_port() {
+  let client = Client::new("127.0.0.1:56813").unwrap();
+  let replies = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let service = UdpSocket::bind("127.0.0.1:0").unwrap();
+  service.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let port = service.local_addr().unwrap().port();
+
+  let state = Payload::Device(Device::StateService(Service::Udp, port.into()));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  let bytes = serialize::encode(&msg).unwrap();
+  replies.send_to(&bytes[..], "127.0.0.1:56813").unwrap();
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+
+  let bulb = client.device(0xAB).unwrap();
+  assert_eq!(u32::from(port), bulb.port());
+  assert_eq!(service.local_addr().unwrap(), bulb.addr());
+
+  bulb.send_msg(Payload::Device(Device::GetPower), false).unwrap();
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  let (amt, _) = service.recv_from(&mut buf).unwrap();
+  let msg = Message::from_bytes(&buf[..amt]).unwrap();
+  assert_eq!(&Payload::Device(Device::GetPower), msg.payload());
+}
+
+#[test]
+fn test_devices_can_be_known_without_discovery() {
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  bulb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let client = Client::builder()
+    .with_device(0xAB, bulb.local_addr().unwrap())
+    .transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+
+  let known = client.device(0xAB).unwrap();
+  assert_eq!(bulb.local_addr().unwrap(), known.addr());
+  known.send_msg(Payload::Device(Device::GetLabel), false).unwrap();
+
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  let (amt, _) = bulb.recv_from(&mut buf).unwrap();
+  let msg = Message::from_bytes(&buf[..amt]).unwrap();
+  assert_eq!(MacAddress::from(0xAB), msg.target());
+  assert_eq!(&Payload::Device(Device::GetLabel), msg.payload());
+}
+
+#[test]
+fn test_hosts_are_resolved_and_cached() {
+  let host = Host::new("127.0.0.1");
+  assert_eq!(
+    vec!["127.0.0.1:56700".parse::<SocketAddr>().unwrap()],
+    host.to_socket_addrs().unwrap().collect::<Vec<_>>()
+  );
+  assert!(host.resolved.lock().unwrap().is_some());
+  host.forget();
+  assert!(host.resolved.lock().unwrap().is_none());
+
+  let host = Host::new("localhost:1234");
+  assert_eq!(1234, resolve(&host).unwrap().port());
+
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  bulb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let name = bulb.local_addr().unwrap().to_string();
+  let client = Client::builder()
+    .with_device_host(0xAB, &name)
+    .transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+
+  let known = client.device(0xAB).unwrap();
+  assert_eq!(Some(&name[..]), known.host().map(Host::name));
+  assert_eq!(bulb.local_addr().unwrap(), known.addr());
+  known.send_msg(Payload::Device(Device::GetLabel), false).unwrap();
+
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  let (amt, _) = bulb.recv_from(&mut buf).unwrap();
+  let msg = Message::from_bytes(&buf[..amt]).unwrap();
+  assert_eq!(&Payload::Device(Device::GetLabel), msg.payload());
+}
+
+#[test]
+fn test_devices_follow_a_new_address() {
+  let old = "10.0.0.2:56700".parse().unwrap();
+  let client = Client::builder()
+    .with_device(0xAB, old)
+    .bind("127.0.0.1:56814")
+    .unwrap();
+  let events = client.events();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let new = bulb.local_addr().unwrap();
+
+  let power = Payload::Device(Device::StatePower(Power::Max));
+  let msg = Message::new(power, false, 0xAB, 0).with_source(client.source());
+  let bytes = serialize::encode(&msg).unwrap();
+  for _ in 0..2 {
+    bulb.send_to(&bytes[..], "127.0.0.1:56814").unwrap();
+    assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  }
+
+  assert_eq!(new, client.device(0xAB).unwrap().ip);
+  let moves: Vec<_> = std::iter::from_fn(|| events.try_next())
+    .filter(|event| match *event {
+      DeviceEvent::AddressChanged(..) => true,
+      _ => false,
+    })
+    .collect();
+  assert_eq!(vec![DeviceEvent::AddressChanged(MacAddress::from(0xAB), new)], moves);
+
+  // another controller telling the device what to do doesn't move it.
+  let phone = "127.0.0.1:40000".parse().unwrap();
+  let set_power = Payload::Device(Device::SetPower(Power::Standby));
+  let msg = Message::new(set_power, false, 0xAB, 0).with_source(0x1234);
+  client
+    .listener()
+    .dispatch(&serialize::encode(&msg).unwrap()[..], phone, true);
+  assert_eq!(new, client.device(0xAB).unwrap().ip);
+  assert!(std::iter::from_fn(|| events.try_next()).all(|event| match event {
+    DeviceEvent::AddressChanged(..) => false,
+    _ => true,
+  }));
+}
+
+#[test]
+fn test_stale_devices_are_evicted() {
+  let client = Client::builder()
+    .with_device(0xCD, "10.0.0.2:56700".parse().unwrap())
+    .bind("127.0.0.1:56815")
+    .unwrap();
+  let events = client.events();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+  let state = Payload::Device(Device::StateService(Service::Udp, 56700));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  let bytes = serialize::encode(&msg).unwrap();
+  bulb.send_to(&bytes[..], "127.0.0.1:56815").unwrap();
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+
+  assert!(!client.poll_once(Duration::from_millis(1)).unwrap());
+  assert!(client.device(0xAB).is_some());
+
+  client.set_evict_after(Some(Duration::from_millis(1)));
+  thread::sleep(Duration::from_millis(5));
+  assert!(!client.poll_once(Duration::from_millis(1)).unwrap());
+  assert!(client.device(0xAB).is_none());
+  assert!(client.device(0xCD).is_some());
+  assert!(std::iter::from_fn(|| events.try_next())
+    .any(|event| event == DeviceEvent::Evicted(MacAddress::from(0xAB))));
+}
+
+#[test]
+fn test_devices_missing_echoes_are_degraded() {
+  let client = Client::new("127.0.0.1:56816").unwrap();
+  let events = client.events();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  bulb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let port = bulb.local_addr().unwrap().port().into();
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+
+  let state = Payload::Device(Device::StateService(Service::Udp, port));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  let bytes = serialize::encode(&msg).unwrap();
+  bulb.send_to(&bytes[..], "127.0.0.1:56816").unwrap();
+  assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+  assert_eq!(Health::Online, client.device(0xAB).unwrap().health());
+
+  // the first probe goes unanswered, and is counted as missed by the second.
+  client.probe_health();
+  bulb.recv_from(&mut buf).unwrap();
+  client.probe_health();
+  assert_eq!(Health::Degraded, client.device(0xAB).unwrap().health());
+
+  for _ in 0..HEALTH_WINDOW {
+    let (amt, _) = bulb.recv_from(&mut buf).unwrap();
+    let msg = Message::from_bytes(&buf[..amt]).unwrap();
+    let echo = match *msg.payload() {
+      Payload::Device(Device::EchoRequest(echo)) => echo,
+      ref payload => panic!("expected an echo request, got {:?}", payload),
+    };
+    let response = Payload::Device(Device::EchoResponse(echo));
+    let msg = Message::new(response, false, 0xAB, 0).with_source(client.source());
+    let bytes = serialize::encode(&msg).unwrap();
+    bulb.send_to(&bytes[..], "127.0.0.1:56816").unwrap();
+    assert!(client.poll_once(Duration::from_secs(5)).unwrap());
+    client.probe_health();
+  }
+  assert_eq!(Health::Online, client.device(0xAB).unwrap().health());
+
+  let changes: Vec<_> = std::iter::from_fn(|| events.try_next())
+    .filter(|event| match *event {
+      DeviceEvent::HealthChanged(..) => true,
+      _ => false,
+    })
+    .collect();
+  assert_eq!(
+    vec![
+      DeviceEvent::HealthChanged(MacAddress::from(0xAB), Health::Degraded),
+      DeviceEvent::HealthChanged(MacAddress::from(0xAB), Health::Online),
+    ],
+    changes
+  );
+}
+
+#[test]
+fn test_devices_can_be_added_and_forgotten() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let events = client.events();
+  let first = "10.0.0.2:56700".parse().unwrap();
+  let second = "10.0.0.3:56700".parse().unwrap();
+
+  assert!(client.add_device(0xAB, first).is_none());
+  assert_eq!(first, client.add_device(0xAB, second).unwrap().addr());
+  assert_eq!(second, client.device(0xAB).unwrap().addr());
+  assert!(client.add_device(0xCD, first).is_none());
+
+  assert!(client.forget(0xAB).is_some());
+  assert!(client.forget(0xAB).is_none());
+  assert!(client.device(0xAB).is_none());
+  client.clear_devices();
+  assert!(client.devices().is_empty());
+
+  assert_eq!(
+    vec![
+      DeviceEvent::Discovered(MacAddress::from(0xAB)),
+      DeviceEvent::Discovered(MacAddress::from(0xCD)),
+      DeviceEvent::Evicted(MacAddress::from(0xAB)),
+      DeviceEvent::Evicted(MacAddress::from(0xCD)),
+    ],
+    std::iter::from_fn(|| events.try_next()).collect::<Vec<_>>()
+  );
+}
+
+#[test]
+fn test_devices_can_be_saved_and_restored() {
+  let path = std::env::temp_dir().join(format!("lifx-{}.devices", random_source()));
+  assert!(Client::builder()
+    .with_saved_devices(&path)
+    .unwrap()
+    .saved
+    .is_empty());
+
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let addr = "10.0.0.2:56700".parse().unwrap();
+  client.add_device(0xAB, addr);
+  client.devices.write().get_mut(&0xAB).unwrap().group =
+    Some("Upstairs".into());
+  client.save_devices(&path).unwrap();
+
+  let restored = Client::builder()
+    .with_saved_devices(&path)
+    .unwrap()
+    .transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  fs::remove_file(&path).unwrap();
+
+  let bulb = restored.device(0xAB).unwrap();
+  assert_eq!(addr, bulb.addr());
+  assert_eq!(Some("Upstairs"), bulb.group());
+  assert_eq!(&[(Service::Udp, 56700)], bulb.services());
+}
+
+#[test]
+fn test_devices_can_be_found_by_alias() {
+  let path = std::env::temp_dir().join(format!("lifx-{}.devices", random_source()));
+  let client = Client::builder()
+    .with_alias("kitchen-left", 0xAB)
+    .transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  assert!(client.device("kitchen-left").is_none());
+  match client.get_label("hallway", Duration::from_millis(10)) {
+    Err(Error::UnknownAlias(alias)) => assert_eq!("hallway", alias),
+    other => panic!("unexpected result: {:?}", other),
+  }
+
+  client.add_device(0xAB, "10.0.0.2:56700".parse().unwrap());
+  assert_eq!(MacAddress::from(0xAB), client.device("kitchen-left").unwrap().target);
+  assert_eq!(None, client.set_alias("hallway", 0xCD));
+  assert_eq!(Some(0xCD.into()), client.set_alias("hallway", 0xAB));
+  client.save_devices(&path).unwrap();
+  assert_eq!(Some(0xAB.into()), client.remove_alias("hallway"));
+  assert!(client.forget("hallway").is_none());
+
+  let restored = Client::builder()
+    .with_saved_devices(&path)
+    .unwrap()
+    .transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  fs::remove_file(&path).unwrap();
+
+  assert_eq!(2, restored.aliases().len());
+  assert!(restored.forget("hallway").is_some());
+  assert!(restored.device("kitchen-left").is_none());
+}
+
+#[test]
+fn test_inventory_is_exported_as_json_and_csv() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  client.add_device(0xCD, "10.0.0.3:56700".parse().unwrap());
+  client.add_device(0xAB, "10.0.0.2:56700".parse().unwrap());
+  if let Some(bulb) = client.devices.write().get_mut(&0xAB) {
+    bulb.label = Some("Kitchen, \"left\"".into());
+    bulb.group = Some("Upstairs".into());
+    bulb.version = Some((1, 27, 0));
+    bulb.host_firmware = Some(FirmwareVersion::new(0, 3 << 16 | 70));
+    bulb.power = Some(Power::Max);
+  }
+
+  let mut json = Vec::new();
+  client.export_inventory(&mut json, InventoryFormat::Json).unwrap();
+  assert_eq!(
+    "[\n  {\"target\": \"ab:00:00:00:00:00\", \"ip\": \"10.0.0.2\", \
+     \"label\": \"Kitchen, \\\"left\\\"\", \"group\": \"Upstairs\", \
+     \"location\": null, \"vendor\": 1, \"product\": 27, \
+     \"host_firmware\": \"3.70\", \"wifi_firmware\": null, \"power\": \"on\"},\n  \
+     {\"target\": \"cd:00:00:00:00:00\", \"ip\": \"10.0.0.3\", \"label\": null, \
+     \"group\": null, \"location\": null, \"vendor\": null, \"product\": null, \
+     \"host_firmware\": null, \"wifi_firmware\": null, \"power\": null}\n]\n",
+    String::from_utf8(json).unwrap()
+  );
+
+  let mut csv = Vec::new();
+  client.export_inventory(&mut csv, InventoryFormat::Csv).unwrap();
+  assert_eq!(
+    "target,ip,label,group,location,vendor,product,host_firmware,wifi_firmware,\
+     power\r\n\
+     ab:00:00:00:00:00,10.0.0.2,\"Kitchen, \"\"left\"\"\",Upstairs,,1,27,\
+     3.70,,on\r\n\
+     cd:00:00:00:00:00,10.0.0.3,,,,,,,,\r\n",
+    String::from_utf8(csv).unwrap()
+  );
+}
+
+#[test]
+fn test_devices_can_be_filtered() {
+  let chars = |s: &str| s.chars().collect::<Vec<_>>();
+  assert!(glob_match(&chars("Kitchen*"), &chars("Kitchen Left")));
+  assert!(glob_match(&chars("*h?n*"), &chars("Kitchen")));
+  assert!(glob_match(&chars("**"), &chars("")));
+  assert!(!glob_match(&chars("Kitchen?"), &chars("Kitchen")));
+  assert!(!glob_match(&chars("*Left"), &chars("Left lamp")));
+
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  for &(target, label, group) in &[
+    (0xCD, "Kitchen Right", "Kitchen"),
+    (0xAB, "Kitchen Left", "Kitchen"),
+    (0xEF, "Hallway", "Downstairs"),
+  ] {
+    client.add_device(target, "10.0.0.2:56700".parse().unwrap());
+    let mut devices = client.devices.write();
+    let bulb = devices.get_mut(&target).unwrap();
+    bulb.label = Some(label.into());
+    bulb.group = Some(group.into());
+    bulb.location = Some("Home".into());
+  }
+  client.add_device(0x12, "10.0.0.3:56700".parse().unwrap());
+
+  let targets = |bulbs: Vec<Bulb<SocketAddr>>| {
+    bulbs.iter().map(|bulb| u64::from(bulb.target)).collect::<Vec<_>>()
+  };
+  assert_eq!(vec![0xAB, 0xCD], targets(client.devices_in_group("Kitchen")));
+  assert_eq!(vec![0xAB, 0xCD, 0xEF], targets(client.devices_in_location("Home")));
+  assert_eq!(vec![0xEF], targets(client.devices_matching("Hall*")));
+  assert_eq!(vec![0xAB], targets(client.devices_matching("* Left")));
+  assert!(client.devices_in_group("kitchen").is_empty());
+  assert_eq!(3, client.devices_matching("*").len());
+}
+
+#[test]
+fn test_registry_snapshots_are_copied_on_write() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  client.add_device(0xAB, "10.0.0.2:56700".parse().unwrap());
+  client.add_device(0xCD, "10.0.0.3:56700".parse().unwrap());
+  let before = client.devices.load();
+
+  {
+    let mut devices = client.devices.write();
+    devices.get_mut(&0xAB).unwrap().label = Some("Kitchen".into());
+    // readers aren't held up by a change in progress, and don't see it yet.
+    assert_eq!(None, client.device(0xAB).unwrap().label());
+  }
+
+  let after = client.devices.load();
+  assert_eq!(None, before[&0xAB].label());
+  assert_eq!(Some("Kitchen"), after[&0xAB].label());
+  assert!(Arc::ptr_eq(&before[&0xCD], &after[&0xCD]));
+
+  client.devices.write().get_mut(&0xEF);
+  assert!(Arc::ptr_eq(&after, &client.devices.load()));
+
+  // a sweep that changes nothing keeps the snapshot, and a frame only copies
+  // the bulb that sent it.
+  let listener = client.listener();
+  listener.sweep();
+  assert!(Arc::ptr_eq(&after, &client.devices.load()));
+
+  let state = Payload::Light(Light::StatePower(Power::Max));
+  let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+  let src = "10.0.0.2:56700".parse().unwrap();
+  listener.dispatch(&serialize::encode(&msg).unwrap()[..], src, false);
+  let latest = client.devices.load();
+  assert_eq!(Some(Power::Max), latest[&0xAB].power());
+  assert!(Arc::ptr_eq(&after[&0xCD], &latest[&0xCD]));
+}
+
+#[test]
+fn test_bulbs_keep_the_state_devices_report() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let listener = client.listener();
+  let src = "10.0.0.2:56700".parse().unwrap();
+  let firmware = FirmwareVersion::new(0, 3 << 16 | 70);
+  for payload in vec![
+    Payload::Device(Device::StateService(Service::Udp, 56700)),
+    Payload::Device(Device::StateGroup([1; 16], "Upstairs".into(), 0)),
+    Payload::Device(Device::StateLocation([2; 16], "Home".into(), 0)),
+    Payload::Device(Device::StateVersion(1, 27, 0)),
+    Payload::Device(Device::StateHostFirmware(firmware)),
+    Payload::Device(Device::StateWifiFirmware(firmware)),
+    Payload::Device(Device::StateHostInfo(0.5, 1, 2)),
+    Payload::Device(Device::StateWifiInfo(0.25, 3, 4)),
+    Payload::Device(Device::StateInfo(5, 6, 7)),
+    Payload::Light(Light::StateInfrared(8)),
+    Payload::Light(Light::State(HSBK::default(), Power::Max, "Lamp".into())),
+  ] {
+    let msg = Message::new(payload, false, 0xAB, 0).with_source(client.source());
+    listener.dispatch(&serialize::encode(&msg).unwrap()[..], src, false);
+  }
+
+  let bulb = client.device(0xAB).unwrap();
+  assert_eq!(Some("Lamp"), bulb.label());
+  assert_eq!(Some("Upstairs"), bulb.group());
+  assert_eq!(Some("Home"), bulb.location());
+  assert_eq!(Some(Power::Max), bulb.power());
+  assert_eq!(Some(HSBK::default()), bulb.color());
+  assert_eq!(Some((1, 27, 0)), bulb.version());
+  assert_eq!(Some(firmware), bulb.host_firmware());
+  assert_eq!(Some(firmware), bulb.wifi_firmware());
+  assert_eq!(Some((0.5, 1, 2)), bulb.host_info());
+  assert_eq!(Some((0.25, 3, 4)), bulb.wifi_info());
+  assert_eq!(Some((5, 6, 7)), bulb.info());
+  assert_eq!(Some(8), bulb.infrared());
+}
+
+#[test]
+fn test_refresh_waits_for_every_property() {
+  let client = Client::new("127.0.0.1:56817").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+  client.add_device(0xAB, bulb_addr);
+
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    for _ in 0..refresh_queries().len() {
+      let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+      let request = Message::from_bytes(&buf[..amt]).unwrap();
+      let state = match *request.payload() {
+        Payload::Device(Device::GetLabel) => {
+          Payload::Device(Device::StateLabel("Lamp".into()))
+        }
+        Payload::Device(Device::GetGroup) => {
+          Payload::Device(Device::StateGroup([1; 16], "Upstairs".into(), 0))
+        }
+        Payload::Device(Device::GetLocation) => {
+          Payload::Device(Device::StateLocation([2; 16], "Home".into(), 0))
+        }
+        Payload::Device(Device::GetVersion) => {
+          Payload::Device(Device::StateVersion(1, 27, 0))
+        }
+        Payload::Device(Device::GetHostFirmware) => Payload::Device(
+          Device::StateHostFirmware(FirmwareVersion::new(0, 3 << 16 | 70)),
+        ),
+        Payload::Light(Light::Get) => Payload::Light(Light::State(
+          HSBK::default(),
+          Power::Max,
+          "Lamp".into(),
+        )),
+        ref other => panic!("unexpected request: {:?}", other),
+      };
+      let reply = Message::new(state, false, 0xAB, request.sequence())
+        .with_source(request.source());
+      bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+    }
+  });
+
+  let bulb = client.refresh(0xAB, Duration::MAX).unwrap();
+  assert_eq!(Some("Lamp"), bulb.label());
+  assert_eq!(Some("Upstairs"), bulb.group());
+  assert_eq!(Some("Home"), bulb.location());
+  assert_eq!(Some((1, 27, 0)), bulb.version());
+  assert_eq!(Some(Power::Max), bulb.power());
+  match client.refresh(0xCD, Duration::from_millis(10)) {
+    Err(Error::NotDiscovered(mac)) => assert_eq!(MacAddress::from(0xCD), mac),
+    other => panic!("expected an unknown device, got {:?}", other),
+  }
+
+  responder.join().unwrap();
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_cached_properties_are_fetched_once_stale() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let src = device.local_addr().unwrap();
+  client.add_device(0xAB, src);
+  assert_eq!(None, client.device(0xAB).unwrap().updated(Property::Power));
+
+  let state = Light::State(HSBK::default(), Power::Max, "Lamp".into());
+  let msg = Message::new(Payload::Light(state), false, 0xAB, 0)
+    .with_source(client.source());
+  client
+    .listener()
+    .dispatch(&serialize::encode(&msg).unwrap()[..], src, false);
+
+  let bulb = client.device(0xAB).unwrap();
+  assert!(bulb.updated(Property::Color).unwrap() <= Instant::now());
+  assert_eq!(None, bulb.updated(Property::Version));
+
+  let (fresh, timeout) = (Duration::from_secs(60), Duration::from_millis(10));
+  assert_eq!(
+    HSBK::default(),
+    client.color_cached_or_fetch(0xAB, fresh, timeout).unwrap()
+  );
+  assert_eq!(
+    Power::Max,
+    client.power_cached_or_fetch(0xAB, fresh, timeout).unwrap()
+  );
+  assert_eq!("Lamp", client.label_cached_or_fetch(0xAB, fresh, timeout).unwrap());
+
+  // a stale property is requested, and nothing answers.
+  thread::sleep(Duration::from_millis(5));
+  let stale = Duration::from_millis(1);
+  match client.color_cached_or_fetch(0xAB, stale, timeout) {
+    Err(Error::Timeout { .. }) => (),
+    other => panic!("expected a timeout, got {:?}", other),
+  }
+  let mut buf = [0; consts::MAX_FRAME_SIZE];
+  let (amt, _) = device.recv_from(&mut buf[..]).unwrap();
+  let request = Message::from_bytes(&buf[..amt]).unwrap();
+  assert_eq!(&Payload::Light(Light::Get), request.payload());
+}
+
+#[test]
+fn test_only_changed_state_emits_events() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let listener = client.listener();
+  let src = "10.0.0.2:56700".parse().unwrap();
+  client.add_device(0xAB, src);
+  let events = client.events();
+
+  for payload in [
+    Payload::Device(Device::StateLocation([2; 16], "Home".into(), 0)),
+    Payload::Device(Device::StateLocation([2; 16], "Home".into(), 0)),
+    Payload::Device(Device::StateGroup([1; 16], "Upstairs".into(), 0)),
+    Payload::Device(Device::StateGroup([1; 16], "Upstairs".into(), 0)),
+    Payload::Light(Light::StateInfrared(8)),
+    Payload::Light(Light::StateInfrared(8)),
+    Payload::Light(Light::StateInfrared(0)),
+  ] {
+    let msg = Message::new(payload, false, 0xAB, 0).with_source(client.source());
+    listener.dispatch(&serialize::encode(&msg).unwrap()[..], src, false);
+  }
+
+  assert_eq!(
+    vec![
+      DeviceEvent::LocationChanged(MacAddress::from(0xAB), "Home".into()),
+      DeviceEvent::GroupChanged(MacAddress::from(0xAB), "Upstairs".into()),
+      DeviceEvent::InfraredChanged(MacAddress::from(0xAB), 8),
+      DeviceEvent::InfraredChanged(MacAddress::from(0xAB), 0),
+    ],
+    std::iter::from_fn(|| events.try_next()).collect::<Vec<_>>()
+  );
+}
+
+#[test]
+fn test_state_watch_yields_each_change() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let listener = client.listener();
+  let src = "10.0.0.2:56700".parse().unwrap();
+  let mut watch = client.subscribe_state(0xAB).unwrap();
+  assert!(client.subscribe_state("kitchen").is_err());
+  assert_eq!(None, watch.latest());
+
+  client.add_device(0xAB, src);
+  let state = watch.next().unwrap();
+  assert_eq!((src, None), (state.addr, state.label));
+
+  for label in &["Lamp", "Lamp"] {
+    let state = Payload::Device(Device::StateLabel(label.to_string()));
+    let msg = Message::new(state, false, 0xAB, 0).with_source(client.source());
+    listener.dispatch(&serialize::encode(&msg).unwrap()[..], src, false);
+  }
+  assert_eq!(Some("Lamp".into()), watch.next().unwrap().label);
+
+  // the repeated label changed nothing.
+  let waker = Waker::from(Arc::new(Unpark(thread::current())));
+  let mut cx = Context::from_waker(&waker);
+  assert!(watch.poll_changed(&mut cx).is_pending());
+
+  client.events.close();
+  assert_eq!(None, watch.next());
+}
+
+#[test]
+fn test_toggled_inverts_power() {
+  let (level, payload) = toggled(Some(Power::Level(10)), None);
+  assert_eq!(Power::Standby, level);
+  assert_eq!(Payload::Device(Device::SetPower(Power::Standby)), payload);
+
+  let duration = Duration::from_millis(250);
+  let (level, payload) = toggled(None, Some(duration));
+  assert_eq!(Power::Max, level);
+  let set_power = Light::SetPower(Power::Max, duration.into());
+  assert_eq!(Payload::Light(set_power), payload);
+}
+
+#[test]
+fn test_toggle_flips_and_caches_power() {
+  let client = Client::new("127.0.0.1:56818").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+  client.add_device(0xAB, bulb_addr);
+
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let mut requests = Vec::new();
+    for _ in 0..3 {
+      let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+      let request = Message::from_bytes(&buf[..amt]).unwrap();
+      let reply = match *request.payload() {
+        Payload::Device(Device::GetPower) => {
+          Payload::Device(Device::StatePower(Power::Max))
+        }
+        _ => Payload::Device(Device::Acknowledgement),
+      };
+      let reply = Message::new(reply, false, 0xAB, request.sequence())
+        .with_source(request.source());
+      bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+      requests.push(request.payload().clone());
+    }
+    requests
+  });
+
+  let max_age = Duration::from_secs(60);
+  let timeout = Duration::from_secs(5);
+  assert_eq!(Power::Standby, client.toggle(0xAB, None, max_age, timeout).unwrap());
+  assert_eq!(Some(Power::Standby), client.device(0xAB).unwrap().power());
+
+  // the second toggle answers from the cache, without requesting the power.
+  let duration = Duration::from_millis(250);
+  let level = client.toggle(0xAB, Some(duration), max_age, timeout).unwrap();
+  assert_eq!(Power::Max, level);
+
+  let requests = responder.join().unwrap();
+  assert_eq!(Payload::Device(Device::GetPower), requests[0]);
+  assert_eq!(Payload::Device(Device::SetPower(Power::Standby)), requests[1]);
+  let set_power = Light::SetPower(Power::Max, duration.into());
+  assert_eq!(Payload::Light(set_power), requests[2]);
+
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_component_payload_prefers_waveform() {
+  let color = sample_color();
+  let duration = Duration::from_millis(250);
+  let dim = Component::Brightness(Brightness::MIN);
+
+  match dim.payload(true, None, duration) {
+    Some(Payload::Light(Light::SetWaveformOptional(
+      false,
+      sent,
+      250,
+      _,
+      _,
+      Waveform::Saw,
+      false,
+      false,
+      true,
+      false,
+    ))) => assert_eq!(HSBK::default(), sent),
+    other => panic!("expected SetWaveformOptional, got {:?}", other),
+  }
+
+  let dimmed = color.with_brightness(Brightness::MIN);
+  let set_color = Payload::Light(Light::SetColor(dimmed, 250.into()));
+  assert_eq!(Some(set_color), dim.payload(false, Some(color), duration));
+  assert_eq!(None, dim.payload(false, None, duration));
+}
+
+#[test]
+fn test_set_component_falls_back_to_set_color() {
+  let client = Client::new("127.0.0.1:56819").unwrap();
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let bulb_addr = bulb.local_addr().unwrap();
+  let thread = client.listen();
+  client.add_device(0xAB, bulb_addr);
+
+  let color = sample_color();
+  let listener = client.listener();
+  for payload in [
+    Payload::Device(Device::StateUnhandled(consts::LIGHT_SET_WAVEFORM_OPTIONAL)),
+    Payload::Light(Light::State(color, Power::Max, "Lamp".into())),
+  ] {
+    let msg = Message::new(payload, false, 0xAB, 0).with_source(client.source());
+    listener.dispatch(&serialize::encode(&msg).unwrap()[..], bulb_addr, false);
+  }
+
+  // the bulb keeps the color it is set to, and reports it when asked.
+  let responder = thread::spawn(move || {
+    let mut buf = [0; consts::MAX_FRAME_SIZE];
+    let mut current = color;
+    let mut requests = Vec::new();
+    for _ in 0..3 {
+      let (amt, src) = bulb.recv_from(&mut buf[..]).unwrap();
+      let request = Message::from_bytes(&buf[..amt]).unwrap();
+      let reply = match *request.payload() {
+        Payload::Light(Light::SetColor(color, _)) => {
+          current = color;
+          Payload::Device(Device::Acknowledgement)
+        }
+        Payload::Light(Light::Get) => {
+          Payload::Light(Light::State(current, Power::Max, "Lamp".into()))
+        }
+        ref other => panic!("unexpected request: {:?}", other),
+      };
+      let reply = Message::new(reply, false, 0xAB, request.sequence())
+        .with_source(request.source());
+      bulb.send_to(&serialize::encode(&reply).unwrap()[..], src).unwrap();
+      requests.push(request.payload().clone());
+    }
+    requests
+  });
+
+  // a change made through a copy of the bulb, which the client hasn't seen.
+  let duration = Duration::from_millis(250);
+  let mut warm = client.device(0xAB).unwrap();
+  warm.set_kelvin_only(Kelvin::MAX, duration, false).unwrap();
+  let warm = color.with_kelvin(Kelvin::MAX);
+
+  let timeout = Duration::from_secs(5);
+  let dim = Brightness::MIN;
+  client.set_brightness_only(0xAB, dim, duration, timeout).unwrap();
+
+  let requests = responder.join().unwrap();
+  let dimmed = warm.with_brightness(dim);
+  assert_eq!(Payload::Light(Light::SetColor(warm, 250.into())), requests[0]);
+  assert_eq!(Payload::Light(Light::Get), requests[1]);
+  assert_eq!(Payload::Light(Light::SetColor(dimmed, 250.into())), requests[2]);
+  assert_eq!(Some(dimmed), client.device(0xAB).unwrap().color());
+
+  client.close();
+  let _ = thread.join();
+}
+
+#[test]
+fn test_unawaited_acks_are_forgotten() {
+  let client = Client::with_transport(UdpSocket::bind("127.0.0.1:0").unwrap());
+  let bulb = UdpSocket::bind("127.0.0.1:0").unwrap();
+  let payload = Payload::Device(Device::SetPower(Power::Max));
+  let seq = client
+    .send_msg(bulb.local_addr().unwrap(), payload, true, 0xAB)
+    .unwrap();
+
+  client.listener().sweep();
+  assert!(client.in_flight.acks.lock().unwrap().contains_key(&(0xAB, seq)));
+
+  let sent = Instant::now() - FORGET_ACK_AFTER - Duration::from_secs(1);
+  client.in_flight.acks.lock().unwrap().get_mut(&(0xAB, seq)).unwrap().sent = sent;
+  client.listener().sweep();
+  match client.await_ack(0xAB, seq, Duration::from_millis(1)) {
+    Err(Error::UnknownSequence(s)) => assert_eq!(seq, s),
+    other => panic!("expected an unknown sequence, got {:?}", other),
   }
-  assert_eq!(0, next_sequence());
+  let sequences = client.endpoint.sequences.0.lock().unwrap();
+  assert!(sequences[&0xAB].1.is_empty());
 }
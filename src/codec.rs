@@ -0,0 +1,139 @@
+use std::io;
+
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use header::Header;
+use message::Message;
+use serialize::{DecodeError, LabelMode, WireEncode};
+
+/// frames messages for `tokio_util::codec`, so they can be sent and received
+/// with `UdpFramed` instead of the blocking `Client`.
+///
+/// # Notes
+///
+///   * Frames are split using the size field in each header, so a datagram
+///     holding several frames decodes to several messages.
+///   * A frame that does not decode is dropped from the buffer before the
+///     error is returned, so the next frame can still be read. Bytes at the
+///     end of a datagram that do not make up a whole frame are dropped.
+///
+#[derive(Debug, Copy, Clone)]
+pub struct LifxCodec {
+  labels: LabelMode,
+}
+
+impl LifxCodec {
+  /// creates a codec that decodes labels lossily.
+  ///
+  #[inline]
+  pub fn new() -> LifxCodec {
+    LifxCodec {
+      labels: LabelMode::Lossy,
+    }
+  }
+
+  /// sets how labels that are not valid UTF-8 are decoded.
+  ///
+  #[inline]
+  pub fn with_label_mode(mut self, labels: LabelMode) -> LifxCodec {
+    self.labels = labels;
+    self
+  }
+}
+
+impl Default for LifxCodec {
+  fn default() -> LifxCodec {
+    LifxCodec::new()
+  }
+}
+
+#[inline]
+fn invalid_data(e: DecodeError) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Decoder for LifxCodec {
+  type Item = Message;
+  type Error = io::Error;
+
+  fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Message>> {
+    if buf.len() < Header::mem_size() as usize {
+      return Ok(None);
+    }
+
+    let size = Header::parse(&buf[..]).map_err(invalid_data)?.size() as usize;
+    if size < Header::mem_size() as usize {
+      // there is no way to find the next frame
+      buf.clear();
+      return Err(invalid_data(DecodeError::SizeMismatch {
+        field: "header.size",
+        offset: 0,
+        expected: Header::mem_size() as usize,
+        actual: size,
+      }));
+    }
+    if size > buf.len() {
+      return Ok(None);
+    }
+
+    let frame = buf.split_to(size);
+    Message::from_bytes_with(&frame[..], self.labels)
+      .map(Some)
+      .map_err(invalid_data)
+  }
+
+  fn decode_eof(&mut self, buf: &mut BytesMut) -> io::Result<Option<Message>> {
+    match self.decode(buf)? {
+      Some(msg) => Ok(Some(msg)),
+      None => {
+        if !buf.is_empty() {
+          warn!("Ignoring {} trailing bytes", buf.len());
+          buf.clear();
+        }
+        Ok(None)
+      }
+    }
+  }
+}
+
+impl Encoder<Message> for LifxCodec {
+  type Error = io::Error;
+
+  fn encode(&mut self, msg: Message, buf: &mut BytesMut) -> io::Result<()> {
+    msg.encode(&mut buf.writer())
+  }
+}
+
+#[test]
+fn test_codec_splits_frames() {
+  use payload::{Device, Payload};
+
+  let mut codec = LifxCodec::new();
+  let mut buf = BytesMut::new();
+
+  codec
+    .encode(
+      Message::new(Payload::Device(Device::GetService), false, 0, 0),
+      &mut buf,
+    )
+    .unwrap();
+  codec
+    .encode(
+      Message::new(Payload::Device(Device::StateUnhandled(2)), false, 0, 1),
+      &mut buf,
+    )
+    .unwrap();
+  buf.extend_from_slice(&[1, 2, 3]);
+
+  match codec.decode_eof(&mut buf).unwrap().unwrap().unpack() {
+    (Payload::Device(Device::GetService), _) => (),
+    _ => panic!("decoded the wrong payload"),
+  }
+  match codec.decode_eof(&mut buf).unwrap().unwrap().unpack() {
+    (Payload::Device(Device::StateUnhandled(2)), _) => (),
+    _ => panic!("decoded the wrong payload"),
+  }
+  assert!(codec.decode_eof(&mut buf).unwrap().is_none());
+  assert!(buf.is_empty());
+}
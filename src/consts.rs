@@ -0,0 +1,124 @@
+/// default udp port bulbs listen on.
+///
+pub const PORT: u16 = 56700;
+
+/// protocol number carried in every header.
+///
+pub const PROTOCOL: u16 = 1024;
+
+/// size of a header in bytes.
+///
+pub const HEADER_SIZE: usize = 36;
+
+/// size of the buffers used to send and receive datagrams. this is larger
+/// than any message the protocol defines.
+///
+pub const MAX_FRAME_SIZE: usize = 4096;
+
+macro_rules! message_types(
+  ($($name:ident = $id:expr;)*) => (
+    $(pub const $name: u16 = $id;)*
+
+    #[cfg(test)]
+    const MESSAGE_TYPES: &[(&str, u16)] = &[$((stringify!($name), $name)),*];
+  )
+);
+
+// message types, named after the payload variant they decode to.
+message_types! {
+  // Device
+  DEVICE_GET_SERVICE = 2;
+  DEVICE_STATE_SERVICE = 3;
+  DEVICE_GET_HOST_INFO = 12;
+  DEVICE_STATE_HOST_INFO = 13;
+  DEVICE_GET_HOST_FIRMWARE = 14;
+  DEVICE_STATE_HOST_FIRMWARE = 15;
+  DEVICE_GET_WIFI_INFO = 16;
+  DEVICE_STATE_WIFI_INFO = 17;
+  DEVICE_GET_WIFI_FIRMWARE = 18;
+  DEVICE_STATE_WIFI_FIRMWARE = 19;
+  DEVICE_GET_POWER = 20;
+  DEVICE_SET_POWER = 21;
+  DEVICE_STATE_POWER = 22;
+  DEVICE_GET_LABEL = 23;
+  DEVICE_SET_LABEL = 24;
+  DEVICE_STATE_LABEL = 25;
+  DEVICE_GET_VERSION = 32;
+  DEVICE_STATE_VERSION = 33;
+  DEVICE_GET_INFO = 34;
+  DEVICE_STATE_INFO = 35;
+  DEVICE_ACKNOWLEDGEMENT = 45;
+  DEVICE_GET_LOCATION = 48;
+  DEVICE_SET_LOCATION = 49;
+  DEVICE_STATE_LOCATION = 50;
+  DEVICE_GET_GROUP = 51;
+  DEVICE_SET_GROUP = 52;
+  DEVICE_STATE_GROUP = 53;
+  DEVICE_ECHO_REQUEST = 58;
+  DEVICE_ECHO_RESPONSE = 59;
+  DEVICE_STATE_UNHANDLED = 223;
+
+  // Light
+  LIGHT_GET = 101;
+  LIGHT_SET_COLOR = 102;
+  LIGHT_SET_WAVEFORM = 103;
+  LIGHT_STATE = 107;
+  LIGHT_GET_POWER = 116;
+  LIGHT_SET_POWER = 117;
+  LIGHT_STATE_POWER = 118;
+  LIGHT_SET_WAVEFORM_OPTIONAL = 119;
+  LIGHT_GET_INFRARED = 120;
+  LIGHT_STATE_INFRARED = 121;
+  LIGHT_SET_INFRARED = 122;
+
+  // MultiZone
+  MULTIZONE_SET_COLOR_ZONES = 501;
+  MULTIZONE_GET_COLOR_ZONES = 502;
+  MULTIZONE_STATE_ZONE = 503;
+  MULTIZONE_STATE_MULTI_ZONE = 506;
+  MULTIZONE_GET_MULTI_ZONE_EFFECT = 507;
+  MULTIZONE_SET_MULTI_ZONE_EFFECT = 508;
+  MULTIZONE_STATE_MULTI_ZONE_EFFECT = 509;
+  MULTIZONE_SET_EXTENDED_COLOR_ZONES = 510;
+  MULTIZONE_GET_EXTENDED_COLOR_ZONES = 511;
+  MULTIZONE_STATE_EXTENDED_COLOR_ZONES = 512;
+
+  // Tile
+  TILE_GET_DEVICE_CHAIN = 701;
+  TILE_STATE_DEVICE_CHAIN = 702;
+  TILE_SET_USER_POSITION = 703;
+  TILE_GET_TILE_STATE64 = 707;
+  TILE_STATE_TILE_STATE64 = 711;
+  TILE_SET_TILE_STATE64 = 715;
+
+  // Relay
+  RELAY_GET_RPOWER = 816;
+  RELAY_SET_RPOWER = 817;
+  RELAY_STATE_RPOWER = 818;
+
+  // Button
+  BUTTON_GET_BUTTON = 905;
+  BUTTON_STATE_BUTTON = 907;
+  BUTTON_GET_BUTTON_CONFIG = 909;
+  BUTTON_SET_BUTTON_CONFIG = 910;
+  BUTTON_STATE_BUTTON_CONFIG = 911;
+}
+
+#[test]
+fn test_message_types_match_payloads() {
+  use payload::{Button, Device, Light, MultiZone, Payload, Relay, Tile};
+
+  for &(name, typ) in MESSAGE_TYPES {
+    let type_name = Payload::type_name(typ).unwrap().replace("::", "");
+
+    assert_eq!(name.replace('_', ""), type_name.to_uppercase());
+  }
+
+  let count = Device::TYPES.len()
+    + Light::TYPES.len()
+    + MultiZone::TYPES.len()
+    + Tile::TYPES.len()
+    + Relay::TYPES.len()
+    + Button::TYPES.len();
+  assert_eq!(count, MESSAGE_TYPES.len());
+}
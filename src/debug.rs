@@ -0,0 +1,193 @@
+use std::fmt::Write;
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use header::Header;
+use payload::Payload;
+use serialize::WireReader;
+
+/// bytes shown on each row of the dump.
+///
+const ROW_LEN: usize = 8;
+
+/// formats a datagram as an annotated hex dump, one row per field, grouped
+/// into the frame, frame address, protocol header and payload of each frame.
+/// works on received datagrams, and on encoded messages before they are sent.
+///
+/// # Notes
+///
+///   * Offsets are in bytes from the start of `bytes`, the same as the offsets
+///     in a `DecodeError`.
+///   * Payloads that fail to decode are dumped as raw bytes, along with the
+///     error. Bytes after the last whole frame are dumped as trailing bytes.
+///
+pub fn dump(bytes: &[u8]) -> String {
+  let header_len = Header::mem_size() as usize;
+  let mut out = String::new();
+  let mut offset = 0;
+
+  while bytes.len() - offset >= header_len {
+    let rest = &bytes[offset..];
+    let size = LittleEndian::read_u16(&rest[0..2]) as usize;
+    let end = if size >= header_len && size <= rest.len() {
+      size
+    } else {
+      rest.len()
+    };
+
+    dump_frame(&mut out, offset, &rest[..end]);
+    offset += end;
+  }
+
+  if offset < bytes.len() {
+    let _ = writeln!(out, "trailing bytes");
+    dump_field(&mut out, offset, &bytes[offset..], "bytes", "");
+  }
+
+  out
+}
+
+/// dumps a single frame, which is at least as long as a header.
+///
+fn dump_frame(out: &mut String, base: usize, frame: &[u8]) {
+  let otap = LittleEndian::read_u16(&frame[2..4]);
+  let flags = frame[22];
+  let typ = LittleEndian::read_u16(&frame[32..34]);
+
+  let _ = writeln!(out, "frame");
+  dump_field(
+    out,
+    base,
+    &frame[0..2],
+    "size",
+    &LittleEndian::read_u16(&frame[0..2]).to_string(),
+  );
+  dump_field(
+    out,
+    base + 2,
+    &frame[2..4],
+    "protocol",
+    &format!(
+      "{} (origin {}, tagged {}, addressable {})",
+      otap & 0b0000_1111_1111_1111,
+      otap >> 14,
+      otap & 0b0010_0000_0000_0000 > 0,
+      otap & 0b0001_0000_0000_0000 > 0
+    ),
+  );
+  dump_field(
+    out,
+    base + 4,
+    &frame[4..8],
+    "source",
+    &LittleEndian::read_u32(&frame[4..8]).to_string(),
+  );
+
+  let _ = writeln!(out, "frame address");
+  dump_field(
+    out,
+    base + 8,
+    &frame[8..16],
+    "target",
+    &format!("{:#X}", LittleEndian::read_u64(&frame[8..16])),
+  );
+  dump_field(out, base + 16, &frame[16..22], "reserved", "");
+  dump_field(
+    out,
+    base + 22,
+    &frame[22..23],
+    "flags",
+    &format!(
+      "ack_required {}, res_required {}",
+      flags & 0b0000_0010 > 0,
+      flags & 0b0000_0001 > 0
+    ),
+  );
+  dump_field(out, base + 23, &frame[23..24], "sequence", &frame[23].to_string());
+
+  let _ = writeln!(out, "protocol header");
+  dump_field(out, base + 24, &frame[24..32], "reserved", "");
+  dump_field(
+    out,
+    base + 32,
+    &frame[32..34],
+    "type",
+    &format!("{} ({})", typ, Payload::type_name(typ).unwrap_or("unknown")),
+  );
+  dump_field(out, base + 34, &frame[34..36], "reserved", "");
+
+  let payload = &frame[Header::mem_size() as usize..];
+  let base = base + Header::mem_size() as usize;
+  let _ = writeln!(out, "payload ({} bytes)", payload.len());
+
+  let mut r = WireReader::traced(payload);
+  if let Err(e) = Payload::decode(&mut r, typ, payload.len() as u16) {
+    let _ = writeln!(out, "  does not decode: {}", e);
+    dump_field(out, base, payload, "bytes", "");
+    return;
+  }
+
+  for &(name, start, end) in r.fields() {
+    let name = name.rsplit('.').next().unwrap_or(name);
+    dump_field(out, base + start, &payload[start..end], name, "");
+  }
+
+  let unmarked = r.fields().last().map_or(0, |&(_, _, end)| end);
+  if unmarked < payload.len() {
+    dump_field(out, base + unmarked, &payload[unmarked..], "bytes", "");
+  }
+}
+
+/// dumps one field, wrapping its bytes over as many rows as it needs. the
+/// name and value are only shown on the first row.
+///
+fn dump_field(
+  out: &mut String,
+  offset: usize,
+  bytes: &[u8],
+  name: &str,
+  value: &str,
+) {
+  for (i, row) in bytes.chunks(ROW_LEN).enumerate() {
+    let mut hex = String::with_capacity(ROW_LEN * 3);
+    for b in row {
+      let _ = write!(hex, "{:02x} ", b);
+    }
+
+    let line = if i == 0 {
+      format!("  {:5}  {:24} {:12} {}", offset, hex, name, value)
+    } else {
+      format!("  {:5}  {}", offset + i * ROW_LEN, hex)
+    };
+    let _ = writeln!(out, "{}", line.trim_end());
+  }
+}
+
+#[test]
+fn test_dump_annotates_fields() {
+  use message::Message;
+  use payload::{Light, Power, HSBK};
+  use serialize;
+  use units::{Brightness, Hue, Kelvin, Saturation};
+
+  let color = HSBK::new(
+    Hue::from(1),
+    Saturation::from(2),
+    Brightness::from(3),
+    Kelvin::new(3500).unwrap(),
+  );
+  let state = Light::State(color, Power::Max, "Lamp".into());
+  let mut bytes =
+    serialize::encode(&Message::new(Payload::Light(state), false, 0, 0)).unwrap();
+  bytes.extend(&[1, 2, 3]);
+
+  let dump = dump(&bytes[..]);
+
+  assert!(dump.contains("107 (Light::State)"));
+  assert!(dump.contains("payload (52 bytes)"));
+  assert!(dump.contains("     36  01 00 02 00 03 00 ac 0d  color"));
+  assert!(dump.contains("     48  4c 61 6d 70 00 00 00 00  label"));
+  assert!(dump.contains("trailing bytes\n     88  01 02 03"));
+}
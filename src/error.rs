@@ -0,0 +1,177 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+
+use mac::MacAddress;
+use payload::Payload;
+use serialize::DecodeError;
+
+/// the errors returned by the client and by encoding and decoding.
+///
+#[derive(Debug)]
+pub enum Error {
+  /// a socket operation failed.
+  ///
+  Io(io::Error),
+
+  /// a message could not be encoded, usually because the buffer it was
+  /// encoded into was too small.
+  ///
+  Encode(io::Error),
+
+  /// a frame could not be decoded.
+  ///
+  Decode(DecodeError),
+
+  /// a datagram was only partly sent.
+  ///
+  ShortWrite { expected: usize, actual: usize },
+
+  /// no reply arrived in time. `waiting_for` names the reply that was
+  /// expected.
+  ///
+  Timeout { waiting_for: &'static str },
+
+  /// a message was sent `attempts` times without being acked.
+  ///
+  Unacknowledged { seq: u8, attempts: u32 },
+
+  /// there is no message in flight with this sequence number.
+  ///
+  UnknownSequence(u8),
+
+  /// the client has not discovered the device.
+  ///
+  NotDiscovered(MacAddress),
+
+  /// no device has been given this alias.
+  ///
+  UnknownAlias(String),
+
+  /// the client has not received a property of the device that it needs.
+  /// `property` names it.
+  ///
+  NotReceived { property: &'static str },
+
+  /// a device replied with a message that does not answer the request.
+  ///
+  UnexpectedResponse(Box<Payload>),
+
+  /// a string is not a mac address or a serial number.
+  ///
+  InvalidMacAddress(String),
+
+  /// `value` is not between `min` and `max`, the range of `quantity`.
+  ///
+  OutOfRange {
+    quantity: &'static str,
+    value: f32,
+    min: f32,
+    max: f32,
+  },
+
+  /// the frame of the test vector `name` and the message it holds first
+  /// differ at byte `offset`.
+  ///
+  VectorMismatch { name: &'static str, offset: usize },
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match *self {
+      Error::Io(ref e) => write!(f, "i/o error: {}", e),
+      Error::Encode(ref e) => write!(f, "encode failed: {}", e),
+      Error::Decode(ref e) => write!(f, "decode failed: {}", e),
+      Error::ShortWrite { expected, actual } => write!(
+        f,
+        "wrong number of bytes written: expected {}, wrote {}",
+        expected, actual
+      ),
+      Error::Timeout { waiting_for } => {
+        write!(f, "timed out waiting for {}", waiting_for)
+      }
+      Error::Unacknowledged { seq, attempts } => write!(
+        f,
+        "message {} was not acknowledged after {} attempts",
+        seq, attempts
+      ),
+      Error::UnknownSequence(seq) => {
+        write!(f, "no message in flight with sequence {}", seq)
+      }
+      Error::NotDiscovered(mac) => write!(f, "unknown device: {}", mac),
+      Error::UnknownAlias(ref alias) => write!(f, "unknown alias: {}", alias),
+      Error::NotReceived { property } => {
+        write!(f, "{} has not been received", property)
+      }
+      Error::UnexpectedResponse(ref payload) => {
+        write!(f, "unexpected response: {:?}", payload)
+      }
+      Error::InvalidMacAddress(ref s) => write!(f, "invalid mac address: '{}'", s),
+      Error::OutOfRange {
+        quantity,
+        value,
+        min,
+        max,
+      } => write!(f, "{} {} is not between {} and {}", quantity, value, min, max),
+      Error::VectorMismatch { name, offset } => {
+        write!(f, "{} does not match its frame at byte {}", name, offset)
+      }
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match *self {
+      Error::Io(ref e) | Error::Encode(ref e) => Some(e),
+      Error::Decode(ref e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  #[inline]
+  fn from(e: io::Error) -> Error {
+    Error::Io(e)
+  }
+}
+
+impl From<DecodeError> for Error {
+  #[inline]
+  fn from(e: DecodeError) -> Error {
+    Error::Decode(e)
+  }
+}
+
+impl From<Error> for String {
+  #[inline]
+  fn from(e: Error) -> String {
+    e.to_string()
+  }
+}
+
+#[test]
+fn test_error_sources() {
+  use std::error::Error as StdError;
+
+  let e = Error::from(DecodeError::Truncated {
+    field: "size",
+    offset: 0,
+    expected: 2,
+    actual: 1,
+  });
+  assert!(e.source().is_some());
+  assert_eq!(
+    "decode failed: size at byte 0 is truncated: needed 2 bytes, 1 left",
+    e.to_string()
+  );
+
+  let e = Error::Timeout {
+    waiting_for: "a response",
+  };
+  assert!(e.source().is_none());
+  assert_eq!("timed out waiting for a response", String::from(e));
+}
@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+
+use mac::MacAddress;
+use payload::{Power, HSBK};
+
+/// a change to the client's view of a device, by the device's target.
+///
+#[derive(Debug, PartialEq, Clone)]
+pub enum DeviceEvent {
+  /// the client heard from a device for the first time, or heard from it
+  /// again after it went offline.
+  ///
+  Discovered(MacAddress),
+
+  /// a device reported a new label.
+  ///
+  LabelChanged(MacAddress, String),
+
+  /// a device reported a new location, by its label.
+  ///
+  LocationChanged(MacAddress, String),
+
+  /// a device reported a new group, by its label.
+  ///
+  GroupChanged(MacAddress, String),
+
+  /// a device reported a new power level.
+  ///
+  PowerChanged(MacAddress, Power),
+
+  /// a device reported a new color.
+  ///
+  ColorChanged(MacAddress, HSBK),
+
+  /// a device reported a new infrared brightness.
+  ///
+  InfraredChanged(MacAddress, u16),
+
+  /// a device the client knew was heard from at a new address, and messages
+  /// to it are sent there from now on.
+  ///
+  AddressChanged(MacAddress, SocketAddr),
+
+  /// the client has not heard from a device for longer than
+  /// `Client::set_offline_after`.
+  ///
+  WentOffline(MacAddress),
+
+  /// a device's health changed, as `Bulb::health` returns.
+  ///
+  HealthChanged(MacAddress, Health),
+
+  /// the client forgot a device, because it had not heard from it for longer
+  /// than `Client::set_evict_after`, or because it was told to with
+  /// `Client::forget` or `Client::clear_devices`.
+  ///
+  Evicted(MacAddress),
+}
+
+/// how a device is doing, from when the client last heard from it, and how it
+/// answered the latest echo probes.
+///
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Health {
+  /// the device answered every recent probe.
+  ///
+  Online,
+
+  /// the device is still heard from, but missed a recent probe.
+  ///
+  Degraded,
+
+  /// the client has not heard from the device for longer than
+  /// `Client::set_offline_after`.
+  ///
+  Offline,
+}
+
+/// the parts of a device that `DeviceEvent`s report changes to, as
+/// `Client::subscribe_state` hands them out.
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeviceState {
+  /// the address the device was last heard from.
+  ///
+  pub addr: SocketAddr,
+
+  pub label: Option<String>,
+  pub location: Option<String>,
+  pub group: Option<String>,
+  pub power: Option<Power>,
+  pub color: Option<HSBK>,
+  pub infrared: Option<u16>,
+  pub online: bool,
+  pub health: Health,
+}
+
+/// the events waiting to be taken by a `DeviceEvents`.
+///
+#[derive(Default)]
+struct Queue {
+  events: VecDeque<DeviceEvent>,
+  waker: Option<Waker>,
+  closed: bool,
+}
+
+/// the `DeviceEvents` handed out by a client. a subscriber is dropped once
+/// its `DeviceEvents` is.
+///
+#[derive(Default)]
+pub(crate) struct Subscribers {
+  queues: Mutex<Vec<Weak<Mutex<Queue>>>>,
+  closed: AtomicBool,
+}
+
+impl Subscribers {
+  /// returns a new subscriber, whose stream has already ended if the
+  /// subscribers were closed.
+  ///
+  pub(crate) fn subscribe(&self) -> DeviceEvents {
+    let mut queues = self.queues.lock().unwrap();
+    let queue = Arc::new(Mutex::new(Queue {
+      closed: self.closed.load(Ordering::SeqCst),
+      ..Queue::default()
+    }));
+    queues.push(Arc::downgrade(&queue));
+    DeviceEvents(queue)
+  }
+
+  pub(crate) fn emit(&self, event: DeviceEvent) {
+    self.each(|queue| queue.events.push_back(event.clone()));
+  }
+
+  /// ends every subscriber's stream, once it has taken the events that are
+  /// already queued.
+  ///
+  pub(crate) fn close(&self) {
+    self.closed.store(true, Ordering::SeqCst);
+    self.each(|queue| queue.closed = true);
+  }
+
+  fn each<F: Fn(&mut Queue)>(&self, f: F) {
+    self.queues.lock().unwrap().retain(|queue| match queue.upgrade() {
+      Some(queue) => {
+        let mut queue = queue.lock().unwrap();
+        f(&mut queue);
+        if let Some(waker) = queue.waker.take() {
+          waker.wake();
+        }
+        true
+      }
+      None => false,
+    });
+  }
+}
+
+/// the events from `Client::events`. with the `stream` feature, this is a
+/// `futures_core::Stream`, which ends once the listen thread stops.
+///
+pub struct DeviceEvents(Arc<Mutex<Queue>>);
+
+impl DeviceEvents {
+  /// returns the next event, if one is waiting.
+  ///
+  pub fn try_next(&self) -> Option<DeviceEvent> {
+    self.0.lock().unwrap().events.pop_front()
+  }
+
+  /// polls for the next event. resolves to `None` once the listen thread has
+  /// stopped and every event has been taken.
+  ///
+  pub fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<DeviceEvent>> {
+    let mut queue = self.0.lock().unwrap();
+
+    match queue.events.pop_front() {
+      Some(event) => Poll::Ready(Some(event)),
+      None if queue.closed => Poll::Ready(None),
+      None => {
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+#[cfg(feature = "stream")]
+impl Stream for DeviceEvents {
+  type Item = DeviceEvent;
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Option<DeviceEvent>> {
+    self.get_mut().poll_event(cx)
+  }
+}
+
+#[test]
+fn test_device_events_are_queued_per_subscriber() {
+  use std::task::Wake;
+
+  struct CountingWaker(Mutex<usize>);
+
+  impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+      *self.0.lock().unwrap() += 1;
+    }
+  }
+
+  let counter = Arc::new(CountingWaker(Mutex::new(0)));
+  let waker = Waker::from(counter.clone());
+  let mut cx = Context::from_waker(&waker);
+  let subscribers = Subscribers::default();
+  let mut first = subscribers.subscribe();
+  let second = subscribers.subscribe();
+
+  assert!(first.poll_event(&mut cx).is_pending());
+  subscribers.emit(DeviceEvent::Discovered(MacAddress::from(0xAB)));
+  assert_eq!(1, *counter.0.lock().unwrap());
+  assert_eq!(
+    Poll::Ready(Some(DeviceEvent::Discovered(MacAddress::from(0xAB)))),
+    first.poll_event(&mut cx)
+  );
+  assert_eq!(
+    Some(DeviceEvent::Discovered(MacAddress::from(0xAB))),
+    second.try_next()
+  );
+
+  drop(second);
+  subscribers.emit(DeviceEvent::WentOffline(MacAddress::from(0xAB)));
+  subscribers.close();
+  assert_eq!(1, subscribers.queues.lock().unwrap().len());
+  assert_eq!(
+    Poll::Ready(Some(DeviceEvent::WentOffline(MacAddress::from(0xAB)))),
+    first.poll_event(&mut cx)
+  );
+  assert_eq!(Poll::Ready(None), first.poll_event(&mut cx));
+  assert_eq!(Poll::Ready(None), subscribers.subscribe().poll_event(&mut cx));
+}
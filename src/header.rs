@@ -1,6 +1,22 @@
-use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use std::convert::TryFrom;
+use std::io::{self, Write};
 
-#[derive(Debug, Eq, PartialEq)]
+use byteorder::{ByteOrder, LittleEndian};
+
+use consts;
+use mac::MacAddress;
+use serialize::{DecodeError, WireDecode, WireEncode, WireReader, WriteBytesExt};
+
+/// the frame header of every message.
+///
+/// # Notes
+///
+///   * Reserved fields, including the 2 bytes after the mac address in the
+///     target, are kept when decoding and re-emitted when encoding, so a
+///     decoded header encodes back to the same bytes. They are zero for
+///     headers created with `new`.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub struct Header {
   size: u16,
   origin: u8,
@@ -8,42 +24,93 @@ pub struct Header {
   addressable: bool,
   protocol: u16,
   source: u32,
-  target: u64,
+  target: MacAddress,
+  res_target: [u8; 2],
+  res0: [u8; 6],
+  res1: u8,
   ack_required: bool,
   res_required: bool,
   sequence: u8,
+  res2: u64,
   typ: u16,
+  res3: u16,
 }
 
 impl Header {
   #[inline]
-  pub fn new(
+  #[allow(clippy::too_many_arguments)]
+  pub fn new<T: Into<MacAddress>>(
     size: u16,
     tagged: bool,
     source: u32,
-    target: u64,
+    target: T,
     ack_required: bool,
     res_required: bool,
     sequence: u8,
     typ: u16,
   ) -> Header {
     Header {
-      size: size,
+      size,
       origin: 0,
-      tagged: tagged,
+      tagged,
       addressable: true,
-      protocol: 1024,
-      source: source,
-      target: target,
-      ack_required: ack_required,
-      res_required: res_required,
-      sequence: sequence,
-      typ: typ,
+      protocol: consts::PROTOCOL,
+      source,
+      target: target.into(),
+      res_target: [0; 2],
+      res0: [0; 6],
+      res1: 0,
+      ack_required,
+      res_required,
+      sequence,
+      res2: 0,
+      typ,
+      res3: 0,
     }
   }
 
+  /// parses only the header from the start of a frame, without going through
+  /// a decoder or touching the payload.
+  ///
+  pub fn parse(bytes: &[u8]) -> Result<Header, DecodeError> {
+    if bytes.len() < Header::mem_size() as usize {
+      return Err(DecodeError::Truncated {
+        field: "header",
+        offset: 0,
+        expected: Header::mem_size() as usize,
+        actual: bytes.len(),
+      });
+    }
+
+    let otap = LittleEndian::read_u16(&bytes[2..4]);
+    let flags = bytes[22];
+    let mut target = [0; 6];
+    target.copy_from_slice(&bytes[8..14]);
+    let mut res0 = [0; 6];
+    res0.copy_from_slice(&bytes[16..22]);
+
+    Ok(Header {
+      size: LittleEndian::read_u16(&bytes[0..2]),
+      origin: ((otap & 0b1100_0000_0000_0000) >> 14) as u8,
+      tagged: (otap & 0b0010_0000_0000_0000) > 0,
+      addressable: (otap & 0b0001_0000_0000_0000) > 0,
+      protocol: otap & 0b0000_1111_1111_1111,
+      source: LittleEndian::read_u32(&bytes[4..8]),
+      target: MacAddress::new(target),
+      res_target: [bytes[14], bytes[15]],
+      res0,
+      res1: flags >> 2,
+      ack_required: flags & 0b0000_0010 > 0,
+      res_required: flags & 0b0000_0001 > 0,
+      sequence: bytes[23],
+      res2: LittleEndian::read_u64(&bytes[24..32]),
+      typ: LittleEndian::read_u16(&bytes[32..34]),
+      res3: LittleEndian::read_u16(&bytes[34..36]),
+    })
+  }
+
   #[inline(always)]
-  pub fn target(&self) -> u64 {
+  pub fn target(&self) -> MacAddress {
     self.target
   }
 
@@ -57,9 +124,90 @@ impl Header {
     self.size
   }
 
+  #[inline(always)]
+  pub fn sequence(&self) -> u8 {
+    self.sequence
+  }
+
+  #[inline(always)]
+  pub fn source(&self) -> u32 {
+    self.source
+  }
+
+  #[inline(always)]
+  pub fn tagged(&self) -> bool {
+    self.tagged
+  }
+
+  #[inline(always)]
+  pub fn ack_required(&self) -> bool {
+    self.ack_required
+  }
+
+  #[inline(always)]
+  pub fn res_required(&self) -> bool {
+    self.res_required
+  }
+
+  #[inline(always)]
+  pub fn origin(&self) -> u8 {
+    self.origin
+  }
+
+  #[inline(always)]
+  pub fn addressable(&self) -> bool {
+    self.addressable
+  }
+
+  #[inline(always)]
+  pub fn protocol(&self) -> u16 {
+    self.protocol
+  }
+
+  /// checks the fields a well behaved device always sets: the protocol must
+  /// be 1024, the frame must be addressable, and the size must cover at least
+  /// the header.
+  ///
+  pub fn validate(&self) -> Result<(), DecodeError> {
+    if self.protocol != consts::PROTOCOL {
+      return Err(DecodeError::InvalidValue {
+        field: "header.protocol",
+        offset: 2,
+        expected: consts::PROTOCOL as u64,
+        actual: self.protocol as u64,
+      });
+    }
+    if !self.addressable {
+      return Err(DecodeError::InvalidValue {
+        field: "header.addressable",
+        offset: 2,
+        expected: 1,
+        actual: 0,
+      });
+    }
+    if (self.size as usize) < consts::HEADER_SIZE {
+      return Err(DecodeError::SizeMismatch {
+        field: "header.size",
+        offset: 0,
+        expected: consts::HEADER_SIZE,
+        actual: self.size as usize,
+      });
+    }
+
+    Ok(())
+  }
+
+  /// starts building a header, from a header with every field zero except
+  /// for the protocol and the addressable flag.
+  ///
+  #[inline]
+  pub fn builder() -> HeaderBuilder {
+    HeaderBuilder::new()
+  }
+
   #[inline(always)]
   pub fn mem_size() -> u16 {
-    36
+    consts::HEADER_SIZE as u16
   }
 }
 
@@ -69,130 +217,168 @@ impl Default for Header {
   }
 }
 
-impl Encodable for Header {
-  fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-    s.emit_struct("Header", 36, |s| {
-      // FRAME
-      try!(s.emit_struct_field("size", 0, |s| s.emit_u16(self.size)));
-      try!(
-        s.emit_struct_field("origin_tagged_addressable_protocol", 1, |s| {
-          let mut value = self.origin as u16;
-          if self.tagged {
-            value |= 0b0010_0000_0000_0000;
-          }
-          if self.addressable {
-            value |= 0b0001_0000_0000_0000;
-          }
-          s.emit_u16(self.protocol | (value as u16))
-        })
-      );
-      try!(s.emit_struct_field("source", 2, |s| s.emit_u32(self.source)));
-
-      // FRAME ADDRESS
-      try!(s.emit_struct_field("target", 3, |s| s.emit_u64(self.target)));
-      try!(s.emit_struct_field("res0", 4, |s| s.emit_seq(6, |s| {
-        for i in 0..6 {
-          try!(s.emit_seq_elt(i, |s| s.emit_u8(0)))
-        }
-        Ok(())
-      })));
-      try!(s.emit_struct_field("res1_ackreq_resreq", 5, |s| {
-        let mut value: u8 = 0;
-        if self.ack_required {
-          value |= 0b0000_0010;
-        }
-        if self.res_required {
-          value |= 0b0000_0001;
-        }
-        s.emit_u8(value)
-      }));
-      try!(s.emit_struct_field("sequence", 6, |s| s.emit_u8(self.sequence)));
-
-      // PROTOCOL HEADER
-      try!(s.emit_struct_field("res2", 7, |s| s.emit_u64(0)));
-      try!(s.emit_struct_field("type", 8, |s| s.emit_u16(self.typ)));
-      try!(s.emit_struct_field("res3", 9, |s| s.emit_u16(0)));
-
-      Ok(())
-    })
+/// builds a header field by field, for crafting frames that `Header::new`
+/// cannot describe.
+///
+/// # Notes
+///
+///   * A builder made from an existing header keeps its reserved fields, so a
+///     captured header can be replayed with only some fields changed.
+///   * Origin is truncated to 2 bits, and protocol to 12 bits, the same as
+///     when they are encoded.
+///
+#[derive(Debug, Clone)]
+pub struct HeaderBuilder {
+  header: Header,
+}
+
+impl HeaderBuilder {
+  #[inline]
+  pub fn new() -> HeaderBuilder {
+    HeaderBuilder {
+      header: Header::new(0, false, 0, 0, false, false, 0, 0),
+    }
+  }
+
+  #[inline]
+  pub fn size(mut self, size: u16) -> HeaderBuilder {
+    self.header.size = size;
+    self
+  }
+
+  #[inline]
+  pub fn origin(mut self, origin: u8) -> HeaderBuilder {
+    self.header.origin = origin & 0b11;
+    self
+  }
+
+  #[inline]
+  pub fn tagged(mut self, tagged: bool) -> HeaderBuilder {
+    self.header.tagged = tagged;
+    self
+  }
+
+  #[inline]
+  pub fn addressable(mut self, addressable: bool) -> HeaderBuilder {
+    self.header.addressable = addressable;
+    self
+  }
+
+  #[inline]
+  pub fn protocol(mut self, protocol: u16) -> HeaderBuilder {
+    self.header.protocol = protocol & 0b0000_1111_1111_1111;
+    self
+  }
+
+  #[inline]
+  pub fn source(mut self, source: u32) -> HeaderBuilder {
+    self.header.source = source;
+    self
+  }
+
+  #[inline]
+  pub fn target<T: Into<MacAddress>>(mut self, target: T) -> HeaderBuilder {
+    self.header.target = target.into();
+    self
+  }
+
+  #[inline]
+  pub fn ack_required(mut self, ack_required: bool) -> HeaderBuilder {
+    self.header.ack_required = ack_required;
+    self
+  }
+
+  #[inline]
+  pub fn res_required(mut self, res_required: bool) -> HeaderBuilder {
+    self.header.res_required = res_required;
+    self
+  }
+
+  #[inline]
+  pub fn sequence(mut self, sequence: u8) -> HeaderBuilder {
+    self.header.sequence = sequence;
+    self
+  }
+
+  #[inline]
+  pub fn typ(mut self, typ: u16) -> HeaderBuilder {
+    self.header.typ = typ;
+    self
+  }
+
+  #[inline]
+  pub fn build(self) -> Header {
+    self.header
   }
 }
 
-impl Decodable for Header {
-  fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-    let mut header: Header = Default::default();
-
-    try!(d.read_struct("Header", 36, |d| {
-      // FRAME
-      try!(
-        d.read_struct_field("size", 0, |d| d.read_u16().and_then(|v| {
-          header.size = v;
-          Ok(())
-        }))
-      );
-      try!(
-        d.read_struct_field(
-          "origin_tagged_addressable_protocol",
-          1,
-          |d| d.read_u16().and_then(|v| {
-            header.origin = ((v & 0b1100_0000_0000_0000) >> 14) as u8;
-            header.tagged = (v & 0b0010_0000_0000_0000) > 0;
-            header.addressable = (v & 0b001_0000_0000_0000) > 0;
-            header.protocol = v & 0b0000_1111_1111_1111;
-            Ok(())
-          })
-        )
-      );
-      try!(
-        d.read_struct_field("source", 2, |d| d.read_u32().and_then(|v| {
-          header.source = v;
-          Ok(())
-        }))
-      );
-
-      // FRAME ADDRESS
-      try!(
-        d.read_struct_field("target", 3, |d| d.read_u64().and_then(|v| {
-          header.target = v;
-          Ok(())
-        }))
-      );
-      try!(d.read_struct_field("res0", 4, |d| d.read_seq(|d, _| {
-        for i in 0..6 {
-          try!(d.read_seq_elt(i, |d| d.read_u8()));
-        }
-        Ok(())
-      })));
-      try!(
-        d.read_struct_field("res1_ackreq_resreq", 5, |d| d.read_u8().and_then(
-          |v| {
-            header.ack_required = v & 0b0000_0010 > 0;
-            header.res_required = v & 0b0000_0001 > 0;
-            Ok(())
-          }
-        ))
-      );
-      try!(
-        d.read_struct_field("sequence", 6, |d| d.read_u8().and_then(|v| {
-          header.sequence = v;
-          Ok(())
-        }))
-      );
-
-      // PROTOCOL HEADER
-      try!(d.read_struct_field("res2", 7, |d| d.read_u64()));
-      try!(
-        d.read_struct_field("type", 8, |d| d.read_u16().and_then(|v| {
-          header.typ = v;
-          Ok(())
-        }))
-      );
-      try!(d.read_struct_field("res3", 9, |d| d.read_u16()));
-
-      Ok(())
-    }));
-
-    Ok(header)
+impl Default for HeaderBuilder {
+  fn default() -> HeaderBuilder {
+    HeaderBuilder::new()
+  }
+}
+
+impl From<Header> for HeaderBuilder {
+  #[inline]
+  fn from(header: Header) -> HeaderBuilder {
+    HeaderBuilder { header }
+  }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Header {
+  type Error = DecodeError;
+
+  /// same as `Header::parse`.
+  ///
+  #[inline]
+  fn try_from(bytes: &'a [u8]) -> Result<Header, DecodeError> {
+    Header::parse(bytes)
+  }
+}
+
+impl WireEncode for Header {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    // FRAME
+    let mut otap = (self.origin as u16) << 14;
+    if self.tagged {
+      otap |= 0b0010_0000_0000_0000;
+    }
+    if self.addressable {
+      otap |= 0b0001_0000_0000_0000;
+    }
+    w.write_u16::<LittleEndian>(self.size)?;
+    w.write_u16::<LittleEndian>(self.protocol | otap)?;
+    w.write_u32::<LittleEndian>(self.source)?;
+
+    // FRAME ADDRESS
+    let mut flags: u8 = self.res1 << 2;
+    if self.ack_required {
+      flags |= 0b0000_0010;
+    }
+    if self.res_required {
+      flags |= 0b0000_0001;
+    }
+    w.write_all(&self.target.octets())?;
+    w.write_all(&self.res_target)?;
+    w.write_all(&self.res0[..])?;
+    w.write_u8(flags)?;
+    w.write_u8(self.sequence)?;
+
+    // PROTOCOL HEADER
+    w.write_u64::<LittleEndian>(self.res2)?;
+    w.write_u16::<LittleEndian>(self.typ)?;
+    w.write_u16::<LittleEndian>(self.res3)
+  }
+}
+
+impl WireDecode for Header {
+  fn decode(r: &mut WireReader) -> Result<Header, DecodeError> {
+    let offset = r.position();
+    let bytes = r
+      .read_bytes(Header::mem_size() as usize)
+      .map_err(|e| e.with_field("header"))?;
+
+    Header::parse(bytes).map_err(|e| e.offset_by(offset))
   }
 }
 
@@ -212,23 +398,110 @@ fn test_header_encode_correctness() {
 }
 
 #[test]
-fn test_encode_decode_json() {
-  use rustc_serialize::json;
+fn test_encode_decode_serializer() {
+  use serialize;
 
   let header: Header = Header::new(128, true, 256, 1000, false, false, 1, 12);
-  let encode = json::as_pretty_json(&header).to_string();
-  let decode: Header = json::decode(&encode[..]).unwrap();
+  let encode = serialize::encode(&header).unwrap();
+  let decode: Header = serialize::decode(&encode[..]).unwrap();
 
   assert_eq!(decode, header);
 }
 
 #[test]
-fn test_encode_decode_serializer() {
+fn test_parse_matches_decode() {
   use serialize;
 
-  let header: Header = Header::new(128, true, 256, 1000, false, false, 1, 12);
+  let header: Header = Header::new(36, true, 2838935849, 1000, true, false, 7, 117);
   let encode = serialize::encode(&header).unwrap();
-  let decode: Header = serialize::decode(&encode[..]).unwrap();
 
-  assert_eq!(decode, header);
+  assert_eq!(header, Header::parse(&encode[..]).unwrap());
+  assert!(Header::parse(&encode[..35]).is_err());
+}
+
+#[test]
+fn test_reserved_fields_round_trip() {
+  use serialize;
+
+  let mut bytes = serialize::encode(&Header::default()).unwrap();
+  for (i, b) in bytes[16..22].iter_mut().enumerate() {
+    *b = i as u8 + 16;
+  }
+  bytes[15] = 0xef;
+  bytes[22] |= 0b1010_0000;
+  bytes[24] = 0xab;
+  bytes[35] = 0xcd;
+
+  let decode: Header = serialize::decode(&bytes[..]).unwrap();
+
+  assert_eq!(decode, Header::parse(&bytes[..]).unwrap());
+  assert_eq!(&bytes[..], &serialize::encode(&decode).unwrap()[..]);
+}
+
+#[test]
+fn test_builder_sets_every_field() {
+  use serialize;
+
+  let header = Header::builder()
+    .size(36)
+    .origin(1)
+    .tagged(true)
+    .addressable(false)
+    .protocol(1025)
+    .source(2838935849)
+    .target(1000)
+    .ack_required(true)
+    .res_required(false)
+    .sequence(7)
+    .typ(117)
+    .build();
+  let decoded = Header::parse(&serialize::encode(&header).unwrap()[..]).unwrap();
+
+  assert_eq!(header, decoded);
+  assert_eq!(36, decoded.size());
+  assert_eq!(1, decoded.origin());
+  assert!(decoded.tagged());
+  assert!(!decoded.addressable());
+  assert_eq!(1025, decoded.protocol());
+  assert_eq!(2838935849, decoded.source());
+  assert_eq!(MacAddress::from(1000), decoded.target());
+  assert!(decoded.ack_required());
+  assert!(!decoded.res_required());
+  assert_eq!(7, decoded.sequence());
+  assert_eq!(117, decoded.typ());
+
+  let mut bytes = serialize::encode(&header).unwrap();
+  bytes[24] = 0xab;
+  let replayed = HeaderBuilder::from(Header::parse(&bytes[..]).unwrap())
+    .sequence(8)
+    .build();
+  let encoded = serialize::encode(&replayed).unwrap();
+
+  assert_eq!(0xab, encoded[24]);
+  assert_eq!(8, encoded[23]);
+}
+
+#[test]
+fn test_builder_takes_a_mac_address() {
+  use serialize;
+
+  let mac = MacAddress::new([0xd0, 0x73, 0xd5, 0x01, 0x02, 0x03]);
+  let header = Header::builder().target(mac).build();
+  assert_eq!(mac, header.target());
+  assert_eq!(&mac.octets()[..], &serialize::encode(&header).unwrap()[8..14]);
+}
+
+#[test]
+fn test_validate_rejects_malformed_headers() {
+  let header = Header::new(36, true, 0, 0, false, false, 0, 2);
+  assert_eq!(Ok(()), header.validate());
+
+  let protocol = HeaderBuilder::from(header.clone()).protocol(1025).build();
+  assert_eq!("header.protocol", protocol.validate().unwrap_err().field());
+
+  let addressable = HeaderBuilder::from(header.clone()).addressable(false).build();
+  assert_eq!("header.addressable", addressable.validate().unwrap_err().field());
+
+  let size = HeaderBuilder::from(header).size(35).build();
+  assert_eq!("header.size", size.validate().unwrap_err().field());
 }
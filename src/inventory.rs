@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+use std::net::SocketAddr;
+
+use client::Bulb;
+use payload::FirmwareVersion;
+
+/// the formats `Client::export_inventory` writes.
+///
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum InventoryFormat {
+  /// an array with an object per device. unknown fields are `null`.
+  ///
+  Json,
+
+  /// a header row, then a row per device. unknown fields are empty.
+  ///
+  Csv,
+}
+
+/// the columns of the inventory, in order.
+///
+const COLUMNS: [&str; 10] = [
+  "target",
+  "ip",
+  "label",
+  "group",
+  "location",
+  "vendor",
+  "product",
+  "host_firmware",
+  "wifi_firmware",
+  "power",
+];
+
+/// a field of the inventory, before it is written in either format.
+///
+enum Field {
+  Text(String),
+  Number(u32),
+  Unknown,
+}
+
+impl<'a> From<Option<&'a str>> for Field {
+  fn from(s: Option<&'a str>) -> Field {
+    s.map_or(Field::Unknown, |s| Field::Text(s.to_string()))
+  }
+}
+
+impl From<Option<FirmwareVersion>> for Field {
+  fn from(firmware: Option<FirmwareVersion>) -> Field {
+    firmware.map_or(Field::Unknown, |firmware| Field::Text(firmware.to_string()))
+  }
+}
+
+fn fields(bulb: &Bulb<SocketAddr>) -> [Field; 10] {
+  let version = bulb.version();
+  let number = |n: Option<u32>| n.map_or(Field::Unknown, Field::Number);
+  let power = bulb.power().map(|power| match power.is_on() {
+    true => "on",
+    false => "off",
+  });
+
+  [
+    Field::Text(bulb.target().to_string()),
+    Field::Text(bulb.addr().ip().to_string()),
+    bulb.label().into(),
+    bulb.group().into(),
+    bulb.location().into(),
+    number(version.map(|(vendor, _, _)| vendor)),
+    number(version.map(|(_, product, _)| product)),
+    bulb.host_firmware().into(),
+    bulb.wifi_firmware().into(),
+    power.into(),
+  ]
+}
+
+/// writes a row per bulb, in the order given.
+///
+pub(crate) fn write_inventory<W: Write>(
+  mut w: W,
+  bulbs: &[Bulb<SocketAddr>],
+  format: InventoryFormat,
+) -> io::Result<()> {
+  match format {
+    InventoryFormat::Json => write_json(&mut w, bulbs)?,
+    InventoryFormat::Csv => write_csv(&mut w, bulbs)?,
+  }
+
+  w.flush()
+}
+
+fn write_json<W: Write>(w: &mut W, bulbs: &[Bulb<SocketAddr>]) -> io::Result<()> {
+  write!(w, "[")?;
+
+  for (i, bulb) in bulbs.iter().enumerate() {
+    write!(w, "{}\n  {{", if i == 0 { "" } else { "," })?;
+    for (j, (column, field)) in COLUMNS.iter().zip(&fields(bulb)).enumerate() {
+      write!(w, "{}\"{}\": ", if j == 0 { "" } else { ", " }, column)?;
+      match *field {
+        Field::Text(ref s) => write!(w, "{}", json_string(s))?,
+        Field::Number(n) => write!(w, "{}", n)?,
+        Field::Unknown => write!(w, "null")?,
+      }
+    }
+    write!(w, "}}")?;
+  }
+
+  writeln!(w, "{}]", if bulbs.is_empty() { "" } else { "\n" })
+}
+
+fn write_csv<W: Write>(w: &mut W, bulbs: &[Bulb<SocketAddr>]) -> io::Result<()> {
+  write!(w, "{}\r\n", COLUMNS.join(","))?;
+
+  for bulb in bulbs {
+    let row: Vec<String> = fields(bulb)
+      .iter()
+      .map(|field| match *field {
+        Field::Text(ref s) => csv_field(s),
+        Field::Number(n) => n.to_string(),
+        Field::Unknown => String::new(),
+      })
+      .collect();
+    write!(w, "{}\r\n", row.join(","))?;
+  }
+
+  Ok(())
+}
+
+/// quotes a string for json, escaping quotes, backslashes and control
+/// characters.
+///
+fn json_string(s: &str) -> String {
+  let mut quoted = String::with_capacity(s.len() + 2);
+  quoted.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+/// quotes a csv field if it holds a comma, quote or line break, doubling any
+/// quotes inside it.
+///
+fn csv_field(s: &str) -> String {
+  match s.contains(&[',', '"', '\n', '\r'][..]) {
+    true => format!("\"{}\"", s.replace('"', "\"\"")),
+    false => s.to_string(),
+  }
+}
+
+#[test]
+fn test_inventory_fields_are_escaped() {
+  assert_eq!(r#""a \"b\"\\\n\u0001""#, json_string("a \"b\"\\\n\u{1}"));
+  assert_eq!("kitchen", csv_field("kitchen"));
+  assert_eq!(r#""left, ""big"" lamp""#, csv_field("left, \"big\" lamp"));
+}
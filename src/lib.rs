@@ -1,25 +1,76 @@
 // #![feature(unicode)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::match_like_matches_macro)]
 
+#[cfg(not(feature = "std"))]
+extern crate alloc;
 extern crate byteorder;
-extern crate net2;
-extern crate rustc_serialize;
-#[macro_use]
+#[cfg_attr(any(feature = "client", feature = "codec"), macro_use)]
 extern crate log;
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "codec")]
+extern crate bytes;
+#[cfg(feature = "stream")]
+extern crate futures_core;
+#[cfg(feature = "client")]
+extern crate if_addrs;
+#[cfg(feature = "client")]
+extern crate socket2;
+#[cfg(feature = "codec")]
+extern crate tokio_util;
 
-macro_rules! err(
-  ($s : expr) => (Err($s.to_string()))
-);
+#[cfg(not(feature = "std"))]
+#[path = "nostd.rs"]
+mod std;
 
+#[cfg(feature = "client")]
 mod client;
+#[cfg(feature = "codec")]
+mod codec;
+pub mod consts;
+pub mod debug;
+mod error;
+#[cfg(feature = "client")]
+mod events;
 mod header;
+#[cfg(feature = "client")]
+mod inventory;
+mod mac;
 mod message;
 mod payload;
+mod registry;
+#[cfg(feature = "client")]
+mod saved;
 pub mod serialize;
+pub mod test_vectors;
+#[cfg(feature = "std")]
+mod transport;
+mod units;
 
-pub use client::{Bulb, Client, DiscoverOptions};
-pub use header::Header;
-pub use message::Message;
-pub use payload::{Color, Device, Light, Payload, Power, Service, HSBK,
-                  MAX_BRIGHTNESS};
+#[cfg(feature = "client")]
+pub use client::{Backoff, Bulb, Client, ClientBuilder, Config, DeviceId,
+                 DiscoverOptions, DiscoveredBulbs, Host, Property, RawHook,
+                 Responses, StateWatch, WaitForDevice, WaitForDevices};
+#[cfg(feature = "codec")]
+pub use codec::LifxCodec;
+pub use error::Error;
+#[cfg(feature = "client")]
+pub use events::{DeviceEvent, DeviceEvents, DeviceState, Health};
+pub use header::{Header, HeaderBuilder};
+#[cfg(feature = "client")]
+pub use inventory::InventoryFormat;
+pub use mac::MacAddress;
+pub use message::{Message, SendOptions};
+pub use payload::{ApplicationRequest, Button, ButtonAction, Color, Device,
+                  FirmwareVersion, Light, MoveDirection, MultiZone,
+                  MultiZoneEffectType, Payload, PayloadRef, Power, Relay,
+                  Service, SwitchButton, Tile, TileDevice, Waveform, HSBK,
+                  MAX_BRIGHTNESS, MAX_BUTTON_ACTIONS, MAX_CHAIN_TILES,
+                  MAX_EXTENDED_ZONES, MAX_SWITCH_BUTTONS};
+pub use registry::{CustomDecoder, DecoderRegistry};
+pub use serialize::{DecodeError, LabelMode};
+#[cfg(feature = "std")]
+pub use transport::{AsyncClient, AsyncTransport, AwaitAck, RecvMsg, Request,
+                    SendMsg, Transport};
+pub use units::{Brightness, Hue, Kelvin, Saturation, TransitionMs};
@@ -0,0 +1,131 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+
+use error::Error;
+
+/// the mac address of a device, which is also its serial number and the
+/// target of the messages sent to it.
+///
+/// # Notes
+///
+///   * On the wire the target is 8 bytes: the 6 bytes of the mac address in
+///     order, followed by 2 zero bytes. Read as a little-endian u64, this is
+///     the number a mac address converts to and from.
+///   * Converting from a u64 ignores the 2 high bytes.
+///   * The all zero address targets every device.
+///
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub struct MacAddress([u8; 6]);
+
+impl MacAddress {
+  /// the target of a message sent to every device.
+  ///
+  pub const ALL: MacAddress = MacAddress([0; 6]);
+
+  #[inline]
+  pub fn new(octets: [u8; 6]) -> MacAddress {
+    MacAddress(octets)
+  }
+
+  #[inline]
+  pub fn octets(&self) -> [u8; 6] {
+    self.0
+  }
+
+  /// checks if this targets every device.
+  ///
+  #[inline]
+  pub fn is_all(&self) -> bool {
+    *self == MacAddress::ALL
+  }
+}
+
+impl From<[u8; 6]> for MacAddress {
+  #[inline]
+  fn from(octets: [u8; 6]) -> MacAddress {
+    MacAddress(octets)
+  }
+}
+
+impl From<u64> for MacAddress {
+  #[inline]
+  fn from(target: u64) -> MacAddress {
+    let mut octets = [0; 6];
+    octets.copy_from_slice(&target.to_le_bytes()[..6]);
+    MacAddress(octets)
+  }
+}
+
+impl From<MacAddress> for u64 {
+  #[inline]
+  fn from(mac: MacAddress) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..6].copy_from_slice(&mac.0);
+    u64::from_le_bytes(bytes)
+  }
+}
+
+impl FromStr for MacAddress {
+  type Err = Error;
+
+  /// parses colon separated hex (`d0:73:d5:01:02:03`), or the 12 hex digits of
+  /// a serial number (`d073d5010203`).
+  ///
+  fn from_str(s: &str) -> Result<MacAddress, Error> {
+    let digits: String = if s.contains(':') {
+      let parts: Vec<&str> = s.split(':').collect();
+      if parts.len() != 6 || parts.iter().any(|p| p.len() != 2) {
+        return Err(Error::InvalidMacAddress(s.to_string()));
+      }
+      parts.concat()
+    } else {
+      s.to_string()
+    };
+
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+      return Err(Error::InvalidMacAddress(s.to_string()));
+    }
+
+    let mut octets = [0; 6];
+    for (i, octet) in octets.iter_mut().enumerate() {
+      *octet = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+        .map_err(|_| Error::InvalidMacAddress(s.to_string()))?;
+    }
+
+    Ok(MacAddress(octets))
+  }
+}
+
+impl Display for MacAddress {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    let o = &self.0;
+    write!(
+      f,
+      "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+      o[0], o[1], o[2], o[3], o[4], o[5]
+    )
+  }
+}
+
+#[test]
+fn test_mac_address_conversions() {
+  let mac: MacAddress = "d0:73:d5:01:02:03".parse().unwrap();
+
+  assert_eq!([0xd0, 0x73, 0xd5, 0x01, 0x02, 0x03], mac.octets());
+  assert_eq!(mac, "D073D5010203".parse().unwrap());
+  assert_eq!("d0:73:d5:01:02:03", mac.to_string());
+  assert_eq!(0x0302_01d5_73d0, u64::from(mac));
+  assert_eq!(mac, MacAddress::from(0xffff_0302_01d5_73d0));
+  assert!(MacAddress::from(0).is_all());
+
+  assert!("d0:73:d5:01:02".parse::<MacAddress>().is_err());
+  assert!("d0:73:d5:01:02:3".parse::<MacAddress>().is_err());
+  assert!("d073d501020g".parse::<MacAddress>().is_err());
+  assert!("+0:73:d5:01:02:03".parse::<MacAddress>().is_err());
+  assert_eq!(
+    "invalid mac address: 'lamp'",
+    "lamp".parse::<MacAddress>().unwrap_err().to_string()
+  );
+}
@@ -1,72 +1,712 @@
+use std::convert::TryFrom;
 use std::fmt::{Debug, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 
-use header::Header;
-use payload::Payload;
-use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use header::{Header, HeaderBuilder};
+use mac::MacAddress;
+use payload::{Payload, PayloadRef};
+use serialize::{self, DecodeError, LabelMode, WireDecode, WireEncode, WireReader};
 
-const CLIENT_ID: u32 = 1111;
+bitflags! {
+  /// the ack_required and res_required flags of a message. without either, a
+  /// device sends nothing back, which suits streaming effects.
+  ///
+  pub struct SendOptions: u8 {
+    const ACK_REQUIRED = 0b0000_0001;
+    const RES_REQUIRED = 0b0000_0010;
+  }
+}
 
+impl SendOptions {
+  /// the flags `Message::new` uses: an ack if `ack_required` is set, and a
+  /// response if the payload requires one.
+  ///
+  pub fn for_payload(payload: &Payload, ack_required: bool) -> SendOptions {
+    let mut options = SendOptions::empty();
+    options.set(SendOptions::ACK_REQUIRED, ack_required);
+    options.set(SendOptions::RES_REQUIRED, payload.requires_response());
+    options
+  }
+}
+
+/// a message is a header followed by a payload.
+///
+/// # Notes
+///
+///   * Decoded messages re-emit the payload bytes they were decoded from,
+///     including any reserved fields, when encoded. This makes it possible to
+///     retransmit a captured frame byte for byte. The bytes are only copied
+///     when encoding the payload would not give them back.
+///   * Messages are equal when their headers and payloads are equal. The raw
+///     payload bytes are not compared.
+///
+#[derive(Clone)]
 pub struct Message {
   header: Header,
   payload: Payload,
+  raw_payload: Option<Vec<u8>>,
 }
 
 impl Message {
-  /// creates a new message.
+  /// creates a new message. its source is 0, for which devices broadcast
+  /// their replies; set one with `with_source`.
   ///
-  pub fn new(msg: Payload, ack_required: bool, target: u64, seq: u8) -> Message {
+  pub fn new<T: Into<MacAddress>>(
+    msg: Payload,
+    ack_required: bool,
+    target: T,
+    seq: u8,
+  ) -> Message {
+    let options = SendOptions::for_payload(&msg, ack_required);
+    Message::new_with(msg, options, target, seq)
+  }
+
+  /// creates a new message, with the ack_required and res_required flags set
+  /// from `options` rather than from the payload. a payload too large to fit
+  /// in a frame fails with `Error::Encode` once the message is encoded.
+  ///
+  pub fn new_with<T: Into<MacAddress>>(
+    msg: Payload,
+    options: SendOptions,
+    target: T,
+    seq: u8,
+  ) -> Message {
+    // a frame is never smaller than its header, so a size of 0 marks one that
+    // overflowed, for `encode` to refuse.
+    let size = serialize::encoded_len(&msg)
+      .checked_add(Header::mem_size() as usize)
+      .and_then(|size| u16::try_from(size).ok())
+      .unwrap_or(0);
+
     Message {
       header: Header::new(
-        msg.size() + Header::mem_size(),
+        size,
         msg.tagged(),
-        CLIENT_ID,
+        0,
         target,
-        ack_required,
-        msg.requires_response(),
+        options.contains(SendOptions::ACK_REQUIRED),
+        options.contains(SendOptions::RES_REQUIRED),
         seq,
         msg.typ(),
       ),
       payload: msg,
+      raw_payload: None,
+    }
+  }
+
+  /// parses only the header of an encoded message. the payload is not
+  /// decoded, which makes this cheap enough to dispatch on before doing a full
+  /// decode.
+  ///
+  #[inline]
+  pub fn peek_header(bytes: &[u8]) -> Result<Header, DecodeError> {
+    Header::parse(bytes)
+  }
+
+  /// decodes a message from a single frame, checking that the size field in
+  /// the header matches the length of the frame. labels that are not valid
+  /// UTF-8 are decoded lossily.
+  ///
+  #[inline]
+  pub fn from_bytes(bytes: &[u8]) -> Result<Message, DecodeError> {
+    Message::from_bytes_with(bytes, LabelMode::Lossy)
+  }
+
+  /// decodes a message from a single frame like `from_bytes`, decoding labels
+  /// with `labels`.
+  ///
+  pub fn from_bytes_with(
+    bytes: &[u8],
+    labels: LabelMode,
+  ) -> Result<Message, DecodeError> {
+    let header = Header::parse(bytes)?;
+
+    if header.size() as usize != bytes.len() {
+      return Err(DecodeError::SizeMismatch {
+        field: "header.size",
+        offset: 0,
+        expected: header.size() as usize,
+        actual: bytes.len(),
+      });
     }
+
+    Message::decode(&mut WireReader::new(bytes).with_label_mode(labels))
+  }
+
+  /// decodes a message from arbitrary bytes, such as a datagram from an
+  /// untrusted source or a fuzzer. this never panics: anything other than
+  /// exactly one well formed frame is returned as a `DecodeError`.
+  ///
+  pub fn decode_any(bytes: &[u8]) -> Result<Message, DecodeError> {
+    Message::from_bytes(bytes)
+  }
+
+  /// decodes every frame in a datagram, using the size field in each header to
+  /// find the next frame. returns the messages along with any trailing bytes
+  /// that do not make up a whole frame.
+  ///
+  pub fn decode_many(bytes: &[u8]) -> Result<(Vec<Message>, &[u8]), DecodeError> {
+    let mut msgs = Vec::new();
+    let mut rest = bytes;
+
+    while rest.len() >= Header::mem_size() as usize {
+      let offset = bytes.len() - rest.len();
+      let size = Header::parse(rest)?.size() as usize;
+
+      if size < Header::mem_size() as usize || size > rest.len() {
+        break;
+      }
+
+      msgs.push(
+        Message::from_bytes(&rest[..size]).map_err(|e| e.offset_by(offset))?,
+      );
+      rest = &rest[size..];
+    }
+
+    Ok((msgs, rest))
+  }
+
+  /// decodes the first frame in a datagram without allocating for labels or
+  /// raw bytes. returns the header, the borrowed payload, and the bytes after
+  /// the frame.
+  ///
+  pub fn decode_ref<'a>(
+    bytes: &'a [u8],
+  ) -> Result<(Header, PayloadRef<'a>, &'a [u8]), DecodeError> {
+    let header = Header::parse(bytes)?;
+    let size = header.size() as usize;
+
+    if size < Header::mem_size() as usize || size > bytes.len() {
+      return Err(DecodeError::SizeMismatch {
+        field: "header.size",
+        offset: 0,
+        expected: size,
+        actual: bytes.len(),
+      });
+    }
+
+    let payload =
+      Payload::decode_ref(header.typ(), &bytes[Header::mem_size() as usize..size])
+        .map_err(|e| e.offset_by(Header::mem_size() as usize))?;
+
+    Ok((header, payload, &bytes[size..]))
+  }
+
+  #[inline(always)]
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  #[inline(always)]
+  pub fn payload(&self) -> &Payload {
+    &self.payload
+  }
+
+  #[inline(always)]
+  pub fn target(&self) -> MacAddress {
+    self.header.target()
+  }
+
+  #[inline(always)]
+  pub fn sequence(&self) -> u8 {
+    self.header.sequence()
+  }
+
+  #[inline(always)]
+  pub fn source(&self) -> u32 {
+    self.header.source()
+  }
+
+  #[inline(always)]
+  pub fn ack_required(&self) -> bool {
+    self.header.ack_required()
+  }
+
+  /// replaces the source identifier in the header, which devices copy into
+  /// their replies.
+  ///
+  pub fn with_source(mut self, source: u32) -> Message {
+    self.header = HeaderBuilder::from(self.header).source(source).build();
+    self
+  }
+
+  /// sets the tagged flag in the header, which `new` takes from the payload.
+  ///
+  pub(crate) fn with_tagged(mut self, tagged: bool) -> Message {
+    self.header = HeaderBuilder::from(self.header).tagged(tagged).build();
+    self
   }
 
   /// unpacks a message into a tuple of (payload, target).
   ///
   #[inline(always)]
-  pub fn unpack(self) -> (Payload, u64) {
+  pub fn unpack(self) -> (Payload, MacAddress) {
     (self.payload, self.header.target())
   }
 }
 
 impl Debug for Message {
   fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-    write!(f, "({:#X}) {:?}", self.header.target(), self.payload)
+    write!(f, "({}) {:?}", self.header.target(), self.payload)
   }
 }
 
-impl Encodable for Message {
-  fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-    s.emit_struct("Message", self.header.size() as usize, |s| {
-      try!(s.emit_struct_field("header", 0, |s| self.header.encode(s)));
-      try!(s.emit_struct_field("payload", 1, |s| self.payload.encode(s)));
-      Ok(())
-    })
+/// a writer that checks the bytes written to it against `expected`, without
+/// keeping them.
+///
+struct Compare<'a> {
+  expected: &'a [u8],
+  same: bool,
+}
+
+impl<'a> Write for Compare<'a> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.same && self.expected.starts_with(buf) {
+      self.expected = &self.expected[buf.len()..];
+    } else {
+      self.same = false;
+    }
+    Ok(buf.len())
+  }
+
+  #[inline(always)]
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// checks whether `payload` encodes to exactly `bytes`, which is false when
+/// they hold reserved fields that are set, or a label that was replaced.
+///
+fn encodes_to(payload: &Payload, bytes: &[u8]) -> bool {
+  let mut compare = Compare {
+    expected: bytes,
+    same: true,
+  };
+  payload.encode(&mut compare).is_ok() && compare.same && compare.expected.is_empty()
+}
+
+impl PartialEq for Message {
+  fn eq(&self, other: &Message) -> bool {
+    self.header == other.header && self.payload == other.payload
   }
 }
 
-impl Decodable for Message {
-  fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-    d.read_struct("Header", 0, |d| {
-      let header = try!(d.read_struct_field("header", 0, |d| Header::decode(d)));
-      let message = try!(d.read_struct_field("payload", 0, |d| Payload::decode(
-        d,
-        header.typ()
-      )));
+impl Eq for Message {}
 
-      Ok(Message {
-        header: header,
-        payload: message,
-      })
+impl Hash for Message {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.header.hash(state);
+    self.payload.hash(state);
+  }
+}
+
+/// the error encoding a message whose payload did not fit in a frame.
+///
+#[cfg(feature = "std")]
+fn too_large() -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, "payload too large for a frame")
+}
+
+#[cfg(not(feature = "std"))]
+fn too_large() -> io::Error {
+  io::Error
+}
+
+impl WireEncode for Message {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    if self.header.size() < Header::mem_size() {
+      return Err(too_large());
+    }
+    self.header.encode(w)?;
+    match self.raw_payload {
+      Some(ref raw) => w.write_all(&raw[..]),
+      None => self.payload.encode(w),
+    }
+  }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Message {
+  type Error = DecodeError;
+
+  /// same as `Message::from_bytes`.
+  ///
+  #[inline]
+  fn try_from(bytes: &'a [u8]) -> Result<Message, DecodeError> {
+    Message::from_bytes(bytes)
+  }
+}
+
+impl<'a> From<&'a Message> for Vec<u8> {
+  fn from(msg: &'a Message) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(msg.header.size() as usize);
+    // writing to a vec never fails, so neither can the encode.
+    let _ = msg.encode(&mut bytes);
+    bytes
+  }
+}
+
+impl From<Message> for Vec<u8> {
+  #[inline]
+  fn from(msg: Message) -> Vec<u8> {
+    Vec::from(&msg)
+  }
+}
+
+impl WireDecode for Message {
+  fn decode(r: &mut WireReader) -> Result<Message, DecodeError> {
+    let offset = r.position();
+    let header = Header::decode(r)?;
+    if header.size() < Header::mem_size() {
+      return Err(DecodeError::SizeMismatch {
+        field: "header.size",
+        offset,
+        expected: Header::mem_size() as usize,
+        actual: header.size() as usize,
+      });
+    }
+
+    let size = header.size() - Header::mem_size();
+    let start = r.position();
+    let message = Payload::decode(r, header.typ(), size)?;
+
+    if message.size() != size {
+      return Err(DecodeError::SizeMismatch {
+        field: "payload",
+        offset: offset + Header::mem_size() as usize,
+        expected: message.size() as usize,
+        actual: size as usize,
+      });
+    }
+
+    // the bytes are only copied when encoding the payload would lose some of
+    // them, like reserved fields that are set.
+    let raw = r.read_since(start);
+    let raw_payload = match encodes_to(&message, raw) {
+      true => None,
+      false => Some(raw.to_vec()),
+    };
+
+    Ok(Message {
+      header,
+      payload: message,
+      raw_payload,
     })
   }
 }
+
+#[test]
+fn test_from_bytes_checks_size() {
+  use payload::Device;
+  use serialize;
+
+  let msg = Message::new(Payload::Device(Device::StateUnhandled(2)), false, 0, 0);
+  let mut encoded = serialize::encode(&msg).unwrap();
+
+  assert!(Message::from_bytes(&encoded[..]).is_ok());
+  assert!(Message::from_bytes(&encoded[..37]).is_err());
+
+  encoded.push(0);
+  assert!(Message::from_bytes(&encoded[..]).is_err());
+
+  // claim a 1 byte payload for a type that expects 2
+  encoded.pop();
+  encoded.pop();
+  encoded[0] = 37;
+  assert!(Message::from_bytes(&encoded[..]).is_err());
+}
+
+#[test]
+fn test_from_bytes_preserves_reserved_fields() {
+  use payload::{Light, Power, HSBK};
+  use serialize;
+  use units::{Brightness, Hue, Kelvin, Saturation};
+
+  let color = HSBK::new(
+    Hue::from(1),
+    Saturation::from(2),
+    Brightness::from(3),
+    Kelvin::new(3500).unwrap(),
+  );
+  let state = Light::State(color, Power::Max, "Lamp".to_string());
+  let msg = Message::new(Payload::Light(state), false, 0, 0);
+  let mut encoded = serialize::encode(&msg).unwrap();
+
+  // reserved i16 after the color, and reserved u64 after the label
+  encoded[44] = 0x12;
+  encoded[80] = 0x34;
+  encoded[29] = 0x56;
+
+  let plain = Message::from_bytes(&serialize::encode(&msg).unwrap()[..]).unwrap();
+  let decoded = Message::from_bytes(&encoded[..]).unwrap();
+
+  assert!(plain.raw_payload.is_none());
+  assert!(decoded.raw_payload.is_some());
+  assert_eq!(&encoded[..], &serialize::encode(&decoded).unwrap()[..]);
+
+  let decoded: Message = serialize::decode(&encoded[..]).unwrap();
+  assert_eq!(&encoded[..], &serialize::encode(&decoded).unwrap()[..]);
+  let decoded = Message::decode_any(&encoded[..]).unwrap();
+  assert_eq!(&encoded[..], &serialize::encode(&decoded).unwrap()[..]);
+}
+
+#[test]
+fn test_oversized_payloads_fail_to_encode() {
+  use error::Error;
+
+  let fits = Payload::Unknown {
+    typ: 9999,
+    bytes: vec![0; u16::MAX as usize - Header::mem_size() as usize],
+  };
+  let msg = Message::new(fits, false, 0, 0);
+  assert_eq!(u16::MAX, msg.header().size());
+  assert!(serialize::encode(&msg).is_ok());
+
+  let bytes = vec![0; u16::MAX as usize];
+  let msg = Message::new(Payload::Unknown { typ: 9999, bytes }, false, 0, 0);
+  match serialize::encode(&msg) {
+    Err(Error::Encode(_)) => (),
+    other => panic!("expected an encode error, got {:?}", other.map(|_| ())),
+  }
+}
+
+#[test]
+fn test_decode_many_splits_frames() {
+  use payload::Device;
+  use serialize;
+
+  let mut bytes = serialize::encode(&Message::new(
+    Payload::Device(Device::GetService),
+    false,
+    0,
+    0,
+  )).unwrap();
+  bytes.extend(serialize::encode(&Message::new(
+    Payload::Device(Device::StateUnhandled(101)),
+    false,
+    0,
+    1,
+  )).unwrap());
+  bytes.extend(&[1, 2, 3]);
+
+  let (msgs, rest) = Message::decode_many(&bytes[..]).unwrap();
+
+  assert_eq!(2, msgs.len());
+  assert_eq!(&[1, 2, 3], rest);
+}
+
+#[test]
+fn test_decode_ref_borrows_labels() {
+  use payload::Device;
+  use serialize;
+
+  let mut bytes = serialize::encode(&Message::new(
+    Payload::Device(Device::StateLabel("Porch".to_string())),
+    false,
+    0,
+    0,
+  )).unwrap();
+  bytes.extend(&[1, 2, 3]);
+
+  match Message::decode_ref(&bytes[..]).unwrap() {
+    (header, PayloadRef::StateLabel(label), rest) => {
+      assert_eq!(25, header.typ());
+      assert_eq!("Porch", label);
+      assert_eq!(&[1, 2, 3], rest);
+    }
+    _ => panic!("decoded the wrong payload"),
+  }
+  assert!(Message::decode_ref(&bytes[..40]).is_err());
+}
+
+#[test]
+fn test_decode_errors_point_into_the_datagram() {
+  use payload::{Device, Service};
+  use serialize;
+
+  let mut bytes = serialize::encode(&Message::new(
+    Payload::Device(Device::GetService),
+    false,
+    0,
+    0,
+  )).unwrap();
+  let mut second = serialize::encode(&Message::new(
+    Payload::Device(Device::StateService(Service::Udp, 56700)),
+    false,
+    0,
+    1,
+  )).unwrap();
+  // cut the port short, and shrink the frame to match
+  second.truncate(40);
+  second[0] = 40;
+  bytes.extend(second);
+
+  match Message::decode_many(&bytes[..]) {
+    Err(e) => {
+      assert_eq!("payload", e.field());
+      assert_eq!(36 + 36, e.offset());
+    }
+    Ok(_) => panic!("decoded a truncated frame"),
+  }
+
+  // a field that is the right length, but holds the wrong thing, is named
+  let mut label = serialize::encode(&Message::new(
+    Payload::Device(Device::StateLabel("Lamp".to_string())),
+    false,
+    0,
+    2,
+  )).unwrap();
+  label[36 + 1] = 0xFF;
+
+  match Message::from_bytes_with(&label[..], LabelMode::Strict) {
+    Err(e) => {
+      assert_eq!("Device::StateLabel.label", e.field());
+      assert_eq!(36, e.offset());
+    }
+    Ok(_) => panic!("decoded an invalid label"),
+  }
+}
+
+#[test]
+fn test_decode_any_never_panics() {
+  use payload::{Device, Light, Power, HSBK};
+  use serialize;
+  use units::{Brightness, Hue, Kelvin, Saturation};
+
+  let color = HSBK::new(
+    Hue::from(1),
+    Saturation::from(2),
+    Brightness::from(3),
+    Kelvin::new(3500).unwrap(),
+  );
+  let state = Light::State(color, Power::Max, "Lamp".into());
+  let frames = [
+    serialize::encode(&Message::new(Payload::Light(state), false, 0, 0)).unwrap(),
+    serialize::encode(&Message::new(
+      Payload::Device(Device::GetService),
+      false,
+      0,
+      0,
+    )).unwrap(),
+  ];
+
+  // every truncation, and every single byte corruption, of valid frames
+  for frame in frames.iter() {
+    for len in 0..frame.len() {
+      assert!(Message::decode_any(&frame[..len]).is_err());
+    }
+    for i in 0..frame.len() {
+      for &b in &[0x00, 0x01, 0x7f, 0x80, 0xc0, 0xff] {
+        let mut corrupt = frame.clone();
+        corrupt[i] = b;
+        let _ = Message::decode_any(&corrupt[..]);
+      }
+    }
+  }
+
+  // random payloads for every type the header can claim
+  let mut seed: u32 = 0x2545_f491;
+  let mut next = || {
+    seed ^= seed << 13;
+    seed ^= seed >> 17;
+    seed ^= seed << 5;
+    seed
+  };
+  for typ in 0..1024u16 {
+    let len = (next() % 1024) as usize;
+    let mut bytes: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+    if bytes.len() >= Header::mem_size() as usize {
+      bytes[0] = len as u8;
+      bytes[1] = (len >> 8) as u8;
+      bytes[32] = typ as u8;
+      bytes[33] = (typ >> 8) as u8;
+    }
+    let _ = Message::decode_any(&bytes[..]);
+  }
+}
+
+#[test]
+fn test_std_conversions() {
+  use payload::Device;
+  use serialize;
+
+  let msg = Message::new(Payload::Device(Device::StateUnhandled(2)), false, 0, 0);
+  let bytes: Vec<u8> = Vec::from(&msg);
+
+  assert_eq!(serialize::encode(&msg).unwrap(), bytes);
+  assert_eq!(bytes, Vec::<u8>::from(Message::try_from(&bytes[..]).unwrap()));
+  assert!(Message::try_from(&bytes[..37]).is_err());
+  assert_eq!(Header::try_from(&bytes[..]).unwrap(), msg.header);
+}
+
+#[test]
+fn test_messages_compare_decoded_fields() {
+  use payload::{Light, Power, HSBK};
+  use serialize;
+  use units::{Brightness, Hue, Kelvin, Saturation};
+
+  let color = HSBK::new(
+    Hue::from(1),
+    Saturation::from(2),
+    Brightness::from(3),
+    Kelvin::new(3500).unwrap(),
+  );
+  let state = Light::State(color, Power::Max, "Lamp".to_string());
+  let msg = Message::new(Payload::Light(state), false, 0, 0);
+  let mut encoded = serialize::encode(&msg).unwrap();
+  encoded[80] = 0x34;
+
+  let decoded = Message::from_bytes(&encoded[..]).unwrap();
+
+  assert_eq!(msg, decoded);
+  assert!(msg == decoded.clone());
+  assert!(msg != Message::new(Payload::Light(Light::Get), false, 0, 0));
+}
+
+#[test]
+fn test_accessors_do_not_consume() {
+  use payload::Device;
+
+  let msg = Message::new(Payload::Device(Device::GetLabel), true, 0xAB, 7);
+
+  assert_eq!(&Payload::Device(Device::GetLabel), msg.payload());
+  assert_eq!(23, msg.header().typ());
+  assert_eq!(MacAddress::from(0xAB), msg.target());
+  assert_eq!(7, msg.sequence());
+  assert_eq!(0, msg.source());
+  assert!(msg.ack_required());
+  assert!(msg.header().res_required());
+  assert_eq!(42, msg.clone().with_source(42).source());
+  let unpacked = (Payload::Device(Device::GetLabel), MacAddress::from(0xAB));
+  assert_eq!(unpacked, msg.unpack());
+}
+
+#[test]
+fn test_send_options_set_header_flags() {
+  use payload::{Device, Light};
+
+  let msg = Message::new(Payload::Light(Light::Get), false, 0, 0);
+  assert!(!msg.ack_required());
+  assert!(msg.header().res_required());
+
+  let none = Message::new_with(
+    Payload::Light(Light::Get),
+    SendOptions::empty(),
+    0,
+    0,
+  );
+  assert!(!none.ack_required());
+  assert!(!none.header().res_required());
+
+  let both = Message::new_with(
+    Payload::Device(Device::SetLabel("Lamp".into())),
+    SendOptions::ACK_REQUIRED | SendOptions::RES_REQUIRED,
+    0,
+    0,
+  );
+  assert!(both.ack_required());
+  assert!(both.header().res_required());
+}
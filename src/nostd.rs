@@ -0,0 +1,151 @@
+//! stands in for the parts of std that the protocol core uses, when the crate
+//! is built without the `std` feature. in the 2015 edition `use std::..`
+//! paths resolve against the crate root, so this module is found instead.
+
+pub use alloc::{borrow, collections};
+pub use core::{cmp, convert, error, fmt, hash, str, time};
+
+/// the names std brings into scope that alloc does not.
+///
+pub mod prelude {
+  pub mod v1 {
+    pub use alloc::boxed::Box;
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    // only the tests use vec!
+    #[allow(unused_imports)]
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+  }
+}
+
+/// the subset of `std::io` used to encode messages.
+///
+pub mod io {
+  use core::fmt::{self, Display, Formatter};
+  use core::result;
+
+  use alloc::vec::Vec;
+  use byteorder::ByteOrder;
+
+  /// the only error an encoder can hit: running out of room to write.
+  ///
+  #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+  pub struct Error;
+
+  impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+      write!(f, "failed to write whole buffer")
+    }
+  }
+
+  impl ::core::error::Error for Error {}
+
+  pub type Result<T> = result::Result<T, Error>;
+
+  /// a sink for bytes, like `std::io::Write`.
+  ///
+  pub trait Write {
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    fn flush(&mut self) -> Result<()>;
+
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+      while !buf.is_empty() {
+        match self.write(buf)? {
+          0 => return Err(Error),
+          n => buf = &buf[n..],
+        }
+      }
+      Ok(())
+    }
+  }
+
+  impl<W: Write + ?Sized> Write for &mut W {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+      (**self).write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+      (**self).flush()
+    }
+  }
+
+  impl Write for Vec<u8> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+      self.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<()> {
+      Ok(())
+    }
+  }
+
+  impl Write for &mut [u8] {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+      let n = buf.len().min(self.len());
+      let (head, tail) = ::core::mem::take(self).split_at_mut(n);
+      head.copy_from_slice(&buf[..n]);
+      *self = tail;
+      Ok(n)
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<()> {
+      Ok(())
+    }
+  }
+
+  /// the methods of `byteorder::WriteBytesExt` that encoders use, which
+  /// byteorder only provides with std.
+  ///
+  pub trait WriteBytesExt: Write {
+    #[inline]
+    fn write_u8(&mut self, n: u8) -> Result<()> {
+      self.write_all(&[n])
+    }
+
+    #[inline]
+    fn write_u16<T: ByteOrder>(&mut self, n: u16) -> Result<()> {
+      let mut buf = [0; 2];
+      T::write_u16(&mut buf, n);
+      self.write_all(&buf)
+    }
+
+    #[inline]
+    fn write_i16<T: ByteOrder>(&mut self, n: i16) -> Result<()> {
+      let mut buf = [0; 2];
+      T::write_i16(&mut buf, n);
+      self.write_all(&buf)
+    }
+
+    #[inline]
+    fn write_u32<T: ByteOrder>(&mut self, n: u32) -> Result<()> {
+      let mut buf = [0; 4];
+      T::write_u32(&mut buf, n);
+      self.write_all(&buf)
+    }
+
+    #[inline]
+    fn write_u64<T: ByteOrder>(&mut self, n: u64) -> Result<()> {
+      let mut buf = [0; 8];
+      T::write_u64(&mut buf, n);
+      self.write_all(&buf)
+    }
+
+    #[inline]
+    fn write_f32<T: ByteOrder>(&mut self, n: f32) -> Result<()> {
+      let mut buf = [0; 4];
+      T::write_f32(&mut buf, n);
+      self.write_all(&buf)
+    }
+  }
+
+  impl<W: Write + ?Sized> WriteBytesExt for W {}
+}
@@ -1,18 +1,45 @@
-use std::convert::Into;
-use std::fmt::{Debug, Error, Formatter};
-
-use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use std::borrow::Cow;
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+
+use byteorder::LittleEndian;
+
+use serialize::{
+  self, DecodeError, LabelMode, WireDecode, WireEncode, WireReader, WriteBytesExt,
+};
+use units::{Brightness, Hue, Kelvin, Saturation, TransitionMs};
 
 /// Max allowable brightness.
 ///
-pub const MAX_BRIGHTNESS: u16 = ::std::u16::MAX;
+pub const MAX_BRIGHTNESS: u16 = u16::MAX;
+
+/// Max number of tiles carried by Tile::StateDeviceChain.
+///
+pub const MAX_CHAIN_TILES: usize = 16;
 
-const MAX_SATURATION: u16 = ::std::u16::MAX;
+/// Max number of buttons carried by Button::StateButton.
+///
+pub const MAX_SWITCH_BUTTONS: usize = 8;
+
+/// Max number of actions that can be bound to a single switch button.
+///
+pub const MAX_BUTTON_ACTIONS: usize = 5;
+
+/// Max number of zones carried by an extended MultiZone message.
+///
+pub const MAX_EXTENDED_ZONES: usize = 82;
+
+const MAX_SATURATION: u16 = u16::MAX;
 const DEFAULT_KELVIN: u16 = 3500;
 
 /// Preseeded HSBK values for convenience.
 ///
-#[derive(Copy, Clone, Debug)]
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub enum Color {
   Red,
   Blue,
@@ -26,103 +53,594 @@ impl Color {
   /// Converts a Color enum value to a HSBK with varying brightness.
   ///
   #[inline]
-  pub fn to_hsbk(self, brightness: u16) -> HSBK {
+  pub fn to_hsbk(self, brightness: Brightness) -> HSBK {
     use Color::*;
 
+    let brightness = brightness.into();
+
     match self {
       Red => HSBK {
         hue: 0,
         saturation: MAX_SATURATION,
-        brightness: brightness,
+        brightness,
         kelvin: DEFAULT_KELVIN,
       },
       Blue => HSBK {
         hue: 0,
         saturation: 0,
-        brightness: brightness,
+        brightness,
         kelvin: 0,
       },
       Green => HSBK {
         hue: 120,
         saturation: MAX_SATURATION,
-        brightness: brightness,
+        brightness,
         kelvin: DEFAULT_KELVIN,
       },
       Violet => HSBK {
         hue: 0,
         saturation: 0,
-        brightness: brightness,
+        brightness,
         kelvin: 0,
       },
       Yellow => HSBK {
         hue: 0,
         saturation: 0,
-        brightness: brightness,
+        brightness,
         kelvin: 0,
       },
       White => HSBK {
         hue: 0,
         saturation: 0,
-        brightness: brightness,
+        brightness,
         kelvin: 0,
       },
     }
   }
 }
 
+/// Defines a family of messages once, with the type id, flags and wire layout
+/// of every variant, and generates the enum along with its typ, tagged,
+/// requires_response, size, decode and encode methods.
+///
+/// # Notes
+///
+///   * Variants are written as
+///     `Name(field: Type, ..) = id (len) [flags] { layout }`, where len is the
+///     payload length in bytes. Payloads shorter than len are rejected before
+///     any field is decoded.
+///   * The flags are `tagged` and `response` (the message requires a
+///     response).
+///   * The layout lists the fields in wire order. `[T]` is a reserved `T`,
+///     which is skipped when decoding and zeroed when encoding, and
+///     `{field: Codec}` encodes a field with a `FieldCodec` instead of its own
+///     `WireEncode` and `WireDecode` impls.
+///   * Decode errors name the failing field as `Family::Variant.field`.
+///   * PartialEq, Eq and Hash are generated too, comparing and hashing f32
+///     fields by their bits, so they must not be derived.
+///
+macro_rules! messages(
+  (
+    $(#[$meta:meta])*
+    pub enum $enum:ident {
+      $(
+        $name:ident $(($($field:ident: $fty:ty),*))? = $id:tt ($len:tt)
+          [$($flag:ident),*] {$($item:tt),*}
+      ),*
+    }
+  ) => (
+    $(#[$meta])*
+    pub enum $enum {
+      $($name $(($($fty),*))?),*
+    }
+
+    impl $enum {
+      /// Every message type in this family.
+      ///
+      pub const TYPES: &'static [u16] = &[$($id),*];
+
+      #[inline]
+      pub fn typ(&self) -> u16 {
+        match *self {
+          $($enum::$name { .. } => $id),*
+        }
+      }
+
+      #[inline]
+      pub fn tagged(&self) -> bool {
+        match *self {
+          $($enum::$name { .. } => message_flag!(tagged; $($flag)*)),*
+        }
+      }
+
+      #[inline]
+      pub fn requires_response(&self) -> bool {
+        match *self {
+          $($enum::$name { .. } => message_flag!(response; $($flag)*)),*
+        }
+      }
+
+      #[inline]
+      pub fn size(&self) -> u16 {
+        serialize::encoded_len(self) as u16
+      }
+
+      /// Name of the message with type `typ`, as `Family::Variant`.
+      ///
+      #[inline]
+      pub fn type_name(typ: u16) -> Option<&'static str> {
+        match typ {
+          $($id => Some(concat!(stringify!($enum), "::", stringify!($name))),)*
+          _ => None,
+        }
+      }
+
+      /// Payload length of the message with type `typ`, or `None` if `typ`
+      /// is not part of this family.
+      ///
+      #[inline]
+      pub fn wire_len(typ: u16) -> Option<usize> {
+        match typ {
+          $($id => Some($len),)*
+          _ => None,
+        }
+      }
+
+      /// Decodes the message with type `typ`, or returns `None` if `typ` is
+      /// not part of this family.
+      ///
+      pub fn decode(
+        r: &mut WireReader,
+        typ: u16,
+      ) -> Result<Option<$enum>, DecodeError> {
+        match typ {
+          $($id => {
+            check_payload_len(r, typ, $len)?;
+            $(message_decode!(r, $enum::$name, $item);)*
+
+            Ok(Some($enum::$name $(($($field),*))?))
+          })*
+          _ => Ok(None),
+        }
+      }
+    }
+
+    impl WireEncode for $enum {
+      fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match *self {
+          $($enum::$name $(($(ref $field),*))? => {
+            $(message_encode!(w, $item);)*
+
+            Ok(())
+          })*
+        }
+      }
+    }
+
+    impl PartialEq for $enum {
+      fn eq(&self, other: &$enum) -> bool {
+        match *self {
+          $($enum::$name $(($(ref $field),*))? => {
+            let fields = ($($($field,)*)?);
+
+            match *other {
+              $enum::$name $(($(ref $field),*))? => {
+                fields.field_eq(&($($($field,)*)?))
+              }
+              #[allow(unreachable_patterns)]
+              _ => false,
+            }
+          })*
+        }
+      }
+    }
+
+    impl Eq for $enum {}
+
+    impl Hash for $enum {
+      fn hash<H: Hasher>(&self, state: &mut H) {
+        self.typ().hash(state);
+
+        match *self {
+          $($enum::$name $(($(ref $field),*))? => {
+            $($($field.field_hash(state);)*)?
+          })*
+        }
+      }
+    }
+  )
+);
+
+macro_rules! message_flag(
+  (tagged; tagged $($rest:ident)*) => (true);
+  (response; response $($rest:ident)*) => (true);
+  ($flag:ident; $other:ident $($rest:ident)*) => (message_flag!($flag; $($rest)*));
+  ($flag:ident;) => (false);
+);
+
+macro_rules! message_decode(
+  ($r:ident, $enum:ident::$name:ident, [$t:ty]) => (
+    message_decode!(@field $r, message_field!($enum::$name.reserved), {
+      <$t as WireDecode>::decode($r)
+    });
+  );
+  ($r:ident, $enum:ident::$name:ident, {$field:ident: $codec:ident}) => (
+    let $field =
+      message_decode!(@field $r, message_field!($enum::$name.$field), {
+        <$codec as FieldCodec>::decode($r)
+      });
+  );
+  ($r:ident, $enum:ident::$name:ident, $field:ident) => (
+    let $field =
+      message_decode!(@field $r, message_field!($enum::$name.$field), {
+        WireDecode::decode($r)
+      });
+  );
+  (@field $r:ident, $name:expr, $decode:block) => ({
+    let start = $r.position();
+    let value = $decode.map_err(|e| e.with_field($name))?;
+    $r.mark($name, start);
+    value
+  });
+);
+
+macro_rules! message_field(
+  ($enum:ident::$name:ident.$field:ident) => (
+    concat!(stringify!($enum), "::", stringify!($name), ".", stringify!($field))
+  );
+);
+
+macro_rules! message_encode(
+  ($w:ident, [$t:ty]) => (<$t as Default>::default().encode($w)?;);
+  ($w:ident, {$field:ident: $codec:ident}) => (
+    <$codec as FieldCodec>::encode($field, $w)?;
+  );
+  ($w:ident, $field:ident) => ($field.encode($w)?;);
+);
+
+/// Compares and hashes a message field. f32 fields are compared and hashed by
+/// their bits, which keeps messages Eq and Hash.
+///
+trait MessageField {
+  fn field_eq(&self, other: &Self) -> bool;
+
+  fn field_hash<H: Hasher>(&self, state: &mut H);
+}
+
+impl MessageField for f32 {
+  #[inline]
+  fn field_eq(&self, other: &f32) -> bool {
+    self.to_bits() == other.to_bits()
+  }
+
+  #[inline]
+  fn field_hash<H: Hasher>(&self, state: &mut H) {
+    self.to_bits().hash(state)
+  }
+}
+
+impl<'a, T> MessageField for &'a T
+where
+  T: MessageField + ?Sized,
+{
+  #[inline]
+  fn field_eq(&self, other: &&'a T) -> bool {
+    (**self).field_eq(*other)
+  }
+
+  #[inline]
+  fn field_hash<H: Hasher>(&self, state: &mut H) {
+    (**self).field_hash(state)
+  }
+}
+
+impl<T> MessageField for [T]
+where
+  T: MessageField,
+{
+  fn field_eq(&self, other: &[T]) -> bool {
+    self.len() == other.len()
+      && self.iter().zip(other).all(|(a, b)| a.field_eq(b))
+  }
+
+  fn field_hash<H: Hasher>(&self, state: &mut H) {
+    self.len().hash(state);
+
+    for v in self {
+      v.field_hash(state);
+    }
+  }
+}
+
+impl<T> MessageField for Vec<T>
+where
+  T: MessageField,
+{
+  #[inline]
+  fn field_eq(&self, other: &Vec<T>) -> bool {
+    self[..].field_eq(&other[..])
+  }
+
+  #[inline]
+  fn field_hash<H: Hasher>(&self, state: &mut H) {
+    self[..].field_hash(state)
+  }
+}
+
+macro_rules! message_fields(
+  ($($t:ty),*) => ($(
+    impl MessageField for $t {
+      #[inline]
+      fn field_eq(&self, other: &$t) -> bool {
+        self == other
+      }
+
+      #[inline]
+      fn field_hash<H: Hasher>(&self, state: &mut H) {
+        self.hash(state)
+      }
+    }
+  )*);
+);
+
+message_fields!(
+  bool,
+  u8,
+  u16,
+  u32,
+  u64,
+  i16,
+  String,
+  [u8; 16],
+  [u32; 8],
+  [HSBK; 8],
+  [HSBK; 64],
+  Array64<u8>,
+  Service,
+  Power,
+  Waveform,
+  ApplicationRequest,
+  MultiZoneEffectType,
+  TransitionMs,
+  HSBK,
+  FirmwareVersion,
+  TileDevice,
+  SwitchButton
+);
+
+macro_rules! message_tuple(
+  ($($t:ident.$i:tt),*) => (
+    impl<$($t: MessageField),*> MessageField for ($($t,)*) {
+      #[inline]
+      #[allow(unused_variables)]
+      fn field_eq(&self, other: &($($t,)*)) -> bool {
+        true $(&& self.$i.field_eq(&other.$i))*
+      }
+
+      #[inline]
+      #[allow(unused_variables)]
+      fn field_hash<H: Hasher>(&self, state: &mut H) {
+        $(self.$i.field_hash(state);)*
+      }
+    }
+  );
+);
+
+message_tuple!();
+message_tuple!(T0.0);
+message_tuple!(T0.0, T1.1);
+message_tuple!(T0.0, T1.1, T2.2);
+message_tuple!(T0.0, T1.1, T2.2, T3.3);
+message_tuple!(T0.0, T1.1, T2.2, T3.3, T4.4);
+message_tuple!(T0.0, T1.1, T2.2, T3.3, T4.4, T5.5);
+message_tuple!(T0.0, T1.1, T2.2, T3.3, T4.4, T5.5, T6.6);
+message_tuple!(T0.0, T1.1, T2.2, T3.3, T4.4, T5.5, T6.6, T7.7);
+message_tuple!(T0.0, T1.1, T2.2, T3.3, T4.4, T5.5, T6.6, T7.7, T8.8);
+message_tuple!(T0.0, T1.1, T2.2, T3.3, T4.4, T5.5, T6.6, T7.7, T8.8, T9.9);
+
+/// Checks there are at least `len` bytes left for a payload of type `typ`.
+///
+#[inline]
+fn check_payload_len(
+  r: &WireReader,
+  typ: u16,
+  len: usize,
+) -> Result<(), DecodeError> {
+  if r.remaining() < len {
+    return Err(DecodeError::PayloadTooShort {
+      typ,
+      offset: r.position(),
+      expected: len,
+      actual: r.remaining(),
+    });
+  }
+
+  Ok(())
+}
+
+/// Encodes and decodes a message field whose wire layout is not implied by
+/// its type.
+///
+trait FieldCodec {
+  type Value;
+
+  fn encode<W: Write>(v: &Self::Value, w: &mut W) -> io::Result<()>;
+
+  fn decode(r: &mut WireReader) -> Result<Self::Value, DecodeError>;
+}
+
 /// Labels from a LiFX blub are always 32 byte strings (not null terminated).
-/// Decodes a 32 byte string.
+/// Decodes a 32 byte string. Invalid UTF-8 is replaced with U+FFFD, or fails
+/// if the reader wants strict labels.
 ///
-fn decode_32_byte_str<D: Decoder>(d: &mut D) -> Result<String, D::Error> {
-  let mut s = Vec::with_capacity(32);
+fn decode_32_byte_str(r: &mut WireReader) -> Result<String, DecodeError> {
+  decode_32_byte_str_ref(r).map(Cow::into_owned)
+}
 
-  for _ in 0..32 {
-    let b = try!(d.read_u8());
-    if b == 0 {
-      break;
+/// Borrows a 32 byte label from the reader, as decode_32_byte_str decodes it.
+/// Only a label with invalid UTF-8 is copied, to replace it.
+///
+fn decode_32_byte_str_ref<'a>(
+  r: &mut WireReader<'a>,
+) -> Result<Cow<'a, str>, DecodeError> {
+  let offset = r.position();
+  let bytes = r.read_bytes(32)?;
+  let len = bytes.iter().position(|&b| b == 0).unwrap_or(32);
+
+  match ::std::str::from_utf8(&bytes[..len]) {
+    Ok(label) => Ok(Cow::Borrowed(label)),
+    Err(_) if r.label_mode() == LabelMode::Strict => {
+      Err(invalid_label(offset, bytes))
     }
-    s.push(b)
+    Err(_) => Ok(String::from_utf8_lossy(&bytes[..len])),
   }
+}
+
+#[inline]
+fn invalid_label(offset: usize, bytes: &[u8]) -> DecodeError {
+  let mut raw = [0; 32];
+  raw.copy_from_slice(bytes);
 
-  unsafe { Ok(String::from_utf8_unchecked(s)) }
+  DecodeError::InvalidLabel {
+    field: "label",
+    offset,
+    raw,
+  }
 }
 
-/// Decodes a 16 byte array.
+/// Encodes a string as a 32 byte, null padded label. Labels longer than 32
+/// bytes are truncated on a character boundary.
 ///
-fn decode_16_byte_arr<D: Decoder>(d: &mut D) -> Result<[u8; 16], D::Error> {
-  let mut arr = [0; 16];
-  for i in 0..16 {
-    arr[i] = try!(d.read_u8());
+fn encode_32_byte_str<W: Write>(w: &mut W, label: &str) -> io::Result<()> {
+  let mut len = ::std::cmp::min(label.len(), 32);
+  while !label.is_char_boundary(len) {
+    len -= 1;
   }
-  Ok(arr)
+
+  w.write_all(&label.as_bytes()[..len])?;
+  w.write_all(&[0; 32][len..])
 }
 
-/// Decodes a 64 byte array.
+/// Decodes `max` values, keeping the first `count`. Variable length arrays are
+/// always padded to their max length on the wire.
 ///
-fn decode_64_byte_arr<D: Decoder>(d: &mut D) -> Result<[u8; 64], D::Error> {
-  let mut arr = [0; 64];
-  for i in 0..64 {
-    arr[i] = try!(d.read_u8());
+fn decode_padded<T: WireDecode>(
+  r: &mut WireReader,
+  count: usize,
+  max: usize,
+) -> Result<Vec<T>, DecodeError> {
+  let mut items = Vec::with_capacity(max);
+  for _ in 0..max {
+    items.push(T::decode(r)?);
   }
-  Ok(arr)
+  items.truncate(count);
+  Ok(items)
 }
 
-/// Service enumeration.
+/// Encodes up to `max` values, padded with defaults to `max`.
 ///
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+fn encode_padded<T: WireEncode + Default, W: Write>(
+  w: &mut W,
+  items: &[T],
+  max: usize,
+) -> io::Result<()> {
+  let count = ::std::cmp::min(items.len(), max);
+  for item in &items[..count] {
+    item.encode(w)?;
+  }
+  for _ in count..max {
+    T::default().encode(w)?;
+  }
+  Ok(())
+}
+
+/// A 32 byte label.
+///
+struct Label;
+
+impl FieldCodec for Label {
+  type Value = String;
+
+  fn encode<W: Write>(label: &String, w: &mut W) -> io::Result<()> {
+    encode_32_byte_str(w, label)
+  }
+
+  fn decode(r: &mut WireReader) -> Result<String, DecodeError> {
+    decode_32_byte_str(r)
+  }
+}
+
+/// A count prefixed array of up to MAX_EXTENDED_ZONES HSBK values.
+///
+struct ExtendedZones;
+
+impl FieldCodec for ExtendedZones {
+  type Value = Vec<HSBK>;
+
+  fn encode<W: Write>(colors: &Vec<HSBK>, w: &mut W) -> io::Result<()> {
+    w.write_u8(::std::cmp::min(colors.len(), MAX_EXTENDED_ZONES) as u8)?;
+    encode_padded(w, colors, MAX_EXTENDED_ZONES)
+  }
+
+  fn decode(r: &mut WireReader) -> Result<Vec<HSBK>, DecodeError> {
+    let count = r.read_u8()? as usize;
+    decode_padded(r, count, MAX_EXTENDED_ZONES)
+  }
+}
+
+/// MAX_CHAIN_TILES tiles, followed by the number of tiles that are used.
+///
+struct DeviceChain;
+
+impl FieldCodec for DeviceChain {
+  type Value = Vec<TileDevice>;
+
+  fn encode<W: Write>(tiles: &Vec<TileDevice>, w: &mut W) -> io::Result<()> {
+    encode_padded(w, tiles, MAX_CHAIN_TILES)?;
+    w.write_u8(::std::cmp::min(tiles.len(), MAX_CHAIN_TILES) as u8)
+  }
+
+  fn decode(r: &mut WireReader) -> Result<Vec<TileDevice>, DecodeError> {
+    let mut tiles = decode_padded(r, MAX_CHAIN_TILES, MAX_CHAIN_TILES)?;
+    tiles.truncate(r.read_u8()? as usize);
+    Ok(tiles)
+  }
+}
+
+/// A count prefixed array of up to MAX_SWITCH_BUTTONS buttons.
+///
+struct SwitchButtons;
+
+impl FieldCodec for SwitchButtons {
+  type Value = Vec<SwitchButton>;
+
+  fn encode<W: Write>(buttons: &Vec<SwitchButton>, w: &mut W) -> io::Result<()> {
+    w.write_u8(::std::cmp::min(buttons.len(), MAX_SWITCH_BUTTONS) as u8)?;
+    encode_padded(w, buttons, MAX_SWITCH_BUTTONS)
+  }
+
+  fn decode(r: &mut WireReader) -> Result<Vec<SwitchButton>, DecodeError> {
+    let count = r.read_u8()? as usize;
+    decode_padded(r, count, MAX_SWITCH_BUTTONS)
+  }
+}
+
+/// Service enumeration. Services other than Udp keep their raw id.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub enum Service {
   Udp,
-  Reserved,
+  Reserved(u8),
 }
 
-impl Into<u8> for Service {
+impl From<Service> for u8 {
   #[inline]
-  fn into(self) -> u8 {
+  fn from(v: Service) -> u8 {
     use Service::*;
 
-    match self {
+    match v {
       Udp => 1,
-      Reserved => 5,
+      Reserved(b) => b,
     }
   }
 }
@@ -134,95 +652,456 @@ impl From<u8> for Service {
 
     match b {
       1 => Udp,
-      _ => Reserved,
+      _ => Reserved(b),
     }
   }
 }
 
-impl Encodable for Service {
-  fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-    use Service::*;
+impl WireEncode for Service {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u8((*self).into())
+  }
+}
 
-    let id = self.clone().into();
-    let var = match *self {
-      Udp => "Udp",
-      Reserved => "Reserved",
-    };
+impl WireDecode for Service {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<Service, DecodeError> {
+    Ok(From::from(r.read_u8()?))
+  }
+}
+
+/// Power level for Device::SetPower and Device::GetPower.
+///
+/// # Notes
+///
+///   * Bulbs only support Standby and Max when setting power, but dimmers and
+///     switches can report anything in between, so intermediate values are
+///     kept as Level.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Power {
+  Standby,
+  Level(u16),
+  Max,
+}
+
+impl Power {
+  /// Creates a power level from a percentage (clamped to 0 - 100).
+  ///
+  #[inline]
+  pub fn from_percent(percent: f32) -> Power {
+    let percent = percent.clamp(0.0, 100.0);
+    // rounds to the nearest level without f32::round, which needs std
+    From::from((percent / 100.0 * u16::MAX as f32 + 0.5) as u16)
+  }
+
+  /// Returns the power level as a percentage.
+  ///
+  #[inline]
+  pub fn percent(self) -> f32 {
+    let level: u16 = self.into();
+    level as f32 / u16::MAX as f32 * 100.0
+  }
+
+  /// Checks if the power level is anything other than Standby.
+  ///
+  #[inline]
+  pub fn is_on(self) -> bool {
+    self != Power::Standby
+  }
+}
+
+impl From<Power> for u16 {
+  #[inline]
+  fn from(v: Power) -> u16 {
+    use Power::*;
+
+    match v {
+      Standby => 0,
+      Level(v) => v,
+      Max => 65535,
+    }
+  }
+}
+
+impl From<u16> for Power {
+  #[inline]
+  fn from(v: u16) -> Power {
+    use Power::*;
+
+    match v {
+      0 => Standby,
+      65535 => Max,
+      _ => Level(v),
+    }
+  }
+}
+
+impl WireEncode for Power {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u16::<LittleEndian>((*self).into())
+  }
+}
+
+impl WireDecode for Power {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<Power, DecodeError> {
+    Ok(From::from(r.read_u16()?))
+  }
+}
+
+/// Waveform used by Light::SetWaveform.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Waveform {
+  Saw,
+  Sine,
+  HalfSine,
+  Triangle,
+  Pulse,
+}
+
+impl From<Waveform> for u8 {
+  #[inline]
+  fn from(v: Waveform) -> u8 {
+    use Waveform::*;
+
+    match v {
+      Saw => 0,
+      Sine => 1,
+      HalfSine => 2,
+      Triangle => 3,
+      Pulse => 4,
+    }
+  }
+}
+
+impl From<u8> for Waveform {
+  #[inline]
+  fn from(b: u8) -> Waveform {
+    use Waveform::*;
+
+    match b {
+      1 => Sine,
+      2 => HalfSine,
+      3 => Triangle,
+      4 => Pulse,
+      _ => Saw,
+    }
+  }
+}
+
+impl WireEncode for Waveform {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u8((*self).into())
+  }
+}
+
+impl WireDecode for Waveform {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<Waveform, DecodeError> {
+    Ok(From::from(r.read_u8()?))
+  }
+}
+
+/// Apply behaviour for MultiZone::SetColorZones.
+///
+///   * NoApply buffers the change until a message with Apply or ApplyOnly is
+///     received.
+///   * Apply applies the change, along with any buffered changes.
+///   * ApplyOnly ignores the color in the message, and applies any buffered
+///     changes.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum ApplicationRequest {
+  NoApply,
+  Apply,
+  ApplyOnly,
+}
+
+impl From<ApplicationRequest> for u8 {
+  #[inline]
+  fn from(v: ApplicationRequest) -> u8 {
+    use ApplicationRequest::*;
+
+    match v {
+      NoApply => 0,
+      Apply => 1,
+      ApplyOnly => 2,
+    }
+  }
+}
+
+impl From<u8> for ApplicationRequest {
+  #[inline]
+  fn from(b: u8) -> ApplicationRequest {
+    use ApplicationRequest::*;
+
+    match b {
+      0 => NoApply,
+      2 => ApplyOnly,
+      _ => Apply,
+    }
+  }
+}
+
+impl WireEncode for ApplicationRequest {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u8((*self).into())
+  }
+}
+
+impl WireDecode for ApplicationRequest {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<ApplicationRequest, DecodeError> {
+    Ok(From::from(r.read_u8()?))
+  }
+}
+
+/// Firmware effect for MultiZone::SetMultiZoneEffect.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum MultiZoneEffectType {
+  Off,
+  Move,
+}
+
+impl From<MultiZoneEffectType> for u8 {
+  #[inline]
+  fn from(v: MultiZoneEffectType) -> u8 {
+    use MultiZoneEffectType::*;
+
+    match v {
+      Off => 0,
+      Move => 1,
+    }
+  }
+}
+
+impl From<u8> for MultiZoneEffectType {
+  #[inline]
+  fn from(b: u8) -> MultiZoneEffectType {
+    use MultiZoneEffectType::*;
+
+    match b {
+      1 => Move,
+      _ => Off,
+    }
+  }
+}
+
+impl WireEncode for MultiZoneEffectType {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u8((*self).into())
+  }
+}
+
+impl WireDecode for MultiZoneEffectType {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<MultiZoneEffectType, DecodeError> {
+    Ok(From::from(r.read_u8()?))
+  }
+}
+
+/// Direction of the MultiZoneEffectType::Move effect. This is stored in the
+/// second effect parameter.
+///
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum MoveDirection {
+  Towards,
+  Away,
+}
+
+impl From<MoveDirection> for u32 {
+  #[inline]
+  fn from(v: MoveDirection) -> u32 {
+    use MoveDirection::*;
+
+    match v {
+      Towards => 0,
+      Away => 1,
+    }
+  }
+}
+
+impl From<u32> for MoveDirection {
+  #[inline]
+  fn from(v: u32) -> MoveDirection {
+    use MoveDirection::*;
+
+    match v {
+      0 => Towards,
+      _ => Away,
+    }
+  }
+}
+
+/// HSBK (Hue, Saturation, Brightness, Kelvin)
+///
+#[derive(Debug, Default, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct HSBK {
+  hue: u16,
+  saturation: u16,
+  brightness: u16,
+  kelvin: u16,
+}
+
+impl HSBK {
+  pub fn new(h: Hue, s: Saturation, b: Brightness, k: Kelvin) -> HSBK {
+    HSBK {
+      hue: h.into(),
+      saturation: s.into(),
+      brightness: b.into(),
+      kelvin: k.into(),
+    }
+  }
+
+  #[inline(always)]
+  pub fn hue(&self) -> Hue {
+    Hue::from(self.hue)
+  }
+
+  #[inline(always)]
+  pub fn saturation(&self) -> Saturation {
+    Saturation::from(self.saturation)
+  }
+
+  #[inline(always)]
+  pub fn brightness(&self) -> Brightness {
+    Brightness::from(self.brightness)
+  }
+
+  #[inline(always)]
+  pub fn kelvin(&self) -> Kelvin {
+    Kelvin::raw(self.kelvin)
+  }
+
+  /// Returns this color with its hue replaced.
+  ///
+  #[inline]
+  pub fn with_hue(self, h: Hue) -> HSBK {
+    HSBK { hue: h.into(), ..self }
+  }
+
+  /// Returns this color with its saturation replaced.
+  ///
+  #[inline]
+  pub fn with_saturation(self, s: Saturation) -> HSBK {
+    HSBK { saturation: s.into(), ..self }
+  }
+
+  /// Returns this color with its brightness replaced.
+  ///
+  #[inline]
+  pub fn with_brightness(self, b: Brightness) -> HSBK {
+    HSBK { brightness: b.into(), ..self }
+  }
+
+  /// Returns this color with its temperature replaced.
+  ///
+  #[inline]
+  pub fn with_kelvin(self, k: Kelvin) -> HSBK {
+    HSBK { kelvin: k.into(), ..self }
+  }
+}
+
+impl WireEncode for HSBK {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u16::<LittleEndian>(self.hue)?;
+    w.write_u16::<LittleEndian>(self.saturation)?;
+    w.write_u16::<LittleEndian>(self.brightness)?;
+    w.write_u16::<LittleEndian>(self.kelvin)
+  }
+}
 
-    s.emit_enum("Service", |s| {
-      s.emit_enum_variant(var, id as usize, 0, |s| s.emit_u8(id))
+impl WireDecode for HSBK {
+  fn decode(r: &mut WireReader) -> Result<HSBK, DecodeError> {
+    Ok(HSBK {
+      hue: r.read_u16()?,
+      saturation: r.read_u16()?,
+      brightness: r.read_u16()?,
+      kelvin: r.read_u16()?,
     })
   }
 }
 
-/// Power level for Device::SetPower and Device::GetPower.
+/// Firmware version for Device::StateHostFirmware and Device::StateWifiFirmware.
 ///
-#[derive(Debug, Copy, Clone)]
-pub enum Power {
-  Standby,
-  Max,
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct FirmwareVersion {
+  build: u64,
+  version: u32,
 }
 
-impl Into<u16> for Power {
+impl FirmwareVersion {
   #[inline]
-  fn into(self) -> u16 {
-    use Power::*;
-
-    match self {
-      Standby => 0,
-      Max => 65535,
+  pub fn new(build: u64, version: u32) -> FirmwareVersion {
+    FirmwareVersion {
+      build,
+      version,
     }
   }
-}
 
-impl From<u16> for Power {
+  /// Returns the raw build timestamp (nanoseconds since the epoch).
+  ///
+  #[inline(always)]
+  pub fn build(&self) -> u64 {
+    self.build
+  }
+
+  /// Returns the time the firmware was built.
+  ///
+  #[cfg(feature = "std")]
   #[inline]
-  fn from(v: u16) -> Power {
-    use Power::*;
+  pub fn build_time(&self) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(self.build)
+  }
 
-    match v {
-      0 => Standby,
-      _ => Max,
-    }
+  /// Returns the packed version (major in the high 16 bits, minor in the low
+  /// 16 bits).
+  ///
+  #[inline(always)]
+  pub fn version(&self) -> u32 {
+    self.version
   }
-}
 
-impl Encodable for Power {
-  fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-    use Power::*;
+  #[inline(always)]
+  pub fn major(&self) -> u16 {
+    (self.version >> 16) as u16
+  }
 
-    let id = self.clone().into();
-    let var = match *self {
-      Standby => "Standby",
-      Max => "Max",
-    };
+  #[inline(always)]
+  pub fn minor(&self) -> u16 {
+    self.version as u16
+  }
+}
 
-    s.emit_enum("Power", |s| {
-      s.emit_enum_variant(var, id as usize, 0, |s| s.emit_u16(id))
-    })
+impl Display for FirmwareVersion {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+    write!(f, "{}.{}", self.major(), self.minor())
   }
 }
 
-/// HSBK (Hue, Saturation, Brightness, Kelvin)
-///
-#[derive(RustcEncodable, RustcDecodable, Debug, Copy, Clone)]
-pub struct HSBK {
-  hue: u16,
-  saturation: u16,
-  brightness: u16,
-  kelvin: u16,
+impl WireEncode for FirmwareVersion {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(self.build)?;
+    w.write_u64::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(self.version)
+  }
 }
 
-impl HSBK {
-  pub fn new(h: u16, s: u16, b: u16, k: u16) -> HSBK {
-    assert!(k >= 2500 && k <= 9000);
+impl WireDecode for FirmwareVersion {
+  fn decode(r: &mut WireReader) -> Result<FirmwareVersion, DecodeError> {
+    let build = r.read_u64()?;
+    r.skip(8)?;
+    let version = r.read_u32()?;
 
-    HSBK {
-      hue: h,
-      saturation: s,
-      brightness: b,
-      kelvin: k,
-    }
+    Ok(FirmwareVersion::new(build, version))
   }
 }
 
@@ -231,12 +1110,20 @@ impl HSBK {
 /// # Notes
 /// /
 ///   * This enum is encodable, but not decodable (since it needs the message
-///     type which is only present in the header)!
+///     type and size which are only present in the header)!
+///   * Unknown holds the raw bytes of any message type this crate does not
+///     understand, so it can still be inspected or forwarded.
 ///
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Payload {
   Device(Device),
   Light(Light),
+  MultiZone(MultiZone),
+  Tile(Tile),
+  Relay(Relay),
+  Button(Button),
+  Unknown { typ: u16, bytes: Vec<u8> },
 }
 
 impl Payload {
@@ -247,6 +1134,11 @@ impl Payload {
     match *self {
       Device(ref devm) => devm.typ(),
       Light(ref lightm) => lightm.typ(),
+      MultiZone(ref zonem) => zonem.typ(),
+      Tile(ref tilem) => tilem.typ(),
+      Relay(ref relaym) => relaym.typ(),
+      Button(ref buttonm) => buttonm.typ(),
+      Unknown { typ, .. } => typ,
     }
   }
 
@@ -257,17 +1149,20 @@ impl Payload {
     match *self {
       Device(ref devm) => devm.tagged(),
       Light(ref lightm) => lightm.tagged(),
+      MultiZone(ref zonem) => zonem.tagged(),
+      Tile(ref tilem) => tilem.tagged(),
+      Relay(ref relaym) => relaym.tagged(),
+      Button(ref buttonm) => buttonm.tagged(),
+      Unknown { .. } => false,
     }
   }
 
+  /// Size of the payload on the wire. This is computed by running the
+  /// encoder, so it can never disagree with what gets sent.
+  ///
   #[inline]
   pub fn size(&self) -> u16 {
-    use Payload::*;
-
-    match *self {
-      Device(ref devm) => devm.size(),
-      Light(ref lightm) => lightm.size(),
-    }
+    serialize::encoded_len(self) as u16
   }
 
   #[inline]
@@ -277,287 +1172,307 @@ impl Payload {
     match *self {
       Device(ref devm) => devm.requires_response(),
       Light(ref lightm) => lightm.requires_response(),
+      MultiZone(ref zonem) => zonem.requires_response(),
+      Tile(ref tilem) => tilem.requires_response(),
+      Relay(ref relaym) => relaym.requires_response(),
+      Button(ref buttonm) => buttonm.requires_response(),
+      Unknown { .. } => false,
     }
   }
 
-  /// Yes, this is scary huge. All it is doing is decoding the specific payloads
-  /// depending on the tag that was in the message header. Some of these messages
-  /// technically aren't even sent by a LiFX bulb, but are still implemented
-  /// anyways.
+  /// Checks if this is a `State*` message, which a device sends to report its
+  /// state.
+  ///
+  #[inline]
+  pub fn is_state(&self) -> bool {
+    Payload::type_name(self.typ()).is_some_and(|name| name.contains("::State"))
+  }
+
+  /// Name of a message type, as `Family::Variant`, or `None` for types this
+  /// crate does not understand.
   ///
-  pub fn decode<D: Decoder>(d: &mut D, tag: u16) -> Result<Payload, D::Error> {
-    match tag {
-      2 => Ok(Payload::Device(Device::GetService)),
-      3 => {
-        let service = From::from(try!(d.read_u8()));
-        let port = try!(d.read_u32());
+  pub fn type_name(typ: u16) -> Option<&'static str> {
+    Device::type_name(typ)
+      .or_else(|| Light::type_name(typ))
+      .or_else(|| MultiZone::type_name(typ))
+      .or_else(|| Tile::type_name(typ))
+      .or_else(|| Relay::type_name(typ))
+      .or_else(|| Button::type_name(typ))
+  }
 
-        Ok(Payload::Device(Device::StateService(service, port)))
-      }
-      12 => Ok(Payload::Device(Device::GetHostInfo)),
-      13 => {
-        let signal = try!(d.read_f32());
-        let tx = try!(d.read_u32());
-        let rx = try!(d.read_u32());
-        let _ = try!(d.read_i16());
-
-        Ok(Payload::Device(Device::StateHostInfo(signal, tx, rx)))
-      }
-      14 => Ok(Payload::Device(Device::GetHostFirmware)),
-      15 => {
-        let build = try!(d.read_u64());
-        let _ = try!(d.read_u64());
-        let version = try!(d.read_u32());
-
-        Ok(Payload::Device(Device::StateHostFirmware(
-          build,
-          version,
-        )))
-      }
-      16 => Ok(Payload::Device(Device::GetWifiInfo)),
-      17 => {
-        let signal = try!(d.read_f32());
-        let tx = try!(d.read_u32());
-        let rx = try!(d.read_u32());
-        let _ = try!(d.read_i16());
-
-        Ok(Payload::Device(Device::StateWifiInfo(signal, tx, rx)))
-      }
-      18 => Ok(Payload::Device(Device::GetWifiFirmware)),
-      19 => {
-        let build = try!(d.read_u64());
-        let _ = try!(d.read_u64());
-        let version = try!(d.read_u32());
-
-        Ok(Payload::Device(Device::StateWifiFirmware(
-          build,
-          version,
-        )))
-      }
-      20 => Ok(Payload::Device(Device::GetPower)),
-      21 => Ok(Payload::Device(Device::SetPower(From::from(try!(
-        d.read_u16()
-      ))))),
-      22 => Ok(Payload::Device(Device::StatePower(From::from(try!(
-        d.read_u16()
-      ))))),
-      23 => Ok(Payload::Device(Device::GetLabel)),
-      25 => {
-        let label = try!(decode_32_byte_str(d));
-
-        Ok(Payload::Device(Device::StateLabel(label)))
-      }
-      32 => Ok(Payload::Device(Device::GetVersion)),
-      33 => {
-        let vendor = try!(d.read_u32());
-        let product = try!(d.read_u32());
-        let version = try!(d.read_u32());
-
-        Ok(Payload::Device(Device::StateVersion(
-          vendor,
-          product,
-          version,
-        )))
-      }
-      34 => Ok(Payload::Device(Device::GetInfo)),
-      35 => {
-        let time = try!(d.read_u64());
-        let uptime = try!(d.read_u64());
-        let downtime = try!(d.read_u64());
-
-        Ok(Payload::Device(Device::StateInfo(
-          time,
-          uptime,
-          downtime,
-        )))
-      }
-      45 => Ok(Payload::Device(Device::Acknowledgement)),
-      48 => Ok(Payload::Device(Device::GetLocation)),
-      50 => {
-        let location = try!(decode_16_byte_arr(d));
-        let label = try!(decode_32_byte_str(d));
-        let updated = try!(d.read_u64());
-
-        Ok(Payload::Device(Device::StateLocation(
-          location,
-          label,
-          updated,
-        )))
-      }
-      51 => Ok(Payload::Device(Device::GetGroup)),
-      53 => {
-        let group = try!(decode_16_byte_arr(d));
-        let label = try!(decode_32_byte_str(d));
-        let updated = try!(d.read_u64());
-
-        Ok(Payload::Device(Device::StateGroup(
-          group,
-          label,
-          updated,
-        )))
+  /// Payload length in bytes of a message type, or `None` for types this
+  /// crate does not understand.
+  ///
+  pub fn wire_len(typ: u16) -> Option<usize> {
+    Device::wire_len(typ)
+      .or_else(|| Light::wire_len(typ))
+      .or_else(|| MultiZone::wire_len(typ))
+      .or_else(|| Tile::wire_len(typ))
+      .or_else(|| Relay::wire_len(typ))
+      .or_else(|| Button::wire_len(typ))
+  }
+
+  /// Decodes the payload for the message type in the header, by asking each
+  /// message family in turn. Unrecognized types are decoded as
+  /// `Payload::Unknown`, consuming `size` bytes.
+  ///
+  pub fn decode(
+    r: &mut WireReader,
+    tag: u16,
+    size: u16,
+  ) -> Result<Payload, DecodeError> {
+    if let Some(m) = Device::decode(r, tag)? {
+      return Ok(Payload::Device(m));
+    }
+    if let Some(m) = Light::decode(r, tag)? {
+      return Ok(Payload::Light(m));
+    }
+    if let Some(m) = MultiZone::decode(r, tag)? {
+      return Ok(Payload::MultiZone(m));
+    }
+    if let Some(m) = Tile::decode(r, tag)? {
+      return Ok(Payload::Tile(m));
+    }
+    if let Some(m) = Relay::decode(r, tag)? {
+      return Ok(Payload::Relay(m));
+    }
+    if let Some(m) = Button::decode(r, tag)? {
+      return Ok(Payload::Button(m));
+    }
+
+    let bytes = r.read_bytes(size as usize)?.to_vec();
+
+    Ok(Payload::Unknown { typ: tag, bytes })
+  }
+
+  /// Decodes a payload without copying its labels or raw bytes out of
+  /// `bytes`, which must hold exactly one payload of type `tag`. This is meant
+  /// for receive loops that inspect a message and throw it away.
+  ///
+  pub fn decode_ref<'a>(
+    tag: u16,
+    bytes: &'a [u8],
+  ) -> Result<PayloadRef<'a>, DecodeError> {
+    let mut r = WireReader::new(bytes);
+    if let Some(len) = Payload::wire_len(tag) {
+      check_payload_len(&r, tag, len)?;
+    }
+
+    let payload = match tag {
+      24 | 25 => {
+        let label = decode_32_byte_str_ref(&mut r)?;
+
+        if tag == 24 {
+          PayloadRef::SetLabel(label)
+        } else {
+          PayloadRef::StateLabel(label)
+        }
       }
-      58 => Ok(Payload::Device(Device::EchoRequest(Array64(try!(
-        decode_64_byte_arr(d)
-      ))))),
-      59 => Ok(Payload::Device(Device::EchoResponse(Array64(try!(
-        decode_64_byte_arr(d)
-      ))))),
-      101 => Ok(Payload::Light(Light::Get)),
-      102 => {
-        let _ = try!(d.read_u8());
-        let color = try!(HSBK::decode(d));
-        let duration = try!(d.read_u32());
-
-        Ok(Payload::Light(Light::SetColor(color, duration)))
+      49 | 50 | 52 | 53 => {
+        let id = WireDecode::decode(&mut r)?;
+        let label = decode_32_byte_str_ref(&mut r)?;
+        let updated = r.read_u64()?;
+
+        match tag {
+          49 => PayloadRef::SetLocation(id, label, updated),
+          50 => PayloadRef::StateLocation(id, label, updated),
+          52 => PayloadRef::SetGroup(id, label, updated),
+          _ => PayloadRef::StateGroup(id, label, updated),
+        }
       }
       107 => {
-        let color = try!(HSBK::decode(d));
-        let _ = try!(d.read_i16());
-        let power = try!(d.read_u16());
-        let label = try!(decode_32_byte_str(d));
-        let _ = try!(d.read_u64());
+        let color = HSBK::decode(&mut r)?;
+        r.skip(2)?;
+        let power = From::from(r.read_u16()?);
+        let label = decode_32_byte_str_ref(&mut r)?;
+        r.skip(8)?;
 
-        Ok(Payload::Light(Light::State(color, power, label)))
+        PayloadRef::LightState(color, power, label)
       }
-      116 => Ok(Payload::Light(Light::GetPower)),
-      117 => {
-        let level = From::from(try!(d.read_u16()));
-        let duration = try!(d.read_u32());
+      // unknown payloads are decoded as empty, and borrow the bytes instead
+      _ => match Payload::decode(&mut r, tag, 0)? {
+        Payload::Unknown { typ, .. } => {
+          r.skip(bytes.len())?;
+
+          PayloadRef::Unknown { typ, bytes }
+        }
+        payload => PayloadRef::Owned(payload),
+      },
+    };
 
-        Ok(Payload::Light(Light::SetPower(level, duration)))
-      }
-      118 => Ok(Payload::Light(Light::StatePower(From::from(try!(
-        d.read_u16()
-      ))))),
-      _ => Err(d.error("unrecognized message")),
+    if r.remaining() != 0 {
+      return Err(DecodeError::SizeMismatch {
+        field: "payload",
+        offset: 0,
+        expected: r.position(),
+        actual: bytes.len(),
+      });
     }
+
+    Ok(payload)
   }
 }
 
-pub struct Array64<T>(pub [T; 64]);
+impl WireEncode for Payload {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    use Payload::*;
 
-impl<T> Encodable for Array64<T>
-where
-  T: Encodable,
-{
-  fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-    let &Array64(ref inner) = self;
-    s.emit_seq(64, |s| {
-      for i in 0..64 {
-        try!(s.emit_seq_elt(i, |s| inner[i].encode(s)));
-      }
-      Ok(())
-    })
+    match *self {
+      Device(ref devm) => devm.encode(w),
+      Light(ref lightm) => lightm.encode(w),
+      MultiZone(ref zonem) => zonem.encode(w),
+      Tile(ref tilem) => tilem.encode(w),
+      Relay(ref relaym) => relaym.encode(w),
+      Button(ref buttonm) => buttonm.encode(w),
+      Unknown { ref bytes, .. } => w.write_all(&bytes[..]),
+    }
   }
 }
 
-/// Device message.
-///
-#[derive(RustcEncodable)]
-pub enum Device {
-  GetService,
-  StateService(Service, u32),
-  GetHostInfo,
-  StateHostInfo(f32, u32, u32),
-  GetHostFirmware,
-  StateHostFirmware(u64, u32),
-  GetWifiInfo,
-  StateWifiInfo(f32, u32, u32),
-  GetWifiFirmware,
-  StateWifiFirmware(u64, u32),
-  GetPower,
-  SetPower(Power),
-  StatePower(Power),
-  GetLabel,
-  StateLabel(String),
-  GetVersion,
-  StateVersion(u32, u32, u32),
-  GetInfo,
-  StateInfo(u64, u64, u64),
-  Acknowledgement,
-  GetLocation,
-  StateLocation([u8; 16], String, u64),
-  GetGroup,
-  StateGroup([u8; 16], String, u64),
-  EchoRequest(Array64<u8>),
-  EchoResponse(Array64<u8>),
-}
-
-impl Device {
+/// Borrowed payload, as returned by `Payload::decode_ref`.
+///
+/// # Notes
+///
+///   * Payloads carrying a label or raw bytes borrow them from the frame. A
+///     label with invalid UTF-8 is copied, with U+FFFD in its place, as
+///     Payload::decode does. Every other payload is decoded into `Owned`,
+///     which only allocates for the variable length MultiZone, Tile and
+///     Button messages.
+///   * LightState is the borrowed form of Light::State.
+///
+#[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum PayloadRef<'a> {
+  SetLabel(Cow<'a, str>),
+  StateLabel(Cow<'a, str>),
+  SetLocation([u8; 16], Cow<'a, str>, u64),
+  StateLocation([u8; 16], Cow<'a, str>, u64),
+  SetGroup([u8; 16], Cow<'a, str>, u64),
+  StateGroup([u8; 16], Cow<'a, str>, u64),
+  LightState(HSBK, Power, Cow<'a, str>),
+  Unknown { typ: u16, bytes: &'a [u8] },
+  Owned(Payload),
+}
+
+impl<'a> PayloadRef<'a> {
   #[inline]
   pub fn typ(&self) -> u16 {
-    use Device::*;
+    use PayloadRef::*;
 
     match *self {
-      GetService => 2,
-      StateService(_, _) => 3,
-      GetHostInfo => 12,
-      StateHostInfo(_, _, _) => 13,
-      GetHostFirmware => 14,
-      StateHostFirmware(_, _) => 15,
-      GetWifiInfo => 16,
-      StateWifiInfo(_, _, _) => 17,
-      GetWifiFirmware => 18,
-      StateWifiFirmware(_, _) => 19,
-      GetPower => 20,
-      SetPower(_) => 21,
-      StatePower(_) => 22,
-      GetLabel => 23,
+      SetLabel(_) => 24,
       StateLabel(_) => 25,
-      GetVersion => 32,
-      StateVersion(_, _, _) => 33,
-      GetInfo => 34,
-      StateInfo(_, _, _) => 35,
-      Acknowledgement => 45,
-      GetLocation => 48,
+      SetLocation(_, _, _) => 49,
       StateLocation(_, _, _) => 50,
-      GetGroup => 51,
+      SetGroup(_, _, _) => 52,
       StateGroup(_, _, _) => 53,
-      EchoRequest(_) => 58,
-      EchoResponse(_) => 59,
+      LightState(_, _, _) => 107,
+      Unknown { typ, .. } => typ,
+      Owned(ref payload) => payload.typ(),
     }
   }
 
-  #[inline]
-  pub fn tagged(&self) -> bool {
-    use Device::*;
+  /// Copies any borrowed labels or bytes into an owned payload.
+  ///
+  pub fn into_owned(self) -> Payload {
+    use PayloadRef::*;
 
-    match *self {
-      GetService => true,
-      _ => false,
+    match self {
+      SetLabel(label) => Payload::Device(Device::SetLabel(label.into_owned())),
+      StateLabel(label) => Payload::Device(Device::StateLabel(label.into_owned())),
+      SetLocation(id, label, updated) => {
+        Payload::Device(Device::SetLocation(id, label.into_owned(), updated))
+      }
+      StateLocation(id, label, updated) => {
+        Payload::Device(Device::StateLocation(id, label.into_owned(), updated))
+      }
+      SetGroup(id, label, updated) => {
+        Payload::Device(Device::SetGroup(id, label.into_owned(), updated))
+      }
+      StateGroup(id, label, updated) => {
+        Payload::Device(Device::StateGroup(id, label.into_owned(), updated))
+      }
+      LightState(color, power, label) => {
+        Payload::Light(Light::State(color, power, label.into_owned()))
+      }
+      Unknown { typ, bytes } => Payload::Unknown {
+        typ,
+        bytes: bytes.to_vec(),
+      },
+      Owned(payload) => payload,
     }
   }
+}
 
-  #[inline]
-  pub fn requires_response(&self) -> bool {
-    use Device::*;
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct Array64<T>(pub [T; 64]);
 
-    match *self {
-      GetService | GetHostInfo | GetHostFirmware | GetWifiInfo | GetWifiFirmware
-      | GetPower | SetPower(_) | GetLabel | GetVersion | GetInfo | GetLocation
-      | GetGroup | EchoRequest(_) => true,
-      _ => false,
+impl<T> WireEncode for Array64<T>
+where
+  T: WireEncode,
+{
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let Array64(ref inner) = *self;
+    for item in inner.iter() {
+      item.encode(w)?;
     }
+    Ok(())
   }
+}
 
-  #[inline]
-  pub fn size(&self) -> u16 {
-    use Device::*;
 
-    match *self {
-      GetService | GetHostInfo | GetHostFirmware | GetWifiInfo | GetWifiFirmware
-      | GetPower | GetLabel | GetVersion | GetInfo | Acknowledgement
-      | GetLocation | GetGroup => 0,
-      SetPower(_) | StatePower(_) => 2,
-      StateService(_, _) => 5,
-      StateVersion(_, _, _) => 12,
-      StateHostInfo(_, _, _) | StateWifiInfo(_, _, _) => 14,
-      StateHostFirmware(_, _) | StateWifiFirmware(_, _) => 20,
-      StateInfo(_, _, _) => 24,
-      StateLabel(_) => 32,
-      StateLocation(_, _, _) | StateGroup(_, _, _) => 56,
-      EchoRequest(_) | EchoResponse(_) => 64,
-    }
+impl<T> WireDecode for Array64<T>
+where
+  [T; 64]: WireDecode,
+{
+  fn decode(r: &mut WireReader) -> Result<Array64<T>, DecodeError> {
+    Ok(Array64(WireDecode::decode(r)?))
+  }
+}
+
+messages! {
+  /// Device message.
+  ///
+  #[derive(Clone)]
+  pub enum Device {
+    GetService = 2 (0) [tagged, response] {},
+    StateService(service: Service, port: u32) = 3 (5) [] {service, port},
+    GetHostInfo = 12 (0) [response] {},
+    StateHostInfo(signal: f32, tx: u32, rx: u32) = 13 (14) []
+      {signal, tx, rx, [i16]},
+    GetHostFirmware = 14 (0) [response] {},
+    StateHostFirmware(firmware: FirmwareVersion) = 15 (20) [] {firmware},
+    GetWifiInfo = 16 (0) [response] {},
+    StateWifiInfo(signal: f32, tx: u32, rx: u32) = 17 (14) []
+      {signal, tx, rx, [i16]},
+    GetWifiFirmware = 18 (0) [response] {},
+    StateWifiFirmware(firmware: FirmwareVersion) = 19 (20) [] {firmware},
+    GetPower = 20 (0) [response] {},
+    SetPower(level: Power) = 21 (2) [response] {level},
+    StatePower(level: Power) = 22 (2) [] {level},
+    GetLabel = 23 (0) [response] {},
+    SetLabel(label: String) = 24 (32) [response] {{label: Label}},
+    StateLabel(label: String) = 25 (32) [] {{label: Label}},
+    GetVersion = 32 (0) [response] {},
+    StateVersion(vendor: u32, product: u32, version: u32) = 33 (12) []
+      {vendor, product, version},
+    GetInfo = 34 (0) [response] {},
+    StateInfo(time: u64, uptime: u64, downtime: u64) = 35 (24) []
+      {time, uptime, downtime},
+    Acknowledgement = 45 (0) [] {},
+    GetLocation = 48 (0) [response] {},
+    SetLocation(location: [u8; 16], label: String, updated: u64) = 49 (56) [response]
+      {location, {label: Label}, updated},
+    StateLocation(location: [u8; 16], label: String, updated: u64) = 50 (56) []
+      {location, {label: Label}, updated},
+    GetGroup = 51 (0) [response] {},
+    SetGroup(group: [u8; 16], label: String, updated: u64) = 52 (56) [response]
+      {group, {label: Label}, updated},
+    StateGroup(group: [u8; 16], label: String, updated: u64) = 53 (56) []
+      {group, {label: Label}, updated},
+    EchoRequest(payload: Array64<u8>) = 58 (64) [response] {payload},
+    EchoResponse(payload: Array64<u8>) = 59 (64) [] {payload},
+    StateUnhandled(typ: u16) = 223 (2) [] {typ}
   }
 }
 
@@ -573,21 +1488,22 @@ impl Debug for Device {
         write!(f, "StateHostInfo({}, {}, {})", signal, tx, rx)
       }
       GetHostFirmware => write!(f, "GetHostFirmware"),
-      StateHostFirmware(build, version) => {
-        write!(f, "StateHostFirmware({}, {})", build, version)
+      StateHostFirmware(firmware) => {
+        write!(f, "StateHostFirmware({}, {})", firmware.build(), firmware)
       }
       GetWifiInfo => write!(f, "GetWifiInfo"),
       StateWifiInfo(signal, tx, rx) => {
         write!(f, "StateWifiInfo({}, {}, {})", signal, tx, rx)
       }
       GetWifiFirmware => write!(f, "GetWifiFirmware"),
-      StateWifiFirmware(build, version) => {
-        write!(f, "StateWifiFirmware({}, {})", build, version)
+      StateWifiFirmware(firmware) => {
+        write!(f, "StateWifiFirmware({}, {})", firmware.build(), firmware)
       }
       GetPower => write!(f, "GetPower"),
       SetPower(pow) => write!(f, "SetPower({:?})", pow),
       StatePower(pow) => write!(f, "StatePower({:?})", pow),
       GetLabel => write!(f, "GetLabel"),
+      SetLabel(ref label) => write!(f, "SetLabel({})", label),
       StateLabel(ref label) => write!(f, "StateLabel({})", label),
       GetVersion => write!(f, "GetVersion"),
       StateVersion(vendor, product, version) => write!(
@@ -601,111 +1517,693 @@ impl Debug for Device {
       }
       Acknowledgement => write!(f, "Acknowledgement"),
       GetLocation => write!(f, "GetLocation"),
+      SetLocation(_, ref label, updated) => {
+        write!(f, "SetLocation([16], {}, {})", label, updated)
+      }
       StateLocation(_, ref label, updated) => {
         write!(f, "StateLocation([16], {}, {})", label, updated)
       }
       GetGroup => write!(f, "GetGroup"),
+      SetGroup(_, ref label, updated) => {
+        write!(f, "SetGroup([16], {}, {})", label, updated)
+      }
       StateGroup(_, ref label, updated) => {
         write!(f, "StateGroup([16], {}, {})", label, updated)
       }
       EchoRequest(_) => write!(f, "EchoRequest([64])"),
       EchoResponse(_) => write!(f, "EchoResponse([64])"),
+      StateUnhandled(typ) => write!(f, "StateUnhandled({})", typ),
     }
   }
 }
 
-/// Light messages.
-///
-#[derive(Debug)]
-pub enum Light {
-  Get,
-  SetColor(HSBK, u32),
-  State(HSBK, u16, String),
-  GetPower,
-  SetPower(Power, u32),
-  StatePower(Power),
+messages! {
+  /// Light messages.
+  ///
+  /// # Notes
+  ///
+  ///   * SetWaveform is (transient, color, period, cycles, skew_ratio, waveform).
+  ///   * SetWaveformOptional is the same as SetWaveform, followed by the
+  ///     (set_hue, set_saturation, set_brightness, set_kelvin) flags. Only the
+  ///     components with their flag set are changed on the bulb.
+  ///
+  #[derive(Debug, Clone)]
+  pub enum Light {
+    Get = 101 (0) [response] {},
+    SetColor(color: HSBK, duration: TransitionMs) = 102 (13) [response]
+      {[u8], color, duration},
+    SetWaveform(
+      transient: bool,
+      color: HSBK,
+      period: u32,
+      cycles: f32,
+      skew_ratio: i16,
+      waveform: Waveform
+    ) = 103 (21) [response]
+      {[u8], transient, color, period, cycles, skew_ratio, waveform},
+    State(color: HSBK, power: Power, label: String) = 107 (52) []
+      {color, [i16], power, {label: Label}, [u64]},
+    GetPower = 116 (0) [response] {},
+    SetPower(level: Power, duration: TransitionMs) = 117 (6) [response]
+      {level, duration},
+    StatePower(level: Power) = 118 (2) [] {level},
+    SetWaveformOptional(
+      transient: bool,
+      color: HSBK,
+      period: u32,
+      cycles: f32,
+      skew_ratio: i16,
+      waveform: Waveform,
+      set_hue: bool,
+      set_saturation: bool,
+      set_brightness: bool,
+      set_kelvin: bool
+    ) = 119 (25) [response] {
+      [u8],
+      transient,
+      color,
+      period,
+      cycles,
+      skew_ratio,
+      waveform,
+      set_hue,
+      set_saturation,
+      set_brightness,
+      set_kelvin
+    },
+    GetInfrared = 120 (0) [response] {},
+    StateInfrared(brightness: u16) = 121 (2) [] {brightness},
+    SetInfrared(brightness: u16) = 122 (2) [response] {brightness}
+  }
 }
 
 impl Light {
+  /// Returns the color reported by Light::State.
+  ///
   #[inline]
-  pub fn typ(&self) -> u16 {
-    use Light::*;
-
+  pub fn color(&self) -> Option<HSBK> {
     match *self {
-      Get => 101,
-      SetColor(_, _) => 102,
-      State(_, _, _) => 107,
-      GetPower => 116,
-      SetPower(_, _) => 117,
-      StatePower(_) => 118,
+      Light::State(color, _, _) => Some(color),
+      _ => None,
     }
   }
 
+  /// Returns the power level reported by Light::State or Light::StatePower.
+  ///
   #[inline]
-  pub fn tagged(&self) -> bool {
-    false
+  pub fn power(&self) -> Option<Power> {
+    match *self {
+      Light::State(_, power, _) | Light::StatePower(power) => Some(power),
+      _ => None,
+    }
   }
 
+  /// Returns the label reported by Light::State.
+  ///
   #[inline]
-  pub fn requires_response(&self) -> bool {
-    use Light::*;
-
+  pub fn label(&self) -> Option<&str> {
     match *self {
-      Get | GetPower | SetPower(_, _) | SetColor(_, _) => true,
-      _ => false,
+      Light::State(_, _, ref label) => Some(&label[..]),
+      _ => None,
     }
   }
+}
 
-  #[inline]
-  pub fn size(&self) -> u16 {
-    use Light::*;
+messages! {
+  /// MultiZone messages (LiFX Z and Beam).
+  ///
+  /// # Notes
+  ///
+  ///   * SetColorZones is (start_index, end_index, color, duration, apply).
+  ///   * GetColorZones is (start_index, end_index).
+  ///   * StateZone is (count, index, color).
+  ///   * StateMultiZone is (count, index, colors), where colors holds the zones
+  ///     starting at index.
+  ///   * SetMultiZoneEffect and StateMultiZoneEffect are (instance_id, effect,
+  ///     speed, duration, parameters). Speed is the time in milliseconds for one
+  ///     cycle of the effect, and duration is the run time in nanoseconds (0 runs
+  ///     forever).
+  ///   * SetExtendedColorZones is (duration, apply, index, colors).
+  ///   * StateExtendedColorZones is (count, index, colors).
+  ///   * The extended messages carry at most MAX_EXTENDED_ZONES colors. Any
+  ///     extra colors are not encoded.
+  ///
+  #[derive(Debug, Clone)]
+  pub enum MultiZone {
+    SetColorZones(
+      start_index: u8,
+      end_index: u8,
+      color: HSBK,
+      duration: TransitionMs,
+      apply: ApplicationRequest
+    ) = 501 (15) [response] {start_index, end_index, color, duration, apply},
+    GetColorZones(start_index: u8, end_index: u8) = 502 (2) [response]
+      {start_index, end_index},
+    StateZone(count: u8, index: u8, color: HSBK) = 503 (10) [] {count, index, color},
+    StateMultiZone(count: u8, index: u8, colors: [HSBK; 8]) = 506 (66) []
+      {count, index, colors},
+    GetMultiZoneEffect = 507 (0) [response] {},
+    SetMultiZoneEffect(
+      instance_id: u32,
+      effect: MultiZoneEffectType,
+      speed: u32,
+      duration: u64,
+      parameters: [u32; 8]
+    ) = 508 (59) [response]
+      {instance_id, effect, [u16], speed, duration, [u64], parameters},
+    StateMultiZoneEffect(
+      instance_id: u32,
+      effect: MultiZoneEffectType,
+      speed: u32,
+      duration: u64,
+      parameters: [u32; 8]
+    ) = 509 (59) [] {instance_id, effect, [u16], speed, duration, [u64], parameters},
+    SetExtendedColorZones(
+      duration: TransitionMs,
+      apply: ApplicationRequest,
+      index: u16,
+      colors: Vec<HSBK>
+    ) = 510 (664) [response] {duration, apply, index, {colors: ExtendedZones}},
+    GetExtendedColorZones = 511 (0) [response] {},
+    StateExtendedColorZones(count: u16, index: u16, colors: Vec<HSBK>) = 512 (661) []
+      {count, index, {colors: ExtendedZones}}
+  }
+}
 
-    match *self {
-      Get | GetPower => 0,
-      StatePower(_) => 2,
-      SetPower(_, _) => 6,
-      SetColor(_, _) => 13,
-      State(_, _, _) => 24,
-    }
+/// A single tile in a LiFX Tile chain, as reported by Tile::StateDeviceChain.
+///
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TileDevice {
+  pub accel_meas_x: i16,
+  pub accel_meas_y: i16,
+  pub accel_meas_z: i16,
+  pub user_x: f32,
+  pub user_y: f32,
+  pub width: u8,
+  pub height: u8,
+  pub vendor: u32,
+  pub product: u32,
+  pub version: u32,
+  pub firmware_build: u64,
+  pub firmware_version: u32,
+}
+
+impl PartialEq for TileDevice {
+  fn eq(&self, other: &TileDevice) -> bool {
+    self.accel_meas_x == other.accel_meas_x
+      && self.accel_meas_y == other.accel_meas_y
+      && self.accel_meas_z == other.accel_meas_z
+      && self.user_x.field_eq(&other.user_x)
+      && self.user_y.field_eq(&other.user_y)
+      && self.width == other.width
+      && self.height == other.height
+      && self.vendor == other.vendor
+      && self.product == other.product
+      && self.version == other.version
+      && self.firmware_build == other.firmware_build
+      && self.firmware_version == other.firmware_version
   }
 }
 
-impl Encodable for Light {
-  fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-    use Light::*;
+impl Eq for TileDevice {}
+
+impl Hash for TileDevice {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.accel_meas_x.hash(state);
+    self.accel_meas_y.hash(state);
+    self.accel_meas_z.hash(state);
+    self.user_x.field_hash(state);
+    self.user_y.field_hash(state);
+    self.width.hash(state);
+    self.height.hash(state);
+    self.vendor.hash(state);
+    self.product.hash(state);
+    self.version.hash(state);
+    self.firmware_build.hash(state);
+    self.firmware_version.hash(state);
+  }
+}
 
-    s.emit_enum("Light", |s| match *self {
-      Get => s.emit_enum_variant("Get", 0, self.size() as usize, |s| s.emit_nil()),
-      SetColor(color, power) => {
-        s.emit_enum_variant("SetColor", 1, self.size() as usize, |s| {
-          try!(s.emit_enum_variant_arg(0, |s| s.emit_u8(0)));
-          try!(s.emit_enum_variant_arg(1, |s| color.encode(s)));
-          s.emit_enum_variant_arg(2, |s| s.emit_u32(power))
-        })
-      }
-      State(color, power, ref label) => {
-        s.emit_enum_variant("State", 2, self.size() as usize, |s| {
-          try!(s.emit_enum_variant_arg(0, |s| color.encode(s)));
-          try!(s.emit_enum_variant_arg(1, |s| s.emit_u16(0)));
-          try!(s.emit_enum_variant_arg(2, |s| s.emit_u16(power)));
-          try!(s.emit_enum_variant_arg(3, |s| label.encode(s)));
-          s.emit_enum_variant_arg(3, |s| s.emit_u64(0))
-        })
-      }
-      GetPower => {
-        s.emit_enum_variant("GetPower", 3, self.size() as usize, |s| s.emit_nil())
-      }
-      SetPower(level, duration) => {
-        s.emit_enum_variant("SetPower", 4, self.size() as usize, |s| {
-          try!(s.emit_enum_variant_arg(0, |s| level.encode(s)));
-          s.emit_enum_variant_arg(1, |s| s.emit_u32(duration))
-        })
-      }
-      StatePower(level) => {
-        s.emit_enum_variant("StatePower", 5, self.size() as usize, |s| {
-          s.emit_enum_variant_arg(0, |s| level.encode(s))
-        })
-      }
+impl WireDecode for TileDevice {
+  /// Decodes a tile. Tiles are 55 bytes on the wire.
+  ///
+  fn decode(r: &mut WireReader) -> Result<TileDevice, DecodeError> {
+    let accel_meas_x = r.read_i16()?;
+    let accel_meas_y = r.read_i16()?;
+    let accel_meas_z = r.read_i16()?;
+    r.skip(2)?;
+    let user_x = r.read_f32()?;
+    let user_y = r.read_f32()?;
+    let width = r.read_u8()?;
+    let height = r.read_u8()?;
+    r.skip(1)?;
+    let vendor = r.read_u32()?;
+    let product = r.read_u32()?;
+    let version = r.read_u32()?;
+    let firmware_build = r.read_u64()?;
+    r.skip(8)?;
+    let firmware_version = r.read_u32()?;
+    r.skip(4)?;
+
+    Ok(TileDevice {
+      accel_meas_x,
+      accel_meas_y,
+      accel_meas_z,
+      user_x,
+      user_y,
+      width,
+      height,
+      vendor,
+      product,
+      version,
+      firmware_build,
+      firmware_version,
+    })
+  }
+}
+
+impl WireEncode for TileDevice {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_i16::<LittleEndian>(self.accel_meas_x)?;
+    w.write_i16::<LittleEndian>(self.accel_meas_y)?;
+    w.write_i16::<LittleEndian>(self.accel_meas_z)?;
+    w.write_i16::<LittleEndian>(0)?;
+    w.write_f32::<LittleEndian>(self.user_x)?;
+    w.write_f32::<LittleEndian>(self.user_y)?;
+    w.write_u8(self.width)?;
+    w.write_u8(self.height)?;
+    w.write_u8(0)?;
+    w.write_u32::<LittleEndian>(self.vendor)?;
+    w.write_u32::<LittleEndian>(self.product)?;
+    w.write_u32::<LittleEndian>(self.version)?;
+    w.write_u64::<LittleEndian>(self.firmware_build)?;
+    w.write_u64::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(self.firmware_version)?;
+    w.write_u32::<LittleEndian>(0)
+  }
+}
+
+messages! {
+  /// Tile messages (LiFX Tile).
+  ///
+  /// # Notes
+  ///
+  ///   * StateDeviceChain is (start_index, tiles). At most MAX_CHAIN_TILES
+  ///     tiles are encoded.
+  ///   * SetUserPosition is (tile_index, user_x, user_y).
+  ///   * GetTileState64 is (tile_index, length, x, y, width).
+  ///   * StateTileState64 is (tile_index, x, y, width, colors).
+  ///   * SetTileState64 is (tile_index, length, x, y, width, duration, colors).
+  ///
+  #[derive(Debug, Clone)]
+  pub enum Tile {
+    GetDeviceChain = 701 (0) [response] {},
+    StateDeviceChain(start_index: u8, tiles: Vec<TileDevice>) = 702 (882) []
+      {start_index, {tiles: DeviceChain}},
+    SetUserPosition(tile_index: u8, user_x: f32, user_y: f32) = 703 (11) []
+      {tile_index, [u16], user_x, user_y},
+    GetTileState64(tile_index: u8, length: u8, x: u8, y: u8, width: u8) = 707 (6)
+      [response] {tile_index, length, [u8], x, y, width},
+    StateTileState64(tile_index: u8, x: u8, y: u8, width: u8, colors: [HSBK; 64])
+      = 711 (517) [] {tile_index, [u8], x, y, width, colors},
+    SetTileState64(
+      tile_index: u8,
+      length: u8,
+      x: u8,
+      y: u8,
+      width: u8,
+      duration: TransitionMs,
+      colors: [HSBK; 64]
+    ) = 715 (522) [] {tile_index, length, [u8], x, y, width, duration, colors}
+  }
+}
+
+messages! {
+  /// Relay messages (LiFX Switch).
+  ///
+  /// # Notes
+  ///
+  ///   * Every message is addressed by the relay index, starting at 0.
+  ///
+  #[derive(Debug, Copy, Clone)]
+  pub enum Relay {
+    GetRPower(relay_index: u8) = 816 (1) [response] {relay_index},
+    SetRPower(relay_index: u8, level: Power) = 817 (3) [response]
+      {relay_index, level},
+    StateRPower(relay_index: u8, level: Power) = 818 (3) [] {relay_index, level}
+  }
+}
+
+/// An action bound to a switch button.
+///
+/// # Notes
+///
+///   * gesture is 1 (press), 2 (hold), 3 (press press), 4 (press hold) or 5
+///     (hold hold).
+///   * target_type is 2 (relays), 3 (device), 4 (location), 5 (group), 6
+///     (scene) or 7 (device relays), and determines how the 16 target bytes
+///     are interpreted.
+///
+#[derive(Debug, Default, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct ButtonAction {
+  pub gesture: u16,
+  pub target_type: u16,
+  pub target: [u8; 16],
+}
+
+impl WireDecode for ButtonAction {
+  /// Decodes an action. Actions are 20 bytes on the wire.
+  ///
+  fn decode(r: &mut WireReader) -> Result<ButtonAction, DecodeError> {
+    let gesture = r.read_u16()?;
+    let target_type = r.read_u16()?;
+    let target = WireDecode::decode(r)?;
+
+    Ok(ButtonAction {
+      gesture,
+      target_type,
+      target,
     })
   }
 }
+
+impl WireEncode for ButtonAction {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u16::<LittleEndian>(self.gesture)?;
+    w.write_u16::<LittleEndian>(self.target_type)?;
+    w.write_all(&self.target[..])
+  }
+}
+
+/// A single button on a LiFX Switch, as reported by Button::StateButton.
+///
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone)]
+pub struct SwitchButton {
+  pub actions: Vec<ButtonAction>,
+}
+
+impl WireDecode for SwitchButton {
+  /// Decodes a button. Buttons are 101 bytes on the wire.
+  ///
+  fn decode(r: &mut WireReader) -> Result<SwitchButton, DecodeError> {
+    let count = r.read_u8()? as usize;
+    let actions = decode_padded(r, count, MAX_BUTTON_ACTIONS)?;
+
+    Ok(SwitchButton { actions })
+  }
+}
+
+impl WireEncode for SwitchButton {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let count = ::std::cmp::min(self.actions.len(), MAX_BUTTON_ACTIONS);
+
+    w.write_u8(count as u8)?;
+    encode_padded(w, &self.actions, MAX_BUTTON_ACTIONS)
+  }
+}
+
+messages! {
+  /// Button messages (LiFX Switch).
+  ///
+  /// # Notes
+  ///
+  ///   * StateButton is (count, index, buttons). At most MAX_SWITCH_BUTTONS
+  ///     buttons are encoded, each with at most MAX_BUTTON_ACTIONS actions.
+  ///   * SetButtonConfig and StateButtonConfig are (haptic_duration,
+  ///     backlight_on, backlight_off), with the haptic duration in milliseconds.
+  ///
+  #[derive(Debug, Clone)]
+  pub enum Button {
+    GetButton = 905 (0) [response] {},
+    StateButton(count: u8, index: u8, buttons: Vec<SwitchButton>) = 907 (811) []
+      {count, index, {buttons: SwitchButtons}},
+    GetButtonConfig = 909 (0) [response] {},
+    SetButtonConfig(haptic_duration: u16, backlight_on: HSBK, backlight_off: HSBK)
+      = 910 (18) [response] {haptic_duration, backlight_on, backlight_off},
+    StateButtonConfig(
+      haptic_duration: u16,
+      backlight_on: HSBK,
+      backlight_off: HSBK
+    ) = 911 (18) [] {haptic_duration, backlight_on, backlight_off}
+  }
+}
+
+#[test]
+fn test_power_preserves_level() {
+  assert_eq!(Power::Standby, Power::from(0));
+  assert_eq!(Power::Max, Power::from(65535));
+  assert_eq!(Power::Level(32768), Power::from(32768));
+
+  let level: u16 = Power::from(1234).into();
+  assert_eq!(1234, level);
+  assert_eq!(Power::Max, Power::from_percent(100.0));
+  assert_eq!(Power::Standby, Power::from_percent(-5.0));
+}
+
+#[test]
+fn test_light_state_encode_correctness() {
+  use serialize;
+
+  let mut correct = vec![
+    0x10, 0x0, 0x20, 0x0, 0x30, 0x0, 0xac, 0xd, 0x0, 0x0, 0xff, 0xff, 0x4b, 0x69,
+    0x74, 0x63, 0x68, 0x65, 0x6e,
+  ];
+  correct.resize(52, 0);
+
+  let color = HSBK::new(
+    Hue::from(0x10),
+    Saturation::from(0x20),
+    Brightness::from(0x30),
+    Kelvin::new(3500).unwrap(),
+  );
+  let state = Payload::Light(Light::State(color, Power::Max, "Kitchen".to_string()));
+  let encoded = serialize::encode(&state).unwrap();
+
+  assert_eq!(state.size() as usize, encoded.len());
+  assert_eq!(&correct[..], &encoded[..]);
+}
+
+#[test]
+fn test_light_state_encode_decode() {
+  use message::Message;
+  use serialize;
+
+  let color = HSBK::new(
+    Hue::from(120),
+    Saturation::from(65535),
+    Brightness::from(32768),
+    Kelvin::new(1500).unwrap(),
+  );
+  let state = Light::State(color, Power::Level(100), "Lamp".to_string());
+  let msg = Message::new(Payload::Light(state), false, 0, 0);
+  let encoded = serialize::encode(&msg).unwrap();
+  let decoded: Message = serialize::decode(&encoded[..]).unwrap();
+
+  match decoded.unpack() {
+    (Payload::Light(ref light), _) => {
+      assert_eq!(Some(Power::Level(100)), light.power());
+      assert_eq!(Some("Lamp"), light.label());
+      assert_eq!(Some(Kelvin::MIN), light.color().map(|c| c.kelvin()));
+    }
+    _ => panic!("decoded the wrong payload"),
+  }
+}
+
+#[test]
+fn test_decode_ref_matches_decode() {
+  use serialize;
+
+  let color = HSBK::new(
+    Hue::from(1),
+    Saturation::from(2),
+    Brightness::from(3),
+    Kelvin::new(4000).unwrap(),
+  );
+  let state = Payload::Light(Light::State(color, Power::Max, "Desk".to_string()));
+  let encoded = serialize::encode(&state).unwrap();
+
+  match Payload::decode_ref(107, &encoded[..]).unwrap() {
+    PayloadRef::LightState(c, power, label) => {
+      assert_eq!(4000, u16::from(c.kelvin()));
+      assert_eq!(Power::Max, power);
+      assert_eq!("Desk", label);
+    }
+    _ => panic!("decoded the wrong payload"),
+  }
+  match Payload::decode_ref(4000, &[1, 2]).unwrap() {
+    PayloadRef::Unknown { typ, bytes } => {
+      assert_eq!(4000, typ);
+      assert_eq!(&[1, 2], bytes);
+    }
+    _ => panic!("decoded the wrong payload"),
+  }
+  assert!(Payload::decode_ref(118, &[0, 0, 0]).is_err());
+
+  let mut invalid = [0; 32];
+  invalid[..6].copy_from_slice(b"Lamp\xff!");
+  let decoded = Payload::decode(&mut WireReader::new(&invalid[..]), 25, 0).unwrap();
+  let borrowed = Payload::decode_ref(25, &invalid[..]).unwrap();
+  assert_eq!(decoded, borrowed.into_owned());
+}
+
+#[test]
+fn test_sizes_match_protocol() {
+  let color = HSBK::default();
+
+  assert_eq!(0, Device::GetService.size());
+  assert_eq!(5, Device::StateService(Service::Udp, 56700).size());
+  assert_eq!(56, Device::StateGroup([0; 16], String::new(), 0).size());
+  assert_eq!(52, Light::State(color, Power::Max, String::new()).size());
+  let effect = MultiZoneEffectType::Off;
+  assert_eq!(59, MultiZone::SetMultiZoneEffect(0, effect, 0, 0, [0; 8]).size());
+  let apply = ApplicationRequest::Apply;
+  assert_eq!(664, MultiZone::SetExtendedColorZones(
+    TransitionMs::ZERO,
+    apply,
+    0,
+    vec![]
+  ).size());
+  assert_eq!(882, Tile::StateDeviceChain(0, vec![]).size());
+  assert_eq!(3, Relay::SetRPower(0, Power::Max).size());
+  assert_eq!(811, Button::StateButton(0, 0, vec![]).size());
+}
+
+#[test]
+fn test_firmware_version_split() {
+  let firmware = FirmwareVersion::new(1_500_000_000_000_000_000, (3 << 16) | 70);
+
+  assert_eq!(3, firmware.major());
+  assert_eq!(70, firmware.minor());
+  assert_eq!("3.70", firmware.to_string());
+  #[cfg(feature = "std")]
+  assert_eq!(
+    Duration::from_secs(1_500_000_000),
+    firmware.build_time().duration_since(UNIX_EPOCH).unwrap()
+  );
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn test_hsbk_replaces_one_component() {
+  use client::sample_color;
+
+  let color = sample_color();
+  let brighter = color.with_brightness(Brightness::MAX);
+
+  assert_eq!(Brightness::MAX, brighter.brightness());
+  assert_eq!(color, brighter.with_brightness(Brightness::from(3)));
+  assert_eq!(Hue::from(4), color.with_hue(Hue::from(4)).hue());
+  assert_eq!(Saturation::MIN, color.with_saturation(Saturation::MIN).saturation());
+  assert_eq!(Kelvin::MAX, color.with_kelvin(Kelvin::MAX).kelvin());
+}
+
+#[test]
+fn test_message_table_round_trips() {
+  let families = [
+    Device::TYPES,
+    Light::TYPES,
+    MultiZone::TYPES,
+    Tile::TYPES,
+    Relay::TYPES,
+    Button::TYPES,
+  ];
+  let mut types: Vec<u16> =
+    families.iter().flat_map(|t| t.iter()).cloned().collect();
+  let count = types.len();
+  types.sort();
+  types.dedup();
+  assert_eq!(count, types.len());
+
+  let zeros = [0; 1024];
+  for &typ in &types {
+    let mut r = WireReader::new(&zeros[..]);
+    let payload = Payload::decode(&mut r, typ, 0).unwrap();
+
+    assert_eq!(typ, payload.typ());
+    assert_eq!(r.position(), payload.size() as usize);
+    assert_eq!(Some(r.position()), Payload::wire_len(typ));
+    assert!(serialize::encode(&payload).unwrap().iter().all(|&b| b == 0));
+  }
+}
+
+#[test]
+fn test_short_payloads_are_rejected() {
+  let bytes = [0; 51];
+
+  match Payload::decode_ref(107, &bytes[..]) {
+    Err(DecodeError::PayloadTooShort {
+      typ,
+      expected,
+      actual,
+      ..
+    }) => assert_eq!((107, 52, 51), (typ, expected, actual)),
+    _ => panic!("decoded a short Light::State"),
+  }
+  assert!(Payload::decode_ref(118, &bytes[..1]).is_err());
+  assert_eq!(None, Payload::wire_len(4000));
+}
+
+#[test]
+fn test_invalid_labels_are_lossy_or_strict() {
+  let mut bytes = [0; 32];
+  bytes[..6].copy_from_slice(b"Lamp\xff!");
+
+  let mut r = WireReader::new(&bytes[..]);
+  assert_eq!("Lamp\u{fffd}!", decode_32_byte_str(&mut r).unwrap());
+
+  let mut r = WireReader::new(&bytes[..]).with_label_mode(LabelMode::Strict);
+  match decode_32_byte_str(&mut r) {
+    Err(DecodeError::InvalidLabel { raw, .. }) => assert_eq!(&bytes[..], &raw[..]),
+    _ => panic!("decoded an invalid label in strict mode"),
+  }
+}
+
+#[test]
+fn test_multibyte_labels_decode() {
+  // 15 two byte characters, and a lead byte cut off by the 32 byte limit
+  let mut bytes = [0; 32];
+  bytes[..30].copy_from_slice("ééééééééééééééé".as_bytes());
+  bytes[30] = b'!';
+  bytes[31] = 0xc3;
+
+  let mut r = WireReader::new(&bytes[..]);
+  assert_eq!("ééééééééééééééé!\u{fffd}", decode_32_byte_str(&mut r).unwrap());
+
+  // the borrowed label is the same, though it has to be copied to be.
+  let mut r = WireReader::new(&bytes[..]);
+  match decode_32_byte_str_ref(&mut r).unwrap() {
+    Cow::Owned(label) => assert_eq!("ééééééééééééééé!\u{fffd}", label),
+    Cow::Borrowed(label) => panic!("borrowed an invalid label: {:?}", label),
+  }
+
+  let mut encoded = Vec::new();
+  encode_32_byte_str(&mut encoded, "Café Lamp").unwrap();
+  let mut r = WireReader::new(&encoded[..]);
+  assert_eq!("Café Lamp", decode_32_byte_str(&mut r).unwrap());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_payloads_are_hashable() {
+  use std::collections::HashSet;
+
+  let waveform = |cycles: f32| {
+    Payload::Light(Light::SetWaveform(
+      false,
+      HSBK::default(),
+      1000,
+      cycles,
+      0,
+      Waveform::Sine,
+    ))
+  };
+
+  let mut payloads = HashSet::new();
+  assert!(payloads.insert(waveform(1.5)));
+  assert!(payloads.insert(waveform(f32::NAN)));
+  assert!(payloads.insert(Payload::Device(Device::GetService)));
+  assert!(!payloads.insert(waveform(1.5)));
+  assert!(!payloads.insert(waveform(f32::NAN)));
+  assert!(payloads.contains(&Payload::Device(Device::GetService)));
+  assert_eq!(3, payloads.len());
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use payload::Payload;
+use serialize::DecodeError;
+
+/// decodes the raw bytes of a custom payload.
+///
+pub type CustomDecoder<T> = fn(&[u8]) -> Result<T, DecodeError>;
+
+/// a registry of user supplied decoders for message types that this crate does
+/// not understand. those messages are decoded as `Payload::Unknown`, and can be
+/// routed through the registry to get a custom payload back.
+///
+pub struct DecoderRegistry<T> {
+  decoders: BTreeMap<u16, CustomDecoder<T>>,
+}
+
+impl<T> DecoderRegistry<T> {
+  /// creates an empty registry.
+  ///
+  pub fn new() -> DecoderRegistry<T> {
+    DecoderRegistry {
+      decoders: BTreeMap::new(),
+    }
+  }
+
+  /// registers a decoder for a message type, returning the decoder it
+  /// replaced, if any.
+  ///
+  pub fn register(
+    &mut self,
+    typ: u16,
+    decoder: CustomDecoder<T>,
+  ) -> Option<CustomDecoder<T>> {
+    self.decoders.insert(typ, decoder)
+  }
+
+  /// removes the decoder for a message type.
+  ///
+  pub fn unregister(&mut self, typ: u16) -> Option<CustomDecoder<T>> {
+    self.decoders.remove(&typ)
+  }
+
+  /// checks if there is a decoder for a message type.
+  ///
+  pub fn contains(&self, typ: u16) -> bool {
+    self.decoders.contains_key(&typ)
+  }
+
+  /// decodes the raw bytes of a message type. returns `None` if no decoder is
+  /// registered for the type.
+  ///
+  pub fn decode_bytes(
+    &self,
+    typ: u16,
+    bytes: &[u8],
+  ) -> Option<Result<T, DecodeError>> {
+    self.decoders.get(&typ).map(|decoder| decoder(bytes))
+  }
+
+  /// decodes an unknown payload. returns `None` if the payload is known to
+  /// this crate, or no decoder is registered for its type.
+  ///
+  pub fn decode(&self, payload: &Payload) -> Option<Result<T, DecodeError>> {
+    match *payload {
+      Payload::Unknown { typ, ref bytes } => self.decode_bytes(typ, &bytes[..]),
+      _ => None,
+    }
+  }
+}
+
+impl<T> Default for DecoderRegistry<T> {
+  fn default() -> DecoderRegistry<T> {
+    DecoderRegistry::new()
+  }
+}
+
+#[test]
+fn test_registry_decodes_unknown_payloads() {
+  use payload::Device;
+
+  fn decode_len(bytes: &[u8]) -> Result<usize, DecodeError> {
+    Ok(bytes.len())
+  }
+
+  let mut registry = DecoderRegistry::new();
+  registry.register(4000, decode_len);
+
+  let unknown = Payload::Unknown {
+    typ: 4000,
+    bytes: vec![1, 2, 3],
+  };
+
+  assert_eq!(Some(Ok(3)), registry.decode(&unknown));
+  assert_eq!(None, registry.decode(&Payload::Device(Device::GetService)));
+  assert_eq!(None, registry.decode_bytes(4001, &[]));
+}
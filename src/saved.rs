@@ -0,0 +1,236 @@
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+
+use error::Error;
+use payload::Service;
+
+/// the first line of a file written by `write_saved`, naming its format.
+///
+const HEADER: &str = "# lifx devices v1";
+
+/// a device as `Client::save_devices` writes it, and
+/// `ClientBuilder::with_saved_devices` reads it back.
+///
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct SavedDevice {
+  pub(crate) target: u64,
+  pub(crate) addr: SocketAddr,
+  pub(crate) port: u32,
+  pub(crate) host: Option<String>,
+  pub(crate) label: Option<String>,
+  pub(crate) location: Option<String>,
+  pub(crate) group: Option<String>,
+  pub(crate) services: Vec<(Service, u32)>,
+  pub(crate) unhandled: Vec<u16>,
+}
+
+/// the devices and aliases `Client::save_devices` writes.
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+pub(crate) struct Saved {
+  pub(crate) devices: Vec<SavedDevice>,
+  pub(crate) aliases: Vec<(String, u64)>,
+}
+
+/// writes one device per line, with its fields separated by tabs, then one
+/// line per alias, starting with `alias`.
+///
+pub(crate) fn write_saved<W: Write>(mut w: W, saved: &Saved) -> io::Result<()> {
+  writeln!(w, "{}", HEADER)?;
+
+  for d in &saved.devices {
+    let services: Vec<String> = d
+      .services
+      .iter()
+      .map(|&(service, port)| format!("{}:{}", u8::from(service), port))
+      .collect();
+    let unhandled: Vec<String> =
+      d.unhandled.iter().map(|typ| typ.to_string()).collect();
+
+    writeln!(
+      w,
+      "{:#x}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+      d.target,
+      d.addr,
+      d.port,
+      quote(&d.host),
+      quote(&d.label),
+      quote(&d.location),
+      quote(&d.group),
+      services.join(","),
+      unhandled.join(",")
+    )?;
+  }
+
+  for &(ref alias, target) in &saved.aliases {
+    writeln!(w, "alias\t{}\t{:#x}", quote(&Some(alias.clone())), target)?;
+  }
+
+  w.flush()
+}
+
+/// reads the devices and aliases written by `write_saved`. blank lines and
+/// lines that start with `#` are skipped.
+///
+pub(crate) fn read_saved<R: BufRead>(r: R) -> Result<Saved, Error> {
+  let mut saved = Saved::default();
+
+  for (n, line) in r.lines().enumerate() {
+    let line = line?;
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let parsed = match line.strip_prefix("alias\t") {
+      Some(alias) => parse_alias(alias).map(|alias| saved.aliases.push(alias)),
+      None => parse(&line).map(|device| saved.devices.push(device)),
+    };
+    if parsed.is_none() {
+      return Err(Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("saved device on line {} is malformed", n + 1),
+      )));
+    }
+  }
+
+  Ok(saved)
+}
+
+fn parse_alias(fields: &str) -> Option<(String, u64)> {
+  let (alias, target) = fields.split_once('\t')?;
+  let target = target.strip_prefix("0x")?;
+  Some((unquote(alias)??, u64::from_str_radix(target, 16).ok()?))
+}
+
+fn parse(line: &str) -> Option<SavedDevice> {
+  let fields: Vec<&str> = line.split('\t').collect();
+  if fields.len() != 9 {
+    return None;
+  }
+
+  let target = fields[0].strip_prefix("0x")?;
+  let services = list(fields[7], |service| {
+    let (service, port) = service.split_once(':')?;
+    Some((Service::from(service.parse::<u8>().ok()?), port.parse().ok()?))
+  });
+
+  Some(SavedDevice {
+    target: u64::from_str_radix(target, 16).ok()?,
+    addr: fields[1].parse().ok()?,
+    port: fields[2].parse().ok()?,
+    host: unquote(fields[3])?,
+    label: unquote(fields[4])?,
+    location: unquote(fields[5])?,
+    group: unquote(fields[6])?,
+    services: services?,
+    unhandled: list(fields[8], |typ| typ.parse().ok())?,
+  })
+}
+
+/// parses a comma separated list, which is empty if the field is.
+///
+fn list<T, F>(field: &str, item: F) -> Option<Vec<T>>
+where
+  F: Fn(&str) -> Option<T>,
+{
+  match field {
+    "" => Some(Vec::new()),
+    _ => field.split(',').map(item).collect(),
+  }
+}
+
+/// writes `None` as an empty field, and a string in quotes, with the
+/// characters that would end the field or the line escaped.
+///
+fn quote(s: &Option<String>) -> String {
+  let s = match *s {
+    Some(ref s) => s,
+    None => return String::new(),
+  };
+
+  let mut quoted = String::with_capacity(s.len() + 2);
+  quoted.push('"');
+  for c in s.chars() {
+    match c {
+      '\t' => quoted.push_str("\\t"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '"' | '\\' => {
+        quoted.push('\\');
+        quoted.push(c);
+      }
+      _ => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+/// reverses `quote`. returns `None` if the field is malformed.
+///
+fn unquote(field: &str) -> Option<Option<String>> {
+  if field.is_empty() {
+    return Some(None);
+  }
+
+  let inner = field.strip_prefix('"')?.strip_suffix('"')?;
+  let mut s = String::with_capacity(inner.len());
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => s.push(match chars.next()? {
+        't' => '\t',
+        'n' => '\n',
+        'r' => '\r',
+        c @ '"' | c @ '\\' => c,
+        _ => return None,
+      }),
+      '"' => return None,
+      _ => s.push(c),
+    }
+  }
+  Some(Some(s))
+}
+
+#[test]
+fn test_saved_devices_round_trip() {
+  let devices = vec![
+    SavedDevice {
+      target: 0xD073D5000001,
+      addr: "10.0.0.2:56700".parse().unwrap(),
+      port: 56700,
+      host: None,
+      label: Some("Kitchen \"left\"\tlamp\\".into()),
+      location: Some(String::new()),
+      group: None,
+      services: vec![(Service::Udp, 56700), (Service::Reserved(5), 56700)],
+      unhandled: vec![501, 502],
+    },
+    SavedDevice {
+      target: 0xAB,
+      addr: "[::1]:56701".parse().unwrap(),
+      port: 0,
+      host: Some("bulb.lan".into()),
+      label: None,
+      location: None,
+      group: Some("Upstairs".into()),
+      services: Vec::new(),
+      unhandled: Vec::new(),
+    },
+  ];
+
+  let saved = Saved {
+    devices,
+    aliases: vec![("kitchen-left".into(), 0xD073D5000001), ("\t".into(), 0xAB)],
+  };
+
+  let mut bytes = Vec::new();
+  write_saved(&mut bytes, &saved).unwrap();
+  assert_eq!(2 + 2 + 1, bytes.iter().filter(|&&b| b == b'\n').count());
+  assert_eq!(saved, read_saved(&bytes[..]).unwrap());
+
+  let e = read_saved(&b"# lifx devices v1\n\n0xab\t10.0.0.2\n"[..]).unwrap_err();
+  assert_eq!("i/o error: saved device on line 3 is malformed", e.to_string());
+  let e = read_saved(&b"alias\tkitchen\t0xab\n"[..]).unwrap_err();
+  assert_eq!("i/o error: saved device on line 1 is malformed", e.to_string());
+}
@@ -1,680 +1,529 @@
-use std::io::Cursor;
-use std::mem;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use byteorder::{ByteOrder, LittleEndian};
 
-/// encodes a series of bytes
-///
-pub fn encode<T: Encodable>(data: &T) -> Result<Vec<u8>, String> {
-  let mut encoder = ByteEncoder::new();
-  try!(data.encode(&mut encoder));
-  Ok(encoder.bytes)
-}
+use error::Error;
 
-/// decodes a series of bytes
-///
-pub fn decode<T: Decodable>(data: &[u8]) -> Result<T, String> {
-  let mut decoder = ByteDecoder::new(data);
-  T::decode(&mut decoder)
-}
+#[cfg(feature = "std")]
+pub(crate) use byteorder::WriteBytesExt;
+#[cfg(not(feature = "std"))]
+pub(crate) use std::io::WriteBytesExt;
 
-struct ByteEncoder {
-  bytes: Vec<u8>,
+/// why a frame could not be decoded. offsets are in bytes from the start of
+/// the datagram when the error comes out of `Message`, and from the start of
+/// the input otherwise.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+  /// `field` needed `expected` bytes at `offset`, but only `actual` were left.
+  Truncated {
+    field: &'static str,
+    offset: usize,
+    expected: usize,
+    actual: usize,
+  },
+  /// `field` at `offset` says there are `expected` bytes, but there are
+  /// `actual`.
+  SizeMismatch {
+    field: &'static str,
+    offset: usize,
+    expected: usize,
+    actual: usize,
+  },
+  /// the label `field` at `offset` is not valid UTF-8. only returned when
+  /// decoding with `LabelMode::Strict`.
+  InvalidLabel {
+    field: &'static str,
+    offset: usize,
+    raw: [u8; 32],
+  },
+  /// the payload at `offset` is `actual` bytes, but message type `typ` needs
+  /// `expected`.
+  PayloadTooShort {
+    typ: u16,
+    offset: usize,
+    expected: usize,
+    actual: usize,
+  },
+  /// `field` at `offset` holds `actual`, but only `expected` is accepted.
+  /// only returned when validating headers strictly.
+  InvalidValue {
+    field: &'static str,
+    offset: usize,
+    expected: u64,
+    actual: u64,
+  },
 }
 
-impl ByteEncoder {
-  #[inline(always)]
-  fn new() -> ByteEncoder {
-    ByteEncoder {
-      bytes: Vec::new(),
+impl DecodeError {
+  /// the field that failed to decode.
+  ///
+  pub fn field(&self) -> &'static str {
+    match *self {
+      DecodeError::Truncated { field, .. }
+      | DecodeError::SizeMismatch { field, .. }
+      | DecodeError::InvalidLabel { field, .. }
+      | DecodeError::InvalidValue { field, .. } => field,
+      DecodeError::PayloadTooShort { .. } => "payload",
     }
   }
-}
-
-impl Encoder for ByteEncoder {
-  type Error = String;
-
-  #[inline(always)]
-  fn emit_nil(&mut self) -> Result<(), Self::Error> {
-    Ok(())
-  }
 
-  #[inline]
-  fn emit_usize(&mut self, v: usize) -> Result<(), Self::Error> {
-    if mem::size_of::<usize>() == 4 {
-      self.emit_u32(v as u32)
-    } else {
-      self.emit_u64(v as u64)
+  /// the byte offset the failing field starts at.
+  ///
+  pub fn offset(&self) -> usize {
+    match *self {
+      DecodeError::Truncated { offset, .. }
+      | DecodeError::SizeMismatch { offset, .. }
+      | DecodeError::InvalidLabel { offset, .. }
+      | DecodeError::PayloadTooShort { offset, .. }
+      | DecodeError::InvalidValue { offset, .. } => offset,
     }
   }
 
-  #[inline]
-  fn emit_u64(&mut self, v: u64) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_u64::<LittleEndian>(v)
-      .or(err!("failed to write u64"))
-  }
-
-  #[inline]
-  fn emit_u32(&mut self, v: u32) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_u32::<LittleEndian>(v)
-      .or(err!("failed to write u32"))
-  }
-
-  #[inline]
-  fn emit_u16(&mut self, v: u16) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_u16::<LittleEndian>(v)
-      .or(err!("failed to write u16"))
-  }
-
-  #[inline]
-  fn emit_u8(&mut self, v: u8) -> Result<(), Self::Error> {
-    self.bytes.write_u8(v).or(err!("failed to write u8"))
-  }
-
-  #[inline]
-  fn emit_isize(&mut self, v: isize) -> Result<(), Self::Error> {
-    if mem::size_of::<isize>() == 4 {
-      self.emit_i32(v as i32)
-    } else {
-      self.emit_i64(v as i64)
+  /// renames the failing field, so errors from nested decoders can be
+  /// reported against the field that contains them.
+  ///
+  pub fn with_field(mut self, name: &'static str) -> DecodeError {
+    match self {
+      DecodeError::Truncated { ref mut field, .. }
+      | DecodeError::SizeMismatch { ref mut field, .. }
+      | DecodeError::InvalidLabel { ref mut field, .. }
+      | DecodeError::InvalidValue { ref mut field, .. } => *field = name,
+      DecodeError::PayloadTooShort { .. } => {}
     }
-  }
-
-  #[inline]
-  fn emit_i64(&mut self, v: i64) -> Result<(), Self::Error> {
     self
-      .bytes
-      .write_i64::<LittleEndian>(v)
-      .or(err!("failed to write i64"))
   }
 
-  #[inline]
-  fn emit_i32(&mut self, v: i32) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_i32::<LittleEndian>(v)
-      .or(err!("failed to write i32"))
-  }
-
-  #[inline]
-  fn emit_i16(&mut self, v: i16) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_i16::<LittleEndian>(v)
-      .or(err!("failed to write i16"))
-  }
-
-  #[inline]
-  fn emit_i8(&mut self, v: i8) -> Result<(), Self::Error> {
-    self.bytes.write_i8(v).or(err!("failed to write i8"))
-  }
-
-  #[inline]
-  fn emit_bool(&mut self, v: bool) -> Result<(), Self::Error> {
-    self.emit_u8(v as u8).or(err!("failed to write bool"))
-  }
-
-  #[inline]
-  fn emit_f64(&mut self, v: f64) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_f64::<LittleEndian>(v)
-      .or(err!("failed to write f64"))
-  }
-
-  #[inline]
-  fn emit_f32(&mut self, v: f32) -> Result<(), Self::Error> {
-    self
-      .bytes
-      .write_f32::<LittleEndian>(v)
-      .or(err!("failed to write f32"))
-  }
-
-  #[inline]
-  fn emit_char(&mut self, v: char) -> Result<(), Self::Error> {
-    let mut buf = [0; 4];
-    let encoded = v.encode_utf8(&mut buf[..]);
-
-    for b in encoded.bytes() {
-      try!(self.emit_u8(b))
+  /// moves the offset forward by `n` bytes, for errors from decoders that
+  /// only saw part of the datagram.
+  ///
+  pub fn offset_by(mut self, n: usize) -> DecodeError {
+    match self {
+      DecodeError::Truncated { ref mut offset, .. }
+      | DecodeError::SizeMismatch { ref mut offset, .. }
+      | DecodeError::InvalidLabel { ref mut offset, .. }
+      | DecodeError::PayloadTooShort { ref mut offset, .. }
+      | DecodeError::InvalidValue { ref mut offset, .. } => *offset += n,
     }
-
-    Ok(())
+    self
   }
+}
 
-  #[inline]
-  fn emit_str(&mut self, v: &str) -> Result<(), Self::Error> {
-    for c in v.chars() {
-      try!(self.emit_char(c))
+impl Display for DecodeError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match *self {
+      DecodeError::Truncated {
+        field,
+        offset,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "{} at byte {} is truncated: needed {} bytes, {} left",
+        field, offset, expected, actual
+      ),
+      DecodeError::SizeMismatch {
+        field,
+        offset,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "{} at byte {} does not match: expected {} bytes, got {}",
+        field, offset, expected, actual
+      ),
+      DecodeError::InvalidLabel { field, offset, .. } => {
+        write!(f, "{} at byte {} is not valid UTF-8", field, offset)
+      }
+      DecodeError::PayloadTooShort {
+        typ,
+        offset,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "payload for type {} at byte {} is too short: needed {} bytes, got {}",
+        typ, offset, expected, actual
+      ),
+      DecodeError::InvalidValue {
+        field,
+        offset,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "{} at byte {} is invalid: expected {}, got {}",
+        field, offset, expected, actual
+      ),
     }
-
-    try!(self.emit_u8(0));
-
-    Ok(())
-  }
-
-  #[inline]
-  fn emit_enum<F>(&mut self, _: &str, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
   }
+}
 
-  #[inline]
-  fn emit_enum_variant<F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    _: usize,
-    f: F,
-  ) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+impl error::Error for DecodeError {}
 
+impl From<DecodeError> for String {
   #[inline]
-  fn emit_enum_variant_arg<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
+  fn from(e: DecodeError) -> String {
+    e.to_string()
   }
+}
 
-  #[inline]
-  fn emit_enum_struct_variant<F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    _: usize,
-    f: F,
-  ) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// how labels that are not valid UTF-8 are decoded.
+///
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum LabelMode {
+  /// invalid sequences are replaced with U+FFFD.
+  Lossy,
+  /// invalid labels fail with `DecodeError::InvalidLabel`, which holds the
+  /// raw bytes.
+  Strict,
+}
 
-  #[inline]
-  fn emit_enum_struct_variant_field<F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    f: F,
-  ) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// types that know their own little-endian wire layout.
+///
+pub trait WireEncode {
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+}
 
-  #[inline]
-  fn emit_struct<F>(&mut self, _: &str, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// types that can be read back from their little-endian wire layout.
+///
+pub trait WireDecode: Sized {
+  fn decode(r: &mut WireReader) -> Result<Self, DecodeError>;
+}
 
-  #[inline]
-  fn emit_struct_field<F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    f: F,
-  ) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// encodes a series of bytes
+///
+pub fn encode<T: WireEncode>(data: &T) -> Result<Vec<u8>, Error> {
+  let mut bytes = Vec::new();
+  data.encode(&mut bytes).map_err(Error::Encode)?;
+  Ok(bytes)
+}
 
-  #[inline]
-  fn emit_tuple<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// encodes into a caller provided buffer, returning the number of bytes
+/// written. fails if the buffer is too small to hold the whole encoding.
+///
+pub fn encode_into<T: WireEncode>(
+  data: &T,
+  buf: &mut [u8],
+) -> Result<usize, Error> {
+  let len = buf.len();
+  let mut w = &mut buf[..];
+  data.encode(&mut w).map_err(Error::Encode)?;
+  Ok(len - w.len())
+}
 
-  #[inline]
-  fn emit_tuple_arg<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// returns the number of bytes `data` encodes to, without writing them
+/// anywhere.
+///
+pub fn encoded_len<T: WireEncode>(data: &T) -> usize {
+  let mut counter = ByteCounter(0);
+  // counting never fails, so neither can the encode.
+  let _ = data.encode(&mut counter);
+  counter.0
+}
 
-  #[inline]
-  fn emit_tuple_struct<F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    f: F,
-  ) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// decodes a series of bytes
+///
+pub fn decode<T: WireDecode>(data: &[u8]) -> Result<T, DecodeError> {
+  T::decode(&mut WireReader::new(data))
+}
 
-  #[inline]
-  fn emit_tuple_struct_arg<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
+/// a writer that throws bytes away, keeping only how many there were.
+///
+struct ByteCounter(usize);
 
+impl Write for ByteCounter {
   #[inline]
-  fn emit_option<F>(&mut self, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
-
-  fn emit_option_none(&mut self) -> Result<(), Self::Error> {
-    self.emit_u8(0)
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0 += buf.len();
+    Ok(buf.len())
   }
 
-  fn emit_option_some<F>(&mut self, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    try!(self.emit_u8(1));
-    f(self)
-  }
-
-  fn emit_seq<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
-
-  fn emit_seq_elt<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
-
-  fn emit_map<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
-
-  fn emit_map_elt_key<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
-  }
-
-  fn emit_map_elt_val<F>(&mut self, _: usize, f: F) -> Result<(), Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<(), Self::Error>,
-  {
-    f(self)
+  #[inline(always)]
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
   }
 }
 
-struct ByteDecoder<'a> {
-  cursor: Cursor<&'a [u8]>,
+/// reads little-endian values from the front of a byte slice.
+///
+pub struct WireReader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+  fields: Option<Vec<(&'static str, usize, usize)>>,
+  labels: LabelMode,
 }
 
-impl<'a> ByteDecoder<'a> {
+impl<'a> WireReader<'a> {
   #[inline(always)]
-  fn new(bytes: &[u8]) -> ByteDecoder {
-    ByteDecoder {
-      cursor: Cursor::new(bytes),
+  pub fn new(bytes: &'a [u8]) -> WireReader<'a> {
+    WireReader {
+      bytes,
+      pos: 0,
+      fields: None,
+      labels: LabelMode::Lossy,
     }
   }
 
-  fn read_char_with_first_byte(&mut self, first: u8) -> Result<char, String> {
-    let mut c = 0;
-    let len = match first & 0b1111_1100 {
-      0b0000_0000 => {
-        c |= first as u32;
-        1
-      }
-      0b1100_0000 => {
-        c |= (first & 0b0001_1111) as u32;
-        2
-      }
-      0b1110_0000 => {
-        c |= (first & 0b0000_1111) as u32;
-        3
-      }
-      0b1111_0000 => {
-        c |= (first & 0b0000_0111) as u32;
-        4
-      }
-      0b1111_1000 => {
-        c |= (first & 0b0000_0011) as u32;
-        5
-      }
-      0b1111_1100 => {
-        c |= (first & 0b0000_0001) as u32;
-        6
-      }
-      _ => panic!("unexpected first byte"),
-    };
-
-    for _ in 1..len {
-      c <<= 6;
-      let byte = try!(self.read_u8()) & 0b0011_1111;
-      c |= byte as u32;
+  /// creates a reader that records where each message field starts and ends.
+  /// this is slower than `new`, and is meant for `debug::dump`.
+  ///
+  pub fn traced(bytes: &'a [u8]) -> WireReader<'a> {
+    WireReader {
+      bytes,
+      pos: 0,
+      fields: Some(Vec::new()),
+      labels: LabelMode::Lossy,
     }
-
-    ::std::char::from_u32(c).ok_or("failed to read char".to_string())
-  }
-}
-
-impl<'a> Decoder for ByteDecoder<'a> {
-  type Error = String;
-
-  #[inline(always)]
-  fn read_nil(&mut self) -> Result<(), Self::Error> {
-    Ok(())
   }
 
+  /// sets how labels that are not valid UTF-8 are decoded.
+  ///
   #[inline]
-  fn read_usize(&mut self) -> Result<usize, Self::Error> {
+  pub fn with_label_mode(mut self, labels: LabelMode) -> WireReader<'a> {
+    self.labels = labels;
     self
-      .cursor
-      .read_uint::<LittleEndian>(mem::size_of::<usize>())
-      .and_then(|v| Ok(v as usize))
-      .or(err!("read usize failed"))
   }
 
-  #[inline]
-  fn read_u64(&mut self) -> Result<u64, Self::Error> {
-    self
-      .cursor
-      .read_u64::<LittleEndian>()
-      .or(err!("read u64 failed"))
-  }
-
-  #[inline]
-  fn read_u32(&mut self) -> Result<u32, Self::Error> {
-    self
-      .cursor
-      .read_u32::<LittleEndian>()
-      .or(err!("read u32 failed"))
-  }
-
-  #[inline]
-  fn read_u16(&mut self) -> Result<u16, Self::Error> {
-    self
-      .cursor
-      .read_u16::<LittleEndian>()
-      .or(err!("read u16 failed"))
-  }
-
-  #[inline]
-  fn read_u8(&mut self) -> Result<u8, Self::Error> {
-    self.cursor.read_u8().or(err!("read u8 failed"))
+  #[inline(always)]
+  pub fn label_mode(&self) -> LabelMode {
+    self.labels
   }
 
+  /// records that `field` runs from `start` up to the current position. does
+  /// nothing unless the reader is traced.
+  ///
   #[inline]
-  fn read_isize(&mut self) -> Result<isize, Self::Error> {
-    self
-      .cursor
-      .read_int::<LittleEndian>(mem::size_of::<isize>())
-      .and_then(|v| Ok(v as isize))
-      .or(err!("read isize failed"))
+  pub fn mark(&mut self, field: &'static str, start: usize) {
+    if let Some(ref mut fields) = self.fields {
+      fields.push((field, start, self.pos));
+    }
   }
 
-  #[inline]
-  fn read_i64(&mut self) -> Result<i64, Self::Error> {
-    self
-      .cursor
-      .read_i64::<LittleEndian>()
-      .or(err!("read i64 failed"))
+  /// the fields recorded so far, as (name, start, end).
+  ///
+  pub fn fields(&self) -> &[(&'static str, usize, usize)] {
+    match self.fields {
+      Some(ref fields) => &fields[..],
+      None => &[],
+    }
   }
 
-  #[inline]
-  fn read_i32(&mut self) -> Result<i32, Self::Error> {
-    self
-      .cursor
-      .read_i32::<LittleEndian>()
-      .or(err!("read i32 failed"))
+  /// number of bytes read so far.
+  ///
+  #[inline(always)]
+  pub fn position(&self) -> usize {
+    self.pos
   }
 
-  #[inline]
-  fn read_i16(&mut self) -> Result<i16, Self::Error> {
-    self
-      .cursor
-      .read_i16::<LittleEndian>()
-      .or(err!("read i16 failed"))
+  /// number of bytes left to read.
+  ///
+  #[inline(always)]
+  pub fn remaining(&self) -> usize {
+    self.bytes.len() - self.pos
   }
 
+  /// the bytes read from `start` up to the current position.
+  ///
   #[inline]
-  fn read_i8(&mut self) -> Result<i8, Self::Error> {
-    self.cursor.read_i8().or(err!("read i8 failed"))
+  pub fn read_since(&self, start: usize) -> &'a [u8] {
+    &self.bytes[start.min(self.pos)..self.pos]
   }
 
+  /// reads the next `n` bytes without copying them.
+  ///
   #[inline]
-  fn read_bool(&mut self) -> Result<bool, Self::Error> {
-    self
-      .read_u8()
-      .and_then(|b| Ok(b > 0))
-      .or(err!("read bool failed"))
+  pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+    self.read_field(n, "bytes")
   }
 
+  /// skips over `n` reserved bytes.
+  ///
   #[inline]
-  fn read_f64(&mut self) -> Result<f64, Self::Error> {
-    self
-      .cursor
-      .read_f64::<LittleEndian>()
-      .or(err!("read f64 failed"))
+  pub fn skip(&mut self, n: usize) -> Result<(), DecodeError> {
+    self.read_field(n, "reserved").map(|_| ())
   }
 
   #[inline]
-  fn read_f32(&mut self) -> Result<f32, Self::Error> {
-    self
-      .cursor
-      .read_f32::<LittleEndian>()
-      .or(err!("read f32 failed"))
+  pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+    self.read_field(1, "u8").map(|b| b[0])
   }
 
   #[inline]
-  fn read_char(&mut self) -> Result<char, Self::Error> {
-    let first = try!(self.read_u8());
-    self.read_char_with_first_byte(first)
+  pub fn read_bool(&mut self) -> Result<bool, DecodeError> {
+    self.read_field(1, "bool").map(|b| b[0] != 0)
   }
 
   #[inline]
-  fn read_str(&mut self) -> Result<String, Self::Error> {
-    let mut s = String::new();
-
-    loop {
-      let b = try!(self.read_u8());
-
-      if b == 0b0000_0000 {
-        break;
-      }
-
-      s.push(try!(self.read_char_with_first_byte(b)));
-    }
-
-    Ok(s)
+  pub fn read_u16(&mut self) -> Result<u16, DecodeError> {
+    self.read_field(2, "u16").map(LittleEndian::read_u16)
   }
 
   #[inline]
-  fn read_enum<T, F>(&mut self, _: &str, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+  pub fn read_i16(&mut self) -> Result<i16, DecodeError> {
+    self.read_field(2, "i16").map(LittleEndian::read_i16)
   }
 
   #[inline]
-  fn read_enum_variant<T, F>(
-    &mut self,
-    _: &[&str],
-    mut f: F,
-  ) -> Result<T, Self::Error>
-  where
-    F: FnMut(&mut Self, usize) -> Result<T, Self::Error>,
-  {
-    f(self, 0)
+  pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+    self.read_field(4, "u32").map(LittleEndian::read_u32)
   }
 
   #[inline]
-  fn read_enum_variant_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+  pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+    self.read_field(8, "u64").map(LittleEndian::read_u64)
   }
 
   #[inline]
-  fn read_enum_struct_variant<T, F>(
-    &mut self,
-    _: &[&str],
-    mut f: F,
-  ) -> Result<T, Self::Error>
-  where
-    F: FnMut(&mut Self, usize) -> Result<T, Self::Error>,
-  {
-    f(self, 0)
+  pub fn read_f32(&mut self) -> Result<f32, DecodeError> {
+    self.read_field(4, "f32").map(LittleEndian::read_f32)
   }
 
   #[inline]
-  fn read_enum_struct_variant_field<T, F>(
+  fn read_field(
     &mut self,
-    _: &str,
-    _: usize,
-    f: F,
-  ) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
-  }
+    n: usize,
+    field: &'static str,
+  ) -> Result<&'a [u8], DecodeError> {
+    if n > self.remaining() {
+      return Err(DecodeError::Truncated {
+        field,
+        offset: self.pos,
+        expected: n,
+        actual: self.remaining(),
+      });
+    }
 
-  #[inline]
-  fn read_struct<T, F>(&mut self, _: &str, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
-  }
-
-  #[inline]
-  fn read_struct_field<T, F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    f: F,
-  ) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+    let bytes = &self.bytes[self.pos..self.pos + n];
+    self.pos += n;
+    Ok(bytes)
   }
+}
 
+impl WireEncode for u8 {
   #[inline]
-  fn read_tuple<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u8(*self)
   }
+}
 
+impl WireDecode for u8 {
   #[inline]
-  fn read_tuple_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+  fn decode(r: &mut WireReader) -> Result<u8, DecodeError> {
+    r.read_u8()
   }
+}
 
+impl WireEncode for bool {
   #[inline]
-  fn read_tuple_struct<T, F>(
-    &mut self,
-    _: &str,
-    _: usize,
-    f: F,
-  ) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u8(*self as u8)
   }
+}
 
+impl WireDecode for bool {
   #[inline]
-  fn read_tuple_struct_arg<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
+  fn decode(r: &mut WireReader) -> Result<bool, DecodeError> {
+    r.read_bool()
   }
+}
 
-  #[inline]
-  fn read_option<T, F>(&mut self, mut f: F) -> Result<T, Self::Error>
-  where
-    F: FnMut(&mut Self, bool) -> Result<T, Self::Error>,
-  {
-    f(self, false)
-  }
+macro_rules! wire_little_endian(
+  ($($t:ident: $write:ident, $read:ident);*) => ($(
+    impl WireEncode for $t {
+      #[inline]
+      fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.$write::<LittleEndian>(*self)
+      }
+    }
 
-  #[inline]
-  fn read_seq<T, F>(&mut self, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self, usize) -> Result<T, Self::Error>,
-  {
-    f(self, 0)
-  }
+    impl WireDecode for $t {
+      #[inline]
+      fn decode(r: &mut WireReader) -> Result<$t, DecodeError> {
+        r.$read()
+      }
+    }
+  )*)
+);
+
+wire_little_endian!(
+  u16: write_u16, read_u16;
+  i16: write_i16, read_i16;
+  u32: write_u32, read_u32;
+  u64: write_u64, read_u64;
+  f32: write_f32, read_f32
+);
+
+macro_rules! wire_array(
+  ($($n:expr),*) => ($(
+    impl<T: WireEncode> WireEncode for [T; $n] {
+      fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for item in self.iter() {
+          item.encode(w)?;
+        }
+        Ok(())
+      }
+    }
 
-  #[inline]
-  fn read_seq_elt<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
-  }
+    impl<T: WireDecode + Copy + Default> WireDecode for [T; $n] {
+      fn decode(r: &mut WireReader) -> Result<[T; $n], DecodeError> {
+        let mut arr = [T::default(); $n];
+        for item in arr.iter_mut() {
+          *item = T::decode(r)?;
+        }
+        Ok(arr)
+      }
+    }
+  )*)
+);
+
+wire_array!(8, 16, 64);
+
+#[test]
+fn test_reader_is_little_endian() {
+  let bytes = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
+  let mut r = WireReader::new(&bytes[..]);
+
+  assert_eq!(0x0201, r.read_u16().unwrap());
+  assert_eq!(0x06050403, r.read_u32().unwrap());
+  assert_eq!(1, r.remaining());
+  assert!(r.read_u16().is_err());
+  assert_eq!(7, r.read_u8().unwrap());
+  assert_eq!(7, r.position());
+}
 
-  #[inline]
-  fn read_map<T, F>(&mut self, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self, usize) -> Result<T, Self::Error>,
-  {
-    f(self, 0)
-  }
+#[test]
+fn test_reader_reports_truncated_field() {
+  let bytes = [0x01, 0x02, 0x03];
+  let mut r = WireReader::new(&bytes[..]);
+
+  r.skip(1).unwrap();
+  let e = r.read_u32().unwrap_err();
+
+  assert_eq!(
+    DecodeError::Truncated {
+      field: "u32",
+      offset: 1,
+      expected: 4,
+      actual: 2,
+    },
+    e
+  );
+  assert_eq!("label", e.with_field("label").field());
+}
 
-  #[inline]
-  fn read_map_elt_key<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
-  }
+#[test]
+fn test_encode_into_matches_encode() {
+  use header::Header;
 
-  #[inline]
-  fn read_map_elt_val<T, F>(&mut self, _: usize, f: F) -> Result<T, Self::Error>
-  where
-    F: FnOnce(&mut Self) -> Result<T, Self::Error>,
-  {
-    f(self)
-  }
+  let header = Header::new(36, true, 256, 1000, false, false, 1, 12);
+  let mut buf = [0; 40];
 
-  #[inline(always)]
-  fn error(&mut self, s: &str) -> Self::Error {
-    s.to_string()
-  }
+  assert_eq!(36, encode_into(&header, &mut buf[..]).unwrap());
+  assert_eq!(&encode(&header).unwrap()[..], &buf[..36]);
+  assert!(encode_into(&header, &mut buf[..35]).is_err());
 }
@@ -0,0 +1,605 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+
+use error::Error;
+use message::{Message, SendOptions};
+use payload::{
+  Array64, Device, FirmwareVersion, Light, Payload, Power, Service, Waveform, HSBK,
+};
+use serialize;
+use units::{Brightness, Hue, Kelvin, Saturation, TransitionMs};
+
+/// a known-good frame, and the payload it holds.
+///
+/// # Notes
+///
+///   * Every frame has the header from the sample in the protocol
+///     documentation: tagged, addressable, protocol 1024, with the source,
+///     target, flags and sequence all zero.
+///
+pub struct TestVector {
+  pub name: &'static str,
+  pub frame: &'static [u8],
+  pub payload: fn() -> Payload,
+}
+
+impl TestVector {
+  /// checks that the frame decodes to a message that encodes back to it, and
+  /// that the payload, in a tagged message with the source, target, flags and
+  /// sequence all zero, encodes to the whole frame.
+  ///
+  pub fn check(&self) -> Result<(), Error> {
+    let decoded = Message::from_bytes(self.frame)?;
+    let expected = Message::new_with((self.payload)(), SendOptions::empty(), 0, 0)
+      .with_tagged(true);
+
+    for msg in &[decoded, expected] {
+      let encoded = serialize::encode(msg)?;
+      if let Some(offset) = first_difference(self.frame, &encoded[..]) {
+        return Err(self.mismatch(offset));
+      }
+    }
+
+    Ok(())
+  }
+
+  fn mismatch(&self, offset: usize) -> Error {
+    Error::VectorMismatch {
+      name: self.name,
+      offset,
+    }
+  }
+}
+
+/// the first offset at which `a` and `b` differ, if they do.
+///
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+  match a.iter().zip(b).position(|(a, b)| a != b) {
+    Some(offset) => Some(offset),
+    None if a.len() != b.len() => Some(a.len().min(b.len())),
+    None => None,
+  }
+}
+
+/// build time used by the firmware and info vectors.
+///
+const BUILD: u64 = 1_500_000_000_000_000_000;
+
+/// location and group id used by the location and group vectors.
+///
+const LOCATION: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+fn color() -> HSBK {
+  HSBK::new(
+    Hue::from(21845),
+    Saturation::MAX,
+    Brightness::MAX,
+    Kelvin::new(3500).unwrap(),
+  )
+}
+
+fn echo() -> Array64<u8> {
+  let mut payload = [0; 64];
+  for (i, b) in payload.iter_mut().enumerate() {
+    *b = i as u8;
+  }
+  Array64(payload)
+}
+
+/// a vector for every Device and Light message. the Light::SetColor frame is
+/// the sample from the protocol documentation.
+///
+pub static VECTORS: &[TestVector] = &[
+  TestVector {
+    name: "Device::GetService",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetService),
+  },
+  TestVector {
+    name: "Device::StateService",
+    frame: &[
+      0x29, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x7c, 0xdd,
+      0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateService(Service::Udp, 56700)),
+  },
+  TestVector {
+    name: "Device::GetHostInfo",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetHostInfo),
+  },
+  TestVector {
+    name: "Device::StateHostInfo",
+    frame: &[
+      0x32, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+      0x3e, 0x00, 0x04, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateHostInfo(0.25, 1024, 2048)),
+  },
+  TestVector {
+    name: "Device::GetHostFirmware",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetHostFirmware),
+  },
+  TestVector {
+    name: "Device::StateHostFirmware",
+    frame: &[
+      0x38, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16,
+      0x7b, 0x0d, 0x12, 0xd1, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x50, 0x00, 0x02, 0x00,
+    ],
+    payload: || {
+      Payload::Device(Device::StateHostFirmware(FirmwareVersion::new(
+        BUILD,
+        (2 << 16) | 80,
+      )))
+    },
+  },
+  TestVector {
+    name: "Device::GetWifiInfo",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetWifiInfo),
+  },
+  TestVector {
+    name: "Device::StateWifiInfo",
+    frame: &[
+      0x32, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+      0x3e, 0x00, 0x04, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateWifiInfo(0.25, 1024, 2048)),
+  },
+  TestVector {
+    name: "Device::GetWifiFirmware",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetWifiFirmware),
+  },
+  TestVector {
+    name: "Device::StateWifiFirmware",
+    frame: &[
+      0x38, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16,
+      0x7b, 0x0d, 0x12, 0xd1, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x01, 0x00, 0x01, 0x00,
+    ],
+    payload: || {
+      Payload::Device(Device::StateWifiFirmware(FirmwareVersion::new(
+        BUILD,
+        (1 << 16) | 1,
+      )))
+    },
+  },
+  TestVector {
+    name: "Device::GetPower",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetPower),
+  },
+  TestVector {
+    name: "Device::SetPower",
+    frame: &[
+      0x26, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, 0xff, 0xff,
+    ],
+    payload: || Payload::Device(Device::SetPower(Power::Max)),
+  },
+  TestVector {
+    name: "Device::StatePower",
+    frame: &[
+      0x26, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StatePower(Power::Standby)),
+  },
+  TestVector {
+    name: "Device::GetLabel",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetLabel),
+  },
+  TestVector {
+    name: "Device::SetLabel",
+    frame: &[
+      0x44, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x4b, 0x69, 0x74,
+      0x63, 0x68, 0x65, 0x6e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::SetLabel("Kitchen".to_string())),
+  },
+  TestVector {
+    name: "Device::StateLabel",
+    frame: &[
+      0x44, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x19, 0x00, 0x00, 0x00, 0x4b, 0x69, 0x74,
+      0x63, 0x68, 0x65, 0x6e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateLabel("Kitchen".to_string())),
+  },
+  TestVector {
+    name: "Device::GetVersion",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetVersion),
+  },
+  TestVector {
+    name: "Device::StateVersion",
+    frame: &[
+      0x30, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+      0x00, 0x1b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateVersion(1, 27, 0)),
+  },
+  TestVector {
+    name: "Device::GetInfo",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetInfo),
+  },
+  TestVector {
+    name: "Device::StateInfo",
+    frame: &[
+      0x3c, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x23, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16,
+      0x7b, 0x0d, 0x12, 0xd1, 0x14, 0x00, 0xa0, 0xb8, 0x30, 0x46, 0x03, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateInfo(BUILD, 3_600_000_000_000, 0)),
+  },
+  TestVector {
+    name: "Device::Acknowledgement",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::Acknowledgement),
+  },
+  TestVector {
+    name: "Device::GetLocation",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetLocation),
+  },
+  TestVector {
+    name: "Device::SetLocation",
+    frame: &[
+      0x5c, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x31, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03,
+      0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+      0x48, 0x6f, 0x6d, 0x65, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x7b, 0x0d, 0x12, 0xd1,
+      0x14,
+    ],
+    payload: || {
+      Payload::Device(Device::SetLocation(LOCATION, "Home".to_string(), BUILD))
+    },
+  },
+  TestVector {
+    name: "Device::StateLocation",
+    frame: &[
+      0x5c, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x32, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03,
+      0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+      0x48, 0x6f, 0x6d, 0x65, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x7b, 0x0d, 0x12, 0xd1,
+      0x14,
+    ],
+    payload: || {
+      Payload::Device(Device::StateLocation(LOCATION, "Home".to_string(), BUILD))
+    },
+  },
+  TestVector {
+    name: "Device::GetGroup",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetGroup),
+  },
+  TestVector {
+    name: "Device::SetGroup",
+    frame: &[
+      0x5c, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03,
+      0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+      0x4c, 0x69, 0x76, 0x69, 0x6e, 0x67, 0x20, 0x52, 0x6f, 0x6f, 0x6d, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x7b, 0x0d, 0x12, 0xd1,
+      0x14,
+    ],
+    payload: || {
+      Payload::Device(Device::SetGroup(LOCATION, "Living Room".to_string(), BUILD))
+    },
+  },
+  TestVector {
+    name: "Device::StateGroup",
+    frame: &[
+      0x5c, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x35, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03,
+      0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+      0x4c, 0x69, 0x76, 0x69, 0x6e, 0x67, 0x20, 0x52, 0x6f, 0x6f, 0x6d, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x7b, 0x0d, 0x12, 0xd1,
+      0x14,
+    ],
+    payload: || {
+      Payload::Device(Device::StateGroup(
+        LOCATION,
+        "Living Room".to_string(),
+        BUILD,
+      ))
+    },
+  },
+  TestVector {
+    name: "Device::EchoRequest",
+    frame: &[
+      0x64, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02,
+      0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+      0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c,
+      0x1d, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29,
+      0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36,
+      0x37, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, 0x3f,
+    ],
+    payload: || Payload::Device(Device::EchoRequest(echo())),
+  },
+  TestVector {
+    name: "Device::EchoResponse",
+    frame: &[
+      0x64, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02,
+      0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+      0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c,
+      0x1d, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29,
+      0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36,
+      0x37, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, 0x3f,
+    ],
+    payload: || Payload::Device(Device::EchoResponse(echo())),
+  },
+  TestVector {
+    name: "Device::StateUnhandled",
+    frame: &[
+      0x26, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdf, 0x00, 0x00, 0x00, 0x65, 0x00,
+    ],
+    payload: || Payload::Device(Device::StateUnhandled(101)),
+  },
+  TestVector {
+    name: "Light::Get",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Light(Light::Get),
+  },
+  TestVector {
+    name: "Light::SetColor",
+    frame: &[
+      0x31, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00, 0x00, 0x55, 0x55,
+      0xff, 0xff, 0xff, 0xff, 0xac, 0x0d, 0x00, 0x04, 0x00, 0x00,
+    ],
+    payload: || {
+      Payload::Light(Light::SetColor(color(), TransitionMs::from(1024)))
+    },
+  },
+  TestVector {
+    name: "Light::SetWaveform",
+    frame: &[
+      0x39, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x67, 0x00, 0x00, 0x00, 0x00, 0x01, 0x55,
+      0x55, 0xff, 0xff, 0xff, 0xff, 0xac, 0x0d, 0xe8, 0x03, 0x00, 0x00, 0x00, 0x00,
+      0xa0, 0x40, 0x00, 0x00, 0x01,
+    ],
+    payload: || {
+      Payload::Light(Light::SetWaveform(
+        true,
+        color(),
+        1000,
+        5.0,
+        0,
+        Waveform::Sine,
+      ))
+    },
+  },
+  TestVector {
+    name: "Light::State",
+    frame: &[
+      0x58, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x00, 0x00, 0x00, 0x55, 0x55, 0xff,
+      0xff, 0xff, 0xff, 0xac, 0x0d, 0x00, 0x00, 0xff, 0xff, 0x4b, 0x69, 0x74, 0x63,
+      0x68, 0x65, 0x6e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    payload: || {
+      Payload::Light(Light::State(color(), Power::Max, "Kitchen".to_string()))
+    },
+  },
+  TestVector {
+    name: "Light::GetPower",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x74, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Light(Light::GetPower),
+  },
+  TestVector {
+    name: "Light::SetPower",
+    frame: &[
+      0x2a, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x75, 0x00, 0x00, 0x00, 0xff, 0xff, 0xe8,
+      0x03, 0x00, 0x00,
+    ],
+    payload: || {
+      Payload::Light(Light::SetPower(Power::Max, TransitionMs::from(1000)))
+    },
+  },
+  TestVector {
+    name: "Light::StatePower",
+    frame: &[
+      0x26, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x76, 0x00, 0x00, 0x00, 0xff, 0xff,
+    ],
+    payload: || Payload::Light(Light::StatePower(Power::Max)),
+  },
+  TestVector {
+    name: "Light::SetWaveformOptional",
+    frame: &[
+      0x3d, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x00, 0x01, 0x55,
+      0x55, 0xff, 0xff, 0xff, 0xff, 0xac, 0x0d, 0xe8, 0x03, 0x00, 0x00, 0x00, 0x00,
+      0xa0, 0x40, 0x00, 0x00, 0x04, 0x01, 0x00, 0x01, 0x00,
+    ],
+    payload: || {
+      Payload::Light(Light::SetWaveformOptional(
+        true,
+        color(),
+        1000,
+        5.0,
+        0,
+        Waveform::Pulse,
+        true,
+        false,
+        true,
+        false,
+      ))
+    },
+  },
+  TestVector {
+    name: "Light::GetInfrared",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Light(Light::GetInfrared),
+  },
+  TestVector {
+    name: "Light::StateInfrared",
+    frame: &[
+      0x26, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79, 0x00, 0x00, 0x00, 0x00, 0x80,
+    ],
+    payload: || Payload::Light(Light::StateInfrared(32768)),
+  },
+  TestVector {
+    name: "Light::SetInfrared",
+    frame: &[
+      0x26, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7a, 0x00, 0x00, 0x00, 0xff, 0xff,
+    ],
+    payload: || Payload::Light(Light::SetInfrared(65535)),
+  },
+];
+
+#[test]
+fn test_vectors_round_trip() {
+  for vector in VECTORS.iter() {
+    vector.check().unwrap();
+  }
+}
+
+#[test]
+fn test_vectors_cover_device_and_light() {
+  for &typ in Device::TYPES.iter().chain(Light::TYPES.iter()) {
+    assert!(
+      VECTORS.iter().any(|v| (v.payload)().typ() == typ),
+      "no vector for type {}",
+      typ
+    );
+  }
+}
+
+#[test]
+fn test_vectors_check_the_header() {
+  // Device::GetService, sent with source 1.
+  let vector = TestVector {
+    name: "Device::GetService",
+    frame: &[
+      0x24, 0x00, 0x00, 0x34, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+    ],
+    payload: || Payload::Device(Device::GetService),
+  };
+
+  match vector.check() {
+    Err(Error::VectorMismatch { offset, .. }) => assert_eq!(4, offset),
+    res => panic!("source was not checked: {:?}", res),
+  }
+}
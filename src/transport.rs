@@ -0,0 +1,689 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use consts;
+use error::Error;
+use header::Header;
+use mac::MacAddress;
+use message::{Message, SendOptions};
+use payload::Payload;
+use serialize;
+
+/// a blocking udp socket, which `Client` sends and receives through. `Client::new`
+/// uses a `UdpSocket`; implement this to give `Client::with_transport` an in
+/// memory fake for tests, or a proxy or capture replay.
+///
+pub trait Transport: Send + Sync {
+  /// sends a datagram to `addr`, returning the number of bytes sent.
+  ///
+  fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+  /// receives a datagram into `buf`, returning its length and where it came
+  /// from. this should give up with an error after a short time, so the
+  /// listen thread can check for devices that went offline, and notice that
+  /// the client was closed when `wake` is not overridden.
+  ///
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+  /// allows or forbids sending to broadcast addresses. a client allows it once,
+  /// when it is created, and leaves it on. does nothing unless overridden.
+  ///
+  fn set_broadcast(&self, _broadcast: bool) -> io::Result<()> {
+    Ok(())
+  }
+
+  /// sets how long `recv_from` waits for a datagram, for `Client::poll_once`.
+  /// does nothing unless overridden.
+  ///
+  fn set_read_timeout(&self, _timeout: Duration) -> io::Result<()> {
+    Ok(())
+  }
+
+  /// wakes a thread blocked in `recv_from`, so a closed client's listen
+  /// thread stops right away rather than when the receive gives up. does
+  /// nothing unless overridden.
+  ///
+  fn wake(&self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl Transport for UdpSocket {
+  #[inline]
+  fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+    UdpSocket::send_to(self, buf, addr)
+  }
+
+  #[inline]
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    UdpSocket::recv_from(self, buf)
+  }
+
+  #[inline]
+  fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+    UdpSocket::set_broadcast(self, broadcast)
+  }
+
+  #[inline]
+  fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+    UdpSocket::set_read_timeout(self, Some(timeout))
+  }
+
+  /// sends an empty datagram to the socket itself.
+  ///
+  fn wake(&self) -> io::Result<()> {
+    let mut addr = self.local_addr()?;
+    if addr.ip().is_unspecified() {
+      addr.set_ip(match addr {
+        SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+        SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+      });
+    }
+
+    UdpSocket::send_to(self, &[], addr).map(|_| ())
+  }
+}
+
+/// sequence numbers, counted separately for every target. a sequence that is
+/// reserved, while waiting for an ack or a response, is skipped when its
+/// counter wraps around.
+///
+#[derive(Default)]
+pub(crate) struct Sequences(pub(crate) Mutex<HashMap<u64, (u8, HashSet<u8>)>>);
+
+impl Sequences {
+  /// returns the next sequence number for `target`.
+  ///
+  pub(crate) fn next(&self, target: u64) -> u8 {
+    self.take(target, false)
+  }
+
+  /// returns the next sequence number for `target`, and keeps it from being
+  /// handed out again until it is released.
+  ///
+  pub(crate) fn reserve(&self, target: u64) -> u8 {
+    self.take(target, true)
+  }
+
+  pub(crate) fn release(&self, target: u64, seq: u8) {
+    if let Some(counter) = self.0.lock().unwrap().get_mut(&target) {
+      counter.1.remove(&seq);
+    }
+  }
+
+  fn take(&self, target: u64, reserve: bool) -> u8 {
+    let mut counters = self.0.lock().unwrap();
+    let &mut (ref mut next, ref mut reserved) = counters.entry(target).or_default();
+
+    // once every sequence is reserved, the next one is reused anyway.
+    let mut seq = *next;
+    for _ in 0..=u8::MAX {
+      if !reserved.contains(&seq) {
+        break;
+      }
+      seq = seq.wrapping_add(1);
+    }
+
+    *next = seq.wrapping_add(1);
+    if reserve {
+      reserved.insert(seq);
+    }
+    seq
+  }
+}
+
+/// identifies the replies to a message: the source and sequence number it was
+/// sent with, and its target.
+///
+pub(crate) type RouteKey = (u32, u8, u64);
+
+/// the keys of the messages a frame with `header` may answer: the one sent to
+/// its target, and one sent to every device.
+///
+pub(crate) fn route_keys(header: &Header) -> [RouteKey; 2] {
+  let (source, seq) = (header.source(), header.sequence());
+  [(source, seq, u64::from(header.target())), (source, seq, 0)]
+}
+
+/// returns a random, non-zero source identifier. std has no random number
+/// generator, but every `RandomState` is seeded with fresh random keys.
+///
+pub(crate) fn random_source() -> u32 {
+  loop {
+    let source = RandomState::new().build_hasher().finish() as u32;
+    if source != 0 {
+      return source;
+    }
+  }
+}
+
+/// a udp socket driven by some async runtime. implement this for the socket
+/// type of your runtime (tokio, async-std, smol, ..) to use `AsyncClient` with
+/// it. the methods have the same meaning as the `poll_send_to` and
+/// `poll_recv_from` methods of tokio's `UdpSocket`.
+///
+pub trait AsyncTransport {
+  /// sends a datagram to `addr`, returning the number of bytes sent.
+  ///
+  fn poll_send_to(
+    &self,
+    cx: &mut Context,
+    buf: &[u8],
+    addr: SocketAddr,
+  ) -> Poll<io::Result<usize>>;
+
+  /// receives a datagram into `buf`, returning its length and where it came
+  /// from.
+  ///
+  fn poll_recv_from(
+    &self,
+    cx: &mut Context,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<(usize, SocketAddr)>>;
+}
+
+/// sends and receives messages over an `AsyncTransport`, without depending on
+/// any particular async runtime.
+///
+/// # Notes
+///
+///   * Unlike `Client`, this does not spawn a listener or track devices.
+///     Received messages are handed back from `recv_msg` as they arrive.
+///   * Like `Client`, it sends a random source identifier, and counts
+///     sequence numbers separately for every target. Frames carrying another
+///     source are dropped.
+///   * `request` and `await_ack` match replies like `Client` does, by source,
+///     sequence number and target. Frames received while waiting that do not
+///     match are dropped, and nothing is retransmitted, so wrap them in the
+///     timeout of your runtime.
+///
+pub struct AsyncClient<T> {
+  transport: T,
+  source: u32,
+  sequences: Sequences,
+}
+
+impl<T: AsyncTransport> AsyncClient<T> {
+  /// creates a client that sends and receives with `transport`.
+  ///
+  pub fn new(transport: T) -> AsyncClient<T> {
+    AsyncClient {
+      transport,
+      source: random_source(),
+      sequences: Sequences::default(),
+    }
+  }
+
+  /// returns the source identifier the client sends in every message, which
+  /// is random unless it was set with `set_source`.
+  ///
+  #[inline]
+  pub fn source(&self) -> u32 {
+    self.source
+  }
+
+  /// sets the source identifier the client sends in every message. 0 asks
+  /// devices to broadcast their replies, so it is replaced with a random
+  /// source.
+  ///
+  pub fn set_source(&mut self, source: u32) {
+    self.source = if source == 0 { random_source() } else { source };
+  }
+
+  /// returns the transport the client was created with.
+  ///
+  #[inline]
+  pub fn transport(&self) -> &T {
+    &self.transport
+  }
+
+  /// sends a message to the specified address. resolves to the sequence
+  /// number of the message.
+  ///
+  pub fn send_msg<A: Into<MacAddress>>(
+    &self,
+    addr: SocketAddr,
+    payload: Payload,
+    ack_required: bool,
+    target: A,
+  ) -> SendMsg<'_, T> {
+    let options = SendOptions::for_payload(&payload, ack_required);
+    self.send_msg_with(addr, payload, options, target)
+  }
+
+  /// sends a message to the specified address, choosing whether it should be
+  /// acked and responded to.
+  ///
+  pub fn send_msg_with<A: Into<MacAddress>>(
+    &self,
+    addr: SocketAddr,
+    payload: Payload,
+    options: SendOptions,
+    target: A,
+  ) -> SendMsg<'_, T> {
+    let target = u64::from(target.into());
+    let seq = self.sequences.next(target);
+    let msg =
+      Message::new_with(payload, options, target, seq).with_source(self.source);
+    let (bytes, error) = match serialize::encode(&msg) {
+      Ok(bytes) => (bytes, None),
+      Err(e) => (Vec::new(), Some(e)),
+    };
+
+    SendMsg {
+      transport: &self.transport,
+      addr,
+      bytes,
+      error,
+      seq,
+    }
+  }
+
+  /// receives the next datagram, and decodes it as a single message.
+  /// datagrams carrying another controller's source are skipped.
+  ///
+  pub fn recv_msg(&self) -> RecvMsg<'_, T> {
+    RecvMsg {
+      transport: &self.transport,
+      source: self.source,
+      buf: vec![0; consts::MAX_FRAME_SIZE],
+    }
+  }
+
+  /// sends a request to the specified address, and resolves to the response,
+  /// which is matched to the request by its source, sequence number and
+  /// target.
+  ///
+  pub fn request<A: Into<MacAddress>>(
+    &self,
+    addr: SocketAddr,
+    payload: Payload,
+    target: A,
+  ) -> Request<'_, T> {
+    Request(self.exchange(addr, payload, SendOptions::RES_REQUIRED, target))
+  }
+
+  /// sends a message with ack_required to the specified address, and resolves
+  /// once the device acks it.
+  ///
+  pub fn await_ack<A: Into<MacAddress>>(
+    &self,
+    addr: SocketAddr,
+    payload: Payload,
+    target: A,
+  ) -> AwaitAck<'_, T> {
+    AwaitAck(self.exchange(addr, payload, SendOptions::ACK_REQUIRED, target))
+  }
+
+  /// sends a message, keeping its sequence number reserved until the reply
+  /// arrives.
+  ///
+  fn exchange<A: Into<MacAddress>>(
+    &self,
+    addr: SocketAddr,
+    payload: Payload,
+    options: SendOptions,
+    target: A,
+  ) -> Exchange<'_, T> {
+    let target = u64::from(target.into());
+    let seq = self.sequences.reserve(target);
+    let msg =
+      Message::new_with(payload, options, target, seq).with_source(self.source);
+    let (bytes, error) = match serialize::encode(&msg) {
+      Ok(bytes) => (bytes, None),
+      Err(e) => (Vec::new(), Some(e)),
+    };
+
+    Exchange {
+      send: Some(SendMsg {
+        transport: &self.transport,
+        addr,
+        bytes,
+        error,
+        seq,
+      }),
+      recv: self.recv_msg(),
+      key: (self.source, seq, target),
+      ack: options.contains(SendOptions::ACK_REQUIRED),
+      sequences: &self.sequences,
+    }
+  }
+}
+
+/// future returned by `AsyncClient::send_msg`.
+///
+pub struct SendMsg<'a, T: 'a> {
+  transport: &'a T,
+  addr: SocketAddr,
+  bytes: Vec<u8>,
+  error: Option<Error>,
+  seq: u8,
+}
+
+impl<'a, T: AsyncTransport> Future for SendMsg<'a, T> {
+  type Output = Result<u8, Error>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<u8, Error>> {
+    let this = self.get_mut();
+    if let Some(e) = this.error.take() {
+      return Poll::Ready(Err(e));
+    }
+    let bytes = &this.bytes;
+
+    match this.transport.poll_send_to(cx, &bytes[..], this.addr) {
+      Poll::Ready(Ok(n)) if n == bytes.len() => Poll::Ready(Ok(this.seq)),
+      Poll::Ready(Ok(n)) => Poll::Ready(Err(Error::ShortWrite {
+        expected: bytes.len(),
+        actual: n,
+      })),
+      Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+/// future returned by `AsyncClient::recv_msg`.
+///
+pub struct RecvMsg<'a, T: 'a> {
+  transport: &'a T,
+  source: u32,
+  buf: Vec<u8>,
+}
+
+impl<'a, T: AsyncTransport> RecvMsg<'a, T> {
+  fn poll_msg(
+    &mut self,
+    cx: &mut Context,
+  ) -> Poll<Result<(Message, SocketAddr), Error>> {
+    loop {
+      let (amt, src) = match self.transport.poll_recv_from(cx, &mut self.buf[..]) {
+        Poll::Ready(Ok(received)) => received,
+        Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+        Poll::Pending => return Poll::Pending,
+      };
+
+      match Message::from_bytes(&self.buf[..amt]) {
+        Ok(ref msg) if msg.source() != self.source => continue,
+        Ok(msg) => return Poll::Ready(Ok((msg, src))),
+        Err(e) => return Poll::Ready(Err(Error::Decode(e))),
+      }
+    }
+  }
+}
+
+impl<'a, T: AsyncTransport> Future for RecvMsg<'a, T> {
+  type Output = Result<(Message, SocketAddr), Error>;
+
+  fn poll(
+    self: Pin<&mut Self>,
+    cx: &mut Context,
+  ) -> Poll<Result<(Message, SocketAddr), Error>> {
+    self.get_mut().poll_msg(cx)
+  }
+}
+
+/// a message sent by `AsyncClient`, waiting for its ack or response. frames
+/// that fail to decode are skipped, like `Client` does.
+///
+struct Exchange<'a, T: 'a> {
+  send: Option<SendMsg<'a, T>>,
+  recv: RecvMsg<'a, T>,
+  key: RouteKey,
+  ack: bool,
+  sequences: &'a Sequences,
+}
+
+impl<'a, T: AsyncTransport> Exchange<'a, T> {
+  fn poll_reply(&mut self, cx: &mut Context) -> Poll<Result<Message, Error>> {
+    if let Some(ref mut send) = self.send {
+      match Pin::new(send).poll(cx) {
+        Poll::Ready(Ok(_)) => (),
+        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+    self.send = None;
+
+    loop {
+      let msg = match self.recv.poll_msg(cx) {
+        Poll::Ready(Ok((msg, _))) => msg,
+        Poll::Ready(Err(Error::Decode(_))) => continue,
+        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+        Poll::Pending => return Poll::Pending,
+      };
+      let ack = msg.header().typ() == consts::DEVICE_ACKNOWLEDGEMENT;
+
+      if ack == self.ack && route_keys(msg.header()).contains(&self.key) {
+        return Poll::Ready(Ok(msg));
+      }
+    }
+  }
+}
+
+impl<'a, T> Drop for Exchange<'a, T> {
+  fn drop(&mut self) {
+    self.sequences.release(self.key.2, self.key.1);
+  }
+}
+
+/// future returned by `AsyncClient::request`.
+///
+pub struct Request<'a, T: 'a>(Exchange<'a, T>);
+
+impl<'a, T: AsyncTransport> Future for Request<'a, T> {
+  type Output = Result<Payload, Error>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Payload, Error>> {
+    match self.get_mut().0.poll_reply(cx) {
+      Poll::Ready(res) => Poll::Ready(res.map(|msg| msg.unpack().0)),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+/// future returned by `AsyncClient::await_ack`.
+///
+pub struct AwaitAck<'a, T: 'a>(Exchange<'a, T>);
+
+impl<'a, T: AsyncTransport> Future for AwaitAck<'a, T> {
+  type Output = Result<(), Error>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+    match self.get_mut().0.poll_reply(cx) {
+      Poll::Ready(res) => Poll::Ready(res.map(|_| ())),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+/// a waker for futures polled by hand in tests.
+///
+#[cfg(test)]
+struct NoopWaker;
+
+#[cfg(test)]
+impl std::task::Wake for NoopWaker {
+  fn wake(self: std::sync::Arc<Self>) {}
+}
+
+#[test]
+fn test_async_client_uses_the_transport() {
+  use std::sync::{Arc, Mutex};
+  use std::task::Waker;
+
+  use payload::Device;
+
+  /// loops every datagram sent back to the receiver, and is pending until
+  /// something has been sent.
+  struct Loopback(Mutex<Vec<(Vec<u8>, SocketAddr)>>);
+
+  impl AsyncTransport for Loopback {
+    fn poll_send_to(
+      &self,
+      _: &mut Context,
+      buf: &[u8],
+      addr: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+      self.0.lock().unwrap().push((buf.to_vec(), addr));
+      Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(
+      &self,
+      _: &mut Context,
+      buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+      match self.0.lock().unwrap().pop() {
+        Some((bytes, addr)) => {
+          buf[..bytes.len()].copy_from_slice(&bytes[..]);
+          Poll::Ready(Ok((bytes.len(), addr)))
+        }
+        None => Poll::Pending,
+      }
+    }
+  }
+
+  let waker = Waker::from(Arc::new(NoopWaker));
+  let mut cx = Context::from_waker(&waker);
+  let mut client = AsyncClient::new(Loopback(Mutex::new(Vec::new())));
+  let addr = "127.0.0.1:56700".parse().unwrap();
+  assert_ne!(0, client.source());
+
+  let mut recv = client.recv_msg();
+  assert!(Pin::new(&mut recv).poll(&mut cx).is_pending());
+
+  let mut send =
+    client.send_msg(addr, Payload::Device(Device::GetLabel), false, 0xAB);
+  match Pin::new(&mut send).poll(&mut cx) {
+    Poll::Ready(Ok(seq)) => assert_eq!(0, seq),
+    _ => panic!("send did not complete"),
+  }
+
+  match Pin::new(&mut recv).poll(&mut cx) {
+    Poll::Ready(Ok((msg, from))) => {
+      assert_eq!(addr, from);
+      assert_eq!(MacAddress::from(0xAB), msg.target());
+      assert_eq!(client.source(), msg.source());
+      assert_eq!(&Payload::Device(Device::GetLabel), msg.payload());
+    }
+    _ => panic!("recv did not complete"),
+  }
+
+  // sequences are counted per target, and the source can be replaced.
+  client.set_source(42);
+  for &(target, expected) in &[(0xCD, 0), (0xAB, 1)] {
+    let payload = Payload::Device(Device::GetLabel);
+    let mut send = client.send_msg(addr, payload, false, target);
+    match Pin::new(&mut send).poll(&mut cx) {
+      Poll::Ready(Ok(seq)) => assert_eq!(expected, seq),
+      _ => panic!("send did not complete"),
+    }
+    match Pin::new(&mut client.recv_msg()).poll(&mut cx) {
+      Poll::Ready(Ok((msg, _))) => assert_eq!(42, msg.source()),
+      _ => panic!("recv did not complete"),
+    }
+  }
+  client.set_source(0);
+  assert_ne!(0, client.source());
+}
+
+#[test]
+fn test_async_client_matches_replies() {
+  use std::collections::VecDeque;
+  use std::sync::Arc;
+  use std::task::Waker;
+
+  use payload::Device;
+
+  /// answers every message, after a frame for another source, a frame with
+  /// another sequence number, and an ack if one was asked for.
+  struct Lamp(Mutex<VecDeque<Vec<u8>>>);
+
+  impl AsyncTransport for Lamp {
+    fn poll_send_to(
+      &self,
+      _: &mut Context,
+      buf: &[u8],
+      _: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+      let msg = Message::from_bytes(buf).unwrap();
+      let (source, seq) = (msg.source(), msg.sequence());
+      let reply = |payload: Payload, source: u32, seq: u8| {
+        let msg = Message::new(payload, false, 0xAB, seq).with_source(source);
+        serialize::encode(&msg).unwrap()
+      };
+      let label = || Payload::Device(Device::StateLabel("Lamp".to_string()));
+
+      let mut replies = self.0.lock().unwrap();
+      replies.push_back(reply(label(), source ^ 1, seq));
+      replies.push_back(reply(label(), source, seq.wrapping_add(1)));
+      if msg.ack_required() {
+        let ack = Payload::Device(Device::Acknowledgement);
+        replies.push_back(reply(ack, source, seq));
+      }
+      replies.push_back(reply(label(), source, seq));
+      Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(
+      &self,
+      _: &mut Context,
+      buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+      match self.0.lock().unwrap().pop_front() {
+        Some(bytes) => {
+          buf[..bytes.len()].copy_from_slice(&bytes[..]);
+          Poll::Ready(Ok((bytes.len(), "127.0.0.1:56700".parse().unwrap())))
+        }
+        None => Poll::Pending,
+      }
+    }
+  }
+
+  let waker = Waker::from(Arc::new(NoopWaker));
+  let mut cx = Context::from_waker(&waker);
+  let client = AsyncClient::new(Lamp(Mutex::new(VecDeque::new())));
+  let addr = "127.0.0.1:56700".parse().unwrap();
+
+  // the response skips the ack and the frames for other requests.
+  let mut request = client.request(addr, Payload::Device(Device::GetLabel), 0xAB);
+  match Pin::new(&mut request).poll(&mut cx) {
+    Poll::Ready(Ok(payload)) => {
+      assert_eq!(Payload::Device(Device::StateLabel("Lamp".to_string())), payload)
+    }
+    _ => panic!("request did not complete"),
+  }
+  assert!(client.transport().0.lock().unwrap().is_empty());
+
+  // a message sent to every device is answered by any target.
+  let payload = Payload::Device(Device::SetLabel("Lamp".to_string()));
+  let mut ack = client.await_ack(addr, payload, 0);
+  match Pin::new(&mut ack).poll(&mut cx) {
+    Poll::Ready(Ok(())) => (),
+    _ => panic!("ack did not arrive"),
+  }
+  assert_eq!(1, client.transport().0.lock().unwrap().len());
+  drop(ack);
+
+  // recv_msg drops frames for another source too.
+  let get = Payload::Device(Device::GetLabel);
+  let foreign = Message::new(get, false, 0xAB, 0).with_source(client.source() ^ 1);
+  let foreign = serialize::encode(&foreign).unwrap();
+  client.transport().0.lock().unwrap().push_front(foreign);
+  match Pin::new(&mut client.recv_msg()).poll(&mut cx) {
+    Poll::Ready(Ok((msg, _))) => {
+      assert_eq!(client.source(), msg.source());
+      assert!(msg.payload().is_state());
+    }
+    _ => panic!("recv did not complete"),
+  }
+  assert!(client.transport().0.lock().unwrap().is_empty());
+}
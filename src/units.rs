@@ -0,0 +1,280 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use byteorder::LittleEndian;
+
+use error::Error;
+use serialize::{DecodeError, WireDecode, WireEncode, WireReader, WriteBytesExt};
+
+/// Defines a u16 newtype that spans the full range of a u16 on the wire, with
+/// conversions to and from the raw value and a scaled `$unit` value between 0
+/// and `$scale`.
+///
+macro_rules! scaled_u16(
+  (
+    $(#[$attr:meta])*
+    $name:ident, $from:ident, $to:ident, $unit:expr, $scale:expr
+  ) => (
+    $(#[$attr])*
+    #[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+    pub struct $name(u16);
+
+    impl $name {
+      /// The smallest value.
+      ///
+      pub const MIN: $name = $name(0);
+
+      /// The largest value.
+      ///
+      pub const MAX: $name = $name(u16::MAX);
+
+      #[doc = concat!(
+        "converts from ", $unit, ", which must be between 0 and ",
+        stringify!($scale), "."
+      )]
+      ///
+      pub fn $from(v: f32) -> Result<$name, Error> {
+        if !(0.0..=$scale).contains(&v) {
+          return Err(Error::OutOfRange {
+            quantity: stringify!($name),
+            value: v,
+            min: 0.0,
+            max: $scale,
+          });
+        }
+
+        // rounds to the nearest value without f32::round, which needs std
+        Ok($name((v / $scale * u16::MAX as f32 + 0.5) as u16))
+      }
+
+      #[doc = concat!("converts to ", $unit, ".")]
+      ///
+      #[inline]
+      pub fn $to(self) -> f32 {
+        self.0 as f32 / u16::MAX as f32 * $scale
+      }
+    }
+
+    impl From<u16> for $name {
+      #[inline]
+      fn from(v: u16) -> $name {
+        $name(v)
+      }
+    }
+
+    impl From<$name> for u16 {
+      #[inline]
+      fn from(v: $name) -> u16 {
+        v.0
+      }
+    }
+
+    impl WireEncode for $name {
+      #[inline]
+      fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u16::<LittleEndian>(self.0)
+      }
+    }
+
+    impl WireDecode for $name {
+      #[inline]
+      fn decode(r: &mut WireReader) -> Result<$name, DecodeError> {
+        Ok($name(r.read_u16()?))
+      }
+    }
+  );
+);
+
+scaled_u16!(
+  /// Hue of a HSBK, where u16::MAX is a full turn of the color wheel.
+  ///
+  Hue, from_degrees, degrees, "degrees", 360.0
+);
+
+scaled_u16!(
+  /// Saturation of a HSBK.
+  ///
+  Saturation, from_percent, percent, "a percentage", 100.0
+);
+
+scaled_u16!(
+  /// Brightness of a HSBK.
+  ///
+  Brightness, from_percent, percent, "a percentage", 100.0
+);
+
+/// Color temperature of a HSBK, in kelvin.
+///
+/// # Notes
+///
+///   * Only Kelvin::new validates the temperature. Values decoded from a
+///     device are kept as is, since some devices report a temperature of 0
+///     for saturated colors.
+///
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub struct Kelvin(u16);
+
+impl Kelvin {
+  /// The coolest temperature accepted by Kelvin::new.
+  ///
+  pub const MIN: Kelvin = Kelvin(1500);
+
+  /// The warmest temperature accepted by Kelvin::new.
+  ///
+  pub const MAX: Kelvin = Kelvin(9000);
+
+  /// creates a temperature, which must be between Kelvin::MIN and Kelvin::MAX.
+  ///
+  pub fn new(k: u16) -> Result<Kelvin, Error> {
+    if !(Kelvin::MIN.0..=Kelvin::MAX.0).contains(&k) {
+      return Err(Error::OutOfRange {
+        quantity: "Kelvin",
+        value: f32::from(k),
+        min: f32::from(Kelvin::MIN.0),
+        max: f32::from(Kelvin::MAX.0),
+      });
+    }
+
+    Ok(Kelvin(k))
+  }
+
+  /// wraps a temperature without validating it.
+  ///
+  #[inline]
+  pub(crate) fn raw(k: u16) -> Kelvin {
+    Kelvin(k)
+  }
+}
+
+impl From<Kelvin> for u16 {
+  #[inline]
+  fn from(v: Kelvin) -> u16 {
+    v.0
+  }
+}
+
+impl WireEncode for Kelvin {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u16::<LittleEndian>(self.0)
+  }
+}
+
+impl WireDecode for Kelvin {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<Kelvin, DecodeError> {
+    Ok(Kelvin(r.read_u16()?))
+  }
+}
+
+/// Transition time of a Set* message, in milliseconds.
+///
+/// # Notes
+///
+///   * Converting from a Duration longer than u32::MAX milliseconds (about 49
+///     days) saturates, rather than wrapping around to a short transition.
+///
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub struct TransitionMs(u32);
+
+impl TransitionMs {
+  /// An instant transition.
+  ///
+  pub const ZERO: TransitionMs = TransitionMs(0);
+
+  #[inline]
+  pub fn from_millis(ms: u32) -> TransitionMs {
+    TransitionMs(ms)
+  }
+
+  #[inline]
+  pub fn as_millis(self) -> u32 {
+    self.0
+  }
+}
+
+impl From<u32> for TransitionMs {
+  #[inline]
+  fn from(ms: u32) -> TransitionMs {
+    TransitionMs(ms)
+  }
+}
+
+impl From<TransitionMs> for u32 {
+  #[inline]
+  fn from(v: TransitionMs) -> u32 {
+    v.0
+  }
+}
+
+impl From<Duration> for TransitionMs {
+  #[inline]
+  fn from(d: Duration) -> TransitionMs {
+    TransitionMs(d.as_millis().min(u32::MAX as u128) as u32)
+  }
+}
+
+impl From<TransitionMs> for Duration {
+  #[inline]
+  fn from(v: TransitionMs) -> Duration {
+    Duration::from_millis(v.0 as u64)
+  }
+}
+
+impl WireEncode for TransitionMs {
+  #[inline]
+  fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(self.0)
+  }
+}
+
+impl WireDecode for TransitionMs {
+  #[inline]
+  fn decode(r: &mut WireReader) -> Result<TransitionMs, DecodeError> {
+    Ok(TransitionMs(r.read_u32()?))
+  }
+}
+
+#[test]
+fn test_scaled_units() {
+  assert_eq!(Hue::MAX, Hue::from_degrees(360.0).unwrap());
+  assert_eq!(Hue::from(21845), Hue::from_degrees(120.0).unwrap());
+  assert!((Hue::from(21845).degrees() - 120.0).abs() < 0.01);
+  assert_eq!(Saturation::from(32768), Saturation::from_percent(50.0).unwrap());
+  assert_eq!(Brightness::MIN, Brightness::from_percent(0.0).unwrap());
+  assert_eq!(100.0, Brightness::MAX.percent());
+
+  assert_eq!(
+    "Hue -1 is not between 0 and 360",
+    Hue::from_degrees(-1.0).unwrap_err().to_string()
+  );
+  assert!(Saturation::from_percent(100.5).is_err());
+  assert!(Brightness::from_percent(f32::NAN).is_err());
+}
+
+#[test]
+fn test_kelvin_is_validated() {
+  assert_eq!(3500, u16::from(Kelvin::new(3500).unwrap()));
+  assert_eq!(Kelvin::MIN, Kelvin::new(1500).unwrap());
+  assert_eq!(
+    "Kelvin 1499 is not between 1500 and 9000",
+    Kelvin::new(1499).unwrap_err().to_string()
+  );
+  assert!(Kelvin::new(9001).is_err());
+}
+
+#[test]
+fn test_transition_durations() {
+  assert_eq!(
+    TransitionMs::from(1500),
+    TransitionMs::from(Duration::from_millis(1500))
+  );
+  assert_eq!(
+    Duration::from_secs(2),
+    Duration::from(TransitionMs::from_millis(2000))
+  );
+  assert_eq!(
+    u32::MAX,
+    TransitionMs::from(Duration::from_secs(u64::MAX)).as_millis()
+  );
+}